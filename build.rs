@@ -2,6 +2,10 @@ use std::{env, fs::read_dir, path::PathBuf};
 
 fn main() {
     println!("cargo::rerun-if-changed=build.rs");
+    if env::var_os("CARGO_FEATURE_JNI").is_none() {
+        // tree-sitter-ng natives are only needed to serve java side
+        return;
+    }
     let target = env::var("TARGET").unwrap();
     let tree_sitter_path = PathBuf::from("tree-sitter-ng");
     let src_path = tree_sitter_path.join("tree-sitter/src/main/c");
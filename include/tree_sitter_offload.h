@@ -0,0 +1,134 @@
+#ifndef TREE_SITTER_OFFLOAD_H_
+#define TREE_SITTER_OFFLOAD_H_
+
+// C API of tree-sitter-offload, available when the crate is built with the `capi` feature.
+//
+// Offsets and columns are measured in UTF-16 code units. Arrays returned by the library must be
+// released with the matching tso_*_free function. On failure functions return null/false/-1 and
+// tso_last_error() describes the failure.
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct TSLanguage TSLanguage;
+typedef struct TsoSnapshot TsoSnapshot;
+
+typedef struct TsoPoint {
+  uint32_t row;
+  uint32_t column;
+} TsoPoint;
+
+typedef struct TsoRange {
+  uint32_t start_offset;
+  uint32_t end_offset;
+  TsoPoint start_point;
+  TsoPoint end_point;
+} TsoRange;
+
+typedef struct TsoInputEdit {
+  uint32_t start_offset;
+  uint32_t old_end_offset;
+  uint32_t new_end_offset;
+  TsoPoint start_point;
+  TsoPoint old_end_point;
+  TsoPoint new_end_point;
+} TsoInputEdit;
+
+typedef struct TsoHighlightToken {
+  int64_t language_id;
+  uint16_t kind_id;
+  uint16_t capture_id;
+  uint32_t length;
+} TsoHighlightToken;
+
+typedef struct TsoFoldRange {
+  TsoRange range;
+  char *collapsed_text;
+  bool collapsed_by_default;
+} TsoFoldRange;
+
+typedef struct TsoRangeArray {
+  TsoRange *data;
+  size_t len;
+} TsoRangeArray;
+
+typedef struct TsoFoldRangeArray {
+  TsoFoldRange *data;
+  size_t len;
+} TsoFoldRangeArray;
+
+typedef struct TsoHighlightTokenArray {
+  TsoHighlightToken *data;
+  size_t len;
+} TsoHighlightTokenArray;
+
+typedef struct TsoHighlightTokens {
+  uint32_t start_offset;
+  TsoHighlightTokenArray tokens;
+} TsoHighlightTokens;
+
+typedef enum TsoLogLevel {
+  TSO_LOG_ERROR = 0,
+  TSO_LOG_WARN = 1,
+  TSO_LOG_INFO = 2,
+  TSO_LOG_DEBUG = 3,
+} TsoLogLevel;
+
+typedef void (*TsoLogCallback)(TsoLogLevel level, const char *message, void *user_data);
+
+typedef enum TsoQueryKind {
+  TSO_QUERY_HIGHLIGHTS = 0,
+  TSO_QUERY_FOLDS = 1,
+  TSO_QUERY_INDENTS = 2,
+  TSO_QUERY_INJECTIONS = 3,
+  TSO_QUERY_TAGS = 4,
+  TSO_QUERY_FORMATS = 5,
+  TSO_QUERY_SPLITS = 6,
+} TsoQueryKind;
+
+const char *tso_last_error(void);
+
+// Sets option of the isolate. Known options: "debug.invariants", "profiler.enabled" with values
+// "true"/"false"; "highlight.max_token_length", "query.match_limit", "query.time_budget_ms" with
+// non-negative integer values, 0 for no limit.
+bool tso_set_option(int64_t isolate_id, const char *key, const char *value);
+// Null callback disables logging. Message is valid only during the callback.
+void tso_set_log_callback(TsoLogCallback callback, void *user_data);
+
+int64_t tso_isolate_create(void);
+bool tso_isolate_destroy(int64_t isolate_id);
+
+int64_t tso_register_language(int64_t isolate_id, const char *name, const TSLanguage *language);
+bool tso_add_query(int64_t isolate_id, int64_t language_id, TsoQueryKind kind,
+                   const uint8_t *source, size_t source_length);
+
+TsoSnapshot *tso_snapshot_parse(int64_t isolate_id, int64_t language_id, const uint16_t *text,
+                                size_t text_length);
+TsoSnapshot *tso_snapshot_parse_incremental(const TsoSnapshot *old_snapshot, const uint16_t *text,
+                                            size_t text_length, const TsoInputEdit *edit,
+                                            TsoRangeArray *changed_ranges);
+void tso_snapshot_destroy(TsoSnapshot *snapshot);
+int64_t tso_snapshot_isolate(const TsoSnapshot *snapshot);
+
+TsoHighlightTokens tso_collect_highlights(const TsoSnapshot *snapshot, const uint16_t *text,
+                                          size_t text_length, uint32_t start_offset,
+                                          uint32_t end_offset);
+void tso_highlight_tokens_free(TsoHighlightTokens tokens);
+
+TsoFoldRangeArray tso_collect_fold_ranges(const TsoSnapshot *snapshot, const uint16_t *text,
+                                          size_t text_length, uint32_t start_offset,
+                                          uint32_t end_offset, bool use_inner);
+void tso_fold_ranges_free(TsoFoldRangeArray ranges);
+
+void tso_ranges_free(TsoRangeArray ranges);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif  // TREE_SITTER_OFFLOAD_H_
@@ -0,0 +1,183 @@
+use std::ops::Range;
+
+use streaming_iterator::StreamingIterator as _;
+use tree_sitter::{self as ts, QueryCursor};
+
+use crate::{
+    predicates::AdditionalPredicates,
+    profiler,
+    query::{SourceText, SourceTextProvider},
+    syntax_snapshot::{SyntaxSnapshot, SyntaxSnapshotEntryContent},
+    LanguageId,
+};
+
+#[cfg(feature = "jni")]
+mod jni_methods;
+
+#[derive(thiserror::Error, Debug)]
+pub enum BracketsQueryError {
+    #[error("required captures not found")]
+    NoRequiredCaptures,
+}
+
+/// Query capturing matching delimiters of one pattern as `@open` and `@close`
+pub struct BracketsQuery {
+    query: ts::Query,
+    predicates: AdditionalPredicates,
+    open_capture_id: u32,
+    close_capture_id: u32,
+}
+
+impl BracketsQuery {
+    pub fn new(
+        query: ts::Query,
+        predicates: AdditionalPredicates,
+    ) -> Result<Self, BracketsQueryError> {
+        let (Some(open_capture_id), Some(close_capture_id)) = (
+            query.capture_index_for_name("open"),
+            query.capture_index_for_name("close"),
+        ) else {
+            return Err(BracketsQueryError::NoRequiredCaptures);
+        };
+        Ok(BracketsQuery {
+            query,
+            predicates,
+            open_capture_id,
+            close_capture_id,
+        })
+    }
+
+    pub fn capture_names(&self) -> &[&str] {
+        self.query.capture_names()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BracketPair {
+    pub language_id: LanguageId,
+    pub open: ts::Range,
+    pub close: ts::Range,
+    /// Number of pairs enclosing this one, pairs of all injection layers are counted
+    pub level: usize,
+}
+
+/// Matched delimiter pairs intersecting `byte_range` sorted by start of the open delimiter
+pub fn collect_bracket_pairs(
+    snapshot: &SyntaxSnapshot,
+    text: SourceText<'_>,
+    byte_range: Range<usize>,
+) -> Vec<BracketPair> {
+    let _profile = profiler::call(&snapshot.isolate, "brackets");
+    let text_provider = SourceTextProvider::new(text);
+    let mut pairs = Vec::new();
+    for (_, entry) in snapshot.intersecting_entries(byte_range.clone(), false) {
+        let SyntaxSnapshotEntryContent::Parsed { language, tree } = &entry.content else {
+            continue;
+        };
+        let query = snapshot.isolate.with_language(*language, |language| {
+            language.parser_info().brackets_query.clone()
+        });
+        let Ok(Some(query)) = query else {
+            continue;
+        };
+        let mut cursor = QueryCursor::new();
+        cursor.set_byte_range(byte_range.clone());
+        let mut matches = cursor.matches(
+            &query.query,
+            tree.root_node_with_offset(entry.byte_offset, entry.point_offset),
+            &text_provider,
+        );
+        while let Some(query_match) = matches.next() {
+            if !query
+                .predicates
+                .satisfies_predicates(&text_provider, query_match)
+            {
+                continue;
+            }
+            let (Some(open), Some(close)) = (
+                query_match
+                    .nodes_for_capture_index(query.open_capture_id)
+                    .next(),
+                query_match
+                    .nodes_for_capture_index(query.close_capture_id)
+                    .next(),
+            ) else {
+                continue;
+            };
+            if open.end_byte() > close.start_byte() {
+                continue;
+            }
+            pairs.push(BracketPair {
+                language_id: *language,
+                open: open.range(),
+                close: close.range(),
+                level: 0,
+            });
+        }
+    }
+    pairs.sort_by_key(|pair| (pair.open.start_byte, std::cmp::Reverse(pair.close.end_byte)));
+    pairs.dedup_by(|pair, other| pair.open == other.open && pair.close == other.close);
+    // Levels are counted by nesting alone, so pairs of injected layers continue the levels of
+    // pairs around them
+    let mut enclosing_ends: Vec<usize> = Vec::new();
+    for pair in &mut pairs {
+        while enclosing_ends
+            .last()
+            .is_some_and(|end| *end <= pair.open.start_byte)
+        {
+            enclosing_ends.pop();
+        }
+        pair.level = enclosing_ends.len();
+        enclosing_ends.push(pair.close.end_byte);
+    }
+    pairs
+}
+
+/// Opening and closing delimiters of the node if they are its first and last tokens, e.g.
+/// `do`/`end` or `if`/`fi`
+fn structural_delimiters(node: ts::Node) -> Option<(ts::Node, ts::Node)> {
+    let parent = node.parent()?;
+    let (open, close) = (parent.child(0)?, parent.child(parent.child_count() - 1)?);
+    if open.is_named() || close.is_named() || open.id() == close.id() {
+        return None;
+    }
+    (node.id() == open.id() || node.id() == close.id()).then_some((open, close))
+}
+
+/// Pair of delimiters one of which is at `byte_offset`, the one starting at it or containing it
+/// is preferred to the one ending at it. Pairs of brackets queries are used first, then tokens
+/// starting and ending their parent nodes of any layer.
+pub fn find_matching_delimiter(
+    snapshot: &SyntaxSnapshot,
+    text: SourceText<'_>,
+    byte_offset: usize,
+) -> Option<(ts::Range, ts::Range)> {
+    let pairs = collect_bracket_pairs(
+        snapshot,
+        text,
+        byte_offset.saturating_sub(1)..(byte_offset + 1),
+    );
+    let contains =
+        |range: &ts::Range| range.start_byte <= byte_offset && byte_offset < range.end_byte;
+    // Innermost pair wins when delimiters of nested pairs touch
+    let pair = pairs
+        .iter()
+        .rev()
+        .find(|pair| contains(&pair.open) || contains(&pair.close))
+        .or_else(|| {
+            pairs.iter().rev().find(|pair| {
+                pair.open.end_byte == byte_offset || pair.close.end_byte == byte_offset
+            })
+        });
+    if let Some(pair) = pair {
+        return Some((pair.open, pair.close));
+    }
+    let offsets = [Some(byte_offset), byte_offset.checked_sub(1)];
+    offsets.into_iter().flatten().find_map(|offset| {
+        let node = snapshot.node_at_offset(offset)?.node;
+        if node.child_count() > 0 {
+            return None;
+        }
+        structural_delimiters(node).map(|(open, close)| (open.range(), close.range()))
+    })
+}
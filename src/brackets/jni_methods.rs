@@ -0,0 +1,145 @@
+use jni::{
+    errors::Result as JNIResult,
+    objects::{AutoLocal, JCharArray, JClass, JMethodID, JObject, JObjectArray, JValue},
+    sys::{jint, jsize},
+    JNIEnv,
+};
+use once_cell::sync::OnceCell as JOnceLock;
+
+use crate::{
+    jni_utils::{read_char_array, throw_exception_from_result, RangeDesc},
+    query::SourceText,
+    syntax_snapshot::SyntaxSnapshotDesc,
+};
+
+use super::{collect_bracket_pairs, find_matching_delimiter, BracketPair};
+
+static BRACKET_PAIR_CONSTRUCTOR: JOnceLock<JMethodID> = JOnceLock::new();
+
+struct BracketPairDesc<'local> {
+    constructor: JMethodID,
+    class: AutoLocal<'local, JClass<'local>>,
+    range_desc: RangeDesc<'local>,
+}
+
+impl<'local> BracketPairDesc<'local> {
+    fn new(env: &mut JNIEnv<'local>) -> JNIResult<BracketPairDesc<'local>> {
+        let range_desc = RangeDesc::new(env)?;
+        let class = env.find_class("com/hulylabs/treesitter/language/BracketPair")?;
+        let constructor = *BRACKET_PAIR_CONSTRUCTOR.get_or_try_init(|| {
+            env.get_method_id(
+                &class,
+                "<init>",
+                "(JLcom/hulylabs/treesitter/language/Range;Lcom/hulylabs/treesitter/language/Range;I)V",
+            )
+        })?;
+        Ok(BracketPairDesc {
+            constructor,
+            class: env.auto_local(class),
+            range_desc,
+        })
+    }
+
+    fn to_java_object(
+        &self,
+        env: &mut JNIEnv<'local>,
+        pair: &BracketPair,
+    ) -> JNIResult<JObject<'local>> {
+        let open = self.range_desc.to_java_object(env, pair.open)?;
+        let open = env.auto_local(open);
+        let close = self.range_desc.to_java_object(env, pair.close)?;
+        let close = env.auto_local(close);
+        // SAFETY: constructor is valid and derived from class by construction of self
+        unsafe {
+            env.new_object_unchecked(
+                &self.class,
+                self.constructor,
+                &[
+                    JValue::from(pair.language_id).as_jni(),
+                    JValue::Object(&open).as_jni(),
+                    JValue::Object(&close).as_jni(),
+                    JValue::Int(pair.level as jint).as_jni(),
+                ],
+            )
+        }
+    }
+}
+
+/// Matched delimiter pairs with their nesting level, for rainbow brackets and brace matching
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeRangesProvider_nativeGetBracketPairs<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    snapshot: JObject<'local>,
+    text: JCharArray<'local>,
+    start_offset: jint,
+    end_offset: jint,
+) -> JObjectArray<'local> {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        snapshot: JObject<'local>,
+        text: JCharArray<'local>,
+        start_offset: jint,
+        end_offset: jint,
+    ) -> JNIResult<JObjectArray<'local>> {
+        let snapshot = SyntaxSnapshotDesc::from_java_object(env, snapshot)?;
+        let text_buffer = read_char_array(env, &text)?;
+        let pairs = collect_bracket_pairs(
+            snapshot,
+            SourceText::Utf16(&text_buffer),
+            ((start_offset * 2) as usize)..((end_offset * 2) as usize),
+        );
+        let desc = BracketPairDesc::new(env)?;
+        let array = env.new_object_array(pairs.len() as jsize, &desc.class, JObject::null())?;
+        for (index, pair) in pairs.iter().enumerate() {
+            let obj = desc.to_java_object(env, pair)?;
+            let obj = env.auto_local(obj);
+            env.set_object_array_element(&array, index as i32, obj)?;
+        }
+        Ok(array)
+    }
+    let result = inner(&mut env, snapshot, text, start_offset, end_offset);
+    throw_exception_from_result(&mut env, result)
+}
+
+/// Ranges of the opening and closing delimiters of the pair with one of them at `offset`, null if
+/// there is no delimiter at it
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeRangesProvider_nativeFindMatchingDelimiter<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    snapshot: JObject<'local>,
+    text: JCharArray<'local>,
+    offset: jint,
+) -> JObjectArray<'local> {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        snapshot: JObject<'local>,
+        text: JCharArray<'local>,
+        offset: jint,
+    ) -> JNIResult<JObjectArray<'local>> {
+        let snapshot = SyntaxSnapshotDesc::from_java_object(env, snapshot)?;
+        let text_buffer = read_char_array(env, &text)?;
+        let Some((open, close)) = find_matching_delimiter(
+            snapshot,
+            SourceText::Utf16(&text_buffer),
+            (offset * 2) as usize,
+        ) else {
+            return Ok(JObjectArray::default());
+        };
+        let range_desc = RangeDesc::new(env)?;
+        let array = env.new_object_array(2, &range_desc.class, JObject::null())?;
+        for (index, range) in [open, close].into_iter().enumerate() {
+            let obj = range_desc.to_java_object(env, range)?;
+            let obj = env.auto_local(obj);
+            env.set_object_array_element(&array, index as i32, obj)?;
+        }
+        Ok(array)
+    }
+    let result = inner(&mut env, snapshot, text, offset);
+    throw_exception_from_result(&mut env, result)
+}
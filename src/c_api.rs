@@ -0,0 +1,564 @@
+//! C ABI mirroring the JNI entry points for hosts without a JVM.
+//!
+//! Offsets and columns are expressed in UTF-16 code units, same as on the java side. Every
+//! array returned by this module must be released with the matching `tso_*_free` function.
+
+use std::{
+    cell::RefCell,
+    ffi::{c_char, c_void, CStr, CString},
+    ptr, slice,
+};
+
+use crate::{
+    collect_fold_ranges, highlight_tokens_cover,
+    logging::{set_log_sink, LogLevel},
+    AddQueryError, Isolate, IsolateId, LanguageId, SourceText, SyntaxSnapshot,
+};
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: impl ToString) {
+    let message =
+        CString::new(message.to_string().replace('\0', " ")).expect("nul bytes are replaced");
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = Some(message));
+}
+
+/// Returns message of the last error on the calling thread or null. Pointer is valid until the
+/// next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn tso_last_error() -> *const c_char {
+    LAST_ERROR.with(|last_error| {
+        last_error
+            .borrow()
+            .as_ref()
+            .map_or(ptr::null(), |message| message.as_ptr())
+    })
+}
+
+/// Sets option of the isolate, see [`Isolate::set_option`] for known options.
+///
+/// # Safety
+/// `key` and `value` must be valid nul-terminated UTF-8 strings
+#[no_mangle]
+pub unsafe extern "C" fn tso_set_option(
+    isolate_id: i64,
+    key: *const c_char,
+    value: *const c_char,
+) -> bool {
+    if key.is_null() || value.is_null() {
+        set_last_error("null argument");
+        return false;
+    }
+    let isolate = match Isolate::get(IsolateId::from(isolate_id)) {
+        Ok(isolate) => isolate,
+        Err(err) => {
+            set_last_error(err);
+            return false;
+        }
+    };
+    // SAFETY: guaranteed by caller
+    let (key, value) = unsafe { (CStr::from_ptr(key), CStr::from_ptr(value)) };
+    let (Ok(key), Ok(value)) = (key.to_str(), value.to_str()) else {
+        set_last_error("invalid UTF-8 in option");
+        return false;
+    };
+    match isolate.set_option(key, value) {
+        Ok(()) => true,
+        Err(err) => {
+            set_last_error(err);
+            false
+        }
+    }
+}
+
+pub type TsoLogCallback = extern "C" fn(LogLevel, *const c_char, *mut c_void);
+
+struct LogUserData(*mut c_void);
+
+// SAFETY: caller of tso_set_log_callback guarantees user data may be used from any thread
+unsafe impl Send for LogUserData {}
+unsafe impl Sync for LogUserData {}
+
+impl LogUserData {
+    fn get(&self) -> *mut c_void {
+        self.0
+    }
+}
+
+/// Routes library messages to `callback`, null callback disables logging. Message pointer is
+/// valid only during the callback.
+///
+/// # Safety
+/// `callback` must be callable from any thread with `user_data` until it is replaced and the
+/// calls already started on other threads return
+#[no_mangle]
+pub unsafe extern "C" fn tso_set_log_callback(
+    callback: Option<TsoLogCallback>,
+    user_data: *mut c_void,
+) {
+    let Some(callback) = callback else {
+        set_log_sink(None);
+        return;
+    };
+    let user_data = LogUserData(user_data);
+    set_log_sink(Some(Box::new(move |level, message| {
+        let message = CString::new(message.replace('\0', " ")).expect("nul bytes are replaced");
+        callback(level, message.as_ptr(), user_data.get());
+    })));
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TsoPoint {
+    pub row: u32,
+    pub column: u32,
+}
+
+impl From<tree_sitter::Point> for TsoPoint {
+    fn from(point: tree_sitter::Point) -> Self {
+        TsoPoint {
+            row: point.row as u32,
+            column: (point.column / 2) as u32,
+        }
+    }
+}
+
+impl From<TsoPoint> for tree_sitter::Point {
+    fn from(point: TsoPoint) -> Self {
+        tree_sitter::Point {
+            row: point.row as usize,
+            column: point.column as usize * 2,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TsoRange {
+    pub start_offset: u32,
+    pub end_offset: u32,
+    pub start_point: TsoPoint,
+    pub end_point: TsoPoint,
+}
+
+impl From<tree_sitter::Range> for TsoRange {
+    fn from(range: tree_sitter::Range) -> Self {
+        TsoRange {
+            start_offset: (range.start_byte / 2) as u32,
+            end_offset: (range.end_byte / 2) as u32,
+            start_point: range.start_point.into(),
+            end_point: range.end_point.into(),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct TsoInputEdit {
+    pub start_offset: u32,
+    pub old_end_offset: u32,
+    pub new_end_offset: u32,
+    pub start_point: TsoPoint,
+    pub old_end_point: TsoPoint,
+    pub new_end_point: TsoPoint,
+}
+
+impl From<TsoInputEdit> for tree_sitter::InputEdit {
+    fn from(edit: TsoInputEdit) -> Self {
+        tree_sitter::InputEdit {
+            start_byte: edit.start_offset as usize * 2,
+            old_end_byte: edit.old_end_offset as usize * 2,
+            new_end_byte: edit.new_end_offset as usize * 2,
+            start_position: edit.start_point.into(),
+            old_end_position: edit.old_end_point.into(),
+            new_end_position: edit.new_end_point.into(),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct TsoHighlightToken {
+    pub language_id: i64,
+    pub kind_id: u16,
+    pub capture_id: u16,
+    pub length: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct TsoFoldRange {
+    pub range: TsoRange,
+    /// Nul-terminated, null if pattern has no `fold.text`
+    pub collapsed_text: *mut c_char,
+    pub collapsed_by_default: bool,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct TsoArray<T> {
+    pub data: *mut T,
+    pub len: usize,
+}
+
+impl<T> TsoArray<T> {
+    fn from_vec(items: Vec<T>) -> Self {
+        let items = Box::into_raw(items.into_boxed_slice());
+        TsoArray {
+            data: items as *mut T,
+            len: items.len(),
+        }
+    }
+
+    fn empty() -> Self {
+        TsoArray {
+            data: ptr::null_mut(),
+            len: 0,
+        }
+    }
+
+    /// # Safety
+    /// Array must be created by `from_vec` and not freed yet
+    unsafe fn into_boxed_slice(self) -> Option<Box<[T]>> {
+        if self.data.is_null() {
+            return None;
+        }
+        // SAFETY: data and len come from Box<[T]> by construction
+        Some(unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(self.data, self.len)) })
+    }
+}
+
+pub type TsoRangeArray = TsoArray<TsoRange>;
+pub type TsoFoldRangeArray = TsoArray<TsoFoldRange>;
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct TsoHighlightTokens {
+    pub start_offset: u32,
+    pub tokens: TsoArray<TsoHighlightToken>,
+}
+
+/// # Safety
+/// `text` must point to `text_length` UTF-16 code units or be null when length is 0
+unsafe fn text_slice<'a>(text: *const u16, text_length: usize) -> &'a [u16] {
+    if text.is_null() || text_length == 0 {
+        &[]
+    } else {
+        // SAFETY: guaranteed by caller
+        unsafe { slice::from_raw_parts(text, text_length) }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn tso_isolate_create() -> i64 {
+    Isolate::create().id().into()
+}
+
+#[no_mangle]
+pub extern "C" fn tso_isolate_destroy(isolate_id: i64) -> bool {
+    match Isolate::destroy(IsolateId::from(isolate_id)) {
+        Ok(()) => true,
+        Err(err) => {
+            set_last_error(err);
+            false
+        }
+    }
+}
+
+/// Registers language and returns its id or -1 on failure.
+///
+/// # Safety
+/// `name` must be a valid nul-terminated UTF-8 string, `language` a valid `TSLanguage` pointer.
+/// Language is copied, caller keeps ownership of passed pointer.
+#[no_mangle]
+pub unsafe extern "C" fn tso_register_language(
+    isolate_id: i64,
+    name: *const c_char,
+    language: *const tree_sitter::ffi::TSLanguage,
+) -> i64 {
+    if name.is_null() || language.is_null() {
+        set_last_error("null argument");
+        return LanguageId::UNKNOWN.into();
+    }
+    let isolate = match Isolate::get(IsolateId::from(isolate_id)) {
+        Ok(isolate) => isolate,
+        Err(err) => {
+            set_last_error(err);
+            return LanguageId::UNKNOWN.into();
+        }
+    };
+    // SAFETY: guaranteed by caller
+    let name = match unsafe { CStr::from_ptr(name) }.to_str() {
+        Ok(name) => name,
+        Err(err) => {
+            set_last_error(err);
+            return LanguageId::UNKNOWN.into();
+        }
+    };
+    // SAFETY: guaranteed by caller, language is copied so it can be freed by rust
+    let ts_language = unsafe {
+        let ts_language = tree_sitter::ffi::ts_language_copy(language);
+        tree_sitter::Language::from_raw(ts_language)
+    };
+    isolate.register_language(name, ts_language).into()
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TsoQueryKind {
+    Highlights = 0,
+    Folds = 1,
+    Indents = 2,
+    Injections = 3,
+    Tags = 4,
+    Formats = 5,
+    Splits = 6,
+}
+
+/// # Safety
+/// `kind` must be one of `TsoQueryKind` values, `source` must point to `source_length` bytes of
+/// UTF-8 text
+#[no_mangle]
+pub unsafe extern "C" fn tso_add_query(
+    isolate_id: i64,
+    language_id: i64,
+    kind: TsoQueryKind,
+    source: *const u8,
+    source_length: usize,
+) -> bool {
+    if source.is_null() {
+        set_last_error("null argument");
+        return false;
+    }
+    // SAFETY: guaranteed by caller
+    let source = unsafe { slice::from_raw_parts(source, source_length) };
+    let result = (|| -> Result<(), AddQueryError> {
+        let isolate =
+            Isolate::get(IsolateId::from(isolate_id)).map_err(crate::QueryParseError::from)?;
+        let source = std::str::from_utf8(source).map_err(crate::QueryParseError::from)?;
+        let language_id = LanguageId::from(language_id);
+        match kind {
+            TsoQueryKind::Highlights => {
+                isolate.add_highlight_query(language_id, source).map(|_| ())
+            }
+            TsoQueryKind::Folds => isolate.add_fold_query(language_id, source),
+            TsoQueryKind::Indents => isolate.add_indent_query(language_id, source),
+            TsoQueryKind::Injections => isolate.add_injection_query(language_id, source),
+            TsoQueryKind::Tags => isolate.add_tags_query(language_id, source),
+            TsoQueryKind::Formats => isolate.add_format_query(language_id, source),
+            TsoQueryKind::Splits => isolate.add_split_query(language_id, source),
+        }
+    })();
+    match result {
+        Ok(()) => true,
+        Err(err) => {
+            set_last_error(format!("Failed to parse query: {err}"));
+            false
+        }
+    }
+}
+
+/// Parses text and returns snapshot handle or null.
+///
+/// # Safety
+/// `text` must point to `text_length` UTF-16 code units
+#[no_mangle]
+pub unsafe extern "C" fn tso_snapshot_parse(
+    isolate_id: i64,
+    language_id: i64,
+    text: *const u16,
+    text_length: usize,
+) -> *mut SyntaxSnapshot {
+    let isolate = match Isolate::get(IsolateId::from(isolate_id)) {
+        Ok(isolate) => isolate,
+        Err(err) => {
+            set_last_error(err);
+            return ptr::null_mut();
+        }
+    };
+    // SAFETY: guaranteed by caller
+    let text = unsafe { text_slice(text, text_length) };
+    match SyntaxSnapshot::parse(isolate, LanguageId::from(language_id), text) {
+        Some(snapshot) => Box::into_raw(Box::new(snapshot)),
+        None => {
+            set_last_error("failed to parse text");
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Reparses text after `edit` was applied. Returns new snapshot handle or null, changed ranges
+/// are written to `changed_ranges`.
+///
+/// # Safety
+/// `old_snapshot` must be a live handle, `text` must point to `text_length` UTF-16 code units,
+/// `edit` and `changed_ranges` must be valid pointers
+#[no_mangle]
+pub unsafe extern "C" fn tso_snapshot_parse_incremental(
+    old_snapshot: *const SyntaxSnapshot,
+    text: *const u16,
+    text_length: usize,
+    edit: *const TsoInputEdit,
+    changed_ranges: *mut TsoRangeArray,
+) -> *mut SyntaxSnapshot {
+    // SAFETY: guaranteed by caller
+    let (Some(old_snapshot), Some(edit), Some(changed_ranges)) = (unsafe {
+        (
+            old_snapshot.as_ref(),
+            edit.as_ref(),
+            changed_ranges.as_mut(),
+        )
+    }) else {
+        set_last_error("null argument");
+        return ptr::null_mut();
+    };
+    *changed_ranges = TsoArray::empty();
+    // SAFETY: guaranteed by caller
+    let text = unsafe { text_slice(text, text_length) };
+    match SyntaxSnapshot::parse_incremental(text, old_snapshot, (*edit).into()) {
+        Some((snapshot, ranges)) => {
+            *changed_ranges = TsoArray::from_vec(ranges.into_iter().map(Into::into).collect());
+            Box::into_raw(Box::new(snapshot))
+        }
+        None => {
+            set_last_error("failed to parse text");
+            ptr::null_mut()
+        }
+    }
+}
+
+/// # Safety
+/// `snapshot` must be a handle returned by this library and not destroyed yet
+#[no_mangle]
+pub unsafe extern "C" fn tso_snapshot_destroy(snapshot: *mut SyntaxSnapshot) {
+    if !snapshot.is_null() {
+        // SAFETY: handle is created from Box::into_raw, guaranteed by caller
+        drop(unsafe { Box::from_raw(snapshot) });
+    }
+}
+
+/// Returns id of the isolate owning the snapshot.
+///
+/// # Safety
+/// `snapshot` must be a live handle
+#[no_mangle]
+pub unsafe extern "C" fn tso_snapshot_isolate(snapshot: *const SyntaxSnapshot) -> i64 {
+    // SAFETY: guaranteed by caller
+    unsafe { snapshot.as_ref() }.map_or(-1, |snapshot| snapshot.isolate.id().into())
+}
+
+/// # Safety
+/// `snapshot` must be a live handle, `text` must point to `text_length` UTF-16 code units the
+/// snapshot was parsed from
+#[no_mangle]
+pub unsafe extern "C" fn tso_collect_highlights(
+    snapshot: *const SyntaxSnapshot,
+    text: *const u16,
+    text_length: usize,
+    start_offset: u32,
+    end_offset: u32,
+) -> TsoHighlightTokens {
+    // SAFETY: guaranteed by caller
+    let Some(snapshot) = (unsafe { snapshot.as_ref() }) else {
+        set_last_error("null argument");
+        return TsoHighlightTokens {
+            start_offset,
+            tokens: TsoArray::empty(),
+        };
+    };
+    // SAFETY: guaranteed by caller
+    let text = unsafe { text_slice(text, text_length) };
+    let (start_offset, tokens) = highlight_tokens_cover(
+        snapshot,
+        SourceText::Utf16(text),
+        (start_offset as usize)..(end_offset as usize),
+    );
+    TsoHighlightTokens {
+        start_offset: start_offset as u32,
+        tokens: TsoArray::from_vec(
+            tokens
+                .into_iter()
+                .map(|token| TsoHighlightToken {
+                    language_id: token.language_id.into(),
+                    kind_id: token.kind_id,
+                    capture_id: token.capture_id,
+                    length: token.length,
+                })
+                .collect(),
+        ),
+    }
+}
+
+/// # Safety
+/// `tokens` must be returned by `tso_collect_highlights` and not freed yet
+#[no_mangle]
+pub unsafe extern "C" fn tso_highlight_tokens_free(tokens: TsoHighlightTokens) {
+    // SAFETY: guaranteed by caller
+    drop(unsafe { tokens.tokens.into_boxed_slice() });
+}
+
+/// # Safety
+/// `snapshot` must be a live handle, `text` must point to `text_length` UTF-16 code units the
+/// snapshot was parsed from
+#[no_mangle]
+pub unsafe extern "C" fn tso_collect_fold_ranges(
+    snapshot: *const SyntaxSnapshot,
+    text: *const u16,
+    text_length: usize,
+    start_offset: u32,
+    end_offset: u32,
+    use_inner: bool,
+) -> TsoFoldRangeArray {
+    // SAFETY: guaranteed by caller
+    let Some(snapshot) = (unsafe { snapshot.as_ref() }) else {
+        set_last_error("null argument");
+        return TsoArray::empty();
+    };
+    // SAFETY: guaranteed by caller
+    let text = unsafe { text_slice(text, text_length) };
+    let fold_ranges = collect_fold_ranges(
+        snapshot,
+        SourceText::Utf16(text),
+        (start_offset as usize * 2)..(end_offset as usize * 2),
+        use_inner,
+    );
+    TsoArray::from_vec(
+        fold_ranges
+            .into_iter()
+            .map(|fold_range| TsoFoldRange {
+                range: fold_range.range.into(),
+                collapsed_text: fold_range
+                    .collapsed_text
+                    .and_then(|text| CString::new(text.into_string()).ok())
+                    .map_or(ptr::null_mut(), CString::into_raw),
+                collapsed_by_default: fold_range.collapsed_by_default,
+            })
+            .collect(),
+    )
+}
+
+/// # Safety
+/// `ranges` must be returned by `tso_collect_fold_ranges` and not freed yet
+#[no_mangle]
+pub unsafe extern "C" fn tso_fold_ranges_free(ranges: TsoFoldRangeArray) {
+    // SAFETY: guaranteed by caller
+    let Some(ranges) = (unsafe { ranges.into_boxed_slice() }) else {
+        return;
+    };
+    for range in ranges.iter() {
+        if !range.collapsed_text.is_null() {
+            // SAFETY: created by CString::into_raw in tso_collect_fold_ranges
+            drop(unsafe { CString::from_raw(range.collapsed_text) });
+        }
+    }
+}
+
+/// # Safety
+/// `ranges` must be returned by this library and not freed yet
+#[no_mangle]
+pub unsafe extern "C" fn tso_ranges_free(ranges: TsoRangeArray) {
+    // SAFETY: guaranteed by caller
+    drop(unsafe { ranges.into_boxed_slice() });
+}
@@ -0,0 +1,102 @@
+use std::sync::Arc;
+
+use crate::{
+    isolate::Isolate,
+    language_registry::{Language, LanguageError},
+    syntax_snapshot::SyntaxSnapshot,
+    LanguageId,
+};
+
+#[cfg(feature = "jni")]
+mod jni_methods;
+
+/// Bracket pairs recognized when language has no registered pairs. Angle brackets are not
+/// inferred, most grammars use them as comparison operators too.
+const INFERRED_BRACKETS: &[(&str, &str)] = &[("(", ")"), ("[", "]"), ("{", "}")];
+const INFERRED_QUOTES: &[&str] = &["\"", "'", "`"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CharPair {
+    pub open: Box<str>,
+    pub close: Box<str>,
+    pub is_quote: bool,
+    /// Node kinds inside of which the pair is not applicable, e.g. quotes inside of comments
+    pub not_in: Box<[Box<str>]>,
+}
+
+fn infer_pairs(language: &Language) -> Vec<CharPair> {
+    let ts_language = language.ts_language();
+    let has_token = |token: &str| ts_language.id_for_node_kind(token, false) != 0;
+    let brackets = INFERRED_BRACKETS
+        .iter()
+        .filter(|(open, close)| has_token(open) && has_token(close))
+        .map(|(open, close)| CharPair {
+            open: (*open).into(),
+            close: (*close).into(),
+            is_quote: false,
+            not_in: Box::default(),
+        });
+    let quotes = INFERRED_QUOTES
+        .iter()
+        .filter(|quote| has_token(quote))
+        .map(|quote| CharPair {
+            open: (*quote).into(),
+            close: (*quote).into(),
+            is_quote: true,
+            not_in: Box::default(),
+        });
+    brackets.chain(quotes).collect()
+}
+
+impl Isolate {
+    pub fn set_language_pairs(
+        &self,
+        language_id: LanguageId,
+        pairs: Vec<CharPair>,
+    ) -> Result<(), LanguageError> {
+        self.with_language(language_id, |language| {
+            language.parser_info_mut().char_pairs = Some(pairs.into());
+        })
+    }
+
+    /// Registered pairs of the language, or pairs inferred from grammar tokens
+    pub fn language_pairs(
+        &self,
+        language_id: LanguageId,
+    ) -> Result<Arc<[CharPair]>, LanguageError> {
+        self.with_language(language_id, |language| {
+            if let Some(pairs) = &language.parser_info().char_pairs {
+                return Arc::clone(pairs);
+            }
+            let pairs: Arc<[CharPair]> = infer_pairs(language).into();
+            language.parser_info_mut().char_pairs = Some(Arc::clone(&pairs));
+            pairs
+        })
+    }
+}
+
+/// Pairs applicable at `byte_offset` in the language of the innermost layer there
+pub fn char_pairs_at(
+    snapshot: &SyntaxSnapshot,
+    byte_offset: usize,
+) -> Result<(LanguageId, Vec<CharPair>), LanguageError> {
+    let (language_id, node) = snapshot.innermost_node_at(byte_offset);
+    let pairs = snapshot.isolate.language_pairs(language_id)?;
+    let mut ancestors = Vec::new();
+    let mut current = Some(node);
+    while let Some(node) = current {
+        ancestors.push(node.kind());
+        current = node.parent();
+    }
+    let applicable = pairs
+        .iter()
+        .filter(|pair| {
+            !pair
+                .not_in
+                .iter()
+                .any(|kind| ancestors.contains(&kind.as_ref()))
+        })
+        .cloned()
+        .collect();
+    Ok((language_id, applicable))
+}
@@ -0,0 +1,173 @@
+use jni::{
+    errors::{Error as JNIError, Result as JNIResult},
+    objects::{AutoLocal, JBooleanArray, JClass, JMethodID, JObject, JObjectArray, JValue},
+    sys::{jint, jsize},
+    JNIEnv,
+};
+use once_cell::sync::OnceCell as JOnceLock;
+
+use crate::{
+    isolate::{Isolate, IsolateId},
+    jni_utils::{read_string_array, throw_exception_from_result},
+    syntax_snapshot::SyntaxSnapshotDesc,
+    LanguageId,
+};
+
+use super::{char_pairs_at, CharPair};
+
+static CHAR_PAIR_CONSTRUCTOR: JOnceLock<JMethodID> = JOnceLock::new();
+
+struct CharPairDesc<'local> {
+    constructor: JMethodID,
+    class: AutoLocal<'local, JClass<'local>>,
+}
+
+impl<'local> CharPairDesc<'local> {
+    fn new(env: &mut JNIEnv<'local>) -> JNIResult<CharPairDesc<'local>> {
+        let class = env.find_class("com/hulylabs/treesitter/language/CharPair")?;
+        let constructor = *CHAR_PAIR_CONSTRUCTOR.get_or_try_init(|| {
+            env.get_method_id(
+                &class,
+                "<init>",
+                "(JLjava/lang/String;Ljava/lang/String;Z)V",
+            )
+        })?;
+        Ok(CharPairDesc {
+            constructor,
+            class: env.auto_local(class),
+        })
+    }
+
+    fn to_java_object(
+        &self,
+        env: &mut JNIEnv<'local>,
+        language_id: LanguageId,
+        pair: &CharPair,
+    ) -> JNIResult<JObject<'local>> {
+        let open: JObject = env.new_string(&pair.open)?.into();
+        let open = env.auto_local(open);
+        let close: JObject = env.new_string(&pair.close)?.into();
+        let close = env.auto_local(close);
+        // SAFETY: constructor is valid and derived from class by construction of self
+        unsafe {
+            env.new_object_unchecked(
+                &self.class,
+                self.constructor,
+                &[
+                    JValue::from(language_id).as_jni(),
+                    JValue::Object(&open).as_jni(),
+                    JValue::Object(&close).as_jni(),
+                    JValue::from(pair.is_quote).as_jni(),
+                ],
+            )
+        }
+    }
+}
+
+/// Pairs are passed as parallel arrays, `not_in` holds space separated node kinds for each pair
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeLanguageRegistry_nativeSetLanguagePairs<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    isolate_id: IsolateId,
+    language_id: LanguageId,
+    opens: JObjectArray<'local>,
+    closes: JObjectArray<'local>,
+    is_quotes: JBooleanArray<'local>,
+    not_in: JObjectArray<'local>,
+) {
+    #[allow(clippy::too_many_arguments)]
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        isolate_id: IsolateId,
+        language_id: LanguageId,
+        opens: JObjectArray<'local>,
+        closes: JObjectArray<'local>,
+        is_quotes: JBooleanArray<'local>,
+        not_in: JObjectArray<'local>,
+    ) -> JNIResult<()> {
+        let Ok(isolate) = Isolate::get(isolate_id) else {
+            env.throw_new("java/lang/IllegalStateException", "unknown isolate")?;
+            return Ok(());
+        };
+        let opens = read_string_array(env, &opens)?;
+        let closes = read_string_array(env, &closes)?;
+        let not_in = read_string_array(env, &not_in)?;
+        let mut quotes = vec![0; env.get_array_length(&is_quotes)? as usize];
+        env.get_boolean_array_region(&is_quotes, 0, &mut quotes)?;
+        if closes.len() != opens.len() || quotes.len() != opens.len() || not_in.len() != opens.len()
+        {
+            return Err(JNIError::WrongJValueType("array", "arrays of equal length"));
+        }
+        let pairs = opens
+            .into_iter()
+            .zip(closes)
+            .zip(quotes)
+            .zip(not_in)
+            .map(|(((open, close), is_quote), not_in)| CharPair {
+                open: open.into(),
+                close: close.into(),
+                is_quote: is_quote != 0,
+                not_in: not_in.split_whitespace().map(Into::into).collect(),
+            })
+            .collect();
+        if let Err(err) = isolate.set_language_pairs(language_id, pairs) {
+            env.throw_new(
+                "java/lang/IllegalArgumentException",
+                format!("Failed to set language pairs: {err}"),
+            )?;
+        }
+        Ok(())
+    }
+    let result = inner(
+        &mut env,
+        isolate_id,
+        language_id,
+        opens,
+        closes,
+        is_quotes,
+        not_in,
+    );
+    throw_exception_from_result(&mut env, result)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativePairsProvider_nativeGetPairsAt<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    snapshot: JObject<'local>,
+    offset: jint,
+) -> JObjectArray<'local> {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        snapshot: JObject<'local>,
+        offset: jint,
+    ) -> JNIResult<JObjectArray<'local>> {
+        let snapshot = SyntaxSnapshotDesc::from_java_object(env, snapshot)?;
+        let pair_desc = CharPairDesc::new(env)?;
+        let (language_id, pairs) = match char_pairs_at(snapshot, (offset * 2) as usize) {
+            Ok(pairs) => pairs,
+            Err(err) => {
+                env.throw_new(
+                    "java/lang/IllegalStateException",
+                    format!("Failed to get language pairs: {err}"),
+                )?;
+                return Ok(JObjectArray::default());
+            }
+        };
+        let pairs_array =
+            env.new_object_array(pairs.len() as jsize, &pair_desc.class, JObject::null())?;
+        for (index, pair) in pairs.iter().enumerate() {
+            let obj = pair_desc.to_java_object(env, language_id, pair)?;
+            let obj = env.auto_local(obj);
+            env.set_object_array_element(&pairs_array, index as i32, obj)?;
+        }
+        Ok(pairs_array)
+    }
+    let result = inner(&mut env, snapshot, offset);
+    throw_exception_from_result(&mut env, result)
+}
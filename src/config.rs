@@ -0,0 +1,139 @@
+use std::{
+    sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use crate::Isolate;
+
+#[cfg(feature = "jni")]
+mod jni_methods;
+
+#[derive(thiserror::Error, Debug)]
+pub enum ConfigError {
+    #[error("unknown option {0}")]
+    UnknownOption(Box<str>),
+    #[error("invalid value {value} for option {key}")]
+    InvalidValue { key: Box<str>, value: Box<str> },
+}
+
+fn parse_flag(key: &str, value: &str) -> Result<bool, ConfigError> {
+    match value {
+        "true" | "1" | "on" => Ok(true),
+        "false" | "0" | "off" => Ok(false),
+        _ => Err(ConfigError::InvalidValue {
+            key: key.into(),
+            value: value.into(),
+        }),
+    }
+}
+
+fn parse_size(key: &str, value: &str) -> Result<usize, ConfigError> {
+    value.parse().map_err(|_| ConfigError::InvalidValue {
+        key: key.into(),
+        value: value.into(),
+    })
+}
+
+/// Options of an isolate set by [`Isolate::set_option`], read by calls running on its snapshots
+#[derive(Default)]
+pub(crate) struct IsolateOptions {
+    /// Validate snapshots, tokens and ranges after each operation
+    pub(crate) invariants: AtomicBool,
+    /// Highlight tokens longer than this number of code units are split, 0 for no limit. Keeps
+    /// tokens of minified one-line files whose nodes span megabytes short.
+    pub(crate) max_token_length: AtomicUsize,
+    /// Maximum number of in-progress matches of a query cursor, 0 for tree-sitter default
+    pub(crate) match_limit: AtomicU32,
+    /// Time queries of one call may take in microseconds, 0 for no limit
+    pub(crate) time_budget_micros: AtomicU64,
+}
+
+impl Isolate {
+    /// Sets option of the isolate. Known options:
+    /// - `debug.invariants` validate snapshots, tokens and ranges after each operation;
+    /// - `profiler.enabled` record per-phase timings of native calls;
+    /// - `highlight.max_token_length` split highlight tokens longer than this number of code units,
+    ///   `0` for no limit;
+    /// - `query.match_limit` maximum number of in-progress matches of a query, `0` for the default;
+    /// - `query.time_budget_ms` time highlights, ranges and injections queries of one call may take,
+    ///   results are partial once it is spent, `0` for no limit. Both limits do not apply to queries
+    ///   run while parsing, so snapshots always have all of their injections.
+    pub fn set_option(&self, key: &str, value: &str) -> Result<(), ConfigError> {
+        match key {
+            "debug.invariants" => self
+                .options
+                .invariants
+                .store(parse_flag(key, value)?, Ordering::Relaxed),
+            "profiler.enabled" => self.profiler.set_enabled(parse_flag(key, value)?),
+            "highlight.max_token_length" => self
+                .options
+                .max_token_length
+                .store(parse_size(key, value)?, Ordering::Relaxed),
+            "query.match_limit" => {
+                let limit =
+                    parse_size(key, value)?
+                        .try_into()
+                        .map_err(|_| ConfigError::InvalidValue {
+                            key: key.into(),
+                            value: value.into(),
+                        })?;
+                self.options.match_limit.store(limit, Ordering::Relaxed);
+            }
+            "query.time_budget_ms" => {
+                let budget = Duration::from_millis(parse_size(key, value)? as u64);
+                self.options
+                    .time_budget_micros
+                    .store(budget.as_micros() as u64, Ordering::Relaxed);
+            }
+            _ => return Err(ConfigError::UnknownOption(key.into())),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn options_are_kept_per_isolate() {
+        let (first, second) = (Isolate::create(), Isolate::create());
+        first.set_option("query.match_limit", "16").unwrap();
+        first.set_option("query.time_budget_ms", "5").unwrap();
+        assert_eq!(first.options.match_limit.load(Ordering::Relaxed), 16);
+        assert_eq!(
+            first.options.time_budget_micros.load(Ordering::Relaxed),
+            5000
+        );
+        assert_eq!(second.options.match_limit.load(Ordering::Relaxed), 0);
+        assert_eq!(second.options.time_budget_micros.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn invariants_are_checked_per_isolate() {
+        let (checked, unchecked) = (Isolate::create(), Isolate::create());
+        checked.set_option("debug.invariants", "on").unwrap();
+        assert!(checked.options.invariants.load(Ordering::Relaxed));
+        assert!(!unchecked.options.invariants.load(Ordering::Relaxed));
+        checked.set_option("debug.invariants", "0").unwrap();
+        assert!(!checked.options.invariants.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn invalid_options_are_rejected() {
+        let isolate = Isolate::create();
+        assert!(matches!(
+            isolate.set_option("query.unknown", "1"),
+            Err(ConfigError::UnknownOption(_))
+        ));
+        assert!(matches!(
+            isolate.set_option("query.match_limit", "-1"),
+            Err(ConfigError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            isolate.set_option("query.match_limit", "4294967296"),
+            Err(ConfigError::InvalidValue { .. })
+        ));
+        assert_eq!(isolate.options.match_limit.load(Ordering::Relaxed), 0);
+    }
+}
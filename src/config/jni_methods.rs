@@ -0,0 +1,44 @@
+use jni::{
+    errors::Result as JNIResult,
+    objects::{JClass, JString},
+    JNIEnv,
+};
+
+use crate::{jni_utils::throw_exception_from_result, Isolate, IsolateId};
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeConfig_nativeSetOption<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    isolate_id: IsolateId,
+    key: JString<'local>,
+    value: JString<'local>,
+) {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        isolate_id: IsolateId,
+        key: JString<'local>,
+        value: JString<'local>,
+    ) -> JNIResult<()> {
+        let key: String = env.get_string(&key)?.into();
+        let value: String = env.get_string(&value)?.into();
+        let result = Isolate::get(isolate_id)
+            .map_err(|err| err.to_string())
+            .and_then(|isolate| {
+                isolate
+                    .set_option(&key, &value)
+                    .map_err(|err| err.to_string())
+            });
+        if let Err(err) = result {
+            env.throw_new(
+                "java/lang/IllegalArgumentException",
+                format!("Failed to set option: {err}"),
+            )?;
+        }
+        Ok(())
+    }
+    let result = inner(&mut env, isolate_id, key, value);
+    throw_exception_from_result(&mut env, result)
+}
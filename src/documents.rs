@@ -0,0 +1,300 @@
+use std::{
+    collections::HashMap,
+    ops::Range,
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc, LazyLock, Mutex, RwLock,
+    },
+};
+
+use tree_sitter as ts;
+
+use crate::{
+    collect_fold_ranges, highlight_tokens_cover, FoldRange, HighlightToken, Isolate, IsolateId,
+    LanguageId, SourceText, SyntaxSnapshot,
+};
+
+#[cfg(feature = "jni")]
+mod jni_methods;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(transparent)]
+pub struct DocumentId(i64);
+
+impl From<i64> for DocumentId {
+    fn from(value: i64) -> Self {
+        Self(value)
+    }
+}
+
+impl From<DocumentId> for i64 {
+    fn from(value: DocumentId) -> Self {
+        value.0
+    }
+}
+
+static DOCUMENT_ID_COUNTER: AtomicI64 = AtomicI64::new(0);
+/// Open documents along with isolates they belong to, documents are closed when their isolate is
+/// destroyed so they do not keep it alive
+type DocumentsMap = HashMap<DocumentId, (IsolateId, Arc<Mutex<Document>>)>;
+
+static DOCUMENTS: LazyLock<RwLock<DocumentsMap>> = LazyLock::new(RwLock::default);
+
+#[derive(thiserror::Error, Debug)]
+pub enum DocumentError {
+    #[error("unknown document")]
+    InvalidDocumentId,
+    #[error("edit range {0:?} is out of document bounds")]
+    EditOutOfBounds(Range<usize>),
+    #[error("failed to parse document")]
+    ParseFailed,
+}
+
+/// Replacement of `range` with `new_text`. Offsets are in UTF-16 code units of the text produced
+/// by preceding edits of the same batch.
+#[derive(Debug, Clone)]
+pub struct DocumentEdit {
+    pub range: Range<usize>,
+    pub new_text: Vec<u16>,
+}
+
+/// Open document owning its text, current snapshot and caches derived from it. Every applied
+/// batch of edits increments generation and invalidates caches.
+pub struct Document {
+    id: DocumentId,
+    text: Vec<u16>,
+    snapshot: SyntaxSnapshot,
+    generation: u64,
+    highlights: Option<(Range<usize>, usize, Arc<[HighlightToken]>)>,
+    folds: Option<Arc<[FoldRange]>>,
+}
+
+/// Single edit covering a batch of sequential edits, in code units: start, end in text before
+/// the batch and end in text after it
+fn merge_edits(edits: &[DocumentEdit]) -> Option<(usize, usize, usize)> {
+    let mut merged: Option<(usize, usize, usize)> = None;
+    for edit in edits {
+        let (start, old_end) = (edit.range.start, edit.range.end);
+        let new_end = start + edit.new_text.len();
+        merged = Some(match merged {
+            None => (start, old_end, new_end),
+            Some((merged_start, merged_old_end, merged_new_end)) => {
+                // Text after merged edit is only shifted by it
+                let old_end_before_batch = if old_end > merged_new_end {
+                    old_end - merged_new_end + merged_old_end
+                } else {
+                    merged_old_end
+                };
+                let covered_end = merged_new_end.max(old_end);
+                (
+                    merged_start.min(start),
+                    old_end_before_batch,
+                    covered_end - old_end + new_end,
+                )
+            }
+        });
+    }
+    merged
+}
+
+impl Document {
+    pub fn open(
+        isolate: Arc<Isolate>,
+        language_id: LanguageId,
+        text: Vec<u16>,
+    ) -> Result<DocumentId, DocumentError> {
+        let isolate_id = isolate.id();
+        let snapshot =
+            SyntaxSnapshot::parse(isolate, language_id, &text).ok_or(DocumentError::ParseFailed)?;
+        let id = DocumentId(DOCUMENT_ID_COUNTER.fetch_add(1, Ordering::SeqCst));
+        let document = Document {
+            id,
+            text,
+            snapshot,
+            generation: 0,
+            highlights: None,
+            folds: None,
+        };
+        DOCUMENTS
+            .write()
+            .unwrap()
+            .insert(id, (isolate_id, Arc::new(Mutex::new(document))));
+        Ok(id)
+    }
+
+    pub fn get(id: DocumentId) -> Result<Arc<Mutex<Document>>, DocumentError> {
+        DOCUMENTS
+            .read()
+            .unwrap()
+            .get(&id)
+            .map(|(_, document)| Arc::clone(document))
+            .ok_or(DocumentError::InvalidDocumentId)
+    }
+
+    pub fn close(id: DocumentId) -> Result<(), DocumentError> {
+        DOCUMENTS
+            .write()
+            .unwrap()
+            .remove(&id)
+            .map(|_| ())
+            .ok_or(DocumentError::InvalidDocumentId)
+    }
+
+    /// Closes documents of the isolate, called when it is destroyed
+    pub(crate) fn close_isolate_documents(isolate_id: IsolateId) {
+        DOCUMENTS
+            .write()
+            .unwrap()
+            .retain(|_, (document_isolate_id, _)| *document_isolate_id != isolate_id);
+    }
+
+    #[cfg(feature = "jni")]
+    pub(crate) fn close_all() {
+        DOCUMENTS.write().unwrap().clear();
+    }
+
+    pub fn id(&self) -> DocumentId {
+        self.id
+    }
+
+    pub fn text(&self) -> &[u16] {
+        &self.text
+    }
+
+    pub fn snapshot(&self) -> &SyntaxSnapshot {
+        &self.snapshot
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Applies edits, reparses the document incrementally and returns ranges whose syntax may
+    /// have changed. Edits are validated before any of them is applied, document is left
+    /// unchanged if the edited text fails to parse.
+    pub fn apply_edits(&mut self, edits: &[DocumentEdit]) -> Result<Vec<ts::Range>, DocumentError> {
+        let mut length = self.text.len();
+        for edit in edits {
+            if edit.range.start > edit.range.end || edit.range.end > length {
+                return Err(DocumentError::EditOutOfBounds(edit.range.clone()));
+            }
+            length = length - edit.range.len() + edit.new_text.len();
+        }
+        let Some((start, old_end, new_end)) = merge_edits(edits) else {
+            return Ok(Vec::new());
+        };
+        let old_text = SourceText::Utf16(&self.text);
+        let start_position = old_text.point_for_byte(start * 2);
+        let old_end_position = old_text.point_for_byte(old_end * 2);
+        let mut new_text = self.text.clone();
+        for edit in edits {
+            new_text.splice(edit.range.clone(), edit.new_text.iter().copied());
+        }
+        let text = SourceText::Utf16(&new_text);
+        let input_edit = ts::InputEdit {
+            start_byte: start * 2,
+            old_end_byte: old_end * 2,
+            new_end_byte: new_end * 2,
+            start_position,
+            old_end_position,
+            new_end_position: text.point_for_byte(new_end * 2),
+        };
+        let (snapshot, changed_ranges) =
+            match SyntaxSnapshot::parse_incremental_text(text, &self.snapshot, input_edit) {
+                Some(result) => result,
+                None => {
+                    // Full parse keeps the document consistent with its text if incremental
+                    // parse failed
+                    let snapshot = SyntaxSnapshot::parse_text(
+                        Arc::clone(&self.snapshot.isolate),
+                        self.snapshot.base_language(),
+                        text,
+                    )
+                    .ok_or(DocumentError::ParseFailed)?;
+                    let whole_text = ts::Range {
+                        start_byte: 0,
+                        end_byte: text.byte_len(),
+                        start_point: ts::Point::default(),
+                        end_point: text.point_for_byte(text.byte_len()),
+                    };
+                    (snapshot, vec![whole_text])
+                }
+            };
+        self.text = new_text;
+        self.snapshot = snapshot;
+        self.generation += 1;
+        self.highlights = None;
+        self.folds = None;
+        Ok(changed_ranges)
+    }
+
+    /// Highlight tokens covering `range` of code units, cached until the next edit
+    pub fn highlight_tokens(&mut self, range: Range<usize>) -> (usize, Arc<[HighlightToken]>) {
+        if let Some((cached_range, start_offset, tokens)) = &self.highlights {
+            if *cached_range == range {
+                return (*start_offset, Arc::clone(tokens));
+            }
+        }
+        let (start_offset, tokens) =
+            highlight_tokens_cover(&self.snapshot, SourceText::Utf16(&self.text), range.clone());
+        let tokens: Arc<[HighlightToken]> = tokens.into();
+        self.highlights = Some((range, start_offset, Arc::clone(&tokens)));
+        (start_offset, tokens)
+    }
+
+    /// Fold ranges of the whole document, cached until the next edit
+    pub fn fold_ranges(&mut self) -> Arc<[FoldRange]> {
+        let text = SourceText::Utf16(&self.text);
+        Arc::clone(self.folds.get_or_insert_with(|| {
+            collect_fold_ranges(&self.snapshot, text, 0..text.byte_len(), false).into()
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edit(range: Range<usize>, new_len: usize) -> DocumentEdit {
+        DocumentEdit {
+            range,
+            new_text: vec![u16::from(b'x'); new_len],
+        }
+    }
+
+    #[test]
+    fn merge_edits_empty_batch() {
+        assert_eq!(merge_edits(&[]), None);
+    }
+
+    #[test]
+    fn merge_edits_single_edit() {
+        assert_eq!(merge_edits(&[edit(2..5, 1)]), Some((2, 5, 3)));
+    }
+
+    #[test]
+    fn merge_edits_later_edit_before_earlier() {
+        // Deleting the first unit shifts the earlier replacement left
+        assert_eq!(
+            merge_edits(&[edit(10..12, 4), edit(0..1, 0)]),
+            Some((0, 12, 13))
+        );
+    }
+
+    #[test]
+    fn merge_edits_later_edit_after_earlier() {
+        // Offset 10 after the batch is offset 9 before the first edit grew the text by one unit
+        assert_eq!(
+            merge_edits(&[edit(0..2, 3), edit(10..11, 0)]),
+            Some((0, 10, 10))
+        );
+    }
+
+    #[test]
+    fn merge_edits_later_edit_inside_earlier() {
+        assert_eq!(
+            merge_edits(&[edit(4..6, 5), edit(5..7, 1)]),
+            Some((4, 6, 8))
+        );
+    }
+}
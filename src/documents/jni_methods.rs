@@ -0,0 +1,241 @@
+use jni::{
+    errors::{Error as JNIError, Result as JNIResult},
+    objects::{JCharArray, JClass, JIntArray, JObject, JObjectArray},
+    sys::{jint, jlong},
+    JNIEnv,
+};
+
+use crate::{
+    highlighting_lexer::tokens_to_java_object,
+    isolate::{Isolate, IsolateId},
+    jni_utils::{throw_exception_from_result, RangeDesc},
+    ranges::fold_ranges_to_java_array,
+    syntax_snapshot::SyntaxSnapshotDesc,
+    LanguageId,
+};
+
+use super::{Document, DocumentEdit, DocumentId};
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeDocuments_nativeOpenDocument<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    isolate_id: IsolateId,
+    text: JCharArray<'local>,
+    language_id: LanguageId,
+) -> jlong {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        isolate_id: IsolateId,
+        text: JCharArray<'local>,
+        language_id: LanguageId,
+    ) -> JNIResult<jlong> {
+        let Ok(isolate) = Isolate::get(isolate_id) else {
+            env.throw_new("java/lang/IllegalStateException", "unknown isolate")?;
+            return Ok(-1);
+        };
+        // Document keeps its own copy of text, so it is not read into a pooled buffer
+        let mut text_buffer = vec![0u16; env.get_array_length(&text)? as usize];
+        env.get_char_array_region(&text, 0, &mut text_buffer)?;
+        match Document::open(isolate, language_id, text_buffer) {
+            Ok(document_id) => Ok(document_id.into()),
+            Err(err) => {
+                env.throw_new(
+                    "java/lang/IllegalStateException",
+                    format!("Failed to open document: {err}"),
+                )?;
+                Ok(-1)
+            }
+        }
+    }
+    let result = inner(&mut env, isolate_id, text, language_id);
+    throw_exception_from_result(&mut env, result)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeDocuments_nativeCloseDocument<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    document_id: jlong,
+) {
+    if let Err(err) = Document::close(DocumentId::from(document_id)) {
+        env.throw_new(
+            "java/lang/IllegalStateException",
+            format!("Failed to close document: {err}"),
+        )
+        .unwrap();
+    }
+}
+
+/// Edits are passed as flat start/end offset pairs along with array of replacement texts.
+/// Returns ranges invalidated by the edits.
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeDocuments_nativeApplyEdits<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    document_id: jlong,
+    edit_offsets: JIntArray<'local>,
+    new_texts: JObjectArray<'local>,
+) -> JObjectArray<'local> {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        document_id: jlong,
+        edit_offsets: JIntArray<'local>,
+        new_texts: JObjectArray<'local>,
+    ) -> JNIResult<JObjectArray<'local>> {
+        let Ok(document) = Document::get(DocumentId::from(document_id)) else {
+            env.throw_new("java/lang/IllegalStateException", "unknown document")?;
+            return Ok(JObjectArray::default());
+        };
+        let mut offsets = vec![0; env.get_array_length(&edit_offsets)? as usize];
+        env.get_int_array_region(&edit_offsets, 0, &mut offsets)?;
+        let edits_count = env.get_array_length(&new_texts)? as usize;
+        if offsets.len() != edits_count * 2 {
+            return Err(JNIError::WrongJValueType(
+                "array",
+                "offset pair for each new text",
+            ));
+        }
+        let mut edits = Vec::with_capacity(edits_count);
+        for (index, offsets) in offsets.chunks_exact(2).enumerate() {
+            let new_text: JCharArray = env
+                .get_object_array_element(&new_texts, index as i32)?
+                .into();
+            let new_text = env.auto_local(new_text);
+            let mut new_text_buffer = vec![0u16; env.get_array_length(&*new_text)? as usize];
+            env.get_char_array_region(&*new_text, 0, &mut new_text_buffer)?;
+            edits.push(DocumentEdit {
+                range: (offsets[0] as usize)..(offsets[1] as usize),
+                new_text: new_text_buffer,
+            });
+        }
+        let changed_ranges = match document.lock().unwrap().apply_edits(&edits) {
+            Ok(changed_ranges) => changed_ranges,
+            Err(err) => {
+                env.throw_new(
+                    "java/lang/IllegalArgumentException",
+                    format!("Failed to apply edits: {err}"),
+                )?;
+                return Ok(JObjectArray::default());
+            }
+        };
+        let range_desc = RangeDesc::new(env)?;
+        let array = env.new_object_array(
+            changed_ranges.len() as i32,
+            &range_desc.class,
+            JObject::null(),
+        )?;
+        for (idx, range) in changed_ranges.into_iter().enumerate() {
+            let range_obj = range_desc.to_java_object(env, range)?;
+            let range_obj = env.auto_local(range_obj);
+            env.set_object_array_element(&array, idx as i32, &range_obj)?;
+        }
+        Ok(array)
+    }
+    let result = inner(&mut env, document_id, edit_offsets, new_texts);
+    throw_exception_from_result(&mut env, result)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeDocuments_nativeGetGeneration<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    document_id: jlong,
+) -> jlong {
+    let Ok(document) = Document::get(DocumentId::from(document_id)) else {
+        env.throw_new("java/lang/IllegalStateException", "unknown document")
+            .unwrap();
+        return -1;
+    };
+    let generation = document.lock().unwrap().generation();
+    generation as jlong
+}
+
+/// Returns snapshot of the current document text, it stays valid after further edits
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeDocuments_nativeGetSnapshot<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    document_id: jlong,
+) -> JObject<'local> {
+    fn inner<'local>(env: &mut JNIEnv<'local>, document_id: jlong) -> JNIResult<JObject<'local>> {
+        let Ok(document) = Document::get(DocumentId::from(document_id)) else {
+            env.throw_new("java/lang/IllegalStateException", "unknown document")?;
+            return Ok(JObject::null());
+        };
+        let snapshot = document.lock().unwrap().snapshot().clone();
+        let desc = SyntaxSnapshotDesc::new(env)?;
+        desc.to_java_object(env, snapshot.base_language(), snapshot)
+    }
+    let result = inner(&mut env, document_id);
+    throw_exception_from_result(&mut env, result)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeDocuments_nativeCollectHighlights<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    document_id: jlong,
+    start_offset: jint,
+    end_offset: jint,
+) -> JObject<'local> {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        document_id: jlong,
+        start_offset: jint,
+        end_offset: jint,
+    ) -> JNIResult<JObject<'local>> {
+        let Ok(document) = Document::get(DocumentId::from(document_id)) else {
+            env.throw_new("java/lang/IllegalStateException", "unknown document")?;
+            return Ok(JObject::null());
+        };
+        let (start_offset, tokens) = document
+            .lock()
+            .unwrap()
+            .highlight_tokens((start_offset as usize)..(end_offset as usize));
+        tokens_to_java_object(
+            env,
+            start_offset,
+            &tokens,
+            |_, token| token.capture_id,
+            None,
+        )
+    }
+    let result = inner(&mut env, document_id, start_offset, end_offset);
+    throw_exception_from_result(&mut env, result)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeDocuments_nativeGetFoldRanges<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    document_id: jlong,
+) -> JObjectArray<'local> {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        document_id: jlong,
+    ) -> JNIResult<JObjectArray<'local>> {
+        let Ok(document) = Document::get(DocumentId::from(document_id)) else {
+            env.throw_new("java/lang/IllegalStateException", "unknown document")?;
+            return Ok(JObjectArray::default());
+        };
+        let fold_ranges = document.lock().unwrap().fold_ranges();
+        fold_ranges_to_java_array(env, &fold_ranges)
+    }
+    let result = inner(&mut env, document_id);
+    throw_exception_from_result(&mut env, result)
+}
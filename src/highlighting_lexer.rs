@@ -1,11 +1,223 @@
-use crate::LanguageId;
+use std::{collections::HashMap, ops::Range};
 
+use crate::{
+    isolate::Isolate, query::SourceText, syntax_snapshot::SyntaxSnapshotEntryContent, LanguageId,
+    SyntaxSnapshot,
+};
+
+#[cfg(feature = "jni")]
+mod jni_methods;
 pub mod query;
 
+#[cfg(feature = "jni")]
+pub(crate) use jni_methods::tokens_to_java_object;
+
+/// Group id of tokens whose capture has no highlight group in the theme mapping
+pub const NO_HIGHLIGHT_GROUP: u16 = u16::MAX;
+
 #[derive(Debug, Clone, Copy)]
 pub struct HighlightToken {
     pub language_id: LanguageId,
     pub kind_id: u16,
     pub capture_id: u16,
     pub length: u32,
+    /// Token is covered by an ERROR or MISSING node of its layer or of a layer around it
+    pub error: bool,
+}
+
+/// Tokens of the line `row` clipped to it, along with the offset of the line start. Line break
+/// is not included. `None` if text has fewer lines.
+pub fn highlight_line_tokens(
+    snapshot: &SyntaxSnapshot,
+    text: SourceText<'_>,
+    row: usize,
+) -> Option<(usize, Vec<HighlightToken>)> {
+    let unit_size = text.unit_size();
+    let line_byte_range = snapshot.line_index(text).line_byte_range(row)?;
+    let line_range = (line_byte_range.start / unit_size)..(line_byte_range.end / unit_size);
+    if line_range.is_empty() {
+        return Some((line_range.start, Vec::new()));
+    }
+    let (start_offset, tokens) =
+        query::highlight_tokens_cover_cached(snapshot, text, line_range.clone());
+    let mut line_tokens = Vec::new();
+    let mut token_end = start_offset;
+    for token in tokens {
+        let token_start = token_end;
+        token_end += token.length as usize;
+        if token_end <= line_range.start {
+            continue;
+        }
+        if token_start >= line_range.end {
+            break;
+        }
+        let length = token_end.min(line_range.end) - token_start.max(line_range.start);
+        line_tokens.push(HighlightToken {
+            length: length as u32,
+            ..token
+        });
+    }
+    Some((line_range.start, line_tokens))
+}
+
+/// Token covers of the regions which have to be re-tokenized after an edit, given ranges (in
+/// units of the text) changed by the incremental parse. Covers are sorted and disjoint, the ones
+/// which would overlap are merged into a single cover.
+pub fn changed_highlight_tokens(
+    snapshot: &SyntaxSnapshot,
+    text: SourceText<'_>,
+    changed_ranges: &[Range<usize>],
+) -> Vec<(usize, Vec<HighlightToken>)> {
+    let mut changed_ranges = changed_ranges.to_vec();
+    changed_ranges.sort_by_key(|range| range.start);
+    let cover_end = |start_offset: usize, tokens: &[HighlightToken]| {
+        start_offset
+            + tokens
+                .iter()
+                .map(|token| token.length as usize)
+                .sum::<usize>()
+    };
+    // Requested range, start offset and tokens of each region
+    let mut regions: Vec<(Range<usize>, usize, Vec<HighlightToken>)> = Vec::new();
+    for range in changed_ranges {
+        let mut requested = range.start.min(text.len())..range.end.min(text.len());
+        loop {
+            let (start_offset, tokens) =
+                query::highlight_tokens_cover_cached(snapshot, text, requested.clone());
+            match regions.last() {
+                Some((last_requested, last_start, last_tokens))
+                    if start_offset < cover_end(*last_start, last_tokens) =>
+                {
+                    requested = last_requested.start..requested.end.max(last_requested.end);
+                    regions.pop();
+                }
+                _ => {
+                    regions.push((requested, start_offset, tokens));
+                    break;
+                }
+            }
+        }
+    }
+    regions
+        .into_iter()
+        .map(|(_, start_offset, tokens)| (start_offset, tokens))
+        .collect()
+}
+
+/// Byte offsets within `byte_range` where highlighting may be restarted with empty highlight
+/// state, i.e. starts of top-level nodes of the base layer. Captures of the root node itself are
+/// not considered, highlight queries capture nodes below it.
+pub fn highlight_restart_offsets(
+    snapshot: &SyntaxSnapshot,
+    byte_range: Range<usize>,
+) -> Vec<usize> {
+    let Some(entry) = snapshot.entries.iter().find(|entry| entry.depth == 0) else {
+        return Vec::new();
+    };
+    let SyntaxSnapshotEntryContent::Parsed { tree, .. } = &entry.content else {
+        return Vec::new();
+    };
+    let root_node = tree.root_node_with_offset(entry.byte_offset, entry.point_offset);
+    let mut cursor = root_node.walk();
+    let mut offsets: Vec<usize> = root_node
+        .children(&mut cursor)
+        .map(|node| node.start_byte())
+        .filter(|offset| byte_range.contains(offset))
+        .collect();
+    offsets.dedup();
+    offsets
+}
+
+/// Highlighted part of the text, offsets are in units of the text
+#[derive(Debug, Clone)]
+pub struct HighlightSpan {
+    pub range: Range<usize>,
+    pub language_id: LanguageId,
+    pub capture_id: u16,
+}
+
+/// Captured spans intersecting `range` clipped to it, unhighlighted gaps are skipped and adjacent
+/// tokens with the same capture are merged
+pub fn highlight_spans(
+    snapshot: &SyntaxSnapshot,
+    text: SourceText<'_>,
+    range: Range<usize>,
+) -> Vec<HighlightSpan> {
+    let (start_offset, tokens) =
+        query::highlight_tokens_cover_cached(snapshot, text, range.clone());
+    let mut spans: Vec<HighlightSpan> = Vec::new();
+    let mut token_end = start_offset;
+    for token in tokens {
+        let token_start = token_end;
+        token_end += token.length as usize;
+        let span_range = token_start.max(range.start)..token_end.min(range.end);
+        if token.capture_id == u16::MAX || span_range.is_empty() {
+            continue;
+        }
+        if let Some(last) = spans.last_mut() {
+            if last.range.end == span_range.start
+                && last.language_id == token.language_id
+                && last.capture_id == token.capture_id
+            {
+                last.range.end = span_range.end;
+                continue;
+            }
+        }
+        spans.push(HighlightSpan {
+            range: span_range,
+            language_id: token.language_id,
+            capture_id: token.capture_id,
+        });
+    }
+    spans
+}
+
+/// Highlight group ids of the tokens. Captures without own mapping use group of their parent
+/// capture name, e.g. `function.method` falls back to `function`. Tokens of languages without
+/// theme mapping keep their capture ids.
+pub fn highlight_token_groups(isolate: &Isolate, tokens: &[HighlightToken]) -> Vec<u16> {
+    let mut group_tables: HashMap<LanguageId, Option<Vec<u16>>> = HashMap::new();
+    tokens
+        .iter()
+        .map(|token| {
+            let table = group_tables.entry(token.language_id).or_insert_with(|| {
+                isolate
+                    .with_language(token.language_id, |language| {
+                        let parser_info = language.parser_info();
+                        let groups = parser_info.highlight_groups.as_ref()?;
+                        let query = parser_info.highlights_query.as_ref()?;
+                        Some(
+                            query
+                                .0
+                                .capture_names()
+                                .iter()
+                                .map(|name| group_for_capture(groups, name))
+                                .collect(),
+                        )
+                    })
+                    .ok()
+                    .flatten()
+            });
+            match table {
+                Some(table) => table
+                    .get(token.capture_id as usize)
+                    .copied()
+                    .unwrap_or(NO_HIGHLIGHT_GROUP),
+                None => token.capture_id,
+            }
+        })
+        .collect()
+}
+
+fn group_for_capture(groups: &HashMap<Box<str>, u16>, capture_name: &str) -> u16 {
+    let mut name = capture_name;
+    loop {
+        if let Some(group_id) = groups.get(name) {
+            return *group_id;
+        }
+        let Some((parent, _)) = name.rsplit_once('.') else {
+            return NO_HIGHLIGHT_GROUP;
+        };
+        name = parent;
+    }
 }
@@ -0,0 +1,529 @@
+use std::ops::Deref;
+
+use jni::{
+    errors::Result as JNIResult,
+    objects::{JCharArray, JClass, JIntArray, JObject, JObjectArray, JValue},
+    sys::{jint, jsize},
+    JNIEnv,
+};
+
+use crate::{
+    jni_utils::{throw_exception_from_result, with_java_text, JavaText, JavaTextRange, PointDesc},
+    syntax_snapshot::SyntaxSnapshotDesc,
+    textmate_scopes::highlight_token_scopes,
+};
+
+use super::{
+    changed_highlight_tokens, highlight_line_tokens, highlight_restart_offsets, highlight_spans,
+    highlight_token_groups,
+    query::{highlight_token_conceals, highlight_tokens_cover_cached},
+    HighlightToken,
+};
+
+/// Tokens are passed to java as parallel arrays, `token_class` gives value of the class array
+/// (capture id or scope id). Conceal replacements are passed as nullable array of nullable
+/// strings.
+pub(crate) fn tokens_to_java_object<'local>(
+    env: &mut JNIEnv<'local>,
+    start_offset: usize,
+    tokens: &[HighlightToken],
+    token_class: impl Fn(usize, &HighlightToken) -> u16,
+    conceals: Option<&[Option<Box<str>>]>,
+) -> JNIResult<JObject<'local>> {
+    let token_lengths = env.new_int_array(tokens.len() as i32)?;
+    let token_node_kinds = env.new_short_array(tokens.len() as i32)?;
+    let token_capture_ids = env.new_short_array(tokens.len() as i32)?;
+    let token_languages = env.new_long_array(tokens.len() as i32)?;
+    let token_errors = env.new_boolean_array(tokens.len() as i32)?;
+    const CHUNK_SIZE: usize = 2048;
+    let mut token_lengths_buf: Vec<i32> = Vec::with_capacity(CHUNK_SIZE);
+    let mut token_node_kinds_buf: Vec<i16> = Vec::with_capacity(CHUNK_SIZE);
+    let mut token_capture_ids_buf: Vec<i16> = Vec::with_capacity(CHUNK_SIZE);
+    let mut token_languages_buf: Vec<i64> = Vec::with_capacity(CHUNK_SIZE);
+    let mut token_errors_buf: Vec<u8> = Vec::with_capacity(CHUNK_SIZE);
+    for (slice_idx, tokens_slice) in tokens.chunks(CHUNK_SIZE).enumerate() {
+        for (token_idx, token) in tokens_slice
+            .iter()
+            .enumerate()
+            .map(|(idx, token)| (slice_idx * CHUNK_SIZE + idx, token))
+        {
+            token_lengths_buf.push(token.length as i32);
+            token_node_kinds_buf.push(token.kind_id as i16);
+            token_capture_ids_buf.push(token_class(token_idx, token) as i16);
+            token_languages_buf.push(token.language_id.into());
+            token_errors_buf.push(token.error.into());
+        }
+        env.set_int_array_region(
+            &token_lengths,
+            (slice_idx * CHUNK_SIZE) as jsize,
+            &token_lengths_buf,
+        )?;
+        env.set_short_array_region(
+            &token_node_kinds,
+            (slice_idx * CHUNK_SIZE) as jsize,
+            &token_node_kinds_buf,
+        )?;
+        env.set_short_array_region(
+            &token_capture_ids,
+            (slice_idx * CHUNK_SIZE) as jsize,
+            &token_capture_ids_buf,
+        )?;
+        env.set_long_array_region(
+            &token_languages,
+            (slice_idx * CHUNK_SIZE) as jsize,
+            &token_languages_buf,
+        )?;
+        env.set_boolean_array_region(
+            &token_errors,
+            (slice_idx * CHUNK_SIZE) as jsize,
+            &token_errors_buf,
+        )?;
+        token_lengths_buf.clear();
+        token_node_kinds_buf.clear();
+        token_capture_ids_buf.clear();
+        token_languages_buf.clear();
+        token_errors_buf.clear();
+    }
+    let token_conceals = match conceals {
+        Some(conceals) => {
+            let array =
+                env.new_object_array(conceals.len() as jsize, "java/lang/String", JObject::null())?;
+            for (index, conceal) in conceals.iter().enumerate() {
+                let Some(conceal) = conceal else {
+                    continue;
+                };
+                let conceal = env.new_string(conceal)?;
+                let conceal = env.auto_local(conceal);
+                env.set_object_array_element(&array, index as jsize, &conceal)?;
+            }
+            array
+        }
+        None => JObjectArray::default(),
+    };
+    let tokens_obj = env.new_object(
+        "com/hulylabs/treesitter/rusty/TreeSitterNativeHighlightLexer$Tokens",
+        "(I[I[S[S[J[Z[Ljava/lang/String;)V",
+        &[
+            JValue::Int(start_offset as i32),
+            JValue::Object(token_lengths.deref()),
+            JValue::Object(token_node_kinds.deref()),
+            JValue::Object(token_capture_ids.deref()),
+            JValue::Object(token_languages.deref()),
+            JValue::Object(token_errors.deref()),
+            JValue::Object(token_conceals.deref()),
+        ],
+    )?;
+    Ok(tokens_obj)
+}
+
+fn collect_highlights<'local>(
+    env: &mut JNIEnv<'local>,
+    snapshot: JObject<'local>,
+    text: JavaText<'local>,
+    range: JavaTextRange,
+) -> JNIResult<JObject<'local>> {
+    let snapshot = SyntaxSnapshotDesc::from_java_object(env, snapshot)?;
+    with_java_text(env, &text, |env, text| {
+        let (start_offset, tokens) =
+            highlight_tokens_cover_cached(snapshot, text, range.unit_range(snapshot, text));
+        let groups = highlight_token_groups(&snapshot.isolate, &tokens);
+        let conceals = highlight_token_conceals(snapshot, text, start_offset, &tokens);
+        tokens_to_java_object(
+            env,
+            start_offset,
+            &tokens,
+            |idx, _| groups[idx],
+            conceals.as_deref(),
+        )
+    })
+}
+
+fn collect_highlights_for_points<'local>(
+    env: &mut JNIEnv<'local>,
+    snapshot: JObject<'local>,
+    text: JavaText<'local>,
+    start_point: JObject<'local>,
+    end_point: JObject<'local>,
+) -> JNIResult<JObject<'local>> {
+    let start_point = PointDesc::from_java_object(env, &start_point)?;
+    let end_point = PointDesc::from_java_object(env, &end_point)?;
+    collect_highlights(
+        env,
+        snapshot,
+        text,
+        JavaTextRange::Points(start_point, end_point),
+    )
+}
+
+fn collect_highlight_scopes<'local>(
+    env: &mut JNIEnv<'local>,
+    snapshot: JObject<'local>,
+    text: JavaText<'local>,
+    start_offset: jint,
+    end_offset: jint,
+) -> JNIResult<JObject<'local>> {
+    let snapshot = SyntaxSnapshotDesc::from_java_object(env, snapshot)?;
+    with_java_text(env, &text, |env, text| {
+        let (start_offset, tokens) = highlight_tokens_cover_cached(
+            snapshot,
+            text,
+            (start_offset as usize)..(end_offset as usize),
+        );
+        let scopes = highlight_token_scopes(&snapshot.isolate, &tokens);
+        tokens_to_java_object(env, start_offset, &tokens, |idx, _| scopes[idx], None)
+    })
+}
+
+/// Empty tokens are returned for lines past the end of the text
+fn collect_line_highlights<'local>(
+    env: &mut JNIEnv<'local>,
+    snapshot: JObject<'local>,
+    text: JavaText<'local>,
+    line: jint,
+) -> JNIResult<JObject<'local>> {
+    let snapshot = SyntaxSnapshotDesc::from_java_object(env, snapshot)?;
+    with_java_text(env, &text, |env, text| {
+        let (start_offset, tokens) = highlight_line_tokens(snapshot, text, line as usize)
+            .unwrap_or((text.len(), Vec::new()));
+        let groups = highlight_token_groups(&snapshot.isolate, &tokens);
+        let conceals = highlight_token_conceals(snapshot, text, start_offset, &tokens);
+        tokens_to_java_object(
+            env,
+            start_offset,
+            &tokens,
+            |idx, _| groups[idx],
+            conceals.as_deref(),
+        )
+    })
+}
+
+/// Spans are passed to java as parallel arrays of absolute start and end offsets, capture ids
+/// and languages
+fn collect_highlight_spans<'local>(
+    env: &mut JNIEnv<'local>,
+    snapshot: JObject<'local>,
+    text: JavaText<'local>,
+    start_offset: jint,
+    end_offset: jint,
+) -> JNIResult<JObject<'local>> {
+    let snapshot = SyntaxSnapshotDesc::from_java_object(env, snapshot)?;
+    with_java_text(env, &text, |env, text| {
+        let spans = highlight_spans(
+            snapshot,
+            text,
+            (start_offset as usize)..(end_offset as usize),
+        );
+        let starts: Vec<i32> = spans.iter().map(|span| span.range.start as i32).collect();
+        let ends: Vec<i32> = spans.iter().map(|span| span.range.end as i32).collect();
+        let capture_ids: Vec<i16> = spans.iter().map(|span| span.capture_id as i16).collect();
+        let languages: Vec<i64> = spans.iter().map(|span| span.language_id.into()).collect();
+        let starts_array = env.new_int_array(spans.len() as jsize)?;
+        env.set_int_array_region(&starts_array, 0, &starts)?;
+        let ends_array = env.new_int_array(spans.len() as jsize)?;
+        env.set_int_array_region(&ends_array, 0, &ends)?;
+        let capture_ids_array = env.new_short_array(spans.len() as jsize)?;
+        env.set_short_array_region(&capture_ids_array, 0, &capture_ids)?;
+        let languages_array = env.new_long_array(spans.len() as jsize)?;
+        env.set_long_array_region(&languages_array, 0, &languages)?;
+        env.new_object(
+            "com/hulylabs/treesitter/rusty/TreeSitterNativeHighlightLexer$Spans",
+            "([I[I[S[J)V",
+            &[
+                JValue::Object(starts_array.deref()),
+                JValue::Object(ends_array.deref()),
+                JValue::Object(capture_ids_array.deref()),
+                JValue::Object(languages_array.deref()),
+            ],
+        )
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeHighlightLexer_nativeCollectHighlights<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    snapshot: JObject<'local>,
+    text: JCharArray<'local>,
+    start_offset: jint,
+    end_offset: jint,
+) -> JObject<'local> {
+    let result = collect_highlights(
+        &mut env,
+        snapshot,
+        JavaText::Array(text),
+        JavaTextRange::Offsets(start_offset, end_offset),
+    );
+    throw_exception_from_result(&mut env, result)
+}
+
+/// Same as `nativeCollectHighlights`, text is passed as `char[][]` chunks
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeHighlightLexer_nativeCollectHighlightsChunked<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    snapshot: JObject<'local>,
+    text_chunks: JObjectArray<'local>,
+    start_offset: jint,
+    end_offset: jint,
+) -> JObject<'local> {
+    let result = collect_highlights(
+        &mut env,
+        snapshot,
+        JavaText::Chunks(text_chunks),
+        JavaTextRange::Offsets(start_offset, end_offset),
+    );
+    throw_exception_from_result(&mut env, result)
+}
+
+/// Same as `nativeCollectHighlights`, range is given by `com.hulylabs.treesitter.language.Point`
+/// positions with columns in chars, columns past the end of the line are clamped to it
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeHighlightLexer_nativeCollectHighlightsForPoints<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    snapshot: JObject<'local>,
+    text: JCharArray<'local>,
+    start_point: JObject<'local>,
+    end_point: JObject<'local>,
+) -> JObject<'local> {
+    let result = collect_highlights_for_points(
+        &mut env,
+        snapshot,
+        JavaText::Array(text),
+        start_point,
+        end_point,
+    );
+    throw_exception_from_result(&mut env, result)
+}
+
+/// Same as `nativeCollectHighlightsForPoints`, text is passed as `char[][]` chunks
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeHighlightLexer_nativeCollectHighlightsForPointsChunked<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    snapshot: JObject<'local>,
+    text_chunks: JObjectArray<'local>,
+    start_point: JObject<'local>,
+    end_point: JObject<'local>,
+) -> JObject<'local> {
+    let result = collect_highlights_for_points(
+        &mut env,
+        snapshot,
+        JavaText::Chunks(text_chunks),
+        start_point,
+        end_point,
+    );
+    throw_exception_from_result(&mut env, result)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeHighlightLexer_nativeCollectHighlightScopes<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    snapshot: JObject<'local>,
+    text: JCharArray<'local>,
+    start_offset: jint,
+    end_offset: jint,
+) -> JObject<'local> {
+    let result = collect_highlight_scopes(
+        &mut env,
+        snapshot,
+        JavaText::Array(text),
+        start_offset,
+        end_offset,
+    );
+    throw_exception_from_result(&mut env, result)
+}
+
+/// Same as `nativeCollectHighlightScopes`, text is passed as `char[][]` chunks
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeHighlightLexer_nativeCollectHighlightScopesChunked<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    snapshot: JObject<'local>,
+    text_chunks: JObjectArray<'local>,
+    start_offset: jint,
+    end_offset: jint,
+) -> JObject<'local> {
+    let result = collect_highlight_scopes(
+        &mut env,
+        snapshot,
+        JavaText::Chunks(text_chunks),
+        start_offset,
+        end_offset,
+    );
+    throw_exception_from_result(&mut env, result)
+}
+
+/// Only captured spans of `[start_offset, end_offset)` without filler tokens for the gaps
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeHighlightLexer_nativeCollectHighlightSpans<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    snapshot: JObject<'local>,
+    text: JCharArray<'local>,
+    start_offset: jint,
+    end_offset: jint,
+) -> JObject<'local> {
+    let result = collect_highlight_spans(
+        &mut env,
+        snapshot,
+        JavaText::Array(text),
+        start_offset,
+        end_offset,
+    );
+    throw_exception_from_result(&mut env, result)
+}
+
+/// Same as `nativeCollectHighlightSpans`, text is passed as `char[][]` chunks
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeHighlightLexer_nativeCollectHighlightSpansChunked<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    snapshot: JObject<'local>,
+    text_chunks: JObjectArray<'local>,
+    start_offset: jint,
+    end_offset: jint,
+) -> JObject<'local> {
+    let result = collect_highlight_spans(
+        &mut env,
+        snapshot,
+        JavaText::Chunks(text_chunks),
+        start_offset,
+        end_offset,
+    );
+    throw_exception_from_result(&mut env, result)
+}
+
+/// Tokens of the `line` clipped to it, line break is not included
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeHighlightLexer_nativeCollectLineHighlights<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    snapshot: JObject<'local>,
+    text: JCharArray<'local>,
+    line: jint,
+) -> JObject<'local> {
+    let result = collect_line_highlights(&mut env, snapshot, JavaText::Array(text), line);
+    throw_exception_from_result(&mut env, result)
+}
+
+/// Same as `nativeCollectLineHighlights`, text is passed as `char[][]` chunks
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeHighlightLexer_nativeCollectLineHighlightsChunked<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    snapshot: JObject<'local>,
+    text_chunks: JObjectArray<'local>,
+    line: jint,
+) -> JObject<'local> {
+    let result = collect_line_highlights(&mut env, snapshot, JavaText::Chunks(text_chunks), line);
+    throw_exception_from_result(&mut env, result)
+}
+
+/// Offsets within `[start_offset, end_offset)` where the lexer may restart highlighting with empty
+/// state instead of restarting from the start of the file
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeHighlightLexer_nativeGetRestartOffsets<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    snapshot: JObject<'local>,
+    start_offset: jint,
+    end_offset: jint,
+) -> JIntArray<'local> {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        snapshot: JObject<'local>,
+        start_offset: jint,
+        end_offset: jint,
+    ) -> JNIResult<JIntArray<'local>> {
+        let snapshot = SyntaxSnapshotDesc::from_java_object(env, snapshot)?;
+        let offsets: Vec<i32> = highlight_restart_offsets(
+            snapshot,
+            ((start_offset * 2) as usize)..((end_offset * 2) as usize),
+        )
+        .into_iter()
+        .map(|offset| (offset / 2) as i32)
+        .collect();
+        let array = env.new_int_array(offsets.len() as jsize)?;
+        env.set_int_array_region(&array, 0, &offsets)?;
+        Ok(array)
+    }
+    let result = inner(&mut env, snapshot, start_offset, end_offset);
+    throw_exception_from_result(&mut env, result)
+}
+
+/// Tokens of the regions to re-tokenize after an incremental parse, `changed_offsets` are flat
+/// start/end pairs of the changed ranges. Returns array of sorted disjoint `Tokens`.
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeHighlightLexer_nativeCollectChangedHighlights<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    snapshot: JObject<'local>,
+    text: JCharArray<'local>,
+    changed_offsets: JIntArray<'local>,
+) -> JObjectArray<'local> {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        snapshot: JObject<'local>,
+        text: JCharArray<'local>,
+        changed_offsets: JIntArray<'local>,
+    ) -> JNIResult<JObjectArray<'local>> {
+        let snapshot = SyntaxSnapshotDesc::from_java_object(env, snapshot)?;
+        let offsets_length = env.get_array_length(&changed_offsets)?;
+        let mut offsets = vec![0; offsets_length as usize];
+        env.get_int_array_region(&changed_offsets, 0, &mut offsets)?;
+        let changed_ranges: Vec<_> = offsets
+            .chunks_exact(2)
+            .map(|pair| (pair[0] as usize)..(pair[1] as usize))
+            .collect();
+        with_java_text(env, &JavaText::Array(text), |env, text| {
+            let covers = changed_highlight_tokens(snapshot, text, &changed_ranges);
+            let array = env.new_object_array(
+                covers.len() as jsize,
+                "com/hulylabs/treesitter/rusty/TreeSitterNativeHighlightLexer$Tokens",
+                JObject::null(),
+            )?;
+            for (index, (start_offset, tokens)) in covers.iter().enumerate() {
+                let groups = highlight_token_groups(&snapshot.isolate, tokens);
+                let conceals = highlight_token_conceals(snapshot, text, *start_offset, tokens);
+                let obj = tokens_to_java_object(
+                    env,
+                    *start_offset,
+                    tokens,
+                    |idx, _| groups[idx],
+                    conceals.as_deref(),
+                )?;
+                let obj = env.auto_local(obj);
+                env.set_object_array_element(&array, index as jsize, obj)?;
+            }
+            Ok(array)
+        })
+    }
+    let result = inner(&mut env, snapshot, text, changed_offsets);
+    throw_exception_from_result(&mut env, result)
+}
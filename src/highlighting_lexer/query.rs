@@ -1,24 +1,17 @@
 use std::{
     collections::HashMap,
-    ops::{Deref, Range},
+    ops::Range,
+    sync::{atomic::Ordering, Arc, Mutex},
 };
 
-use jni::{
-    errors::Result as JNIResult,
-    objects::{JCharArray, JClass, JObject, JValue},
-    sys::{jint, jsize},
-    JNIEnv,
-};
 use streaming_iterator::StreamingIterator as _;
-use tree_sitter::{Node, QueryCursor};
+use tree_sitter::Node;
 
 use crate::{
-    jni_utils::throw_exception_from_result,
-    language_registry::with_language,
-    query::RecodingUtf16TextProvider,
-    syntax_snapshot::{
-        SyntaxSnapshot, SyntaxSnapshotDesc, SyntaxSnapshotEntryContent, SyntaxSnapshotTreeCursor,
-    },
+    invariants, profiler,
+    query::{pooled_query_cursor, SourceText, SourceTextProvider},
+    query_budget,
+    syntax_snapshot::{SyntaxSnapshot, SyntaxSnapshotEntryContent, SyntaxSnapshotTreeCursor},
     LanguageId,
 };
 
@@ -76,50 +69,183 @@ fn find_cover_start(
     (cover_start_byte, parent_stack, tree_cursor)
 }
 
+/// Capture chosen for a node range
+#[derive(Debug, Clone, Copy)]
+struct RangeHighlight {
+    language_id: LanguageId,
+    capture_id: u16,
+    pattern_index: usize,
+    /// Injection depth of the entry the capture comes from
+    depth: usize,
+}
+
+impl RangeHighlight {
+    /// Captures of deeper injections win over host captures of the same range (e.g. regex
+    /// inside of a string), within one layer later patterns win
+    fn overrides(&self, other: &RangeHighlight) -> bool {
+        match self.depth.cmp(&other.depth) {
+            std::cmp::Ordering::Greater => true,
+            std::cmp::Ordering::Less => false,
+            std::cmp::Ordering::Equal => {
+                self.language_id != other.language_id || self.pattern_index >= other.pattern_index
+            }
+        }
+    }
+}
+
+fn insert_highlight(
+    highlights: &mut HashMap<Range<usize>, RangeHighlight>,
+    range: Range<usize>,
+    highlight: RangeHighlight,
+) {
+    if highlights
+        .get(&range)
+        .is_none_or(|other| highlight.overrides(other))
+    {
+        highlights.insert(range, highlight);
+    }
+}
+
 fn collect_highlights_for_range(
     snapshot: &SyntaxSnapshot,
-    text: &[u16],
+    text: SourceText<'_>,
     byte_range: Range<usize>,
-) -> HashMap<Range<usize>, (LanguageId, u16, usize)> {
-    let mut query_cursor = QueryCursor::new();
+) -> HashMap<Range<usize>, RangeHighlight> {
+    let _budget = query_budget::call(&snapshot.isolate);
+    let mut query_cursor = pooled_query_cursor();
     query_cursor.set_byte_range(byte_range.clone());
-    let text_provider = RecodingUtf16TextProvider::new(text);
-    let intersecting_entries = snapshot.entries.iter().filter(|entry| {
-        entry.byte_range.start <= byte_range.end && entry.byte_range.end >= byte_range.start
-    });
-    let mut highlights: HashMap<Range<usize>, (LanguageId, u16, usize)> = HashMap::new();
-    for entry in intersecting_entries {
+    let text_provider = SourceTextProvider::new(text);
+    let intersecting_entries = snapshot.intersecting_entries(byte_range.clone(), true);
+    let mut highlights: HashMap<Range<usize>, RangeHighlight> = HashMap::new();
+    for (_, entry) in intersecting_entries {
+        if query_budget::is_exhausted() {
+            break;
+        }
         let SyntaxSnapshotEntryContent::Parsed { language, tree } = &entry.content else {
             continue;
         };
-        let query = with_language(*language, |language| {
-            language.parser_info().highlights_query.clone()
+        let query = snapshot.isolate.with_language(*language, |language| {
+            let parser_info = language.parser_info();
+            (
+                parser_info.highlights_query.clone(),
+                parser_info.locals_query.clone(),
+            )
         });
-        let Ok(Some(query)) = query else {
+        let Ok((Some(query), locals_query)) = query else {
             continue;
         };
+        let _phase = profiler::phase("highlights.query", Some(*language));
         let root_node = tree.root_node_with_offset(entry.byte_offset, entry.point_offset);
+        let bindings = match &locals_query {
+            Some(locals_query) => {
+                let _phase = profiler::phase("locals.query", Some(*language));
+                snapshot.locals_cache.resolve_until(
+                    entry,
+                    locals_query,
+                    snapshot.isolate.highlight_queries_generation(),
+                    root_node,
+                    text,
+                    byte_range.end,
+                )
+            }
+            None => Arc::default(),
+        };
+        let none_capture_id = query.0.capture_index_for_name("none");
+        query_budget::prepare_cursor(&mut query_cursor);
         let mut captures = query_cursor.captures(&query.0, root_node, &text_provider);
         while let Some((next_match, cidx)) = captures.next() {
-            if !query
-                .1
-                .satisfies_predicates(&mut &text_provider, next_match)
+            if !query.1.satisfies_predicates(&text_provider, next_match)
+                || !bindings.satisfies_property_predicates(&query.0, next_match)
             {
                 next_match.remove();
                 continue;
             }
             let capture = next_match.captures[*cidx];
             let range = capture.node.start_byte()..capture.node.end_byte();
-            let capture_id = capture.index as u16;
+            let mut capture_id = capture.index as u16;
             if !query.2.contains(capture_id as usize) {
                 continue;
             }
-            if let Some((other_language, _, pattern_index)) = highlights.get(&range) {
-                if other_language == language && next_match.pattern_index < *pattern_index {
-                    continue;
+            // `@none` clears highlights of the node including the ones of its ancestors
+            if Some(capture.index) == none_capture_id {
+                capture_id = u16::MAX;
+            }
+            insert_highlight(
+                &mut highlights,
+                range,
+                RangeHighlight {
+                    language_id: *language,
+                    capture_id,
+                    pattern_index: next_match.pattern_index,
+                    depth: entry.depth,
+                },
+            );
+        }
+        query_budget::finish_cursor(&query_cursor);
+        // References are highlighted same as the definitions they resolve to, highlights of
+        // definitions outside of the range are looked up once per definition
+        let mut outer_definition_highlights: HashMap<Range<usize>, Option<(u16, usize)>> =
+            HashMap::new();
+        for (reference, definition) in &bindings.references {
+            if reference.end <= byte_range.start || reference.start >= byte_range.end {
+                continue;
+            }
+            let definition_highlight = match highlights.get(definition) {
+                Some(highlight) if highlight.language_id == *language => {
+                    Some((highlight.capture_id, highlight.pattern_index))
                 }
+                Some(_) => None,
+                None => *outer_definition_highlights
+                    .entry(definition.clone())
+                    .or_insert_with(|| {
+                        let mut definition_cursor = pooled_query_cursor();
+                        definition_cursor.set_byte_range(definition.clone());
+                        query_budget::prepare_cursor(&mut definition_cursor);
+                        let mut captures =
+                            definition_cursor.captures(&query.0, root_node, &text_provider);
+                        let mut definition_highlight: Option<(u16, usize)> = None;
+                        while let Some((next_match, cidx)) = captures.next() {
+                            if !query.1.satisfies_predicates(&text_provider, next_match)
+                                || !bindings.satisfies_property_predicates(&query.0, next_match)
+                            {
+                                next_match.remove();
+                                continue;
+                            }
+                            let capture = next_match.captures[*cidx];
+                            if capture.node.byte_range() != *definition
+                                || !query.2.contains(capture.index as usize)
+                            {
+                                continue;
+                            }
+                            if definition_highlight.is_none_or(|(_, pattern_index)| {
+                                next_match.pattern_index >= pattern_index
+                            }) {
+                                definition_highlight =
+                                    Some((capture.index as u16, next_match.pattern_index));
+                            }
+                        }
+                        query_budget::finish_cursor(&definition_cursor);
+                        definition_highlight
+                    }),
+            };
+            let Some((capture_id, pattern_index)) = definition_highlight else {
+                continue;
+            };
+            if highlights
+                .get(reference)
+                .is_some_and(|highlight| highlight.language_id != *language)
+            {
+                continue;
             }
-            highlights.insert(range, (*language, capture_id, next_match.pattern_index));
+            highlights.insert(
+                reference.clone(),
+                RangeHighlight {
+                    language_id: *language,
+                    capture_id,
+                    pattern_index,
+                    depth: entry.depth,
+                },
+            );
         }
     }
     highlights
@@ -127,26 +253,27 @@ fn collect_highlights_for_range(
 
 pub fn highlight_tokens_cover(
     snapshot: &SyntaxSnapshot,
-    text: &[u16],
+    text: SourceText<'_>,
     range: Range<usize>,
 ) -> (usize, Vec<HighlightToken>) {
-    let (byte_start, parent_stack, mut tree_cursor) = find_cover_start(snapshot, range.start * 2);
-    let byte_end = range.end * 2;
+    let _profile = profiler::call(&snapshot.isolate, "highlights");
+    let unit_size = text.unit_size();
+    let (byte_start, parent_stack, mut tree_cursor) =
+        find_cover_start(snapshot, range.start * unit_size);
+    let byte_end = range.end * unit_size;
 
     let highlights = collect_highlights_for_range(snapshot, text, byte_start..byte_end);
 
     let mut highlight_stack: Vec<(LanguageId, usize, u16)> = parent_stack
         .into_iter()
         .filter_map(|(language_id, node_id, range)| {
-            highlights
-                .get(&range)
-                .and_then(|(h_language_id, capture_id, _)| {
-                    if language_id == *h_language_id {
-                        Some((language_id, node_id, *capture_id))
-                    } else {
-                        None
-                    }
-                })
+            highlights.get(&range).and_then(|highlight| {
+                if language_id == highlight.language_id {
+                    Some((language_id, node_id, highlight.capture_id))
+                } else {
+                    None
+                }
+            })
         })
         .collect();
 
@@ -166,7 +293,8 @@ pub fn highlight_tokens_cover(
                         }
                     })
                     .unwrap_or(u16::MAX),
-                length: ((node.end_byte() - node.start_byte()) / 2) as u32,
+                length: ((node.end_byte() - node.start_byte()) / unit_size) as u32,
+                error: false,
             }
         };
     let token_from_node_subrange =
@@ -185,7 +313,8 @@ pub fn highlight_tokens_cover(
                     }
                 })
                 .unwrap_or(u16::MAX),
-            length: ((range.end - range.start) / 2) as u32,
+            length: ((range.end - range.start) / unit_size) as u32,
+            error: false,
         };
 
     let mut byte_current = byte_start;
@@ -206,9 +335,13 @@ pub fn highlight_tokens_cover(
                 let node = tree_cursor.node();
                 let node_id = node.id();
                 let range = node.start_byte()..node.end_byte();
-                if let Some((lang, capture_id, _)) = highlights.get(&range).copied() {
-                    if tree_cursor.language() == lang {
-                        highlight_stack.push((lang, node_id, capture_id));
+                if let Some(highlight) = highlights.get(&range) {
+                    if tree_cursor.language() == highlight.language_id {
+                        highlight_stack.push((
+                            highlight.language_id,
+                            node_id,
+                            highlight.capture_id,
+                        ));
                     }
                 }
             } else {
@@ -244,9 +377,13 @@ pub fn highlight_tokens_cover(
                 let node = tree_cursor.node();
                 let node_id = node.id();
                 let range = node.start_byte()..node.end_byte();
-                if let Some((lang, capture_id, _)) = highlights.get(&range).copied() {
-                    if tree_cursor.language() == lang {
-                        highlight_stack.push((lang, node_id, capture_id));
+                if let Some(highlight) = highlights.get(&range) {
+                    if tree_cursor.language() == highlight.language_id {
+                        highlight_stack.push((
+                            highlight.language_id,
+                            node_id,
+                            highlight.capture_id,
+                        ));
                     }
                 }
             } else if tree_cursor.goto_parent() {
@@ -263,92 +400,448 @@ pub fn highlight_tokens_cover(
             }
         }
     }
-    (byte_start / 2, highlight_tokens)
+    let error_ranges = collect_error_ranges(snapshot, byte_start..byte_end);
+    mark_error_tokens(&mut highlight_tokens, byte_start, unit_size, &error_ranges);
+    coalesce_tokens(&mut highlight_tokens);
+    let start_offset = byte_start / unit_size;
+    let max_token_length = snapshot
+        .isolate
+        .options
+        .max_token_length
+        .load(Ordering::Relaxed);
+    if max_token_length > 0 {
+        highlight_tokens =
+            split_long_tokens(highlight_tokens, text, start_offset, max_token_length);
+    }
+    invariants::check_tokens(
+        &snapshot.isolate,
+        start_offset,
+        &highlight_tokens,
+        &range,
+        text.byte_len() / unit_size,
+        "highlights",
+    );
+    (start_offset, highlight_tokens)
+}
+
+/// Sorted disjoint byte ranges of ERROR and MISSING nodes intersecting `byte_range`, missing
+/// nodes are empty so they are extended to the next byte
+fn collect_error_ranges(snapshot: &SyntaxSnapshot, byte_range: Range<usize>) -> Vec<Range<usize>> {
+    let mut error_ranges: Vec<Range<usize>> = Vec::new();
+    for (_, entry) in snapshot.intersecting_entries(byte_range.clone(), true) {
+        let SyntaxSnapshotEntryContent::Parsed { tree, .. } = &entry.content else {
+            continue;
+        };
+        let root_node = tree.root_node_with_offset(entry.byte_offset, entry.point_offset);
+        let mut nodes = vec![root_node];
+        while let Some(node) = nodes.pop() {
+            if node.end_byte() < byte_range.start || node.start_byte() > byte_range.end {
+                continue;
+            }
+            if node.is_error() || node.is_missing() {
+                error_ranges.push(node.start_byte()..node.end_byte().max(node.start_byte() + 1));
+            } else if node.has_error() {
+                let mut cursor = node.walk();
+                nodes.extend(node.children(&mut cursor));
+            }
+        }
+    }
+    error_ranges.sort_by_key(|range| range.start);
+    let mut merged: Vec<Range<usize>> = Vec::with_capacity(error_ranges.len());
+    for range in error_ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+fn mark_error_tokens(
+    tokens: &mut [HighlightToken],
+    byte_start: usize,
+    unit_size: usize,
+    error_ranges: &[Range<usize>],
+) {
+    let mut error_ranges = error_ranges.iter().peekable();
+    let mut token_end = byte_start;
+    for token in tokens {
+        let token_start = token_end;
+        token_end += token.length as usize * unit_size;
+        while error_ranges
+            .peek()
+            .is_some_and(|range| range.end <= token_start)
+        {
+            error_ranges.next();
+        }
+        token.error = error_ranges
+            .peek()
+            .is_some_and(|range| range.start < token_end.max(token_start + 1));
+    }
 }
 
-#[no_mangle]
-pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeHighlightLexer_nativeCollectHighlights<
-    'local,
->(
-    mut env: JNIEnv<'local>,
-    _class: JClass<'local>,
-    snapshot: JObject<'local>,
-    text: JCharArray<'local>,
-    start_offset: jint,
-    end_offset: jint,
-) -> JObject<'local> {
-    fn inner<'local>(
-        env: &mut JNIEnv<'local>,
-        snapshot: JObject<'local>,
-        text: JCharArray<'local>,
-        start_offset: jint,
-        end_offset: jint,
-    ) -> JNIResult<JObject<'local>> {
-        let snapshot = SyntaxSnapshotDesc::from_java_object(env, snapshot)?;
-        let text_length = env.get_array_length(&text)?;
-        let mut text_buffer = vec![0u16; text_length as usize];
-        env.get_char_array_region(&text, 0, &mut text_buffer)?;
-
-        let (start_offset, tokens) = highlight_tokens_cover(
-            snapshot,
-            &text_buffer,
-            (start_offset as usize)..(end_offset as usize),
+/// Merges consecutive tokens of the same language, capture and node kind, e.g. leaves of long
+/// runs of plain text, to keep token arrays passed to the editor small
+fn coalesce_tokens(tokens: &mut Vec<HighlightToken>) {
+    tokens.dedup_by(|token, previous| {
+        if token.language_id == previous.language_id
+            && token.capture_id == previous.capture_id
+            && token.kind_id == previous.kind_id
+            && token.error == previous.error
+        {
+            previous.length += token.length;
+            true
+        } else {
+            false
+        }
+    });
+}
+
+/// Replacement text of tokens within nodes concealed by `(#set! conceal "...")` of highlight
+/// patterns, the setting applies to its capture or to all captures of the pattern. The first token
+/// of a concealed node gets the replacement and the rest of its tokens are hidden (empty text).
+/// `None` if no token is concealed.
+pub fn highlight_token_conceals(
+    snapshot: &SyntaxSnapshot,
+    text: SourceText<'_>,
+    start_offset: usize,
+    tokens: &[HighlightToken],
+) -> Option<Vec<Option<Box<str>>>> {
+    let unit_size = text.unit_size();
+    let byte_start = start_offset * unit_size;
+    let byte_end = byte_start
+        + tokens
+            .iter()
+            .map(|token| token.length as usize)
+            .sum::<usize>()
+            * unit_size;
+    let text_provider = SourceTextProvider::new(text);
+    let mut conceals: Vec<(Range<usize>, Box<str>)> = Vec::new();
+    for (_, entry) in snapshot.intersecting_entries(byte_start..byte_end, false) {
+        let SyntaxSnapshotEntryContent::Parsed { language, tree } = &entry.content else {
+            continue;
+        };
+        let query = snapshot.isolate.with_language(*language, |language| {
+            language.parser_info().highlights_query.clone()
+        });
+        let Ok(Some(query)) = query else {
+            continue;
+        };
+        if !query.1.metadata().has_property("conceal") {
+            continue;
+        }
+        let mut cursor = pooled_query_cursor();
+        cursor.set_byte_range(byte_start..byte_end);
+        let mut matches = cursor.matches(
+            &query.0,
+            tree.root_node_with_offset(entry.byte_offset, entry.point_offset),
+            &text_provider,
         );
-        let token_lengths = env.new_int_array(tokens.len() as i32)?;
-        let token_node_kinds = env.new_short_array(tokens.len() as i32)?;
-        let token_capture_ids = env.new_short_array(tokens.len() as i32)?;
-        let token_languages = env.new_long_array(tokens.len() as i32)?;
-        const CHUNK_SIZE: usize = 2048;
-        let mut token_lengths_buf: Vec<i32> = Vec::with_capacity(CHUNK_SIZE);
-        let mut token_node_kinds_buf: Vec<i16> = Vec::with_capacity(CHUNK_SIZE);
-        let mut token_capture_ids_buf: Vec<i16> = Vec::with_capacity(CHUNK_SIZE);
-        let mut token_languages_buf: Vec<i64> = Vec::with_capacity(CHUNK_SIZE);
-        for (slice_idx, tokens_slice) in tokens.chunks(CHUNK_SIZE).enumerate() {
-            for token in tokens_slice {
-                token_lengths_buf.push(token.length as i32);
-                token_node_kinds_buf.push(token.kind_id as i16);
-                token_capture_ids_buf.push(token.capture_id as i16);
-                token_languages_buf.push(token.language_id.into());
+        while let Some(query_match) = matches.next() {
+            let metadata = query.1.metadata().pattern(query_match.pattern_index);
+            let Some(conceal) = metadata.property("conceal") else {
+                continue;
+            };
+            if !query.1.satisfies_predicates(&text_provider, query_match) {
+                continue;
             }
-            env.set_int_array_region(
-                &token_lengths,
-                (slice_idx * CHUNK_SIZE) as jsize,
-                &token_lengths_buf,
-            )?;
-            env.set_short_array_region(
-                &token_node_kinds,
-                (slice_idx * CHUNK_SIZE) as jsize,
-                &token_node_kinds_buf,
-            )?;
-            env.set_short_array_region(
-                &token_capture_ids,
-                (slice_idx * CHUNK_SIZE) as jsize,
-                &token_capture_ids_buf,
-            )?;
-            env.set_long_array_region(
-                &token_languages,
-                (slice_idx * CHUNK_SIZE) as jsize,
-                &token_languages_buf,
-            )?;
-            token_lengths_buf.clear();
-            token_node_kinds_buf.clear();
-            token_capture_ids_buf.clear();
-            token_languages_buf.clear();
+            let replacement: Box<str> = conceal.value.clone().unwrap_or_default();
+            for capture in query_match.captures {
+                if conceal
+                    .capture_id
+                    .is_some_and(|capture_id| capture_id != capture.index)
+                {
+                    continue;
+                }
+                conceals.push((capture.node.byte_range(), replacement.clone()));
+            }
+        }
+    }
+    if conceals.is_empty() {
+        return None;
+    }
+    conceals.sort_by_key(|(range, _)| (range.start, std::cmp::Reverse(range.end)));
+    let mut conceals = conceals.into_iter().peekable();
+    let mut current: Option<(Range<usize>, Box<str>)> = None;
+    let mut token_end = byte_start;
+    let mut token_conceals = Vec::with_capacity(tokens.len());
+    for token in tokens {
+        let token_start = token_end;
+        token_end += token.length as usize * unit_size;
+        while conceals
+            .peek()
+            .is_some_and(|(range, _)| range.start <= token_start)
+        {
+            let (range, replacement) = conceals.next().expect("peeked");
+            // Nested conceals are covered by the outer one
+            if current
+                .as_ref()
+                .is_none_or(|(current_range, _)| current_range.end <= range.start)
+            {
+                current = Some((range, replacement));
+            }
+        }
+        token_conceals.push(match &current {
+            Some((range, replacement)) if token_start < range.end && range.start < token_end => {
+                if token_start <= range.start {
+                    Some(replacement.clone())
+                } else {
+                    Some("".into())
+                }
+            }
+            _ => None,
+        });
+    }
+    Some(token_conceals)
+}
+
+/// Splits tokens longer than `max_length` units into parts of the same capture, UTF-16 surrogate
+/// pairs are kept within one part
+fn split_long_tokens(
+    tokens: Vec<HighlightToken>,
+    text: SourceText<'_>,
+    start_offset: usize,
+    max_length: usize,
+) -> Vec<HighlightToken> {
+    if tokens
+        .iter()
+        .all(|token| token.length as usize <= max_length)
+    {
+        return tokens;
+    }
+    let unit_size = text.unit_size();
+    let mut split_tokens = Vec::with_capacity(tokens.len());
+    let mut token_start = start_offset;
+    for token in tokens {
+        let token_end = token_start + token.length as usize;
+        let mut part_start = token_start;
+        while token_end - part_start > max_length {
+            let mut part_end = part_start + max_length;
+            if unit_size == 2 && (0xDC00..0xE000).contains(&text.unit(part_end)) {
+                part_end += 1;
+            }
+            // Continuation bytes of UTF-8 characters stay with their first byte
+            while unit_size == 1 && part_end < token_end && text.unit(part_end) & 0xC0 == 0x80 {
+                part_end += 1;
+            }
+            split_tokens.push(HighlightToken {
+                length: (part_end - part_start) as u32,
+                ..token
+            });
+            part_start = part_end;
         }
-        let tokens_obj = env.new_object(
-            "com/hulylabs/treesitter/rusty/TreeSitterNativeHighlightLexer$Tokens",
-            "(I[I[S[S[J)V",
-            &[
-                JValue::Int(start_offset as i32),
-                JValue::Object(token_lengths.deref()),
-                JValue::Object(token_node_kinds.deref()),
-                JValue::Object(token_capture_ids.deref()),
-                JValue::Object(token_languages.deref()),
-            ],
-        )?;
-
-        Ok(tokens_obj)
+        if part_start < token_end {
+            split_tokens.push(HighlightToken {
+                length: (token_end - part_start) as u32,
+                ..token
+            });
+        }
+        token_start = token_end;
+    }
+    split_tokens
+}
+
+const HIGHLIGHT_CACHE_SIZE: usize = 8;
+
+/// Token covers recently computed for the snapshot, most recent last. Lives in the snapshot, so
+/// it is dropped along with it and never outlives the trees the tokens come from.
+#[derive(Default)]
+pub(crate) struct HighlightCache {
+    covers: Mutex<Vec<CachedCover>>,
+}
+
+/// Isolate state the tokens of a cover were computed with besides the snapshot
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct HighlightCacheKey {
+    queries_generation: u64,
+    max_token_length: usize,
+}
+
+impl HighlightCacheKey {
+    fn new(snapshot: &SyntaxSnapshot) -> Self {
+        HighlightCacheKey {
+            queries_generation: snapshot.isolate.highlight_queries_generation(),
+            max_token_length: snapshot
+                .isolate
+                .options
+                .max_token_length
+                .load(Ordering::Relaxed),
+        }
+    }
+}
+
+struct CachedCover {
+    key: HighlightCacheKey,
+    range: Range<usize>,
+    start_offset: usize,
+    tokens: Arc<[HighlightToken]>,
+}
+
+impl HighlightCache {
+    /// Tokens covering `range` sliced from a cover of the same or an enclosing range cached with
+    /// the same `key`
+    fn get(
+        &self,
+        key: HighlightCacheKey,
+        range: &Range<usize>,
+    ) -> Option<(usize, Vec<HighlightToken>)> {
+        let mut covers = self.covers.lock().unwrap();
+        let idx = covers.iter().rposition(|cover| {
+            cover.key == key && cover.range.start <= range.start && range.end <= cover.range.end
+        })?;
+        let cover = covers.remove(idx);
+        let result = if cover.range == *range {
+            (cover.start_offset, cover.tokens.to_vec())
+        } else {
+            let mut start_offset = cover.start_offset;
+            let mut token_end = cover.start_offset;
+            let mut sliced = Vec::new();
+            for token in cover.tokens.iter() {
+                let token_start = token_end;
+                token_end += token.length as usize;
+                if token_end <= range.start {
+                    start_offset = token_end;
+                    continue;
+                }
+                if token_start >= range.end && !sliced.is_empty() {
+                    break;
+                }
+                sliced.push(*token);
+            }
+            (start_offset, sliced)
+        };
+        covers.push(cover);
+        Some(result)
+    }
+
+    fn put(
+        &self,
+        key: HighlightCacheKey,
+        range: Range<usize>,
+        start_offset: usize,
+        tokens: &[HighlightToken],
+    ) {
+        let mut covers = self.covers.lock().unwrap();
+        // Covers computed with other queries or options are never hit again
+        covers.retain(|cover| cover.key == key);
+        if covers.len() >= HIGHLIGHT_CACHE_SIZE {
+            covers.remove(0);
+        }
+        covers.push(CachedCover {
+            key,
+            range,
+            start_offset,
+            tokens: tokens.into(),
+        });
+    }
+}
+
+/// Same as [`highlight_tokens_cover`], results are cached in the snapshot so repeated requests
+/// for the same or overlapped windows of unchanged snapshot do not rerun highlight queries.
+/// Changes of highlight or locals queries and of highlight options of the isolate invalidate
+/// the cached results.
+pub fn highlight_tokens_cover_cached(
+    snapshot: &SyntaxSnapshot,
+    text: SourceText<'_>,
+    range: Range<usize>,
+) -> (usize, Vec<HighlightToken>) {
+    let key = HighlightCacheKey::new(snapshot);
+    if let Some(result) = snapshot.highlight_cache.get(key, &range) {
+        return result;
+    }
+    let (start_offset, tokens) = highlight_tokens_cover(snapshot, text, range.clone());
+    snapshot
+        .highlight_cache
+        .put(key, range, start_offset, &tokens);
+    (start_offset, tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{json_isolate, parse};
+
+    fn token(length: u32) -> HighlightToken {
+        HighlightToken {
+            language_id: LanguageId::from(0),
+            kind_id: 0,
+            capture_id: 0,
+            length,
+            error: false,
+        }
+    }
+
+    fn lengths(tokens: &[HighlightToken]) -> Vec<u32> {
+        tokens.iter().map(|token| token.length).collect()
+    }
+
+    #[test]
+    fn max_token_length_of_the_snapshot_isolate() {
+        let text = "[\"abcdefghij\"]";
+        let token_lengths = |max_token_length: &str| {
+            let (isolate, language_id) = json_isolate();
+            isolate
+                .set_option("highlight.max_token_length", max_token_length)
+                .unwrap();
+            isolate
+                .add_highlight_query(language_id, "(string) @string")
+                .unwrap();
+            let snapshot = parse(&isolate, language_id, text);
+            let (_, tokens) =
+                highlight_tokens_cover(&snapshot, SourceText::Utf8(text), 0..text.len());
+            lengths(&tokens)
+        };
+        assert_eq!(token_lengths("0"), vec![1, 1, 10, 1, 1]);
+        assert_eq!(token_lengths("4"), vec![1, 1, 4, 4, 2, 1, 1]);
+    }
+
+    #[test]
+    fn cached_covers_follow_queries_and_options_of_the_isolate() {
+        let text = "[\"abcdefghij\"]";
+        let (isolate, language_id) = json_isolate();
+        isolate
+            .add_highlight_query(language_id, "(string) @string")
+            .unwrap();
+        let snapshot = parse(&isolate, language_id, text);
+        let cover =
+            || highlight_tokens_cover_cached(&snapshot, SourceText::Utf8(text), 0..text.len()).1;
+        assert_eq!(lengths(&cover()), vec![1, 1, 10, 1, 1]);
+
+        isolate
+            .set_option("highlight.max_token_length", "4")
+            .unwrap();
+        assert_eq!(lengths(&cover()), vec![1, 1, 4, 4, 2, 1, 1]);
+
+        isolate
+            .add_highlight_query(language_id, "(number) @number")
+            .unwrap();
+        assert!(cover().iter().all(|token| token.capture_id == u16::MAX));
+    }
+
+    #[test]
+    fn split_long_tokens_keeps_short_tokens() {
+        let text = SourceText::Utf8("abcdef");
+        let tokens = split_long_tokens(vec![token(3), token(3)], text, 0, 4);
+        assert_eq!(lengths(&tokens), vec![3, 3]);
+    }
+
+    #[test]
+    fn split_long_tokens_keeps_utf8_characters_whole() {
+        // Each "é" takes two bytes, so a split after three bytes would cut one
+        let text = SourceText::Utf8("ééééé");
+        let tokens = split_long_tokens(vec![token(10)], text, 0, 3);
+        assert_eq!(lengths(&tokens), vec![4, 4, 2]);
+        let mut offset = 0;
+        for token in &tokens {
+            offset += token.length as usize;
+            assert!("ééééé".is_char_boundary(offset));
+        }
+    }
+
+    #[test]
+    fn split_long_tokens_keeps_surrogate_pairs_whole() {
+        let units: Vec<u16> = "a😀b".encode_utf16().collect();
+        let tokens = split_long_tokens(vec![token(4)], SourceText::Utf16(&units), 0, 2);
+        assert_eq!(lengths(&tokens), vec![3, 1]);
     }
-    let result = inner(&mut env, snapshot, text, start_offset, end_offset);
-    throw_exception_from_result(&mut env, result)
 }
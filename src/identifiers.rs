@@ -0,0 +1,96 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    ops::Range,
+};
+
+use bit_set::BitSet;
+use streaming_iterator::StreamingIterator as _;
+use tree_sitter::QueryCursor;
+
+use crate::{
+    query::{SourceText, SourceTextProvider},
+    syntax_snapshot::{SyntaxSnapshot, SyntaxSnapshotEntryContent},
+    LanguageId,
+};
+
+#[cfg(feature = "jni")]
+mod jni_methods;
+
+/// Highlight capture classes which name identifiers, subclasses (`function.method`,
+/// `variable.parameter`, ...) are included
+const IDENTIFIER_CAPTURE_CLASSES: &[&str] = &[
+    "attribute",
+    "constant",
+    "constructor",
+    "function",
+    "label",
+    "method",
+    "module",
+    "namespace",
+    "parameter",
+    "property",
+    "type",
+    "variable",
+];
+
+fn is_identifier_capture(capture_name: &str) -> bool {
+    let class = capture_name.split('.').next().unwrap_or(capture_name);
+    IDENTIFIER_CAPTURE_CLASSES.contains(&class)
+}
+
+/// Collects distinct identifier texts in `byte_range` grouped by language, identifiers are
+/// nodes captured by highlights query with one of identifier capture classes.
+pub fn collect_identifiers(
+    snapshot: &SyntaxSnapshot,
+    text: SourceText<'_>,
+    byte_range: Range<usize>,
+) -> BTreeMap<LanguageId, BTreeSet<Box<str>>> {
+    let mut identifiers: BTreeMap<LanguageId, BTreeSet<Box<str>>> = BTreeMap::new();
+    let mut capture_masks: HashMap<LanguageId, BitSet> = HashMap::new();
+    let text_provider = SourceTextProvider::new(text);
+    let mut query_cursor = QueryCursor::new();
+    for (_, entry) in snapshot.intersecting_entries(byte_range.clone(), false) {
+        let SyntaxSnapshotEntryContent::Parsed { language, tree } = &entry.content else {
+            continue;
+        };
+        let query = snapshot.isolate.with_language(*language, |language| {
+            language.parser_info().highlights_query.clone()
+        });
+        let Ok(Some(query)) = query else {
+            continue;
+        };
+        let capture_mask = capture_masks.entry(*language).or_insert_with(|| {
+            query
+                .0
+                .capture_names()
+                .iter()
+                .enumerate()
+                .filter(|(_, name)| is_identifier_capture(name))
+                .map(|(idx, _)| idx)
+                .collect()
+        });
+        if capture_mask.is_empty() {
+            continue;
+        }
+        query_cursor.set_byte_range(
+            byte_range.start.max(entry.byte_range.start)..byte_range.end.min(entry.byte_range.end),
+        );
+        let root_node = tree.root_node_with_offset(entry.byte_offset, entry.point_offset);
+        let mut captures = query_cursor.captures(&query.0, root_node, &text_provider);
+        while let Some((next_match, cidx)) = captures.next() {
+            let capture = next_match.captures[*cidx];
+            if !capture_mask.contains(capture.index as usize) {
+                continue;
+            }
+            if !query.1.satisfies_predicates(&text_provider, next_match) {
+                continue;
+            }
+            let identifier = text.text_for_byte_range(capture.node.byte_range());
+            let language_identifiers = identifiers.entry(*language).or_default();
+            if !language_identifiers.contains(identifier.as_ref()) {
+                language_identifiers.insert(identifier.into());
+            }
+        }
+    }
+    identifiers
+}
@@ -0,0 +1,112 @@
+use jni::{
+    errors::Result as JNIResult,
+    objects::{AutoLocal, JCharArray, JClass, JMethodID, JObject, JObjectArray, JString, JValue},
+    sys::{jint, jsize},
+    JNIEnv,
+};
+use once_cell::sync::OnceCell as JOnceLock;
+
+use crate::{
+    jni_utils::{read_char_array, throw_exception_from_result},
+    query::SourceText,
+    syntax_snapshot::SyntaxSnapshotDesc,
+    LanguageId,
+};
+
+use super::collect_identifiers;
+
+static LANGUAGE_IDENTIFIERS_CONSTRUCTOR: JOnceLock<JMethodID> = JOnceLock::new();
+
+struct LanguageIdentifiersDesc<'local> {
+    constructor: JMethodID,
+    class: AutoLocal<'local, JClass<'local>>,
+}
+
+impl<'local> LanguageIdentifiersDesc<'local> {
+    fn new(env: &mut JNIEnv<'local>) -> JNIResult<LanguageIdentifiersDesc<'local>> {
+        let class = env.find_class("com/hulylabs/treesitter/language/LanguageIdentifiers")?;
+        let constructor = *LANGUAGE_IDENTIFIERS_CONSTRUCTOR
+            .get_or_try_init(|| env.get_method_id(&class, "<init>", "(J[Ljava/lang/String;)V"))?;
+        Ok(LanguageIdentifiersDesc {
+            constructor,
+            class: env.auto_local(class),
+        })
+    }
+
+    fn to_java_object<'a>(
+        &self,
+        env: &mut JNIEnv<'local>,
+        language_id: LanguageId,
+        identifiers: impl ExactSizeIterator<Item = &'a str>,
+    ) -> JNIResult<JObject<'local>> {
+        let identifiers_array = env.new_object_array(
+            identifiers.len() as jsize,
+            "java/lang/String",
+            JString::default(),
+        )?;
+        let identifiers_array = env.auto_local(identifiers_array);
+        for (index, identifier) in identifiers.enumerate() {
+            let identifier = env.new_string(identifier)?;
+            let identifier = env.auto_local(identifier);
+            env.set_object_array_element(&identifiers_array, index as i32, &identifier)?;
+        }
+        // SAFETY: constructor is valid and derived from class by construction of self
+        unsafe {
+            env.new_object_unchecked(
+                &self.class,
+                self.constructor,
+                &[
+                    JValue::from(language_id).as_jni(),
+                    JValue::Object(&identifiers_array).as_jni(),
+                ],
+            )
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeIdentifiersProvider_nativeCollectIdentifiers<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    snapshot: JObject<'local>,
+    text: JCharArray<'local>,
+    start_offset: jint,
+    end_offset: jint,
+) -> JObjectArray<'local> {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        snapshot: JObject<'local>,
+        text: JCharArray<'local>,
+        start_offset: jint,
+        end_offset: jint,
+    ) -> JNIResult<JObjectArray<'local>> {
+        let snapshot = SyntaxSnapshotDesc::from_java_object(env, snapshot)?;
+        let identifiers_desc = LanguageIdentifiersDesc::new(env)?;
+        let text_buffer = read_char_array(env, &text)?;
+
+        let identifiers = collect_identifiers(
+            snapshot,
+            SourceText::Utf16(&text_buffer),
+            ((start_offset * 2) as usize)..((end_offset * 2) as usize),
+        );
+        let identifiers_array = env.new_object_array(
+            identifiers.len() as jsize,
+            &identifiers_desc.class,
+            JObject::null(),
+        )?;
+        for (index, (language_id, language_identifiers)) in identifiers.into_iter().enumerate() {
+            let obj = identifiers_desc.to_java_object(
+                env,
+                language_id,
+                language_identifiers.iter().map(AsRef::as_ref),
+            )?;
+            let obj = env.auto_local(obj);
+            env.set_object_array_element(&identifiers_array, index as i32, obj)?;
+        }
+        Ok(identifiers_array)
+    }
+    let result = inner(&mut env, snapshot, text, start_offset, end_offset);
+    throw_exception_from_result(&mut env, result)
+}
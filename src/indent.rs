@@ -0,0 +1,221 @@
+use std::collections::HashSet;
+
+use streaming_iterator::StreamingIterator as _;
+use tree_sitter as ts;
+
+use crate::{
+    predicates::AdditionalPredicates,
+    profiler,
+    query::{pooled_query_cursor, SourceText, SourceTextProvider},
+    syntax_snapshot::{SyntaxSnapshot, SyntaxSnapshotEntryContent},
+};
+
+#[cfg(feature = "jni")]
+mod jni_methods;
+
+#[derive(thiserror::Error, Debug)]
+pub enum IndentQueryError {
+    #[error("required captures not found")]
+    NoRequiredCaptures,
+}
+
+/// Query following tree-sitter indent convention:
+/// - `@indent.begin` nodes indent their lines after the first one;
+/// - `@indent.end` and `@indent.branch` nodes starting a line are put at the level of the
+///   enclosing construct, e.g. closing braces or `else` branches;
+/// - `@indent.dedent` nodes dedent their lines after the first one;
+/// - `@indent.align` nodes align their lines to the content following the opening delimiter if
+///   there is such content on the line of the delimiter, indent them otherwise.
+pub struct IndentQuery {
+    query: ts::Query,
+    predicates: AdditionalPredicates,
+    begin_capture_id: Option<u32>,
+    end_capture_id: Option<u32>,
+    branch_capture_id: Option<u32>,
+    dedent_capture_id: Option<u32>,
+    align_capture_id: Option<u32>,
+}
+
+impl IndentQuery {
+    pub fn new(
+        query: ts::Query,
+        predicates: AdditionalPredicates,
+    ) -> Result<Self, IndentQueryError> {
+        let begin_capture_id = query.capture_index_for_name("indent.begin");
+        let align_capture_id = query.capture_index_for_name("indent.align");
+        if begin_capture_id.is_none() && align_capture_id.is_none() {
+            return Err(IndentQueryError::NoRequiredCaptures);
+        }
+        Ok(IndentQuery {
+            begin_capture_id,
+            end_capture_id: query.capture_index_for_name("indent.end"),
+            branch_capture_id: query.capture_index_for_name("indent.branch"),
+            dedent_capture_id: query.capture_index_for_name("indent.dedent"),
+            align_capture_id,
+            query,
+            predicates,
+        })
+    }
+
+    pub fn capture_names(&self) -> &[&str] {
+        self.query.capture_names()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ComputedIndent {
+    /// Number of indent units
+    pub level: usize,
+    /// Byte column the line is aligned to instead of using `level`
+    pub align_column: Option<usize>,
+}
+
+/// Column of the content following opening delimiter of the node, if it is on the same line
+fn align_column(node: ts::Node) -> Option<usize> {
+    let open = node.child(0)?;
+    let content = open.next_sibling()?;
+    (content.start_position().row == open.start_position().row)
+        .then_some(content.start_position().column)
+}
+
+/// Indent of the line `row` computed from indent queries of all layers around its first
+/// non-whitespace character, or around its end for blank lines. `None` if text has fewer lines.
+pub fn compute_indent(
+    snapshot: &SyntaxSnapshot,
+    text: SourceText<'_>,
+    row: usize,
+) -> Option<ComputedIndent> {
+    let _profile = profiler::call(&snapshot.isolate, "indent");
+    let line_range = snapshot.line_index(text).line_byte_range(row)?;
+    let unit_size = text.unit_size();
+    let position = (line_range.start / unit_size..line_range.end / unit_size)
+        .find(|idx| !matches!(text.unit(*idx), 0x20 | 0x09 | 0x0d))
+        .map_or(line_range.end, |idx| idx * unit_size);
+    let text_provider = SourceTextProvider::new(text);
+    let mut begin_rows = HashSet::new();
+    let mut dedents = 0;
+    let mut closes_construct = false;
+    let mut align: Option<(usize, Option<usize>)> = None;
+    for (_, entry) in snapshot.intersecting_entries(position..(position + 1), true) {
+        let SyntaxSnapshotEntryContent::Parsed { language, tree } = &entry.content else {
+            continue;
+        };
+        let query = snapshot.isolate.with_language(*language, |language| {
+            language.parser_info().indent_rules_query.clone()
+        });
+        let Ok(Some(query)) = query else {
+            continue;
+        };
+        let mut cursor = pooled_query_cursor();
+        cursor.set_byte_range(position..(position + 1));
+        let mut matches = cursor.matches(
+            &query.query,
+            tree.root_node_with_offset(entry.byte_offset, entry.point_offset),
+            &text_provider,
+        );
+        while let Some(query_match) = matches.next() {
+            if !query
+                .predicates
+                .satisfies_predicates(&text_provider, query_match)
+            {
+                continue;
+            }
+            for capture in query_match.captures {
+                let node = capture.node;
+                let index = Some(capture.index);
+                let encloses_line = node.start_position().row < row && node.end_byte() > position;
+                if index == query.begin_capture_id && encloses_line {
+                    begin_rows.insert(node.start_position().row);
+                } else if index == query.dedent_capture_id && encloses_line {
+                    dedents += 1;
+                } else if (index == query.end_capture_id || index == query.branch_capture_id)
+                    && node.start_byte() == position
+                {
+                    closes_construct = true;
+                } else if index == query.align_capture_id
+                    && encloses_line
+                    && align.is_none_or(|(start, _)| node.start_byte() > start)
+                {
+                    let column = align_column(node);
+                    align = Some((node.start_byte(), column));
+                    if column.is_none() {
+                        begin_rows.insert(node.start_position().row);
+                    }
+                }
+            }
+        }
+    }
+    let level = begin_rows
+        .len()
+        .saturating_sub(dedents)
+        .saturating_sub(closes_construct as usize);
+    Some(ComputedIndent {
+        level,
+        align_column: align.and_then(|(_, column)| column),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{json_isolate, parse};
+
+    fn indent_levels(indent_query: &str, text: &str) -> Vec<Option<ComputedIndent>> {
+        let (isolate, language_id) = json_isolate();
+        isolate
+            .add_indent_rules_query(language_id, indent_query)
+            .unwrap();
+        let snapshot = parse(&isolate, language_id, text);
+        (0..=text.lines().count())
+            .map(|row| compute_indent(&snapshot, SourceText::Utf8(text), row))
+            .collect()
+    }
+
+    fn level(level: usize) -> Option<ComputedIndent> {
+        Some(ComputedIndent {
+            level,
+            align_column: None,
+        })
+    }
+
+    #[test]
+    fn nested_constructs_indent_and_closing_delimiters_dedent() {
+        let text = "{\n  \"a\": [\n    1,\n\n  ],\n  \"b\": {\"c\": [\n    3\n  ]}\n}";
+        let levels = indent_levels(
+            r#"[(object) (array)] @indent.begin ["}" "]"] @indent.end"#,
+            text,
+        );
+        assert_eq!(
+            levels,
+            vec![
+                level(0),
+                level(1),
+                level(2),
+                // Blank lines are indented by the constructs around their end
+                level(2),
+                level(1),
+                level(1),
+                // Constructs starting on the same line indent once
+                level(2),
+                level(1),
+                level(0),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn aligned_lines_follow_content_after_opening_delimiter() {
+        let text = "[1,\n 2,\n [\n   3]]";
+        let levels = indent_levels("(array) @indent.align", text);
+        assert_eq!(
+            levels[1],
+            Some(ComputedIndent {
+                level: 0,
+                align_column: Some(1),
+            })
+        );
+        // Nothing follows the inner opening bracket, so its lines are indented instead
+        assert_eq!(levels[3], level(1));
+    }
+}
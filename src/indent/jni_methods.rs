@@ -0,0 +1,65 @@
+use jni::{
+    errors::Result as JNIResult,
+    objects::{JCharArray, JClass, JMethodID, JObject, JValue},
+    sys::jint,
+    JNIEnv,
+};
+use once_cell::sync::OnceCell as JOnceLock;
+
+use crate::{
+    jni_utils::{read_char_array, throw_exception_from_result},
+    query::SourceText,
+    syntax_snapshot::SyntaxSnapshotDesc,
+};
+
+use super::compute_indent;
+
+static INDENT_CONSTRUCTOR: JOnceLock<JMethodID> = JOnceLock::new();
+
+/// Indent of the line as `Indent(level, alignColumn)`, align column is -1 if the line is not
+/// aligned. Returns null if text has fewer lines.
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeIndentProvider_nativeComputeIndent<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    snapshot: JObject<'local>,
+    text: JCharArray<'local>,
+    line: jint,
+) -> JObject<'local> {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        snapshot: JObject<'local>,
+        text: JCharArray<'local>,
+        line: jint,
+    ) -> JNIResult<JObject<'local>> {
+        let snapshot = SyntaxSnapshotDesc::from_java_object(env, snapshot)?;
+        let text_buffer = read_char_array(env, &text)?;
+
+        let Some(indent) = compute_indent(snapshot, SourceText::Utf16(&text_buffer), line as usize)
+        else {
+            return Ok(JObject::null());
+        };
+        let class = env.find_class("com/hulylabs/treesitter/language/Indent")?;
+        let class = env.auto_local(class);
+        let constructor =
+            *INDENT_CONSTRUCTOR.get_or_try_init(|| env.get_method_id(&class, "<init>", "(II)V"))?;
+        let align_column = indent
+            .align_column
+            .map_or(-1, |column| (column / 2) as jint);
+        // SAFETY: constructor is valid and derived from class above
+        unsafe {
+            env.new_object_unchecked(
+                &class,
+                constructor,
+                &[
+                    JValue::Int(indent.level as jint).as_jni(),
+                    JValue::Int(align_column).as_jni(),
+                ],
+            )
+        }
+    }
+    let result = inner(&mut env, snapshot, text, line);
+    throw_exception_from_result(&mut env, result)
+}
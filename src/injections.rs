@@ -9,7 +9,8 @@ use tree_sitter as ts;
 use crate::{
     language_registry::UnknownLanguage,
     predicates::AdditionalPredicates,
-    query::{CaptureOffset, RecodingUtf16TextProvider},
+    query::{CaptureOffset, MatchProperty, SourceText, SourceTextProvider},
+    query_budget,
 };
 
 #[derive(Debug, Default, Clone, PartialEq, Eq)]
@@ -36,6 +37,39 @@ struct InjectionInfo {
     include_children: bool,
 }
 
+/// Pushes parts of `range` not covered by named children of `node`, the way tree-sitter highlight
+/// treats injections without `injection.include-children`
+fn push_ranges_without_children(ranges: &mut Vec<ts::Range>, node: ts::Node, range: ts::Range) {
+    let mut start_byte = range.start_byte;
+    let mut start_point = range.start_point;
+    let mut cursor = node.walk();
+    for child in node.named_children(&mut cursor) {
+        if child.end_byte() <= start_byte {
+            continue;
+        }
+        if child.start_byte() >= range.end_byte {
+            break;
+        }
+        if child.start_byte() > start_byte {
+            ranges.push(ts::Range {
+                start_byte,
+                start_point,
+                end_byte: child.start_byte(),
+                end_point: child.start_position(),
+            });
+        }
+        start_byte = child.end_byte();
+        start_point = child.end_position();
+    }
+    if start_byte < range.end_byte {
+        ranges.push(ts::Range {
+            start_byte,
+            start_point,
+            ..range
+        });
+    }
+}
+
 pub struct InjectionQuery {
     query: ts::Query,
     predicates: AdditionalPredicates,
@@ -103,10 +137,11 @@ impl InjectionQuery {
         };
         for pattern_idx in 0..result.query.pattern_count() {
             let mut injection_info = InjectionInfo::default();
-            for setting in result.query.property_settings(pattern_idx) {
+            let metadata = result.predicates.metadata().pattern(pattern_idx);
+            for setting in metadata.properties() {
                 match setting.key.deref() {
                     "injection.language" => {
-                        let ts::QueryProperty {
+                        let MatchProperty {
                             key: _,
                             capture_id: None,
                             value: Some(ref language_name),
@@ -131,7 +166,7 @@ impl InjectionQuery {
                         );
                     }
                     "injection.combined" => {
-                        let ts::QueryProperty {
+                        let MatchProperty {
                             key: _,
                             capture_id: None,
                             value: None,
@@ -145,7 +180,7 @@ impl InjectionQuery {
                         injection_info.combined = true;
                     }
                     "injection.include-children" => {
-                        let ts::QueryProperty {
+                        let MatchProperty {
                             key: _,
                             capture_id: None,
                             value: None,
@@ -176,7 +211,7 @@ impl InjectionQuery {
                             };
                             injection_info
                                 .offsets
-                                .insert(*capture_id, CaptureOffset::new(arg1 * 2, arg2 * 2));
+                                .insert(*capture_id, CaptureOffset::new(arg1, arg2));
                         }
                         _ => {
                             return Err(InjectionQueryError::InvalidPredicate(
@@ -192,25 +227,36 @@ impl InjectionQuery {
         Ok(result)
     }
 
+    pub fn capture_names(&self) -> &[&str] {
+        self.query.capture_names()
+    }
+
     pub fn collect_injections(
         &self,
         node: tree_sitter::Node,
-        text: &[u16],
+        text: SourceText<'_>,
         changed_byte_ranges: &[std::ops::Range<usize>],
     ) -> Vec<InjectionMatch> {
+        // Runs while parsing, where limits are lifted by `query_budget::exempt`
         let mut query_cursor = ts::QueryCursor::new();
-        let text_provider = RecodingUtf16TextProvider::new(text);
+        let text_provider = SourceTextProvider::new(text);
+        let unit_size = text.unit_size();
         let mut injections: Vec<InjectionMatch> = Vec::new();
         let mut injection_ranges: HashMap<Range<usize>, usize> = HashMap::new();
         for change_byte_range in changed_byte_ranges {
+            if query_budget::is_exhausted() {
+                break;
+            }
             query_cursor.set_byte_range(
-                change_byte_range.start.saturating_sub(2)..(change_byte_range.end + 2),
+                change_byte_range.start.saturating_sub(unit_size)
+                    ..(change_byte_range.end + unit_size),
             );
+            query_budget::prepare_cursor(&mut query_cursor);
             let mut matches = query_cursor.matches(&self.query, node, &text_provider);
             while let Some(query_match) = matches.next() {
                 if !self
                     .predicates
-                    .satisfies_predicates(&mut &text_provider, query_match)
+                    .satisfies_predicates(&text_provider, query_match)
                 {
                     continue;
                 }
@@ -219,23 +265,23 @@ impl InjectionQuery {
                 let mut query_language: Option<UnknownLanguage> = None;
                 for capture in query_match.captures.iter() {
                     let range = if let Some(offset) = info.offsets.get(&capture.index) {
-                        offset.apply_to_range(&capture.node.range())
+                        offset.apply_to_range(&capture.node.range(), text)
                     } else {
                         capture.node.range()
                     };
                     if self.injection_content_capture_id == capture.index {
-                        query_ranges.push(range);
+                        if info.include_children {
+                            query_ranges.push(range);
+                        } else {
+                            push_ranges_without_children(&mut query_ranges, capture.node, range);
+                        }
                     }
                     if self.injection_language_capture_id == Some(capture.index) {
-                        let language = String::from_utf16_lossy(
-                            &text[(range.start_byte / 2)..(range.end_byte / 2)],
-                        );
+                        let language = text.text_for_byte_range(range.start_byte..range.end_byte);
                         query_language = Some(UnknownLanguage::LanguageName(language.into()));
                     }
                     if self.injection_mimetype_capture_id == Some(capture.index) {
-                        let mimetype = String::from_utf16_lossy(
-                            &text[(range.start_byte / 2)..(range.end_byte / 2)],
-                        );
+                        let mimetype = text.text_for_byte_range(range.start_byte..range.end_byte);
                         query_language = Some(UnknownLanguage::LanguageMimetype(mimetype.into()));
                     }
                 }
@@ -275,6 +321,7 @@ impl InjectionQuery {
                     });
                 }
             }
+            query_budget::finish_cursor(&query_cursor);
         }
         injections
     }
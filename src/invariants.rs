@@ -0,0 +1,124 @@
+use std::{ops::Range, sync::atomic::Ordering};
+
+use crate::{
+    logging::{log, LogLevel},
+    HighlightToken, Isolate, SyntaxSnapshot,
+};
+
+/// Debug mode of the isolate validating results of each operation, violations are reported
+/// through the logging bridge
+fn is_enabled(isolate: &Isolate) -> bool {
+    isolate.options.invariants.load(Ordering::Relaxed)
+}
+
+fn report(operation: &str, violation: impl std::fmt::Display) {
+    log(
+        LogLevel::Error,
+        format_args!("invariant violated after {operation}: {violation}"),
+    );
+}
+
+/// Entries are sorted by depth, entries of the same depth are sorted and do not overlap, all
+/// entries lie within the document
+pub(crate) fn check_snapshot(snapshot: &SyntaxSnapshot, byte_len: usize, operation: &str) {
+    if !is_enabled(&snapshot.isolate) {
+        return;
+    }
+    let Some(base) = snapshot.entries.first() else {
+        report(operation, "snapshot has no entries");
+        return;
+    };
+    if base.depth != 0 || base.byte_range != (0..byte_len) {
+        report(
+            operation,
+            format_args!(
+                "base entry covers {:?} at depth {} instead of whole document {:?}",
+                base.byte_range,
+                base.depth,
+                0..byte_len
+            ),
+        );
+    }
+    for (idx, entry) in snapshot.entries.iter().enumerate() {
+        if entry.byte_range.start > entry.byte_range.end || entry.byte_range.end > byte_len {
+            report(
+                operation,
+                format_args!(
+                    "entry {idx} range {:?} is out of document bounds {:?}",
+                    entry.byte_range,
+                    0..byte_len
+                ),
+            );
+        }
+        let Some(prev) = idx
+            .checked_sub(1)
+            .map(|prev_idx| &snapshot.entries[prev_idx])
+        else {
+            continue;
+        };
+        if prev.depth > entry.depth {
+            report(
+                operation,
+                format_args!(
+                    "entry {idx} at depth {} follows entry at depth {}",
+                    entry.depth, prev.depth
+                ),
+            );
+        } else if prev.depth == entry.depth && prev.byte_range.end > entry.byte_range.start {
+            report(
+                operation,
+                format_args!(
+                    "entry {idx} range {:?} overlaps or precedes {:?} at depth {}",
+                    entry.byte_range, prev.byte_range, entry.depth
+                ),
+            );
+        }
+    }
+}
+
+/// Tokens start at or before requested range and cover it up to the document end
+pub(crate) fn check_tokens(
+    isolate: &Isolate,
+    start_offset: usize,
+    tokens: &[HighlightToken],
+    range: &Range<usize>,
+    len: usize,
+    operation: &str,
+) {
+    if !is_enabled(isolate) {
+        return;
+    }
+    let covered_end = start_offset + tokens.iter().map(|t| t.length as usize).sum::<usize>();
+    if start_offset > range.start || covered_end < range.end.min(len) || covered_end > len {
+        report(
+            operation,
+            format_args!(
+                "tokens cover {:?} for requested {range:?} in document of length {len}",
+                start_offset..covered_end
+            ),
+        );
+    }
+}
+
+pub(crate) fn check_ranges<'a>(
+    isolate: &Isolate,
+    ranges: impl IntoIterator<Item = &'a tree_sitter::Range>,
+    byte_len: usize,
+    operation: &str,
+) {
+    if !is_enabled(isolate) {
+        return;
+    }
+    for range in ranges {
+        if range.start_byte > range.end_byte || range.end_byte > byte_len {
+            report(
+                operation,
+                format_args!(
+                    "range {:?} is out of document bounds {:?}",
+                    range.start_byte..range.end_byte,
+                    0..byte_len
+                ),
+            );
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Arc, LazyLock, Mutex, RwLock,
+    },
+};
+
+use crate::{
+    config::IsolateOptions,
+    language_registry::{
+        LanguageError, LanguageRegistry, UnknownLanguage, UnknownLanguageListener,
+    },
+    profiler,
+    syntax_snapshot::{ParseBuffersPool, ParsersPool},
+    textmate_scopes::TextMateScopes,
+    user_query::UserQueryCache,
+    Document, Language, LanguageId,
+};
+
+#[cfg(feature = "jni")]
+mod jni_methods;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(transparent)]
+pub struct IsolateId(i64);
+
+impl From<i64> for IsolateId {
+    fn from(value: i64) -> Self {
+        Self(value)
+    }
+}
+
+impl From<IsolateId> for i64 {
+    fn from(value: IsolateId) -> Self {
+        value.0
+    }
+}
+
+#[cfg(feature = "jni")]
+impl<O> From<IsolateId> for jni::objects::JValueGen<O> {
+    fn from(value: IsolateId) -> Self {
+        jni::objects::JValueGen::Long(value.0)
+    }
+}
+
+static ISOLATE_ID_COUNTER: AtomicI64 = AtomicI64::new(0);
+static ISOLATES: LazyLock<RwLock<HashMap<IsolateId, Arc<Isolate>>>> =
+    LazyLock::new(RwLock::default);
+
+#[derive(thiserror::Error, Debug)]
+pub enum IsolateError {
+    #[error("unknown isolate")]
+    InvalidIsolateId,
+}
+
+/// Owner of all native state belonging to a single Java-side user of the library (normally one
+/// per classloader). Snapshots keep their isolate alive, so destroying an isolate only drops the
+/// state once no snapshot references it anymore.
+pub struct Isolate {
+    id: IsolateId,
+    registry: RwLock<LanguageRegistry>,
+    pub(crate) parsers_pool: ParsersPool,
+    pub(crate) parse_buffers: ParseBuffersPool,
+    pub(crate) user_queries: Mutex<UserQueryCache>,
+    pub(crate) textmate_scopes: RwLock<TextMateScopes>,
+    pub(crate) unknown_language_listener: RwLock<Option<Arc<UnknownLanguageListener>>>,
+    pub(crate) options: IsolateOptions,
+    pub(crate) profiler: profiler::Recorder,
+    /// Bumped whenever highlight or locals queries of any language change, so highlight results
+    /// cached in snapshots are not reused
+    highlight_queries_generation: AtomicU64,
+}
+
+impl Isolate {
+    pub fn create() -> Arc<Isolate> {
+        let id = IsolateId(ISOLATE_ID_COUNTER.fetch_add(1, Ordering::SeqCst));
+        let isolate = Arc::new(Isolate {
+            id,
+            registry: RwLock::default(),
+            parsers_pool: ParsersPool::default(),
+            parse_buffers: ParseBuffersPool::default(),
+            user_queries: Mutex::default(),
+            textmate_scopes: RwLock::default(),
+            unknown_language_listener: RwLock::default(),
+            options: IsolateOptions::default(),
+            profiler: profiler::Recorder::default(),
+            highlight_queries_generation: AtomicU64::new(0),
+        });
+        ISOLATES.write().unwrap().insert(id, Arc::clone(&isolate));
+        isolate
+    }
+
+    pub fn get(id: IsolateId) -> Result<Arc<Isolate>, IsolateError> {
+        ISOLATES
+            .read()
+            .unwrap()
+            .get(&id)
+            .cloned()
+            .ok_or(IsolateError::InvalidIsolateId)
+    }
+
+    /// Forgets the isolate and closes its documents, it is dropped once snapshots still held by
+    /// callers are released
+    pub fn destroy(id: IsolateId) -> Result<(), IsolateError> {
+        ISOLATES
+            .write()
+            .unwrap()
+            .remove(&id)
+            .ok_or(IsolateError::InvalidIsolateId)?;
+        Document::close_isolate_documents(id);
+        Ok(())
+    }
+
+    #[cfg(feature = "jni")]
+    pub(crate) fn destroy_all() {
+        ISOLATES.write().unwrap().clear();
+        Document::close_all();
+    }
+
+    pub fn id(&self) -> IsolateId {
+        self.id
+    }
+
+    pub(crate) fn highlight_queries_generation(&self) -> u64 {
+        self.highlight_queries_generation.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn highlight_queries_changed(&self) {
+        self.highlight_queries_generation
+            .fetch_add(1, Ordering::AcqRel);
+    }
+
+    pub fn register_language(&self, name: &str, ts_language: tree_sitter::Language) -> LanguageId {
+        self.registry
+            .write()
+            .unwrap()
+            .register_language(name, ts_language)
+    }
+
+    pub fn with_language<T>(
+        &self,
+        language_id: LanguageId,
+        f: impl FnOnce(&Language) -> T,
+    ) -> Result<T, LanguageError> {
+        let registry = self.registry.read().unwrap();
+        let language = registry
+            .language(language_id)
+            .ok_or(LanguageError::InvalidLanguageId)?;
+        Ok(f(language))
+    }
+
+    pub fn with_language_by_name<T>(
+        &self,
+        language_name: impl AsRef<str>,
+        f: impl FnOnce(&Language) -> T,
+    ) -> Result<T, LanguageError> {
+        let registry = self.registry.read().unwrap();
+        let language = registry
+            .language_by_name(language_name.as_ref())
+            .ok_or(LanguageError::InvalidLanguageId)?;
+        Ok(f(language))
+    }
+
+    pub fn with_unknown_language<T>(
+        &self,
+        language: &UnknownLanguage,
+        f: impl FnOnce(&Language) -> T,
+    ) -> Result<T, LanguageError> {
+        if let UnknownLanguage::LanguageName(name) = language {
+            self.with_language_by_name(name, f)
+        } else {
+            Err(LanguageError::InvalidLanguageId)
+        }
+    }
+}
@@ -0,0 +1,30 @@
+use jni::{objects::JClass, JNIEnv};
+
+use super::{Isolate, IsolateId};
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeIsolate_nativeCreate<
+    'local,
+>(
+    _env: JNIEnv<'local>,
+    _class: JClass<'local>,
+) -> IsolateId {
+    Isolate::create().id()
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeIsolate_nativeDestroy<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    isolate_id: IsolateId,
+) {
+    if let Err(err) = Isolate::destroy(isolate_id) {
+        env.throw_new(
+            "java/lang/IllegalStateException",
+            format!("Failed to destroy isolate: {err}"),
+        )
+        .unwrap();
+    }
+}
@@ -1,11 +1,26 @@
+use std::{
+    ops::{Deref, DerefMut, Range},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
 use jni::{
     errors::{Error as JNIError, Result as JNIResult},
-    objects::{AutoLocal, JClass, JMethodID, JObject, JValue},
+    objects::{
+        AutoLocal, JByteArray, JCharArray, JClass, JMethodID, JObject, JObjectArray, JString,
+        JValue, ReleaseMode,
+    },
     signature::{Primitive, ReturnType},
+    sys::jint,
     JNIEnv,
 };
 use once_cell::sync::OnceCell as JOnceLock;
 
+use crate::{
+    query::{SourceText, Utf16Chunks},
+    syntax_snapshot::SyntaxSnapshot,
+};
+
 pub fn throw_exception_from_result<T: Default>(env: &mut JNIEnv<'_>, result: JNIResult<T>) -> T {
     match result {
         Ok(val) => val,
@@ -21,6 +36,182 @@ pub fn throw_exception_from_result<T: Default>(env: &mut JNIEnv<'_>, result: JNI
     }
 }
 
+pub fn read_string_array(env: &mut JNIEnv<'_>, array: &JObjectArray<'_>) -> JNIResult<Vec<String>> {
+    let length = env.get_array_length(array)?;
+    let mut strings = Vec::with_capacity(length as usize);
+    for index in 0..length {
+        let string: JString = env.get_object_array_element(array, index)?.into();
+        let string = env.auto_local(string);
+        strings.push(env.get_string(&string)?.into());
+    }
+    Ok(strings)
+}
+
+/// Pooled buffers unused for longer than this are released instead of being reused
+const SCRATCH_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_POOLED_SCRATCH_BUFFERS: usize = 8;
+
+/// Buffers for copies of java arrays, so calls on large documents do not allocate a copy of the
+/// whole text every time
+pub struct ScratchPool<T: 'static> {
+    buffers: Mutex<Vec<(Vec<T>, Instant)>>,
+}
+
+static CHAR_SCRATCH_POOL: ScratchPool<u16> = ScratchPool::new();
+static BYTE_SCRATCH_POOL: ScratchPool<i8> = ScratchPool::new();
+
+impl<T: Copy + Default> ScratchPool<T> {
+    const fn new() -> Self {
+        ScratchPool {
+            buffers: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn take(&'static self, len: usize) -> ScratchBuffer<T> {
+        let mut buffer = {
+            let mut buffers = self.buffers.lock().unwrap();
+            let now = Instant::now();
+            buffers.retain(|(_, last_used)| now.duration_since(*last_used) < SCRATCH_IDLE_TIMEOUT);
+            // Prefer the smallest buffer which fits, otherwise grow the largest one
+            let fitting = buffers
+                .iter()
+                .enumerate()
+                .filter(|(_, (buffer, _))| buffer.capacity() >= len)
+                .min_by_key(|(_, (buffer, _))| buffer.capacity())
+                .or_else(|| {
+                    buffers
+                        .iter()
+                        .enumerate()
+                        .max_by_key(|(_, (buffer, _))| buffer.capacity())
+                })
+                .map(|(idx, _)| idx);
+            fitting.map_or_else(Vec::new, |idx| buffers.swap_remove(idx).0)
+        };
+        buffer.clear();
+        buffer.resize(len, T::default());
+        ScratchBuffer { buffer, pool: self }
+    }
+
+    fn put(&self, buffer: Vec<T>) {
+        let mut buffers = self.buffers.lock().unwrap();
+        if buffers.len() < MAX_POOLED_SCRATCH_BUFFERS {
+            buffers.push((buffer, Instant::now()));
+        }
+    }
+}
+
+/// Buffer borrowed from a [`ScratchPool`], returned to the pool on drop
+pub struct ScratchBuffer<T: Copy + Default + 'static> {
+    buffer: Vec<T>,
+    pool: &'static ScratchPool<T>,
+}
+
+impl<T: Copy + Default> Deref for ScratchBuffer<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        &self.buffer
+    }
+}
+
+impl<T: Copy + Default> DerefMut for ScratchBuffer<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        &mut self.buffer
+    }
+}
+
+impl<T: Copy + Default> Drop for ScratchBuffer<T> {
+    fn drop(&mut self) {
+        self.pool.put(std::mem::take(&mut self.buffer));
+    }
+}
+
+/// Copies whole char array into a pooled buffer
+pub fn read_char_array(
+    env: &mut JNIEnv<'_>,
+    array: &JCharArray<'_>,
+) -> JNIResult<ScratchBuffer<u16>> {
+    let length = env.get_array_length(array)?;
+    let mut buffer = CHAR_SCRATCH_POOL.take(length as usize);
+    env.get_char_array_region(array, 0, &mut buffer)?;
+    Ok(buffer)
+}
+
+/// Copies whole byte array into a pooled buffer
+pub fn read_byte_array(
+    env: &mut JNIEnv<'_>,
+    array: &JByteArray<'_>,
+) -> JNIResult<ScratchBuffer<i8>> {
+    let length = env.get_array_length(array)?;
+    let mut buffer = BYTE_SCRATCH_POOL.take(length as usize);
+    env.get_byte_array_region(array, 0, &mut buffer)?;
+    Ok(buffer)
+}
+
+/// Text argument of a native call, either a whole `char[]` or a `char[][]` of its chunks (e.g.
+/// leaves of an editor rope) which spares java from flattening the document
+pub enum JavaText<'local> {
+    Array(JCharArray<'local>),
+    Chunks(JObjectArray<'local>),
+}
+
+/// Runs `f` over the text, chunks are accessed in place when JVM allows it, so java must not
+/// modify them until `f` returns
+pub fn with_java_text<'local, T>(
+    env: &mut JNIEnv<'local>,
+    text: &JavaText<'_>,
+    f: impl FnOnce(&mut JNIEnv<'local>, SourceText<'_>) -> JNIResult<T>,
+) -> JNIResult<T> {
+    match text {
+        JavaText::Array(array) => {
+            let text_buffer = read_char_array(env, array)?;
+            f(env, SourceText::Utf16(&text_buffer))
+        }
+        JavaText::Chunks(chunks) => {
+            let length = env.get_array_length(chunks)?;
+            let mut arrays = Vec::with_capacity(length as usize);
+            for index in 0..length {
+                let array: JCharArray = env.get_object_array_element(chunks, index)?.into();
+                arrays.push(env.auto_local(array));
+            }
+            let elements = arrays
+                .iter()
+                .map(|array| {
+                    // SAFETY: elements are only read and are released before arrays
+                    unsafe { env.get_array_elements(&**array, ReleaseMode::NoCopyBack) }
+                })
+                .collect::<JNIResult<Vec<_>>>()?;
+            let text = Utf16Chunks::new(elements.iter().map(|elements| &**elements));
+            f(env, SourceText::Utf16Chunks(&text))
+        }
+    }
+}
+
+/// Range argument of a native call, either char offsets or points with columns in chars (as read
+/// by [`PointDesc::from_java_object`]) for editors with line-based ranges
+pub enum JavaTextRange {
+    Offsets(jint, jint),
+    Points(tree_sitter::Point, tree_sitter::Point),
+}
+
+impl JavaTextRange {
+    /// Range of `text` of the `snapshot` in code units
+    pub fn unit_range(&self, snapshot: &SyntaxSnapshot, text: SourceText<'_>) -> Range<usize> {
+        match self {
+            JavaTextRange::Offsets(start_offset, end_offset) => {
+                (*start_offset as usize)..(*end_offset as usize)
+            }
+            JavaTextRange::Points(start_point, end_point) => {
+                let unit_size = text.unit_size();
+                let line_index = snapshot.line_index(text);
+                let start = line_index.byte_for_point(*start_point) / unit_size;
+                let end = line_index.byte_for_point(*end_point) / unit_size;
+                start..end.max(start)
+            }
+        }
+    }
+}
+
 static POINT_METHODS: JOnceLock<PointMethods> = JOnceLock::new();
 
 struct PointMethods {
@@ -35,7 +226,7 @@ pub struct PointDesc<'local> {
 }
 
 impl<'local> PointDesc<'local> {
-    fn new(env: &mut JNIEnv<'local>) -> JNIResult<PointDesc<'local>> {
+    pub fn new(env: &mut JNIEnv<'local>) -> JNIResult<PointDesc<'local>> {
         let class = env.find_class("com/hulylabs/treesitter/language/Point")?;
         PointDesc::from_class(env, class)
     }
@@ -0,0 +1,84 @@
+use tree_sitter as ts;
+
+use crate::{isolate::Isolate, query::SourceText, LanguageId};
+
+#[cfg(feature = "jni")]
+mod jni_methods;
+
+/// Only this many code units from the start of text are parsed by each candidate
+const DETECTION_PREFIX_LENGTH: usize = 16 * 1024;
+
+/// Parse quality of a candidate, lower is better
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct TrialScore {
+    error_nodes: usize,
+    error_bytes: usize,
+}
+
+fn trial_score(tree: &ts::Tree) -> TrialScore {
+    let mut score = TrialScore {
+        error_nodes: 0,
+        error_bytes: 0,
+    };
+    let mut cursor = tree.walk();
+    loop {
+        let node = cursor.node();
+        if node.is_error() || node.is_missing() {
+            score.error_nodes += 1;
+            score.error_bytes += node.byte_range().len();
+        } else if node.has_error() && cursor.goto_first_child() {
+            continue;
+        }
+        while !cursor.goto_next_sibling() {
+            if !cursor.goto_parent() {
+                return score;
+            }
+        }
+    }
+}
+
+/// Bounded prefix of text ending at a line break when possible
+fn detection_prefix_byte_len(text: SourceText<'_>) -> usize {
+    if text.len() <= DETECTION_PREFIX_LENGTH {
+        return text.byte_len();
+    }
+    let prefix_len = (0..DETECTION_PREFIX_LENGTH)
+        .rev()
+        .find(|idx| text.unit(*idx) == '\n' as u16)
+        .map_or(DETECTION_PREFIX_LENGTH, |idx| idx + 1);
+    prefix_len * text.unit_size()
+}
+
+impl Isolate {
+    /// Parses prefix of text with every candidate language and returns the one producing fewest
+    /// errors, earlier candidates win ties. Returns `None` if no candidate could parse the text.
+    pub fn detect_language_by_content(
+        &self,
+        text: SourceText<'_>,
+        candidates: &[LanguageId],
+    ) -> Option<LanguageId> {
+        let prefix_byte_len = detection_prefix_byte_len(text);
+        let mut best: Option<(TrialScore, LanguageId)> = None;
+        for language_id in candidates {
+            let Ok(ts_language) =
+                self.with_language(*language_id, |language| language.ts_language())
+            else {
+                continue;
+            };
+            let tree = self
+                .parsers_pool
+                .with_parser(*language_id, &ts_language, |parser| {
+                    text.parse(parser, 0..prefix_byte_len, None)
+                })
+                .flatten();
+            let Some(tree) = tree else {
+                continue;
+            };
+            let score = trial_score(&tree);
+            if best.is_none_or(|(best_score, _)| score < best_score) {
+                best = Some((score, *language_id));
+            }
+        }
+        best.map(|(_, language_id)| language_id)
+    }
+}
@@ -0,0 +1,47 @@
+use jni::{
+    errors::Result as JNIResult,
+    objects::{JCharArray, JClass, JLongArray},
+    sys::jlong,
+    JNIEnv,
+};
+
+use crate::{
+    isolate::{Isolate, IsolateId},
+    jni_utils::{read_char_array, throw_exception_from_result},
+    query::SourceText,
+    LanguageId,
+};
+
+/// Returns id of the best matching candidate or -1 if none of them could parse the text
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeLanguageRegistry_nativeDetectLanguageByContent<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    isolate_id: IsolateId,
+    text: JCharArray<'local>,
+    candidate_language_ids: JLongArray<'local>,
+) -> jlong {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        isolate_id: IsolateId,
+        text: JCharArray<'local>,
+        candidate_language_ids: JLongArray<'local>,
+    ) -> JNIResult<jlong> {
+        let Ok(isolate) = Isolate::get(isolate_id) else {
+            env.throw_new("java/lang/IllegalStateException", "unknown isolate")?;
+            return Ok(LanguageId::UNKNOWN.into());
+        };
+        let text_buffer = read_char_array(env, &text)?;
+        let mut candidates = vec![0i64; env.get_array_length(&candidate_language_ids)? as usize];
+        env.get_long_array_region(&candidate_language_ids, 0, &mut candidates)?;
+        let candidates: Vec<LanguageId> = candidates.into_iter().map(LanguageId::from).collect();
+        Ok(isolate
+            .detect_language_by_content(SourceText::Utf16(&text_buffer), &candidates)
+            .unwrap_or(LanguageId::UNKNOWN)
+            .into())
+    }
+    let result = inner(&mut env, isolate_id, text, candidate_language_ids);
+    throw_exception_from_result(&mut env, result)
+}
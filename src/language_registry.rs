@@ -1,74 +1,139 @@
 use std::{
-    borrow::Cow,
-    mem::transmute,
-    ops::{Deref, DerefMut},
+    collections::HashMap,
+    ops::{Deref, DerefMut, Range},
     str,
-    sync::{
-        atomic::{AtomicI64, Ordering},
-        Arc, LazyLock, RwLock,
-    },
+    sync::Arc,
 };
 
 use bit_set::BitSet;
 use crossbeam_utils::sync::ShardedLock;
-use jni::{
-    errors::Error as JNIError,
-    objects::{JByteArray, JClass, JObject, JObjectArray, JString, JValueGen},
-    sys::{jlong, jsize},
-    JNIEnv,
-};
 use tree_sitter::Query;
 
 use crate::{
+    brackets::{BracketsQuery, BracketsQueryError},
+    char_pairs::CharPair,
+    indent::{IndentQuery, IndentQueryError},
     injections::InjectionQueryError,
-    predicates::{AdditionalPredicates, PREDICATE_PARSER},
+    isolate::{Isolate, IsolateError},
+    locals::{LocalsQuery, LocalsQueryError},
+    outline::{OutlineQuery, OutlineQueryError},
+    predicates::{AdditionalPredicates, PredicateMode, UnknownPredicate, PREDICATE_PARSER},
     ranges::RangesQueryError,
+    smart_enter::{SplitQuery, SplitQueryError},
+    tags::{TagsQuery, TagsQueryError},
     InjectionQuery, RangesQuery,
 };
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg(feature = "jni")]
+mod jni_methods;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[repr(transparent)]
-pub struct LanguageId(jlong);
+pub struct LanguageId(i64);
 
-impl From<jlong> for LanguageId {
-    fn from(value: jlong) -> Self {
+impl From<i64> for LanguageId {
+    fn from(value: i64) -> Self {
         Self(value)
     }
 }
 
-impl From<LanguageId> for jlong {
+impl From<LanguageId> for i64 {
     fn from(value: LanguageId) -> Self {
         value.0
     }
 }
 
-impl<O> From<LanguageId> for JValueGen<O> {
+#[cfg(feature = "jni")]
+impl<O> From<LanguageId> for jni::objects::JValueGen<O> {
     fn from(value: LanguageId) -> Self {
-        JValueGen::Long(value.0)
+        jni::objects::JValueGen::Long(value.0)
     }
 }
 
-static LANGUAGE_ID_COUNTER: AtomicI64 = AtomicI64::new(0);
-static LANGUAGE_REGISTRY: LazyLock<RwLock<LanguageRegistry>> = LazyLock::new(RwLock::default);
-
 impl LanguageId {
     pub const UNKNOWN: LanguageId = LanguageId(-1);
-    fn new() -> LanguageId {
-        LanguageId(LANGUAGE_ID_COUNTER.fetch_add(1, Ordering::SeqCst))
-    }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum UnknownLanguage {
     LanguageName(Box<str>),
     LanguageMimetype(Box<str>),
 }
 
+/// Called with the language and range of injections whose language is not registered, on the
+/// thread running the parse. Range is in code units of the parsed text, i.e. UTF-16 units or bytes.
+pub type UnknownLanguageListener = Box<dyn Fn(&UnknownLanguage, Range<usize>) + Send + Sync>;
+
+/// Kind of the queries registered for a language
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum QueryRole {
+    Highlights,
+    Folds,
+    Indents,
+    Formats,
+    Contexts,
+    Injections,
+    Locals,
+    Tags,
+    Splits,
+    Brackets,
+    Outline,
+    IndentRules,
+    Blocks,
+    Statements,
+}
+
+impl QueryRole {
+    /// Role by the name of the query file, e.g. `highlights` for `highlights.scm`
+    pub fn from_name(name: &str) -> Option<QueryRole> {
+        Some(match name {
+            "highlights" => QueryRole::Highlights,
+            "folds" => QueryRole::Folds,
+            "indents" => QueryRole::Indents,
+            "formats" => QueryRole::Formats,
+            "contexts" => QueryRole::Contexts,
+            "injections" => QueryRole::Injections,
+            "locals" => QueryRole::Locals,
+            "tags" => QueryRole::Tags,
+            "splits" => QueryRole::Splits,
+            "brackets" => QueryRole::Brackets,
+            "outline" => QueryRole::Outline,
+            "indent_rules" => QueryRole::IndentRules,
+            "blocks" => QueryRole::Blocks,
+            "statements" => QueryRole::Statements,
+            _ => return None,
+        })
+    }
+}
+
+/// Highlight query along with its additional predicates and mask of highlight captures
+pub(crate) type HighlightsQuery = (Query, AdditionalPredicates, BitSet);
+
 pub struct LanguageParserInfo {
-    pub(crate) highlights_query: Option<Arc<(tree_sitter::Query, AdditionalPredicates, BitSet)>>,
-    pub(crate) folds_query: Option<Arc<RangesQuery>>,
-    pub(crate) indents_query: Option<Arc<RangesQuery>>,
+    pub(crate) highlights_query: Option<Arc<HighlightsQuery>>,
+    /// Sources of highlight query layers sorted by layer order, `highlights_query` is compiled
+    /// from all of them so patterns of later layers take precedence
+    pub(crate) highlight_query_layers: Vec<(i32, Box<str>)>,
+    /// Ranges queries by name, built-in ones are `folds`, `indents`, `formats` and `contexts`
+    pub(crate) ranges_queries: HashMap<Box<str>, Arc<RangesQuery>>,
     pub(crate) injections_query: Option<Arc<InjectionQuery>>,
+    pub(crate) locals_query: Option<Arc<LocalsQuery>>,
+    pub(crate) tags_query: Option<Arc<TagsQuery>>,
+    pub(crate) splits_query: Option<Arc<SplitQuery>>,
+    pub(crate) brackets_query: Option<Arc<BracketsQuery>>,
+    pub(crate) outline_query: Option<Arc<OutlineQuery>>,
+    pub(crate) indent_rules_query: Option<Arc<IndentQuery>>,
+    pub(crate) char_pairs: Option<Arc<[CharPair]>>,
+    /// Theme mapping of highlight capture names to editor highlight groups
+    pub(crate) highlight_groups: Option<Arc<HashMap<Box<str>, u16>>>,
+    /// Injections of the language with more bytes of text are left unparsed
+    pub(crate) max_injection_size: Option<usize>,
+}
+
+impl LanguageParserInfo {
+    pub(crate) fn ranges_query(&self, name: &str) -> Option<Arc<RangesQuery>> {
+        self.ranges_queries.get(name).cloned()
+    }
 }
 
 pub struct Language {
@@ -103,9 +168,41 @@ impl Language {
 #[derive(Default)]
 pub struct LanguageRegistry {
     languages: Vec<Language>,
+    next_language_id: i64,
 }
 
 impl LanguageRegistry {
+    pub fn register_language(
+        &mut self,
+        name: &str,
+        ts_language: tree_sitter::Language,
+    ) -> LanguageId {
+        let id = LanguageId(self.next_language_id);
+        self.next_language_id += 1;
+        let parser_info = ShardedLock::new(LanguageParserInfo {
+            highlights_query: None,
+            highlight_query_layers: Vec::new(),
+            ranges_queries: HashMap::new(),
+            injections_query: None,
+            locals_query: None,
+            tags_query: None,
+            splits_query: None,
+            brackets_query: None,
+            outline_query: None,
+            indent_rules_query: None,
+            char_pairs: None,
+            highlight_groups: None,
+            max_injection_size: None,
+        });
+        self.languages.push(Language {
+            id,
+            name: name.into(),
+            ts_language: Arc::new(ts_language),
+            parser_info,
+        });
+        id
+    }
+
     pub fn language(&self, language_id: LanguageId) -> Option<&Language> {
         self.languages.iter().find(|l| l.id == language_id)
     }
@@ -117,307 +214,671 @@ impl LanguageRegistry {
     }
 }
 
-#[no_mangle]
-pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeLanguageRegistry_nativeRegisterLanguage<
-    'local,
->(
-    mut env: JNIEnv<'local>,
-    _class: JClass<'local>,
-    name: JString<'local>,
-    language: JObject<'local>,
-) -> LanguageId {
-    let name = env
-        .get_string(&name)
-        .expect("valid string from java interface");
-    let name: Cow<'_, str> = (&name).into();
-    let language_handle = env
-        .call_method(&language, "getPtr", "()J", &[])
-        .expect("TSLanguage has getPtr method")
-        .j()
-        .expect("getPtr returns long");
-    let ts_language = language_handle as *const tree_sitter::ffi::TSLanguage;
-    // SAFETY: TSParser language from java has valid language_handle from linked tree-sitter
-    let ts_language = unsafe {
-        // Copy language so it can be freed by rust
-        let ts_language = tree_sitter::ffi::ts_language_copy(ts_language);
-        tree_sitter::Language::from_raw(ts_language)
-    };
-    let id = LanguageId::new();
-    let parser_info = ShardedLock::new(LanguageParserInfo {
-        highlights_query: None,
-        folds_query: None,
-        indents_query: None,
-        injections_query: None,
-    });
-
-    let mut registry = LANGUAGE_REGISTRY.write().unwrap();
-    registry.languages.push(Language {
-        id,
-        name: name.into(),
-        ts_language: Arc::new(ts_language),
-        parser_info,
-    });
-    id
-}
-
 #[derive(thiserror::Error, Debug)]
 pub enum LanguageError {
     #[error("unknown language")]
     InvalidLanguageId,
 }
 
-pub fn with_language<T>(
-    language_id: LanguageId,
-    f: impl FnOnce(&Language) -> T,
-) -> Result<T, LanguageError> {
-    let registry = LANGUAGE_REGISTRY.read().unwrap();
-    let language = registry
-        .language(language_id)
-        .ok_or(LanguageError::InvalidLanguageId)?;
-    Ok(f(language))
-}
-
-pub fn with_language_by_name<T>(
-    language_name: impl AsRef<str>,
-    f: impl FnOnce(&Language) -> T,
-) -> Result<T, LanguageError> {
-    let registry = LANGUAGE_REGISTRY.read().unwrap();
-    let language = registry
-        .language_by_name(language_name.as_ref())
-        .ok_or(LanguageError::InvalidLanguageId)?;
-    Ok(f(language))
-}
-
-pub fn with_unknown_language<T>(
-    language: &UnknownLanguage,
-    f: impl FnOnce(&Language) -> T,
-) -> Result<T, LanguageError> {
-    if let UnknownLanguage::LanguageName(name) = language {
-        with_language_by_name(name, f)
-    } else {
-        Err(LanguageError::InvalidLanguageId)
-    }
-}
-
 #[derive(thiserror::Error, Debug)]
 pub enum QueryParseError {
+    #[error(transparent)]
+    InvalidIsolate(#[from] IsolateError),
     #[error(transparent)]
     InvalideLanguage(#[from] LanguageError),
     #[error(transparent)]
     InvalidEncoding(#[from] str::Utf8Error),
     #[error("tree-sitter parse error: {0}")]
     TreeSitterError(#[from] tree_sitter::QueryError),
+    #[error("unknown predicates: {}", format_unknown_predicates(.0))]
+    UnknownPredicates(Box<[UnknownPredicate]>),
+    #[cfg(feature = "jni")]
     #[error("jni error: {0}")]
-    JNIError(#[from] JNIError),
+    JNIError(#[from] jni::errors::Error),
+}
+
+fn format_unknown_predicates(predicates: &[UnknownPredicate]) -> String {
+    predicates
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
 }
 
-fn parse_query<'local>(
-    env: &mut JNIEnv<'local>,
+pub fn parse_query(
     language: &tree_sitter::Language,
-    query_data: JByteArray<'local>,
+    query_str: &str,
 ) -> Result<(Query, AdditionalPredicates), QueryParseError> {
-    let query_size = env.get_array_length(&query_data)? as usize;
-    let mut query_buffer = vec![0i8; query_size];
-    env.get_byte_array_region(&query_data, 0, &mut query_buffer)?;
-    // SAFETY: transmute from &[i8] to &[u8] is valid
-    let query_slice = unsafe { transmute::<&[i8], &[u8]>(query_buffer.as_slice()) };
-    let query_str = str::from_utf8(query_slice)?;
     let query = Query::new(language, query_str)?;
     let additional_predicates =
         PREDICATE_PARSER.with(|parser| AdditionalPredicates::parse(&query, query_str, parser))?;
     Ok((query, additional_predicates))
 }
 
-#[no_mangle]
-pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeLanguageRegistry_nativeAddHighlightQuery<
-    'local,
->(
-    mut env: JNIEnv<'local>,
-    _class: JClass<'local>,
-    language_id: LanguageId,
-    query_data: JByteArray<'local>,
-) -> JObjectArray<'local> {
-    fn inner<'local>(
-        env: &mut JNIEnv<'local>,
-        language_id: LanguageId,
-        query_data: JByteArray<'local>,
-    ) -> Result<JObjectArray<'local>, QueryParseError> {
-        let ts_language = with_language(language_id, |language| language.ts_language.clone())?;
-        let (query, predicates) = parse_query(env, &ts_language, query_data)?;
-        let capture_names = query.capture_names();
-        let mut capture_mask = BitSet::with_capacity(capture_names.len());
-        for (idx, capture_name) in capture_names.iter().enumerate() {
-            if !capture_name.starts_with('_') {
-                capture_mask.insert(idx);
-            }
-        }
-        let query = Arc::new((query, predicates, capture_mask));
-        with_language(language_id, |language| {
-            language.parser_info_mut().highlights_query = Some(Arc::clone(&query));
-        })?;
-        let capture_names = query.0.capture_names();
-        let capture_names_array = env.new_object_array(
-            capture_names.len() as jsize,
-            "java/lang/String",
-            JString::default(),
-        )?;
-        for (index, capture_name) in capture_names.iter().enumerate() {
-            let capture_name = env.new_string(capture_name)?;
-            env.set_object_array_element(&capture_names_array, index as i32, &capture_name)?;
-            env.delete_local_ref(capture_name)?;
-        }
-        Ok(capture_names_array)
-    }
-    let result = inner(&mut env, language_id, query_data);
-    match result {
-        Ok(captures) => captures,
-        Err(QueryParseError::JNIError(JNIError::JavaException)) => JObjectArray::default(),
-        Err(err) => {
-            env.throw_new(
-                "java/lang/RuntimeException",
-                format!("Failed to parse query: {err}"),
-            )
-            .unwrap();
-            JObjectArray::default()
-        }
-    }
-}
-
 #[derive(thiserror::Error, Debug)]
-enum AddRangesQueryError {
+pub enum AddQueryError {
     #[error(transparent)]
     ParseError(#[from] QueryParseError),
     #[error(transparent)]
     RangesError(#[from] RangesQueryError),
+    #[error(transparent)]
+    InjectionError(#[from] InjectionQueryError),
+    #[error(transparent)]
+    TagsError(#[from] TagsQueryError),
+    #[error(transparent)]
+    SplitError(#[from] SplitQueryError),
+    #[error(transparent)]
+    LocalsError(#[from] LocalsQueryError),
+    #[error(transparent)]
+    BracketsError(#[from] BracketsQueryError),
+    #[error(transparent)]
+    OutlineError(#[from] OutlineQueryError),
+    #[error(transparent)]
+    IndentError(#[from] IndentQueryError),
+}
+
+fn compile_highlight_layers(
+    ts_language: &tree_sitter::Language,
+    layers: &[(i32, Box<str>)],
+) -> Result<Arc<HighlightsQuery>, AddQueryError> {
+    let query_str = layers
+        .iter()
+        .map(|(_, source)| source.as_ref())
+        .collect::<Vec<_>>()
+        .join("\n");
+    let (query, predicates) = parse_query(ts_language, &query_str)?;
+    Ok(highlights_query(query, predicates))
 }
 
-#[no_mangle]
-pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeLanguageRegistry_nativeAddFoldQuery<
-    'local,
->(
-    mut env: JNIEnv<'local>,
-    _class: JClass<'local>,
-    language_id: LanguageId,
-    query_data: JByteArray<'local>,
-) {
-    fn inner<'local>(
-        env: &mut JNIEnv<'local>,
+fn highlights_query(query: Query, predicates: AdditionalPredicates) -> Arc<HighlightsQuery> {
+    let capture_names = query.capture_names();
+    let mut capture_mask = BitSet::with_capacity(capture_names.len());
+    for (idx, capture_name) in capture_names.iter().enumerate() {
+        // Spell check captures mark regions for the spell checker, not highlights
+        if !capture_name.starts_with('_') && !matches!(*capture_name, "spell" | "nospell") {
+            capture_mask.insert(idx);
+        }
+    }
+    Arc::new((query, predicates, capture_mask))
+}
+
+/// Query of a role compiled by [`Isolate::build_query`] to be installed by
+/// [`Isolate::install_query`]
+enum RoleQuery {
+    Highlights(Query, AdditionalPredicates),
+    Ranges(Box<str>, RangesQuery),
+    Injections(InjectionQuery),
+    Locals(LocalsQuery),
+    Tags(TagsQuery),
+    Splits(SplitQuery),
+    Brackets(BracketsQuery),
+    Outline(OutlineQuery),
+    IndentRules(IndentQuery),
+}
+
+impl From<LanguageError> for AddQueryError {
+    fn from(value: LanguageError) -> Self {
+        AddQueryError::ParseError(value.into())
+    }
+}
+
+impl Isolate {
+    fn parse_language_query(
+        &self,
         language_id: LanguageId,
-        query_data: JByteArray<'local>,
-    ) -> Result<(), AddRangesQueryError> {
-        let ts_language = with_language(language_id, |language| language.ts_language.clone())
-            .map_err(QueryParseError::from)?;
-        let (query, predicates) = parse_query(env, &ts_language, query_data)?;
-        let query = RangesQuery::new(query, predicates, "fold")?;
-        let query = Arc::new(query);
-        with_language(language_id, |language| {
-            language.parser_info_mut().folds_query = Some(query);
-        })
-        .map_err(QueryParseError::from)?;
-        Ok(())
+        query_str: &str,
+    ) -> Result<(Query, AdditionalPredicates), QueryParseError> {
+        let ts_language =
+            self.with_language(language_id, |language| language.ts_language.clone())?;
+        parse_query(&ts_language, query_str)
     }
-    let result = inner(&mut env, language_id, query_data);
-    match result {
-        Ok(()) => (),
-        Err(AddRangesQueryError::ParseError(QueryParseError::JNIError(
-            JNIError::JavaException,
-        ))) => (),
-        Err(err) => {
-            env.throw_new(
-                "java/lang/RuntimeException",
-                format!("Failed to parse query: {err}"),
-            )
-            .unwrap();
+
+    /// Sets the base highlight query of the language (layer 0), see
+    /// [`Isolate::add_highlight_query_layer`]
+    pub fn add_highlight_query(
+        &self,
+        language_id: LanguageId,
+        query_str: &str,
+    ) -> Result<Arc<HighlightsQuery>, AddQueryError> {
+        self.add_highlight_query_layer(language_id, 0, query_str)
+    }
+
+    /// Adds or replaces highlight query of the `layer`, e.g. user overrides or plugin additions
+    /// on top of the base query. Layers are merged into a single query in layer order, so
+    /// captures of higher layers win for the same node. Returns the merged query, whose capture
+    /// ids are used by highlight tokens.
+    pub fn add_highlight_query_layer(
+        &self,
+        language_id: LanguageId,
+        layer: i32,
+        query_str: &str,
+    ) -> Result<Arc<HighlightsQuery>, AddQueryError> {
+        // Parsed alone first, so errors point into the layer source
+        let (query, predicates) = self.parse_language_query(language_id, query_str)?;
+        self.install_highlight_layer(language_id, layer, query_str, query, predicates)
+    }
+
+    /// Installs highlight query of the `layer` parsed from `query_str`, the parsed query is used
+    /// as is when there are no other layers to merge it with
+    fn install_highlight_layer(
+        &self,
+        language_id: LanguageId,
+        layer: i32,
+        query_str: &str,
+        query: Query,
+        predicates: AdditionalPredicates,
+    ) -> Result<Arc<HighlightsQuery>, AddQueryError> {
+        self.with_language(language_id, |language| {
+            let mut parser_info = language.parser_info_mut();
+            let mut layers = parser_info.highlight_query_layers.clone();
+            match layers.binary_search_by_key(&layer, |(order, _)| *order) {
+                Ok(idx) => layers[idx].1 = query_str.into(),
+                Err(idx) => layers.insert(idx, (layer, query_str.into())),
+            }
+            let query = if layers.len() == 1 {
+                highlights_query(query, predicates)
+            } else {
+                compile_highlight_layers(&language.ts_language, &layers)?
+            };
+            parser_info.highlight_query_layers = layers;
+            parser_info.highlights_query = Some(Arc::clone(&query));
+            self.highlight_queries_changed();
+            Ok(query)
+        })?
+    }
+
+    /// Removes highlight query of the `layer`, returns the merged query of the remaining layers
+    pub fn remove_highlight_query_layer(
+        &self,
+        language_id: LanguageId,
+        layer: i32,
+    ) -> Result<Option<Arc<HighlightsQuery>>, AddQueryError> {
+        self.with_language(language_id, |language| {
+            let mut parser_info = language.parser_info_mut();
+            let mut layers = parser_info.highlight_query_layers.clone();
+            layers.retain(|(order, _)| *order != layer);
+            let query = if layers.is_empty() {
+                None
+            } else {
+                Some(compile_highlight_layers(&language.ts_language, &layers)?)
+            };
+            parser_info.highlight_query_layers = layers;
+            parser_info.highlights_query = query.clone();
+            self.highlight_queries_changed();
+            Ok(query)
+        })?
+    }
+
+    /// Adds ranges query of `main_capture_name` captures which is collected by its `name`,
+    /// replacing the query of the language registered with the same name
+    pub fn add_ranges_query(
+        &self,
+        language_id: LanguageId,
+        name: &str,
+        main_capture_name: &str,
+        query_str: &str,
+    ) -> Result<(), AddQueryError> {
+        let (query, predicates) = self.parse_language_query(language_id, query_str)?;
+        let query = RangesQuery::new(query, predicates, main_capture_name)?;
+        self.install_query(
+            language_id,
+            query_str,
+            RoleQuery::Ranges(name.into(), query),
+        )
+    }
+
+    pub fn add_fold_query(
+        &self,
+        language_id: LanguageId,
+        query_str: &str,
+    ) -> Result<(), AddQueryError> {
+        self.add_ranges_query(language_id, "folds", "fold", query_str)
+    }
+
+    pub fn add_indent_query(
+        &self,
+        language_id: LanguageId,
+        query_str: &str,
+    ) -> Result<(), AddQueryError> {
+        self.add_ranges_query(language_id, "indents", "indent", query_str)
+    }
+
+    pub fn add_context_query(
+        &self,
+        language_id: LanguageId,
+        query_str: &str,
+    ) -> Result<(), AddQueryError> {
+        self.add_ranges_query(language_id, "contexts", "context", query_str)
+    }
+
+    pub fn add_block_query(
+        &self,
+        language_id: LanguageId,
+        query_str: &str,
+    ) -> Result<(), AddQueryError> {
+        self.add_ranges_query(language_id, "blocks", "block", query_str)
+    }
+
+    pub fn add_statement_query(
+        &self,
+        language_id: LanguageId,
+        query_str: &str,
+    ) -> Result<(), AddQueryError> {
+        self.add_ranges_query(language_id, "statements", "statement", query_str)
+    }
+
+    pub fn add_format_query(
+        &self,
+        language_id: LanguageId,
+        query_str: &str,
+    ) -> Result<(), AddQueryError> {
+        self.add_ranges_query(language_id, "formats", "format", query_str)
+    }
+
+    pub fn add_injection_query(
+        &self,
+        language_id: LanguageId,
+        query_str: &str,
+    ) -> Result<(), AddQueryError> {
+        let (query, predicates) = self.parse_language_query(language_id, query_str)?;
+        let query = InjectionQuery::new(query, predicates)?;
+        self.install_query(language_id, query_str, RoleQuery::Injections(query))
+    }
+
+    pub fn add_tags_query(
+        &self,
+        language_id: LanguageId,
+        query_str: &str,
+    ) -> Result<(), AddQueryError> {
+        let (query, predicates) = self.parse_language_query(language_id, query_str)?;
+        let query = TagsQuery::new(query, predicates)?;
+        self.install_query(language_id, query_str, RoleQuery::Tags(query))
+    }
+
+    pub fn add_locals_query(
+        &self,
+        language_id: LanguageId,
+        query_str: &str,
+    ) -> Result<(), AddQueryError> {
+        let (query, predicates) = self.parse_language_query(language_id, query_str)?;
+        let query = LocalsQuery::new(query, predicates)?;
+        self.install_query(language_id, query_str, RoleQuery::Locals(query))
+    }
+
+    pub fn add_split_query(
+        &self,
+        language_id: LanguageId,
+        query_str: &str,
+    ) -> Result<(), AddQueryError> {
+        let (query, predicates) = self.parse_language_query(language_id, query_str)?;
+        let query = SplitQuery::new(query, predicates)?;
+        self.install_query(language_id, query_str, RoleQuery::Splits(query))
+    }
+
+    pub fn add_brackets_query(
+        &self,
+        language_id: LanguageId,
+        query_str: &str,
+    ) -> Result<(), AddQueryError> {
+        let (query, predicates) = self.parse_language_query(language_id, query_str)?;
+        let query = BracketsQuery::new(query, predicates)?;
+        self.install_query(language_id, query_str, RoleQuery::Brackets(query))
+    }
+
+    pub fn add_outline_query(
+        &self,
+        language_id: LanguageId,
+        query_str: &str,
+    ) -> Result<(), AddQueryError> {
+        let (query, predicates) = self.parse_language_query(language_id, query_str)?;
+        let query = OutlineQuery::new(query, predicates)?;
+        self.install_query(language_id, query_str, RoleQuery::Outline(query))
+    }
+
+    /// Adds query with `@indent.*` captures used to compute indent of lines
+    pub fn add_indent_rules_query(
+        &self,
+        language_id: LanguageId,
+        query_str: &str,
+    ) -> Result<(), AddQueryError> {
+        let (query, predicates) = self.parse_language_query(language_id, query_str)?;
+        let query = IndentQuery::new(query, predicates)?;
+        self.install_query(language_id, query_str, RoleQuery::IndentRules(query))
+    }
+
+    /// Adds query of the `role`, unknown predicates of the query are rejected in
+    /// [`PredicateMode::Strict`] and returned as warnings otherwise
+    pub fn add_query(
+        &self,
+        language_id: LanguageId,
+        role: QueryRole,
+        query_str: &str,
+        mode: PredicateMode,
+    ) -> Result<Vec<UnknownPredicate>, AddQueryError> {
+        // Built before registration, so strict mode leaves the current query in place
+        let (query, unknown_predicates) = self.build_query(language_id, role, query_str)?;
+        if mode == PredicateMode::Strict && !unknown_predicates.is_empty() {
+            return Err(QueryParseError::UnknownPredicates(unknown_predicates.into()).into());
         }
+        self.install_query(language_id, query_str, query)?;
+        Ok(unknown_predicates)
     }
-}
 
-#[no_mangle]
-pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeLanguageRegistry_nativeAddIndentQuery<
-    'local,
->(
-    mut env: JNIEnv<'local>,
-    _class: JClass<'local>,
-    language_id: LanguageId,
-    query_data: JByteArray<'local>,
-) {
-    fn inner<'local>(
-        env: &mut JNIEnv<'local>,
+    /// Compiles query of the `role` along with its additional predicates without adding it to
+    /// the language, returns unknown predicates as warnings
+    pub fn validate_query(
+        &self,
         language_id: LanguageId,
-        query_data: JByteArray<'local>,
-    ) -> Result<(), AddRangesQueryError> {
-        let ts_language = with_language(language_id, |language| language.ts_language.clone())
-            .map_err(QueryParseError::from)?;
-        let (query, predicates) = parse_query(env, &ts_language, query_data)?;
-        let query = RangesQuery::new(query, predicates, "indent")?;
-        let query = Arc::new(query);
-        with_language(language_id, |language| {
-            language.parser_info_mut().indents_query = Some(query);
-        })
-        .map_err(QueryParseError::from)?;
+        role: QueryRole,
+        query_str: &str,
+    ) -> Result<Vec<UnknownPredicate>, AddQueryError> {
+        let (_, unknown_predicates) = self.build_query(language_id, role, query_str)?;
+        Ok(unknown_predicates)
+    }
+
+    /// Compiles query of the `role` into the query object used by the role, along with unknown
+    /// predicates of the query
+    fn build_query(
+        &self,
+        language_id: LanguageId,
+        role: QueryRole,
+        query_str: &str,
+    ) -> Result<(RoleQuery, Vec<UnknownPredicate>), AddQueryError> {
+        let (query, predicates) = self.parse_language_query(language_id, query_str)?;
+        let unknown_predicates = predicates.unknown_predicates().to_vec();
+        let ranges = |name: &str, main_capture_name: &str, query, predicates| {
+            RangesQuery::new(query, predicates, main_capture_name)
+                .map(|query| RoleQuery::Ranges(name.into(), query))
+        };
+        let query = match role {
+            QueryRole::Highlights => RoleQuery::Highlights(query, predicates),
+            QueryRole::Folds => ranges("folds", "fold", query, predicates)?,
+            QueryRole::Indents => ranges("indents", "indent", query, predicates)?,
+            QueryRole::Formats => ranges("formats", "format", query, predicates)?,
+            QueryRole::Contexts => ranges("contexts", "context", query, predicates)?,
+            QueryRole::Blocks => ranges("blocks", "block", query, predicates)?,
+            QueryRole::Statements => ranges("statements", "statement", query, predicates)?,
+            QueryRole::Injections => RoleQuery::Injections(InjectionQuery::new(query, predicates)?),
+            QueryRole::Locals => RoleQuery::Locals(LocalsQuery::new(query, predicates)?),
+            QueryRole::Tags => RoleQuery::Tags(TagsQuery::new(query, predicates)?),
+            QueryRole::Splits => RoleQuery::Splits(SplitQuery::new(query, predicates)?),
+            QueryRole::Brackets => RoleQuery::Brackets(BracketsQuery::new(query, predicates)?),
+            QueryRole::Outline => RoleQuery::Outline(OutlineQuery::new(query, predicates)?),
+            QueryRole::IndentRules => RoleQuery::IndentRules(IndentQuery::new(query, predicates)?),
+        };
+        Ok((query, unknown_predicates))
+    }
+
+    /// Sets compiled query of the role for the language, `query_str` is the source it was
+    /// compiled from
+    fn install_query(
+        &self,
+        language_id: LanguageId,
+        query_str: &str,
+        query: RoleQuery,
+    ) -> Result<(), AddQueryError> {
+        if let RoleQuery::Highlights(query, predicates) = query {
+            self.install_highlight_layer(language_id, 0, query_str, query, predicates)?;
+            return Ok(());
+        }
+        self.with_language(language_id, |language| {
+            let mut parser_info = language.parser_info_mut();
+            match query {
+                RoleQuery::Highlights(..) => unreachable!("highlights are installed as a layer"),
+                RoleQuery::Ranges(name, query) => {
+                    parser_info.ranges_queries.insert(name, Arc::new(query));
+                }
+                RoleQuery::Injections(query) => {
+                    parser_info.injections_query = Some(Arc::new(query));
+                }
+                RoleQuery::Locals(query) => {
+                    parser_info.locals_query = Some(Arc::new(query));
+                    self.highlight_queries_changed();
+                }
+                RoleQuery::Tags(query) => parser_info.tags_query = Some(Arc::new(query)),
+                RoleQuery::Splits(query) => parser_info.splits_query = Some(Arc::new(query)),
+                RoleQuery::Brackets(query) => parser_info.brackets_query = Some(Arc::new(query)),
+                RoleQuery::Outline(query) => parser_info.outline_query = Some(Arc::new(query)),
+                RoleQuery::IndentRules(query) => {
+                    parser_info.indent_rules_query = Some(Arc::new(query));
+                }
+            }
+        })?;
         Ok(())
     }
-    let result = inner(&mut env, language_id, query_data);
-    match result {
-        Ok(()) => (),
-        Err(AddRangesQueryError::ParseError(QueryParseError::JNIError(
-            JNIError::JavaException,
-        ))) => (),
-        Err(err) => {
-            env.throw_new(
-                "java/lang/RuntimeException",
-                format!("Failed to parse query: {err}"),
+
+    /// Capture names of the query of the role indexed by capture id, `None` if the language has
+    /// no such query
+    pub fn capture_names(
+        &self,
+        language_id: LanguageId,
+        role: QueryRole,
+    ) -> Result<Option<Vec<Box<str>>>, LanguageError> {
+        self.with_language(language_id, |language| {
+            let parser_info = language.parser_info();
+            let capture_names = match role {
+                QueryRole::Highlights => parser_info
+                    .highlights_query
+                    .as_ref()
+                    .map(|query| query.0.capture_names()),
+                QueryRole::Folds => parser_info
+                    .ranges_queries
+                    .get("folds")
+                    .map(|query| query.capture_names()),
+                QueryRole::Indents => parser_info
+                    .ranges_queries
+                    .get("indents")
+                    .map(|query| query.capture_names()),
+                QueryRole::Formats => parser_info
+                    .ranges_queries
+                    .get("formats")
+                    .map(|query| query.capture_names()),
+                QueryRole::Contexts => parser_info
+                    .ranges_queries
+                    .get("contexts")
+                    .map(|query| query.capture_names()),
+                QueryRole::Blocks => parser_info
+                    .ranges_queries
+                    .get("blocks")
+                    .map(|query| query.capture_names()),
+                QueryRole::Statements => parser_info
+                    .ranges_queries
+                    .get("statements")
+                    .map(|query| query.capture_names()),
+                QueryRole::Injections => parser_info
+                    .injections_query
+                    .as_ref()
+                    .map(|query| query.capture_names()),
+                QueryRole::Locals => parser_info
+                    .locals_query
+                    .as_ref()
+                    .map(|query| query.capture_names()),
+                QueryRole::Tags => parser_info
+                    .tags_query
+                    .as_ref()
+                    .map(|query| query.capture_names()),
+                QueryRole::Splits => parser_info
+                    .splits_query
+                    .as_ref()
+                    .map(|query| query.capture_names()),
+                QueryRole::Brackets => parser_info
+                    .brackets_query
+                    .as_ref()
+                    .map(|query| query.capture_names()),
+                QueryRole::Outline => parser_info
+                    .outline_query
+                    .as_ref()
+                    .map(|query| query.capture_names()),
+                QueryRole::IndentRules => parser_info
+                    .indent_rules_query
+                    .as_ref()
+                    .map(|query| query.capture_names()),
+            };
+            capture_names.map(|names| names.iter().map(|name| (*name).into()).collect())
+        })
+    }
+
+    /// Sets mapping of highlight capture names to highlight groups, highlights of the language
+    /// are then reported with group ids instead of capture ids. `None` removes the mapping.
+    pub fn set_highlight_groups<'a>(
+        &self,
+        language_id: LanguageId,
+        mapping: Option<impl IntoIterator<Item = (&'a str, u16)>>,
+    ) -> Result<(), LanguageError> {
+        let mapping = mapping.map(|mapping| {
+            Arc::new(
+                mapping
+                    .into_iter()
+                    .map(|(capture_name, group_id)| (capture_name.into(), group_id))
+                    .collect(),
             )
-            .unwrap();
-        }
+        });
+        self.with_language(language_id, |language| {
+            language.parser_info_mut().highlight_groups = mapping;
+        })
     }
-}
 
-#[derive(thiserror::Error, Debug)]
-enum AddInjectionQueryError {
-    #[error(transparent)]
-    ParseError(#[from] QueryParseError),
-    #[error(transparent)]
-    InjectionError(#[from] InjectionQueryError),
-}
+    /// Replaces the listener notified about injections of unregistered languages, so the user
+    /// can be offered to install the missing grammar
+    pub fn set_unknown_language_listener(&self, listener: Option<UnknownLanguageListener>) {
+        *self.unknown_language_listener.write().unwrap() = listener.map(Arc::new);
+    }
 
-#[no_mangle]
-pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeLanguageRegistry_nativeAddInjectionQuery<
-    'local,
->(
-    mut env: JNIEnv<'local>,
-    _class: JClass<'local>,
-    language_id: LanguageId,
-    query_data: JByteArray<'local>,
-) {
-    fn inner<'local>(
-        env: &mut JNIEnv<'local>,
+    /// Calls the listener without holding its lock, so it may replace itself
+    pub(crate) fn notify_unknown_language(
+        &self,
+        language: &UnknownLanguage,
+        byte_range: Range<usize>,
+        unit_size: usize,
+    ) {
+        let listener = self.unknown_language_listener.read().unwrap().clone();
+        if let Some(listener) = listener {
+            listener(
+                language,
+                (byte_range.start / unit_size)..(byte_range.end / unit_size),
+            );
+        }
+    }
+
+    /// Limits size of the text injected in the language, larger injections become unparsed
+    /// entries instead of stalling the parse. Size is in bytes of the parsed text.
+    pub fn set_max_injection_size(
+        &self,
         language_id: LanguageId,
-        query_data: JByteArray<'local>,
-    ) -> Result<(), AddInjectionQueryError> {
-        let ts_language = with_language(language_id, |language| language.ts_language.clone())
-            .map_err(QueryParseError::from)?;
-        let (query, predicates) = parse_query(env, &ts_language, query_data)?;
-        let query = InjectionQuery::new(query, predicates)?;
-        let query = Arc::new(query);
-        with_language(language_id, |language| {
-            language.parser_info_mut().injections_query = Some(Arc::clone(&query));
+        max_size: Option<usize>,
+    ) -> Result<(), LanguageError> {
+        self.with_language(language_id, |language| {
+            language.parser_info_mut().max_injection_size = max_size;
         })
-        .map_err(QueryParseError::from)?;
-        Ok(())
     }
-    let result = inner(&mut env, language_id, query_data);
-    match result {
-        Ok(()) => (),
-        Err(AddInjectionQueryError::ParseError(QueryParseError::JNIError(
-            JNIError::JavaException,
-        ))) => (),
-        Err(err) => {
-            env.throw_new(
-                "java/lang/RuntimeException",
-                format!("Failed to parse query: {err}"),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::{
+        highlighting_lexer::query::highlight_tokens_cover,
+        query::SourceText,
+        syntax_snapshot::SyntaxSnapshot,
+        test_utils::{json_isolate, parse},
+    };
+
+    #[test]
+    fn unknown_language_listener_may_replace_itself() {
+        let (isolate, language_id) = json_isolate();
+        isolate
+            .add_injection_query(
+                language_id,
+                "((string_content) @injection.content (#set! injection.language \"foo\"))",
             )
             .unwrap();
-        }
+        let notified = Arc::new(Mutex::new(Vec::new()));
+        let listener_isolate = Arc::downgrade(&isolate);
+        let listener_notified = Arc::clone(&notified);
+        isolate.set_unknown_language_listener(Some(Box::new(move |language, range| {
+            listener_notified
+                .lock()
+                .unwrap()
+                .push((language.clone(), range));
+            if let Some(isolate) = listener_isolate.upgrade() {
+                isolate.set_unknown_language_listener(None);
+            }
+        })));
+        let text: Vec<u16> = "[\"ab\", \"cd\"]".encode_utf16().collect();
+        SyntaxSnapshot::parse(Arc::clone(&isolate), language_id, &text).unwrap();
+        // Range is in UTF-16 units, the listener removed itself after the first injection
+        assert_eq!(
+            *notified.lock().unwrap(),
+            vec![(UnknownLanguage::LanguageName("foo".into()), 2..4)]
+        );
+    }
+
+    /// Capture name highlighting the number of `[1]`
+    fn number_capture(isolate: &Arc<Isolate>, language_id: LanguageId) -> Option<String> {
+        let text = "[1]";
+        let snapshot = parse(isolate, language_id, text);
+        let (_, tokens) = highlight_tokens_cover(&snapshot, SourceText::Utf8(text), 0..text.len());
+        let query = isolate
+            .with_language(language_id, |language| {
+                language.parser_info().highlights_query.clone()
+            })
+            .unwrap()?;
+        let capture_names = query.0.capture_names();
+        capture_names
+            .get(tokens[1].capture_id as usize)
+            .map(|name| name.to_string())
+    }
+
+    #[test]
+    fn highlight_layers_are_merged_in_layer_order() {
+        let (isolate, language_id) = json_isolate();
+        isolate
+            .add_highlight_query_layer(language_id, 10, "(number) @constant")
+            .unwrap();
+        isolate
+            .add_highlight_query(language_id, "(number) @number")
+            .unwrap();
+        assert_eq!(
+            number_capture(&isolate, language_id).as_deref(),
+            Some("constant")
+        );
+
+        // Replacing a layer keeps the others
+        isolate
+            .add_highlight_query_layer(language_id, 10, "(number) @constant.numeric")
+            .unwrap();
+        assert_eq!(
+            number_capture(&isolate, language_id).as_deref(),
+            Some("constant.numeric")
+        );
+
+        isolate
+            .remove_highlight_query_layer(language_id, 10)
+            .unwrap();
+        assert_eq!(
+            number_capture(&isolate, language_id).as_deref(),
+            Some("number")
+        );
+        isolate
+            .remove_highlight_query_layer(language_id, 0)
+            .unwrap();
+        assert_eq!(number_capture(&isolate, language_id), None);
+    }
+
+    #[test]
+    fn invalid_highlight_layer_keeps_installed_layers() {
+        let (isolate, language_id) = json_isolate();
+        isolate
+            .add_highlight_query(language_id, "(number) @number")
+            .unwrap();
+        assert!(isolate
+            .add_highlight_query_layer(language_id, 10, "(number @constant")
+            .is_err());
+        assert_eq!(
+            number_capture(&isolate, language_id).as_deref(),
+            Some("number")
+        );
     }
 }
@@ -0,0 +1,997 @@
+use std::{borrow::Cow, mem::transmute, str};
+
+use jni::{
+    errors::Error as JNIError,
+    objects::{
+        AutoLocal, JByteArray, JClass, JMethodID, JObject, JObjectArray, JShortArray, JString,
+        JValue,
+    },
+    sys::{jboolean, jint, jsize},
+    JNIEnv,
+};
+use once_cell::sync::OnceCell as JOnceLock;
+
+use crate::{
+    isolate::{Isolate, IsolateId},
+    jni_utils::{read_byte_array, read_string_array},
+    predicates::{PredicateMode, UnknownPredicate},
+};
+
+use super::{AddQueryError, LanguageId, QueryParseError, QueryRole, UnknownLanguage};
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeLanguageRegistry_nativeRegisterLanguage<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    isolate_id: IsolateId,
+    name: JString<'local>,
+    language: JObject<'local>,
+) -> LanguageId {
+    let isolate = match Isolate::get(isolate_id) {
+        Ok(isolate) => isolate,
+        Err(err) => {
+            env.throw_new(
+                "java/lang/IllegalStateException",
+                format!("Failed to register language: {err}"),
+            )
+            .unwrap();
+            return LanguageId::UNKNOWN;
+        }
+    };
+    let name = env
+        .get_string(&name)
+        .expect("valid string from java interface");
+    let name: Cow<'_, str> = (&name).into();
+    let language_handle = env
+        .call_method(&language, "getPtr", "()J", &[])
+        .expect("TSLanguage has getPtr method")
+        .j()
+        .expect("getPtr returns long");
+    let ts_language = language_handle as *const tree_sitter::ffi::TSLanguage;
+    // SAFETY: TSParser language from java has valid language_handle from linked tree-sitter
+    let ts_language = unsafe {
+        // Copy language so it can be freed by rust
+        let ts_language = tree_sitter::ffi::ts_language_copy(ts_language);
+        tree_sitter::Language::from_raw(ts_language)
+    };
+    isolate.register_language(&name, ts_language)
+}
+
+fn read_query_source<'local>(
+    env: &mut JNIEnv<'local>,
+    query_data: JByteArray<'local>,
+) -> Result<String, QueryParseError> {
+    let query_buffer = read_byte_array(env, &query_data)?;
+    // SAFETY: transmute from &[i8] to &[u8] is valid
+    let query_slice = unsafe { transmute::<&[i8], &[u8]>(&query_buffer) };
+    Ok(str::from_utf8(query_slice)?.to_owned())
+}
+
+fn throw_add_query_error(env: &mut JNIEnv<'_>, err: AddQueryError) {
+    if let AddQueryError::ParseError(QueryParseError::JNIError(JNIError::JavaException)) = err {
+        return;
+    }
+    env.throw_new(
+        "java/lang/RuntimeException",
+        format!("Failed to parse query: {err}"),
+    )
+    .unwrap();
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeLanguageRegistry_nativeAddHighlightQuery<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    isolate_id: IsolateId,
+    language_id: LanguageId,
+    query_data: JByteArray<'local>,
+) -> JObjectArray<'local> {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        isolate_id: IsolateId,
+        language_id: LanguageId,
+        query_data: JByteArray<'local>,
+    ) -> Result<JObjectArray<'local>, AddQueryError> {
+        let isolate = Isolate::get(isolate_id).map_err(QueryParseError::from)?;
+        let query_str = read_query_source(env, query_data)?;
+        let query = isolate.add_highlight_query(language_id, &query_str)?;
+        capture_names_to_java_array(env, &query.0)
+    }
+    let result = inner(&mut env, isolate_id, language_id, query_data);
+    result.unwrap_or_else(|err| {
+        throw_add_query_error(&mut env, err);
+        JObjectArray::default()
+    })
+}
+
+fn capture_names_to_java_array<'local>(
+    env: &mut JNIEnv<'local>,
+    query: &tree_sitter::Query,
+) -> Result<JObjectArray<'local>, AddQueryError> {
+    let capture_names = query.capture_names();
+    let capture_names_array = env
+        .new_object_array(
+            capture_names.len() as jsize,
+            "java/lang/String",
+            JString::default(),
+        )
+        .map_err(QueryParseError::from)?;
+    for (index, capture_name) in capture_names.iter().enumerate() {
+        let capture_name = env
+            .new_string(capture_name)
+            .map_err(QueryParseError::from)?;
+        env.set_object_array_element(&capture_names_array, index as i32, &capture_name)
+            .map_err(QueryParseError::from)?;
+        env.delete_local_ref(capture_name)
+            .map_err(QueryParseError::from)?;
+    }
+    Ok(capture_names_array)
+}
+
+/// Role by the name of the query file, throws `IllegalArgumentException` for unknown ones
+fn read_query_role<'local>(env: &mut JNIEnv<'local>, role: &JString<'local>) -> Option<QueryRole> {
+    let role: Option<String> = env.get_string(role).ok().map(Into::into);
+    let query_role = role.as_deref().and_then(QueryRole::from_name);
+    if query_role.is_none() && !env.exception_check().unwrap_or(true) {
+        env.throw_new(
+            "java/lang/IllegalArgumentException",
+            format!("unknown query role {}", role.unwrap_or_default()),
+        )
+        .unwrap();
+    }
+    query_role
+}
+
+static UNKNOWN_PREDICATE_CONSTRUCTOR: JOnceLock<JMethodID> = JOnceLock::new();
+static QUERY_DIAGNOSTIC_CONSTRUCTOR: JOnceLock<JMethodID> = JOnceLock::new();
+
+struct QueryDiagnosticDesc<'local> {
+    constructor: JMethodID,
+    class: AutoLocal<'local, JClass<'local>>,
+}
+
+impl<'local> QueryDiagnosticDesc<'local> {
+    fn new(env: &mut JNIEnv<'local>) -> Result<QueryDiagnosticDesc<'local>, JNIError> {
+        let class = env.find_class("com/hulylabs/treesitter/language/QueryDiagnostic")?;
+        let constructor = *QUERY_DIAGNOSTIC_CONSTRUCTOR
+            .get_or_try_init(|| env.get_method_id(&class, "<init>", "(ZIILjava/lang/String;)V"))?;
+        Ok(QueryDiagnosticDesc {
+            constructor,
+            class: env.auto_local(class),
+        })
+    }
+
+    /// Row and column are -1 if unknown
+    fn to_java_object(
+        &self,
+        env: &mut JNIEnv<'local>,
+        is_error: bool,
+        row: jint,
+        column: jint,
+        message: &str,
+    ) -> Result<JObject<'local>, JNIError> {
+        let message: JObject = env.new_string(message)?.into();
+        let message = env.auto_local(message);
+        // SAFETY: constructor is valid and derived from class by construction of self
+        unsafe {
+            env.new_object_unchecked(
+                &self.class,
+                self.constructor,
+                &[
+                    JValue::Bool(is_error.into()).as_jni(),
+                    JValue::Int(row).as_jni(),
+                    JValue::Int(column).as_jni(),
+                    JValue::Object(&message).as_jni(),
+                ],
+            )
+        }
+    }
+}
+
+/// Compiles query of `role` with its predicates without adding it, returns the error and
+/// warnings about unknown predicates, empty array for a valid query
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeLanguageRegistry_nativeValidateQuery<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    isolate_id: IsolateId,
+    language_id: LanguageId,
+    role: JString<'local>,
+    query_data: JByteArray<'local>,
+) -> JObjectArray<'local> {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        isolate_id: IsolateId,
+        language_id: LanguageId,
+        role: QueryRole,
+        query_data: JByteArray<'local>,
+    ) -> Result<JObjectArray<'local>, AddQueryError> {
+        let isolate = Isolate::get(isolate_id).map_err(QueryParseError::from)?;
+        let query_str = read_query_source(env, query_data)?;
+        let validation = isolate.validate_query(language_id, role, &query_str);
+        let result = (|| {
+            let desc = QueryDiagnosticDesc::new(env)?;
+            let diagnostics = match validation {
+                Ok(warnings) => warnings
+                    .iter()
+                    .map(|warning| {
+                        let message = format!("unknown predicate #{}", warning.operator);
+                        desc.to_java_object(env, false, warning.row as jint, -1, &message)
+                    })
+                    .collect::<Result<Vec<_>, _>>()?,
+                Err(AddQueryError::ParseError(QueryParseError::JNIError(err))) => {
+                    return Err(err);
+                }
+                Err(AddQueryError::ParseError(QueryParseError::TreeSitterError(err))) => {
+                    vec![desc.to_java_object(
+                        env,
+                        true,
+                        err.row as jint,
+                        err.column as jint,
+                        &err.to_string(),
+                    )?]
+                }
+                Err(err) => vec![desc.to_java_object(env, true, -1, -1, &err.to_string())?],
+            };
+            let array =
+                env.new_object_array(diagnostics.len() as jsize, &desc.class, JObject::null())?;
+            for (index, diagnostic) in diagnostics.into_iter().enumerate() {
+                let diagnostic = env.auto_local(diagnostic);
+                env.set_object_array_element(&array, index as jsize, diagnostic)?;
+            }
+            Ok(array)
+        })();
+        result.map_err(|err: JNIError| QueryParseError::from(err).into())
+    }
+    let Some(role) = read_query_role(&mut env, &role) else {
+        return JObjectArray::default();
+    };
+    let result = inner(&mut env, isolate_id, language_id, role, query_data);
+    result.unwrap_or_else(|err| {
+        throw_add_query_error(&mut env, err);
+        JObjectArray::default()
+    })
+}
+
+struct UnknownPredicateDesc<'local> {
+    constructor: JMethodID,
+    class: AutoLocal<'local, JClass<'local>>,
+}
+
+impl<'local> UnknownPredicateDesc<'local> {
+    fn new(env: &mut JNIEnv<'local>) -> Result<UnknownPredicateDesc<'local>, JNIError> {
+        let class = env.find_class("com/hulylabs/treesitter/language/UnknownPredicate")?;
+        let constructor = *UNKNOWN_PREDICATE_CONSTRUCTOR
+            .get_or_try_init(|| env.get_method_id(&class, "<init>", "(IILjava/lang/String;)V"))?;
+        Ok(UnknownPredicateDesc {
+            constructor,
+            class: env.auto_local(class),
+        })
+    }
+
+    fn to_java_object(
+        &self,
+        env: &mut JNIEnv<'local>,
+        predicate: &UnknownPredicate,
+    ) -> Result<JObject<'local>, JNIError> {
+        let operator: JObject = env.new_string(&predicate.operator)?.into();
+        let operator = env.auto_local(operator);
+        // SAFETY: constructor is valid and derived from class by construction of self
+        unsafe {
+            env.new_object_unchecked(
+                &self.class,
+                self.constructor,
+                &[
+                    JValue::Int(predicate.pattern_index as jint).as_jni(),
+                    JValue::Int(predicate.row as jint).as_jni(),
+                    JValue::Object(&operator).as_jni(),
+                ],
+            )
+        }
+    }
+}
+
+/// Adds query of `role` (name of the query file, e.g. `"folds"`). In strict mode a query with
+/// predicates unknown to the library is rejected, otherwise they are returned as warnings.
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeLanguageRegistry_nativeAddQuery<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    isolate_id: IsolateId,
+    language_id: LanguageId,
+    role: JString<'local>,
+    query_data: JByteArray<'local>,
+    strict: jboolean,
+) -> JObjectArray<'local> {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        isolate_id: IsolateId,
+        language_id: LanguageId,
+        role: QueryRole,
+        query_data: JByteArray<'local>,
+        strict: jboolean,
+    ) -> Result<JObjectArray<'local>, AddQueryError> {
+        let isolate = Isolate::get(isolate_id).map_err(QueryParseError::from)?;
+        let query_str = read_query_source(env, query_data)?;
+        let mode = if strict != 0 {
+            PredicateMode::Strict
+        } else {
+            PredicateMode::Lenient
+        };
+        let warnings = isolate.add_query(language_id, role, &query_str, mode)?;
+        let result = (|| {
+            let desc = UnknownPredicateDesc::new(env)?;
+            let array =
+                env.new_object_array(warnings.len() as jsize, &desc.class, JObject::null())?;
+            for (index, warning) in warnings.iter().enumerate() {
+                let obj = desc.to_java_object(env, warning)?;
+                let obj = env.auto_local(obj);
+                env.set_object_array_element(&array, index as jsize, obj)?;
+            }
+            Ok(array)
+        })();
+        result.map_err(|err: JNIError| QueryParseError::from(err).into())
+    }
+    let Some(role) = read_query_role(&mut env, &role) else {
+        return JObjectArray::default();
+    };
+    let result = inner(&mut env, isolate_id, language_id, role, query_data, strict);
+    result.unwrap_or_else(|err| {
+        throw_add_query_error(&mut env, err);
+        JObjectArray::default()
+    })
+}
+
+/// Capture names of the language query of `role` (name of the query file, e.g. `"folds"`) indexed
+/// by capture id, null if there is no such query
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeLanguageRegistry_nativeGetCaptureNames<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    isolate_id: IsolateId,
+    language_id: LanguageId,
+    role: JString<'local>,
+) -> JObjectArray<'local> {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        isolate_id: IsolateId,
+        language_id: LanguageId,
+        role: JString<'local>,
+    ) -> Result<JObjectArray<'local>, String> {
+        let isolate = Isolate::get(isolate_id).map_err(|err| err.to_string())?;
+        let role: String = env.get_string(&role).map_err(|err| err.to_string())?.into();
+        let role = QueryRole::from_name(&role).ok_or_else(|| format!("unknown role {role}"))?;
+        let Some(capture_names) = isolate
+            .capture_names(language_id, role)
+            .map_err(|err| err.to_string())?
+        else {
+            return Ok(JObjectArray::default());
+        };
+        let result = env.new_object_array(
+            capture_names.len() as jsize,
+            "java/lang/String",
+            JString::default(),
+        );
+        let array = result.map_err(|err| err.to_string())?;
+        for (index, capture_name) in capture_names.iter().enumerate() {
+            let capture_name = env
+                .new_string(capture_name)
+                .map_err(|err| err.to_string())?;
+            let capture_name = env.auto_local(capture_name);
+            env.set_object_array_element(&array, index as jsize, &capture_name)
+                .map_err(|err| err.to_string())?;
+        }
+        Ok(array)
+    }
+    inner(&mut env, isolate_id, language_id, role).unwrap_or_else(|err| {
+        if !env.exception_check().unwrap_or(true) {
+            env.throw_new(
+                "java/lang/IllegalArgumentException",
+                format!("Failed to get capture names: {err}"),
+            )
+            .unwrap();
+        }
+        JObjectArray::default()
+    })
+}
+
+/// Adds or replaces highlight query `layer` merged with the other layers in layer order, returns
+/// capture names of the merged query. Base query added by `nativeAddHighlightQuery` is layer 0.
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeLanguageRegistry_nativeAddHighlightQueryLayer<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    isolate_id: IsolateId,
+    language_id: LanguageId,
+    layer: jint,
+    query_data: JByteArray<'local>,
+) -> JObjectArray<'local> {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        isolate_id: IsolateId,
+        language_id: LanguageId,
+        layer: jint,
+        query_data: JByteArray<'local>,
+    ) -> Result<JObjectArray<'local>, AddQueryError> {
+        let isolate = Isolate::get(isolate_id).map_err(QueryParseError::from)?;
+        let query_str = read_query_source(env, query_data)?;
+        let query = isolate.add_highlight_query_layer(language_id, layer, &query_str)?;
+        capture_names_to_java_array(env, &query.0)
+    }
+    let result = inner(&mut env, isolate_id, language_id, layer, query_data);
+    result.unwrap_or_else(|err| {
+        throw_add_query_error(&mut env, err);
+        JObjectArray::default()
+    })
+}
+
+/// Removes highlight query `layer`, returns capture names of the merged query of the remaining
+/// layers or null if there are none
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeLanguageRegistry_nativeRemoveHighlightQueryLayer<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    isolate_id: IsolateId,
+    language_id: LanguageId,
+    layer: jint,
+) -> JObjectArray<'local> {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        isolate_id: IsolateId,
+        language_id: LanguageId,
+        layer: jint,
+    ) -> Result<JObjectArray<'local>, AddQueryError> {
+        let isolate = Isolate::get(isolate_id).map_err(QueryParseError::from)?;
+        match isolate.remove_highlight_query_layer(language_id, layer)? {
+            Some(query) => capture_names_to_java_array(env, &query.0),
+            None => Ok(JObjectArray::default()),
+        }
+    }
+    let result = inner(&mut env, isolate_id, language_id, layer);
+    result.unwrap_or_else(|err| {
+        throw_add_query_error(&mut env, err);
+        JObjectArray::default()
+    })
+}
+
+/// Registers ranges query collected by `nativeGetRanges` under `name`
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeLanguageRegistry_nativeAddRangesQuery<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    isolate_id: IsolateId,
+    language_id: LanguageId,
+    name: JString<'local>,
+    main_capture: JString<'local>,
+    query_data: JByteArray<'local>,
+) {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        isolate_id: IsolateId,
+        language_id: LanguageId,
+        name: JString<'local>,
+        main_capture: JString<'local>,
+        query_data: JByteArray<'local>,
+    ) -> Result<(), AddQueryError> {
+        let isolate = Isolate::get(isolate_id).map_err(QueryParseError::from)?;
+        let name: String = env.get_string(&name).map_err(QueryParseError::from)?.into();
+        let main_capture: String = env
+            .get_string(&main_capture)
+            .map_err(QueryParseError::from)?
+            .into();
+        let query_str = read_query_source(env, query_data)?;
+        isolate.add_ranges_query(language_id, &name, &main_capture, &query_str)
+    }
+    if let Err(err) = inner(
+        &mut env,
+        isolate_id,
+        language_id,
+        name,
+        main_capture,
+        query_data,
+    ) {
+        throw_add_query_error(&mut env, err);
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeLanguageRegistry_nativeAddFoldQuery<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    isolate_id: IsolateId,
+    language_id: LanguageId,
+    query_data: JByteArray<'local>,
+) {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        isolate_id: IsolateId,
+        language_id: LanguageId,
+        query_data: JByteArray<'local>,
+    ) -> Result<(), AddQueryError> {
+        let isolate = Isolate::get(isolate_id).map_err(QueryParseError::from)?;
+        let query_str = read_query_source(env, query_data)?;
+        isolate.add_fold_query(language_id, &query_str)
+    }
+    if let Err(err) = inner(&mut env, isolate_id, language_id, query_data) {
+        throw_add_query_error(&mut env, err);
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeLanguageRegistry_nativeAddContextQuery<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    isolate_id: IsolateId,
+    language_id: LanguageId,
+    query_data: JByteArray<'local>,
+) {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        isolate_id: IsolateId,
+        language_id: LanguageId,
+        query_data: JByteArray<'local>,
+    ) -> Result<(), AddQueryError> {
+        let isolate = Isolate::get(isolate_id).map_err(QueryParseError::from)?;
+        let query_str = read_query_source(env, query_data)?;
+        isolate.add_context_query(language_id, &query_str)
+    }
+    if let Err(err) = inner(&mut env, isolate_id, language_id, query_data) {
+        throw_add_query_error(&mut env, err);
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeLanguageRegistry_nativeAddBlockQuery<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    isolate_id: IsolateId,
+    language_id: LanguageId,
+    query_data: JByteArray<'local>,
+) {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        isolate_id: IsolateId,
+        language_id: LanguageId,
+        query_data: JByteArray<'local>,
+    ) -> Result<(), AddQueryError> {
+        let isolate = Isolate::get(isolate_id).map_err(QueryParseError::from)?;
+        let query_str = read_query_source(env, query_data)?;
+        isolate.add_block_query(language_id, &query_str)
+    }
+    if let Err(err) = inner(&mut env, isolate_id, language_id, query_data) {
+        throw_add_query_error(&mut env, err);
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeLanguageRegistry_nativeAddStatementQuery<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    isolate_id: IsolateId,
+    language_id: LanguageId,
+    query_data: JByteArray<'local>,
+) {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        isolate_id: IsolateId,
+        language_id: LanguageId,
+        query_data: JByteArray<'local>,
+    ) -> Result<(), AddQueryError> {
+        let isolate = Isolate::get(isolate_id).map_err(QueryParseError::from)?;
+        let query_str = read_query_source(env, query_data)?;
+        isolate.add_statement_query(language_id, &query_str)
+    }
+    if let Err(err) = inner(&mut env, isolate_id, language_id, query_data) {
+        throw_add_query_error(&mut env, err);
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeLanguageRegistry_nativeAddIndentQuery<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    isolate_id: IsolateId,
+    language_id: LanguageId,
+    query_data: JByteArray<'local>,
+) {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        isolate_id: IsolateId,
+        language_id: LanguageId,
+        query_data: JByteArray<'local>,
+    ) -> Result<(), AddQueryError> {
+        let isolate = Isolate::get(isolate_id).map_err(QueryParseError::from)?;
+        let query_str = read_query_source(env, query_data)?;
+        isolate.add_indent_query(language_id, &query_str)
+    }
+    if let Err(err) = inner(&mut env, isolate_id, language_id, query_data) {
+        throw_add_query_error(&mut env, err);
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeLanguageRegistry_nativeAddFormatQuery<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    isolate_id: IsolateId,
+    language_id: LanguageId,
+    query_data: JByteArray<'local>,
+) {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        isolate_id: IsolateId,
+        language_id: LanguageId,
+        query_data: JByteArray<'local>,
+    ) -> Result<(), AddQueryError> {
+        let isolate = Isolate::get(isolate_id).map_err(QueryParseError::from)?;
+        let query_str = read_query_source(env, query_data)?;
+        isolate.add_format_query(language_id, &query_str)
+    }
+    if let Err(err) = inner(&mut env, isolate_id, language_id, query_data) {
+        throw_add_query_error(&mut env, err);
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeLanguageRegistry_nativeAddInjectionQuery<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    isolate_id: IsolateId,
+    language_id: LanguageId,
+    query_data: JByteArray<'local>,
+) {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        isolate_id: IsolateId,
+        language_id: LanguageId,
+        query_data: JByteArray<'local>,
+    ) -> Result<(), AddQueryError> {
+        let isolate = Isolate::get(isolate_id).map_err(QueryParseError::from)?;
+        let query_str = read_query_source(env, query_data)?;
+        isolate.add_injection_query(language_id, &query_str)
+    }
+    if let Err(err) = inner(&mut env, isolate_id, language_id, query_data) {
+        throw_add_query_error(&mut env, err);
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeLanguageRegistry_nativeAddTagsQuery<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    isolate_id: IsolateId,
+    language_id: LanguageId,
+    query_data: JByteArray<'local>,
+) {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        isolate_id: IsolateId,
+        language_id: LanguageId,
+        query_data: JByteArray<'local>,
+    ) -> Result<(), AddQueryError> {
+        let isolate = Isolate::get(isolate_id).map_err(QueryParseError::from)?;
+        let query_str = read_query_source(env, query_data)?;
+        isolate.add_tags_query(language_id, &query_str)
+    }
+    if let Err(err) = inner(&mut env, isolate_id, language_id, query_data) {
+        throw_add_query_error(&mut env, err);
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeLanguageRegistry_nativeAddLocalsQuery<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    isolate_id: IsolateId,
+    language_id: LanguageId,
+    query_data: JByteArray<'local>,
+) {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        isolate_id: IsolateId,
+        language_id: LanguageId,
+        query_data: JByteArray<'local>,
+    ) -> Result<(), AddQueryError> {
+        let isolate = Isolate::get(isolate_id).map_err(QueryParseError::from)?;
+        let query_str = read_query_source(env, query_data)?;
+        isolate.add_locals_query(language_id, &query_str)
+    }
+    if let Err(err) = inner(&mut env, isolate_id, language_id, query_data) {
+        throw_add_query_error(&mut env, err);
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeLanguageRegistry_nativeAddBracketsQuery<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    isolate_id: IsolateId,
+    language_id: LanguageId,
+    query_data: JByteArray<'local>,
+) {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        isolate_id: IsolateId,
+        language_id: LanguageId,
+        query_data: JByteArray<'local>,
+    ) -> Result<(), AddQueryError> {
+        let isolate = Isolate::get(isolate_id).map_err(QueryParseError::from)?;
+        let query_str = read_query_source(env, query_data)?;
+        isolate.add_brackets_query(language_id, &query_str)
+    }
+    if let Err(err) = inner(&mut env, isolate_id, language_id, query_data) {
+        throw_add_query_error(&mut env, err);
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeLanguageRegistry_nativeAddOutlineQuery<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    isolate_id: IsolateId,
+    language_id: LanguageId,
+    query_data: JByteArray<'local>,
+) {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        isolate_id: IsolateId,
+        language_id: LanguageId,
+        query_data: JByteArray<'local>,
+    ) -> Result<(), AddQueryError> {
+        let isolate = Isolate::get(isolate_id).map_err(QueryParseError::from)?;
+        let query_str = read_query_source(env, query_data)?;
+        isolate.add_outline_query(language_id, &query_str)
+    }
+    if let Err(err) = inner(&mut env, isolate_id, language_id, query_data) {
+        throw_add_query_error(&mut env, err);
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeLanguageRegistry_nativeAddIndentRulesQuery<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    isolate_id: IsolateId,
+    language_id: LanguageId,
+    query_data: JByteArray<'local>,
+) {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        isolate_id: IsolateId,
+        language_id: LanguageId,
+        query_data: JByteArray<'local>,
+    ) -> Result<(), AddQueryError> {
+        let isolate = Isolate::get(isolate_id).map_err(QueryParseError::from)?;
+        let query_str = read_query_source(env, query_data)?;
+        isolate.add_indent_rules_query(language_id, &query_str)
+    }
+    if let Err(err) = inner(&mut env, isolate_id, language_id, query_data) {
+        throw_add_query_error(&mut env, err);
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeLanguageRegistry_nativeAddSplitQuery<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    isolate_id: IsolateId,
+    language_id: LanguageId,
+    query_data: JByteArray<'local>,
+) {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        isolate_id: IsolateId,
+        language_id: LanguageId,
+        query_data: JByteArray<'local>,
+    ) -> Result<(), AddQueryError> {
+        let isolate = Isolate::get(isolate_id).map_err(QueryParseError::from)?;
+        let query_str = read_query_source(env, query_data)?;
+        isolate.add_split_query(language_id, &query_str)
+    }
+    if let Err(err) = inner(&mut env, isolate_id, language_id, query_data) {
+        throw_add_query_error(&mut env, err);
+    }
+}
+
+/// Non-positive `max_size` removes the limit, size is in chars
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeLanguageRegistry_nativeSetMaxInjectionSize<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    isolate_id: IsolateId,
+    language_id: LanguageId,
+    max_size: jint,
+) {
+    let max_size = (max_size > 0).then_some((max_size as usize) * 2);
+    let result = Isolate::get(isolate_id)
+        .map_err(|err| err.to_string())
+        .and_then(|isolate| {
+            isolate
+                .set_max_injection_size(language_id, max_size)
+                .map_err(|err| err.to_string())
+        });
+    if let Err(err) = result {
+        env.throw_new(
+            "java/lang/IllegalArgumentException",
+            format!("Failed to set max injection size: {err}"),
+        )
+        .unwrap();
+    }
+}
+
+/// Maps capture names of the language highlights to `groupIds`, null arrays remove the mapping
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeLanguageRegistry_nativeSetHighlightGroups<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    isolate_id: IsolateId,
+    language_id: LanguageId,
+    capture_names: JObjectArray<'local>,
+    group_ids: JShortArray<'local>,
+) {
+    fn read_mapping<'local>(
+        env: &mut JNIEnv<'local>,
+        capture_names: &JObjectArray<'local>,
+        group_ids: &JShortArray<'local>,
+    ) -> Result<Option<Vec<(String, u16)>>, JNIError> {
+        if capture_names.is_null() || group_ids.is_null() {
+            return Ok(None);
+        }
+        let capture_names = read_string_array(env, capture_names)?;
+        let mut group_ids_buf = vec![0i16; env.get_array_length(group_ids)? as usize];
+        env.get_short_array_region(group_ids, 0, &mut group_ids_buf)?;
+        Ok(Some(
+            capture_names
+                .into_iter()
+                .zip(group_ids_buf.into_iter().map(|group_id| group_id as u16))
+                .collect(),
+        ))
+    }
+    let result = Isolate::get(isolate_id)
+        .map_err(|err| err.to_string())
+        .and_then(|isolate| {
+            let mapping = read_mapping(&mut env, &capture_names, &group_ids)
+                .map_err(|err| err.to_string())?;
+            isolate
+                .set_highlight_groups(
+                    language_id,
+                    mapping.as_ref().map(|mapping| {
+                        mapping
+                            .iter()
+                            .map(|(capture_name, group_id)| (capture_name.as_str(), *group_id))
+                    }),
+                )
+                .map_err(|err| err.to_string())
+        });
+    if let Err(err) = result {
+        if env.exception_check().unwrap_or(true) {
+            return;
+        }
+        env.throw_new(
+            "java/lang/IllegalArgumentException",
+            format!("Failed to set highlight groups: {err}"),
+        )
+        .unwrap();
+    }
+}
+
+/// Routes injections of unregistered languages to
+/// `listener.onUnknownLanguage(String name, String mimetype, int start, int end)`, one of name and
+/// mimetype is null. Null listener removes it.
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeLanguageRegistry_nativeSetUnknownLanguageListener<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    isolate_id: IsolateId,
+    listener: JObject<'local>,
+) {
+    let isolate = match Isolate::get(isolate_id) {
+        Ok(isolate) => isolate,
+        Err(err) => {
+            env.throw_new(
+                "java/lang/IllegalStateException",
+                format!("Failed to set unknown language listener: {err}"),
+            )
+            .unwrap();
+            return;
+        }
+    };
+    if listener.is_null() {
+        isolate.set_unknown_language_listener(None);
+        return;
+    }
+    let (Ok(vm), Ok(listener)) = (env.get_java_vm(), env.new_global_ref(listener)) else {
+        return;
+    };
+    isolate.set_unknown_language_listener(Some(Box::new(
+        move |language: &UnknownLanguage, range| {
+            let Ok(mut env) = vm.attach_current_thread() else {
+                return;
+            };
+            if env.exception_check().unwrap_or(true) {
+                return;
+            }
+            let (name, mimetype) = match language {
+                UnknownLanguage::LanguageName(name) => (Some(name), None),
+                UnknownLanguage::LanguageMimetype(mimetype) => (None, Some(mimetype)),
+            };
+            // Parse may notify many times within a single native call, local references are freed
+            // after each notification
+            let result = env.with_local_frame(2, |env| {
+                let name = match name {
+                    Some(name) => env.new_string(name)?.into(),
+                    None => JObject::null(),
+                };
+                let mimetype = match mimetype {
+                    Some(mimetype) => env.new_string(mimetype)?.into(),
+                    None => JObject::null(),
+                };
+                env.call_method(
+                    &listener,
+                    "onUnknownLanguage",
+                    "(Ljava/lang/String;Ljava/lang/String;II)V",
+                    &[
+                        JValue::Object(&name),
+                        JValue::Object(&mimetype),
+                        JValue::Int(range.start as i32),
+                        JValue::Int(range.end as i32),
+                    ],
+                )?;
+                Ok::<_, JNIError>(())
+            });
+            if result.is_err() {
+                // Raised by the listener as none was pending before, it must never leak into the
+                // parse call
+                let _ = env.exception_clear();
+            }
+        },
+    )));
+}
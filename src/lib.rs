@@ -1,20 +1,105 @@
+#[cfg(feature = "jni")]
 use std::ffi::c_void;
 
+#[cfg(feature = "jni")]
 use jni::{sys::jint, JavaVM};
 
+mod brackets;
+#[cfg(feature = "capi")]
+pub mod c_api;
+mod char_pairs;
+pub mod config;
+mod documents;
 mod highlighting_lexer;
+mod identifiers;
+mod indent;
 mod injections;
+mod invariants;
+mod isolate;
+#[cfg(feature = "jni")]
 pub mod jni_utils;
+mod language_detection;
 mod language_registry;
+mod locals;
+pub mod logging;
+mod nodes;
+mod outline;
+mod parse_diagnostics;
 mod predicates;
+pub mod profiler;
 mod query;
+pub mod query_budget;
 mod ranges;
+mod selection;
+mod smart_enter;
+mod spellcheck;
+mod structural_diff;
+mod structural_replace;
 mod syntax_snapshot;
+mod tags;
+#[cfg(test)]
+mod test_utils;
+mod textmate_scopes;
+mod user_query;
 
+pub use brackets::{
+    collect_bracket_pairs, find_matching_delimiter, BracketPair, BracketsQuery, BracketsQueryError,
+};
+pub use char_pairs::{char_pairs_at, CharPair};
+pub use documents::{Document, DocumentEdit, DocumentError, DocumentId};
+pub use highlighting_lexer::{
+    changed_highlight_tokens, highlight_line_tokens, highlight_restart_offsets, highlight_spans,
+    highlight_token_groups,
+    query::{highlight_token_conceals, highlight_tokens_cover, highlight_tokens_cover_cached},
+    HighlightSpan, HighlightToken, NO_HIGHLIGHT_GROUP,
+};
+pub use identifiers::collect_identifiers;
+pub use indent::{compute_indent, ComputedIndent, IndentQuery, IndentQueryError};
 pub use injections::InjectionQuery;
-pub use language_registry::{with_language, with_language_by_name, Language, LanguageId};
-pub use ranges::RangesQuery;
+pub use isolate::{Isolate, IsolateError, IsolateId};
+pub use language_registry::{
+    parse_query, AddQueryError, Language, LanguageError, LanguageId, QueryParseError, QueryRole,
+    UnknownLanguage, UnknownLanguageListener,
+};
+pub use locals::{LocalsQuery, LocalsQueryError};
+pub use nodes::SnapshotNode;
+pub use outline::{
+    collect_outline_items, document_outline, update_outline, OutlineDelta, OutlineItem,
+    OutlineQuery, OutlineQueryError, OutlineSymbol,
+};
+pub use parse_diagnostics::{collect_parse_diagnostics, ParseDiagnostic};
+pub use predicates::{
+    AdditionalPredicates, Predicate, PredicateCost, PredicateMode, PredicateParser,
+    TextProviderPredicate, UnknownPredicate,
+};
+pub use query::{
+    LineIndex, MatchProperty, PatternMetadata, QueryMetadata, SourceText, SourceTextChunk,
+    SourceTextProvider, Utf16Chunks,
+};
+pub use ranges::{
+    collect_changed_fold_ranges, collect_code_blocks, collect_context_ranges, collect_fold_ranges,
+    collect_format_ranges, collect_indent_ranges, collect_named_ranges, collect_statement_ranges,
+    CodeBlock, ContextRange, FoldRange, PatternRange, RangesQuery, RangesQueryError,
+};
+pub use selection::{selection_ranges, word_range_at};
+pub use smart_enter::{collect_split_points, SplitPoint, SplitQuery, SplitQueryError};
+pub use spellcheck::collect_spellcheck_ranges;
+pub use structural_diff::{
+    diff_snapshot_structure, diff_snapshots, SyntaxChange, SyntaxChangeKind,
+};
+pub use structural_replace::{compute_replacements, ReplacementTemplate, TemplateError, TextEdit};
+pub use syntax_snapshot::{
+    InjectionLanguage, InjectionRange, ParseCancellation, ParseOptions, ParseStats,
+    SnapshotNodesIter, SyntaxSnapshot, SyntaxSnapshotTreeCursor,
+};
+pub use tags::{collect_tags, collect_tags_in_range, Tag, TagsQuery, TagsQueryError};
+pub use textmate_scopes::{highlight_token_scopes, TextMateScopes, NO_SCOPE};
+pub use user_query::{
+    execute_query, execute_query_diagnostics, profile_query, DiagnosticCapture, DiagnosticMatch,
+    PatternProfile, PredicateOutcome, UserQuery, UserQueryCapture, UserQueryMatch,
+};
 
+#[cfg(feature = "jni")]
 unsafe extern "system" {
     // Linked from tree-sitter-ng, registers native methods for it
     fn tree_sitter_ng_JNI_OnLoad(vm: *mut jni::sys::JavaVM, reserved: *const c_void) -> jint;
@@ -22,9 +107,19 @@ unsafe extern "system" {
 
 /// # Safety
 /// Function is called from already unsafe JNI context
+#[cfg(feature = "jni")]
 #[no_mangle]
 pub unsafe extern "system" fn JNI_OnLoad(vm: JavaVM, reserved: *const c_void) -> jint {
     let val = unsafe { tree_sitter_ng_JNI_OnLoad(vm.get_java_vm_pointer(), reserved) };
 
     jni::sys::JNI_VERSION_1_2.max(val)
 }
+
+/// # Safety
+/// Function is called from already unsafe JNI context
+#[cfg(feature = "jni")]
+#[no_mangle]
+pub unsafe extern "system" fn JNI_OnUnload(_vm: JavaVM, _reserved: *const c_void) {
+    // Classloader owning the library is gone, no isolate can be reached from java anymore
+    Isolate::destroy_all();
+}
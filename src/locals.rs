@@ -0,0 +1,472 @@
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+    sync::{Arc, Mutex},
+};
+
+use streaming_iterator::StreamingIterator as _;
+use tree_sitter as ts;
+
+use crate::{
+    predicates::AdditionalPredicates,
+    query::{pooled_query_cursor, SourceText, SourceTextProvider},
+    query_budget,
+    syntax_snapshot::SyntaxSnapshotEntry,
+};
+
+#[derive(thiserror::Error, Debug)]
+pub enum LocalsQueryError {
+    #[error("required captures not found")]
+    NoRequiredCaptures,
+}
+
+/// Query following tree-sitter locals convention: `@local.scope` nodes containing
+/// `@local.definition` (or `@local.definition.<kind>`) and `@local.reference` identifiers.
+pub struct LocalsQuery {
+    query: ts::Query,
+    predicates: AdditionalPredicates,
+    scope_capture_id: Option<u32>,
+    definition_capture_ids: Vec<u32>,
+    reference_capture_id: Option<u32>,
+}
+
+impl LocalsQuery {
+    pub fn new(
+        query: ts::Query,
+        predicates: AdditionalPredicates,
+    ) -> Result<Self, LocalsQueryError> {
+        let mut scope_capture_id = None;
+        let mut definition_capture_ids = Vec::new();
+        let mut reference_capture_id = None;
+        for (idx, capture_name) in query.capture_names().iter().enumerate() {
+            if *capture_name == "local.scope" {
+                scope_capture_id = Some(idx as u32);
+            } else if *capture_name == "local.reference" {
+                reference_capture_id = Some(idx as u32);
+            } else if *capture_name == "local.definition"
+                || capture_name.starts_with("local.definition.")
+            {
+                definition_capture_ids.push(idx as u32);
+            }
+        }
+        if definition_capture_ids.is_empty() || reference_capture_id.is_none() {
+            return Err(LocalsQueryError::NoRequiredCaptures);
+        }
+        Ok(LocalsQuery {
+            query,
+            predicates,
+            scope_capture_id,
+            definition_capture_ids,
+            reference_capture_id,
+        })
+    }
+
+    pub fn capture_names(&self) -> &[&str] {
+        self.query.capture_names()
+    }
+}
+
+/// Local definitions and references of a snapshot entry, resolved from the start of the entry
+/// up to `resolved_end` and extended on demand by [`LocalsCache::resolve_until`]
+#[derive(Clone, Default)]
+pub(crate) struct LocalBindings {
+    /// Ranges of references mapped to ranges of definitions they resolve to
+    pub(crate) references: HashMap<Range<usize>, Range<usize>>,
+    pub(crate) definitions: HashSet<Range<usize>>,
+    /// Byte range of the entry the bindings were resolved for
+    entry_range: Range<usize>,
+    /// Generation of highlight and locals queries of the isolate the bindings were resolved with
+    queries_generation: u64,
+    /// Captures starting before this byte are resolved, later ones are not
+    resolved_end: usize,
+    /// Definitions of the root scope in the order they were found, so the root scope can be
+    /// rolled back to a checkpoint without copying it
+    root_definitions: Vec<(Box<str>, Range<usize>)>,
+    root_scope: HashMap<Box<str>, Range<usize>>,
+    /// Scopes open at `resolved_end` except the root one, innermost last
+    open_scopes: Vec<LocalScope>,
+    /// Starts with the entry start, so there is always a checkpoint to roll back to
+    checkpoints: Vec<Checkpoint>,
+}
+
+#[derive(Clone)]
+struct LocalScope {
+    start_byte: usize,
+    end_byte: usize,
+    definitions: HashMap<Box<str>, Range<usize>>,
+    /// Scope was restored from a checkpoint and its node is not found again yet
+    restored: bool,
+}
+
+/// Resolution state before the captures starting at `byte`
+#[derive(Clone)]
+struct Checkpoint {
+    byte: usize,
+    root_definitions: usize,
+    open_scopes: Vec<LocalScope>,
+}
+
+/// Minimal distance in bytes between checkpoints, bounds both the work redone after an edit
+/// and the memory taken by copies of open scopes
+const CHECKPOINT_SPACING: usize = 4096;
+
+impl LocalBindings {
+    fn has_property(&self, key: &str, range: &Range<usize>) -> Option<bool> {
+        Some(match key {
+            "local" => self.definitions.contains(range) || self.references.contains_key(range),
+            "local.definition" => self.definitions.contains(range),
+            "local.reference" => self.references.contains_key(range),
+            _ => return None,
+        })
+    }
+
+    /// Checks `#is?`/`#is-not?` predicates of the match pattern on `local`, `local.definition`
+    /// and `local.reference` properties, e.g. `(#is-not? local)`. Predicates without a capture
+    /// argument apply to all captured nodes of the match, other properties are ignored.
+    pub(crate) fn satisfies_property_predicates(
+        &self,
+        query: &ts::Query,
+        query_match: &ts::QueryMatch,
+    ) -> bool {
+        query
+            .property_predicates(query_match.pattern_index)
+            .iter()
+            .all(|(property, is_positive)| {
+                let mut nodes = query_match
+                    .captures
+                    .iter()
+                    .filter(|capture| {
+                        property
+                            .capture_id
+                            .is_none_or(|id| capture.index as usize == id)
+                    })
+                    .map(|capture| capture.node);
+                nodes.all(|node| {
+                    self.has_property(&property.key, &node.byte_range())
+                        .is_none_or(|has_property| has_property == *is_positive)
+                })
+            })
+    }
+}
+
+/// Bindings of snapshot entries by entry id. Lives in the snapshot next to the highlight cache
+/// and is carried to snapshots parsed from it, so highlight requests resolve locals only up to
+/// the end of the requested range and only past the last edit.
+#[derive(Default)]
+pub(crate) struct LocalsCache {
+    bindings: Mutex<HashMap<u64, Arc<LocalBindings>>>,
+}
+
+impl LocalsCache {
+    /// Bindings of the entry with `root` node, resolved at least up to `until` byte unless the
+    /// locals query is cut short. Bindings of other `queries_generation` are resolved again.
+    pub(crate) fn resolve_until(
+        &self,
+        entry: &SyntaxSnapshotEntry,
+        query: &LocalsQuery,
+        queries_generation: u64,
+        root: ts::Node,
+        text: SourceText<'_>,
+        until: usize,
+    ) -> Arc<LocalBindings> {
+        let mut cache = self.bindings.lock().unwrap();
+        let bindings = cache.entry(entry.id).or_default();
+        if bindings.checkpoints.is_empty() || bindings.queries_generation != queries_generation {
+            *bindings = Arc::new(LocalBindings::new(
+                entry.byte_range.clone(),
+                queries_generation,
+            ));
+        }
+        if bindings.resolved_end < until.min(root.end_byte()) {
+            Arc::make_mut(bindings).resolve(query, root, text, until.min(root.end_byte()));
+        }
+        Arc::clone(bindings)
+    }
+
+    /// Cache for the snapshot parsed from this one with an edit, keeps bindings of `entries`
+    /// resolved before `changed_from` byte, the first byte changed by the edit
+    pub(crate) fn carry(
+        &self,
+        entries: &[SyntaxSnapshotEntry],
+        changed_from: usize,
+    ) -> LocalsCache {
+        let old_bindings = self.bindings.lock().unwrap();
+        let mut bindings = HashMap::new();
+        for entry in entries {
+            let Some(old) = old_bindings.get(&entry.id) else {
+                continue;
+            };
+            if old.entry_range == entry.byte_range && changed_from > entry.byte_range.end {
+                bindings.insert(entry.id, Arc::clone(old));
+            } else if old.entry_range.start == entry.byte_range.start
+                && changed_from >= entry.byte_range.start
+            {
+                let mut carried = LocalBindings::clone(old);
+                carried.entry_range = entry.byte_range.clone();
+                carried.rollback(changed_from);
+                bindings.insert(entry.id, Arc::new(carried));
+            }
+        }
+        LocalsCache {
+            bindings: Mutex::new(bindings),
+        }
+    }
+}
+
+impl LocalBindings {
+    fn new(entry_range: Range<usize>, queries_generation: u64) -> Self {
+        LocalBindings {
+            resolved_end: entry_range.start,
+            checkpoints: vec![Checkpoint {
+                byte: entry_range.start,
+                root_definitions: 0,
+                open_scopes: Vec::new(),
+            }],
+            entry_range,
+            queries_generation,
+            ..LocalBindings::default()
+        }
+    }
+
+    /// Resolves captures starting from `resolved_end` up to `until` byte. Definitions are looked
+    /// up in enclosing scopes and must precede the reference. If locals query is cut short,
+    /// the bindings are rolled back to the last checkpoint reached.
+    fn resolve(&mut self, query: &LocalsQuery, root: ts::Node, text: SourceText<'_>, until: usize) {
+        let text_provider = SourceTextProvider::new(text);
+        let mut cursor = pooled_query_cursor();
+        cursor.set_byte_range(self.resolved_end..until);
+        query_budget::prepare_cursor(&mut cursor);
+        let mut captures = cursor.captures(&query.query, root, &text_provider);
+        let mut confirmed_scopes = false;
+        while let Some((query_match, capture_idx)) = captures.next() {
+            if !query
+                .predicates
+                .satisfies_predicates(&text_provider, query_match)
+            {
+                query_match.remove();
+                continue;
+            }
+            let capture = query_match.captures[*capture_idx];
+            let node_range = capture.node.byte_range();
+            let is_scope = Some(capture.index) == query.scope_capture_id;
+            if node_range.start < self.resolved_end {
+                // Scopes open at a checkpoint get their ends from the current tree, an edit
+                // after the checkpoint may have moved them
+                if is_scope {
+                    if let Some(scope) = self
+                        .open_scopes
+                        .iter_mut()
+                        .find(|scope| scope.restored && scope.start_byte == node_range.start)
+                    {
+                        scope.end_byte = node_range.end;
+                        scope.restored = false;
+                    }
+                }
+                continue;
+            }
+            if node_range.start >= until {
+                continue;
+            }
+            if !confirmed_scopes {
+                // Scopes not found again are gone from the tree
+                self.open_scopes.retain(|scope| !scope.restored);
+                confirmed_scopes = true;
+            }
+            while self
+                .open_scopes
+                .last()
+                .is_some_and(|scope| scope.end_byte <= node_range.start)
+            {
+                self.open_scopes.pop();
+            }
+            let last_checkpoint = self
+                .checkpoints
+                .last()
+                .map_or(0, |checkpoint| checkpoint.byte);
+            if node_range.start >= last_checkpoint + CHECKPOINT_SPACING {
+                self.checkpoints.push(Checkpoint {
+                    byte: node_range.start,
+                    root_definitions: self.root_definitions.len(),
+                    open_scopes: self.open_scopes.clone(),
+                });
+            }
+            if is_scope {
+                self.open_scopes.push(LocalScope {
+                    start_byte: node_range.start,
+                    end_byte: node_range.end,
+                    definitions: HashMap::new(),
+                    restored: false,
+                });
+            } else if query.definition_capture_ids.contains(&capture.index) {
+                let name: Box<str> = text.text_for_byte_range(node_range.clone()).into();
+                self.definitions.insert(node_range.clone());
+                if let Some(scope) = self.open_scopes.last_mut() {
+                    scope.definitions.insert(name, node_range);
+                } else {
+                    self.root_scope.insert(name.clone(), node_range.clone());
+                    self.root_definitions.push((name, node_range));
+                }
+            } else if Some(capture.index) == query.reference_capture_id {
+                let name = text.text_for_byte_range(node_range.clone());
+                let definition = self
+                    .open_scopes
+                    .iter()
+                    .rev()
+                    .map(|scope| &scope.definitions)
+                    .chain([&self.root_scope])
+                    .find_map(|definitions| definitions.get(name.as_ref()));
+                if let Some(definition) = definition {
+                    if *definition != node_range {
+                        self.references.insert(node_range, definition.clone());
+                    }
+                }
+            }
+        }
+        if query_budget::finish_cursor(&cursor) {
+            self.rollback(usize::MAX);
+        } else {
+            self.resolved_end = until;
+        }
+    }
+
+    /// Drops everything resolved after the last checkpoint before `byte`
+    fn rollback(&mut self, byte: usize) {
+        let checkpoint_idx = self
+            .checkpoints
+            .partition_point(|checkpoint| checkpoint.byte < byte)
+            .max(1)
+            - 1;
+        self.checkpoints.truncate(checkpoint_idx + 1);
+        let checkpoint = &self.checkpoints[checkpoint_idx];
+        self.resolved_end = checkpoint.byte;
+        self.references
+            .retain(|reference, _| reference.start < checkpoint.byte);
+        self.definitions
+            .retain(|definition| definition.start < checkpoint.byte);
+        self.root_definitions.truncate(checkpoint.root_definitions);
+        self.root_scope = self.root_definitions.iter().cloned().collect();
+        self.open_scopes = checkpoint.open_scopes.clone();
+        for scope in &mut self.open_scopes {
+            scope.restored = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tree_sitter as ts;
+
+    use super::*;
+    use crate::{
+        syntax_snapshot::{SyntaxSnapshot, SyntaxSnapshotEntryContent},
+        test_utils::{json_isolate, parse},
+    };
+
+    const LOCALS_QUERY: &str = r#"
+        (object) @local.scope
+        (pair key: (string (string_content) @local.definition))
+        (pair value: (string (string_content) @local.reference))
+    "#;
+
+    fn json_snapshot(text: &str) -> SyntaxSnapshot {
+        let (isolate, language_id) = json_isolate();
+        isolate.add_locals_query(language_id, LOCALS_QUERY).unwrap();
+        parse(&isolate, language_id, text)
+    }
+
+    fn resolve_until(snapshot: &SyntaxSnapshot, text: &str, until: usize) -> Arc<LocalBindings> {
+        let entry = &snapshot.entries[0];
+        let SyntaxSnapshotEntryContent::Parsed { language, tree } = &entry.content else {
+            panic!("base entry is parsed");
+        };
+        let query = snapshot
+            .isolate
+            .with_language(*language, |language| {
+                language.parser_info().locals_query.clone()
+            })
+            .unwrap()
+            .unwrap();
+        snapshot.locals_cache.resolve_until(
+            entry,
+            &query,
+            snapshot.isolate.highlight_queries_generation(),
+            tree.root_node(),
+            SourceText::Utf8(text),
+            until,
+        )
+    }
+
+    fn references(bindings: &LocalBindings, text: &str) -> Vec<(usize, usize)> {
+        let mut references: Vec<_> = bindings
+            .references
+            .iter()
+            .map(|(reference, definition)| {
+                assert_eq!(text[reference.clone()], text[definition.clone()]);
+                (reference.start, definition.start)
+            })
+            .collect();
+        references.sort_unstable();
+        references
+    }
+
+    #[test]
+    fn references_resolve_to_preceding_definitions_of_enclosing_scopes() {
+        let text = r#"{"a": 1, "b": "a", "c": {"d": "a", "a": 2, "e": "a"}, "f": "e"}"#;
+        let snapshot = json_snapshot(text);
+        let bindings = resolve_until(&snapshot, text, text.len());
+        assert_eq!(
+            references(&bindings, text),
+            vec![(15, 2), (31, 2), (49, 36)]
+        );
+        assert_eq!(bindings.definitions.len(), 7);
+    }
+
+    #[test]
+    fn bindings_are_resolved_up_to_requested_byte() {
+        let text = r#"{"a": 1, "b": "a", "c": "b", "d": "c"}"#;
+        let snapshot = json_snapshot(text);
+        let bindings = resolve_until(&snapshot, text, 20);
+        assert_eq!(references(&bindings, text), vec![(15, 2)]);
+        let bindings = resolve_until(&snapshot, text, text.len());
+        assert_eq!(
+            references(&bindings, text),
+            vec![(15, 2), (25, 10), (35, 20)]
+        );
+    }
+
+    #[test]
+    fn bindings_before_edit_are_carried_to_next_snapshot() {
+        let pairs: Vec<String> = (0..1000)
+            .map(|idx| format!(r#""k{idx}": "k{}""#, idx.max(1) - 1))
+            .collect();
+        let text = format!("{{{}}}", pairs.join(", "));
+        let snapshot = json_snapshot(&text);
+        resolve_until(&snapshot, &text, text.len());
+
+        let edit_start = text.rfind("k998").unwrap();
+        let new_text = format!("{}k0{}", &text[..edit_start], &text[edit_start + 4..]);
+        let edit = ts::InputEdit {
+            start_byte: edit_start,
+            old_end_byte: edit_start + 4,
+            new_end_byte: edit_start + 2,
+            start_position: ts::Point::new(0, edit_start),
+            old_end_position: ts::Point::new(0, edit_start + 4),
+            new_end_position: ts::Point::new(0, edit_start + 2),
+        };
+        let (new_snapshot, _) =
+            SyntaxSnapshot::parse_incremental_str(&new_text, &snapshot, edit).unwrap();
+        let carried =
+            new_snapshot.locals_cache.bindings.lock().unwrap()[&new_snapshot.entries[0].id].clone();
+        assert!(carried.resolved_end > CHECKPOINT_SPACING);
+        assert!(carried.resolved_end < edit_start);
+
+        let bindings = resolve_until(&new_snapshot, &new_text, new_text.len());
+        let fresh_snapshot = json_snapshot(&new_text);
+        let fresh_bindings = resolve_until(&fresh_snapshot, &new_text, new_text.len());
+        assert_eq!(
+            references(&bindings, &new_text),
+            references(&fresh_bindings, &new_text)
+        );
+        assert_eq!(bindings.definitions, fresh_bindings.definitions);
+    }
+}
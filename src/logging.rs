@@ -0,0 +1,34 @@
+use std::{
+    fmt::Display,
+    sync::{Arc, RwLock},
+};
+
+#[cfg(feature = "jni")]
+mod jni_methods;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Error = 0,
+    Warn = 1,
+    Info = 2,
+    Debug = 3,
+}
+
+pub type LogSink = Box<dyn Fn(LogLevel, &str) + Send + Sync>;
+
+static LOG_SINK: RwLock<Option<Arc<LogSink>>> = RwLock::new(None);
+
+/// Replaces the sink receiving library messages, messages are dropped while no sink is set
+pub fn set_log_sink(sink: Option<LogSink>) {
+    *LOG_SINK.write().unwrap() = sink.map(Arc::new);
+}
+
+/// Passes the message to the current sink. The sink is called without holding the lock, so it
+/// may log or replace the sink itself
+pub fn log(level: LogLevel, message: impl Display) {
+    let sink = LOG_SINK.read().unwrap().clone();
+    if let Some(sink) = sink {
+        sink(level, &message.to_string());
+    }
+}
@@ -0,0 +1,55 @@
+use jni::{
+    errors::Result as JNIResult,
+    objects::{JClass, JObject, JValue},
+    JNIEnv,
+};
+
+use crate::jni_utils::throw_exception_from_result;
+
+use super::{set_log_sink, LogLevel};
+
+/// Routes library messages to `logger.log(int level, String message)`, null logger disables
+/// logging
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeLogger_nativeSetLogger<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    logger: JObject<'local>,
+) {
+    fn inner<'local>(env: &mut JNIEnv<'local>, logger: JObject<'local>) -> JNIResult<()> {
+        if logger.is_null() {
+            set_log_sink(None);
+            return Ok(());
+        }
+        let vm = env.get_java_vm()?;
+        let logger = env.new_global_ref(logger)?;
+        set_log_sink(Some(Box::new(move |level: LogLevel, message: &str| {
+            let Ok(mut env) = vm.attach_current_thread() else {
+                return;
+            };
+            // Java may not be called with an exception pending in the native call being logged
+            // from, the message is dropped then so the exception reaches its caller
+            if env.exception_check().unwrap_or(true) {
+                return;
+            }
+            let result = env.new_string(message).and_then(|message| {
+                env.call_method(
+                    &logger,
+                    "log",
+                    "(ILjava/lang/String;)V",
+                    &[JValue::Int(level as i32), JValue::Object(&message)],
+                )
+            });
+            if result.is_err() {
+                // No exception was pending before the call, so this one is raised by the logger
+                // and must not leak into the unrelated native call
+                let _ = env.exception_clear();
+            }
+        })));
+        Ok(())
+    }
+    let result = inner(&mut env, logger);
+    throw_exception_from_result(&mut env, result)
+}
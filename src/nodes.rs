@@ -0,0 +1,135 @@
+use tree_sitter as ts;
+
+use crate::{
+    syntax_snapshot::{SyntaxSnapshot, SyntaxSnapshotEntryContent},
+    LanguageId,
+};
+
+#[cfg(feature = "jni")]
+mod jni_methods;
+
+/// Node of one of the snapshot trees along with the entry it belongs to
+#[derive(Debug, Clone, Copy)]
+pub struct SnapshotNode<'tree> {
+    pub(crate) entry_idx: usize,
+    pub language: LanguageId,
+    pub node: ts::Node<'tree>,
+}
+
+impl SyntaxSnapshot {
+    fn entry_tree_root(&self, entry_idx: usize) -> Option<SnapshotNode<'_>> {
+        let entry = &self.entries[entry_idx];
+        let SyntaxSnapshotEntryContent::Parsed { language, tree } = &entry.content else {
+            return None;
+        };
+        Some(SnapshotNode {
+            entry_idx,
+            language: *language,
+            node: tree.root_node_with_offset(entry.byte_offset, entry.point_offset),
+        })
+    }
+
+    /// Smallest node of any snapshot layer which contains `byte_offset`
+    pub fn node_at_offset(&self, byte_offset: usize) -> Option<SnapshotNode<'_>> {
+        let (language, node) = self.innermost_node_at(byte_offset);
+        if byte_offset < node.start_byte() || byte_offset >= node.end_byte() {
+            return None;
+        }
+        let mut root = node;
+        while let Some(parent) = root.parent() {
+            root = parent;
+        }
+        let entry_idx = self.entries.iter().position(|entry| {
+            matches!(
+                &entry.content,
+                SyntaxSnapshotEntryContent::Parsed { tree, .. } if tree.root_node().id() == root.id()
+            )
+        })?;
+        Some(SnapshotNode {
+            entry_idx,
+            language,
+            node,
+        })
+    }
+
+    /// Parent of the node, roots of injected trees have the smallest node of the enclosing
+    /// layer spanning them as a parent
+    pub fn node_parent<'a>(&'a self, node: &SnapshotNode<'a>) -> Option<SnapshotNode<'a>> {
+        if let Some(parent) = node.node.parent() {
+            return Some(SnapshotNode {
+                node: parent,
+                ..*node
+            });
+        }
+        let entry = &self.entries[node.entry_idx];
+        let host_depth = entry.depth.checked_sub(1)?;
+        let (host_idx, _) = self
+            .intersecting_entries_at_depth(host_depth, entry.byte_range.clone())
+            .find(|(_, host)| {
+                host.byte_range.start <= entry.byte_range.start
+                    && host.byte_range.end >= entry.byte_range.end
+                    && matches!(host.content, SyntaxSnapshotEntryContent::Parsed { .. })
+            })?;
+        let host_root = self.entry_tree_root(host_idx)?;
+        Some(SnapshotNode {
+            node: host_root
+                .node
+                .descendant_for_byte_range(entry.byte_range.start, entry.byte_range.end)?,
+            ..host_root
+        })
+    }
+
+    /// Named nodes enclosing `byte_offset` from the outermost one, crossing injection boundaries,
+    /// e.g. for breadcrumbs
+    pub fn node_path_at_offset(&self, byte_offset: usize) -> Vec<SnapshotNode<'_>> {
+        let mut path = Vec::new();
+        let mut node = self.node_at_offset(byte_offset);
+        while let Some(current) = node {
+            if current.node.is_named() {
+                path.push(current);
+            }
+            node = self.node_parent(&current);
+        }
+        path.reverse();
+        path
+    }
+
+    /// Named children of the node, leaf nodes hosting injections have roots of injected trees
+    /// as children, same as for [`crate::SyntaxSnapshotTreeCursor`]
+    pub fn node_named_children<'a>(&'a self, node: &SnapshotNode<'a>) -> Vec<SnapshotNode<'a>> {
+        if node.node.child_count() > 0 {
+            let mut cursor = node.node.walk();
+            return node
+                .node
+                .named_children(&mut cursor)
+                .map(|child| SnapshotNode {
+                    node: child,
+                    ..*node
+                })
+                .collect();
+        }
+        let depth = self.entries[node.entry_idx].depth + 1;
+        self.entries_at_depth_within(depth, node.node.byte_range())
+            .filter_map(|(idx, _)| self.entry_tree_root(idx))
+            .collect()
+    }
+}
+
+impl SnapshotNode<'_> {
+    /// Name of the field node is stored in by its parent of the same tree
+    pub fn field_name(&self) -> Option<&'static str> {
+        let parent = self.node.parent()?;
+        let mut cursor = parent.walk();
+        if !cursor.goto_first_child() {
+            return None;
+        }
+        loop {
+            if cursor.node().id() == self.node.id() {
+                return cursor.field_name();
+            }
+            if !cursor.goto_next_sibling() {
+                return None;
+            }
+        }
+    }
+}
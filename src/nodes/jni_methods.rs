@@ -0,0 +1,280 @@
+use std::sync::Arc;
+
+use jni::{
+    errors::Result as JNIResult,
+    objects::{AutoLocal, JClass, JLongArray, JMethodID, JObject, JObjectArray, JValue},
+    sys::{jint, jlong, jsize},
+    JNIEnv,
+};
+use once_cell::sync::OnceCell as JOnceLock;
+
+use crate::{
+    jni_utils::{throw_exception_from_result, RangeDesc},
+    syntax_snapshot::{SyntaxSnapshot, SyntaxSnapshotDesc},
+};
+
+use super::SnapshotNode;
+
+/// Node handed to java, shares a copy of its snapshot with the nodes reached from it so they
+/// stay valid after the java snapshot object is collected
+struct NodeHandle {
+    node: SnapshotNode<'static>,
+    snapshot: Arc<SyntaxSnapshot>,
+}
+
+impl NodeHandle {
+    /// Boxes handle for the node, returns raw pointer passed to java
+    fn into_java(snapshot: &Arc<SyntaxSnapshot>, node: SnapshotNode<'_>) -> jlong {
+        // SAFETY: node borrows a tree of the snapshot which is kept alive by the handle and is
+        // never mutated
+        let node: SnapshotNode<'static> = unsafe { std::mem::transmute(node) };
+        Box::into_raw(Box::new(NodeHandle {
+            node,
+            snapshot: Arc::clone(snapshot),
+        })) as jlong
+    }
+
+    /// # Safety
+    /// `handle` is created by one of node methods and not destroyed yet
+    unsafe fn from_handle<'a>(handle: jlong) -> &'a NodeHandle {
+        &*(handle as *const NodeHandle)
+    }
+}
+
+/// Returns 0 if there is no node at `offset`
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeNode_nativeNodeAtOffset<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    snapshot: JObject<'local>,
+    offset: jint,
+) -> jlong {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        snapshot: JObject<'local>,
+        offset: jint,
+    ) -> JNIResult<jlong> {
+        let snapshot = Arc::new(SyntaxSnapshotDesc::from_java_object(env, snapshot)?.clone());
+        Ok(snapshot
+            .node_at_offset((offset as usize) * 2)
+            .map_or(0, |node| NodeHandle::into_java(&snapshot, node)))
+    }
+    let result = inner(&mut env, snapshot, offset);
+    throw_exception_from_result(&mut env, result)
+}
+
+static PATH_NODE_CONSTRUCTOR: JOnceLock<JMethodID> = JOnceLock::new();
+
+struct PathNodeDesc<'local> {
+    constructor: JMethodID,
+    class: AutoLocal<'local, JClass<'local>>,
+    range_desc: RangeDesc<'local>,
+}
+
+impl<'local> PathNodeDesc<'local> {
+    fn new(env: &mut JNIEnv<'local>) -> JNIResult<PathNodeDesc<'local>> {
+        let class =
+            env.find_class("com/hulylabs/treesitter/rusty/TreeSitterNativeNode$PathNode")?;
+        let constructor = *PATH_NODE_CONSTRUCTOR.get_or_try_init(|| {
+            env.get_method_id(
+                &class,
+                "<init>",
+                "(Ljava/lang/String;Ljava/lang/String;Lcom/hulylabs/treesitter/language/Range;J)V",
+            )
+        })?;
+        Ok(PathNodeDesc {
+            constructor,
+            class: env.auto_local(class),
+            range_desc: RangeDesc::new(env)?,
+        })
+    }
+
+    fn to_java_object(
+        &self,
+        env: &mut JNIEnv<'local>,
+        node: &SnapshotNode<'_>,
+    ) -> JNIResult<JObject<'local>> {
+        let kind: JObject = env.new_string(node.node.kind())?.into();
+        let kind = env.auto_local(kind);
+        let field_name = match node.field_name() {
+            Some(field_name) => env.new_string(field_name)?.into(),
+            None => JObject::null(),
+        };
+        let field_name = env.auto_local(field_name);
+        let range = self.range_desc.to_java_object(env, node.node.range())?;
+        let range = env.auto_local(range);
+        // SAFETY: constructor is valid and derived from class by construction of self
+        unsafe {
+            env.new_object_unchecked(
+                &self.class,
+                self.constructor,
+                &[
+                    JValue::Object(&kind).as_jni(),
+                    JValue::Object(&field_name).as_jni(),
+                    JValue::Object(&range).as_jni(),
+                    JValue::from(node.language).as_jni(),
+                ],
+            )
+        }
+    }
+}
+
+/// Named nodes enclosing `offset` from the outermost one, crossing injection boundaries
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeNode_nativeNodeAtOffsetPath<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    snapshot: JObject<'local>,
+    offset: jint,
+) -> JObjectArray<'local> {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        snapshot: JObject<'local>,
+        offset: jint,
+    ) -> JNIResult<JObjectArray<'local>> {
+        let snapshot = SyntaxSnapshotDesc::from_java_object(env, snapshot)?;
+        let path = snapshot.node_path_at_offset((offset as usize) * 2);
+        let desc = PathNodeDesc::new(env)?;
+        let array = env.new_object_array(path.len() as jsize, &desc.class, JObject::null())?;
+        for (index, node) in path.iter().enumerate() {
+            let obj = desc.to_java_object(env, node)?;
+            let obj = env.auto_local(obj);
+            env.set_object_array_element(&array, index as jsize, obj)?;
+        }
+        Ok(array)
+    }
+    let result = inner(&mut env, snapshot, offset);
+    throw_exception_from_result(&mut env, result)
+}
+
+/// Returns 0 for the root of the base language tree
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeNode_nativeParent<
+    'local,
+>(
+    _env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+) -> jlong {
+    // SAFETY: handle is passed by java node object which is not destroyed yet
+    let handle = unsafe { NodeHandle::from_handle(handle) };
+    handle
+        .snapshot
+        .node_parent(&handle.node)
+        .map_or(0, |node| NodeHandle::into_java(&handle.snapshot, node))
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeNode_nativeNamedChildren<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+) -> JLongArray<'local> {
+    // SAFETY: handle is passed by java node object which is not destroyed yet
+    let handle = unsafe { NodeHandle::from_handle(handle) };
+    let children: Vec<jlong> = handle
+        .snapshot
+        .node_named_children(&handle.node)
+        .into_iter()
+        .map(|node| NodeHandle::into_java(&handle.snapshot, node))
+        .collect();
+    let result = env
+        .new_long_array(children.len() as jsize)
+        .and_then(|array| {
+            env.set_long_array_region(&array, 0, &children)?;
+            Ok(array)
+        });
+    throw_exception_from_result(&mut env, result)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeNode_nativeKindId<
+    'local,
+>(
+    _env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+) -> jint {
+    // SAFETY: handle is passed by java node object which is not destroyed yet
+    let handle = unsafe { NodeHandle::from_handle(handle) };
+    handle.node.node.kind_id() as jint
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeNode_nativeKind<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+) -> JObject<'local> {
+    // SAFETY: handle is passed by java node object which is not destroyed yet
+    let handle = unsafe { NodeHandle::from_handle(handle) };
+    let result = env.new_string(handle.node.node.kind()).map(JObject::from);
+    throw_exception_from_result(&mut env, result)
+}
+
+/// Returns null if node is not stored in a field of its parent
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeNode_nativeFieldName<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+) -> JObject<'local> {
+    // SAFETY: handle is passed by java node object which is not destroyed yet
+    let handle = unsafe { NodeHandle::from_handle(handle) };
+    let result = match handle.node.field_name() {
+        Some(field_name) => env.new_string(field_name).map(JObject::from),
+        None => Ok(JObject::null()),
+    };
+    throw_exception_from_result(&mut env, result)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeNode_nativeRange<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+) -> JObject<'local> {
+    // SAFETY: handle is passed by java node object which is not destroyed yet
+    let handle = unsafe { NodeHandle::from_handle(handle) };
+    let range = handle.node.node.range();
+    let result = RangeDesc::new(&mut env).and_then(|desc| desc.to_java_object(&mut env, range));
+    throw_exception_from_result(&mut env, result)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeNode_nativeLanguage<
+    'local,
+>(
+    _env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+) -> jlong {
+    // SAFETY: handle is passed by java node object which is not destroyed yet
+    let handle = unsafe { NodeHandle::from_handle(handle) };
+    handle.node.language.into()
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeNode_nativeDestroy<
+    'local,
+>(
+    _env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+) {
+    let ptr = handle as *mut NodeHandle;
+    // SAFETY: handle is created from Box::into_raw, called by java when the node is released
+    std::mem::drop(unsafe { Box::from_raw(ptr) });
+}
@@ -0,0 +1,383 @@
+use std::{ops::Range, sync::Arc};
+
+use streaming_iterator::StreamingIterator as _;
+use tree_sitter as ts;
+
+use crate::{
+    predicates::AdditionalPredicates,
+    profiler,
+    query::{pooled_query_cursor, SourceText, SourceTextProvider},
+    syntax_snapshot::{SyntaxSnapshot, SyntaxSnapshotEntryContent},
+    tags::{collect_tags, collect_tags_in_range, Tag},
+    LanguageId,
+};
+
+#[cfg(feature = "jni")]
+mod jni_methods;
+
+#[derive(thiserror::Error, Debug)]
+pub enum OutlineQueryError {
+    #[error("required captures not found")]
+    NoRequiredCaptures,
+}
+
+/// Query capturing structure view items as `@item` with their `@name` and optional `@context`
+/// nodes (e.g. keywords) shown before the name. Kind of the item is set by `kind` pattern property
+/// and defaults to the kind of the item node.
+pub struct OutlineQuery {
+    query: ts::Query,
+    predicates: AdditionalPredicates,
+    item_capture_id: u32,
+    name_capture_id: u32,
+    context_capture_id: Option<u32>,
+}
+
+impl OutlineQuery {
+    pub fn new(
+        query: ts::Query,
+        predicates: AdditionalPredicates,
+    ) -> Result<Self, OutlineQueryError> {
+        let (Some(item_capture_id), Some(name_capture_id)) = (
+            query.capture_index_for_name("item"),
+            query.capture_index_for_name("name"),
+        ) else {
+            return Err(OutlineQueryError::NoRequiredCaptures);
+        };
+        let context_capture_id = query.capture_index_for_name("context");
+        Ok(OutlineQuery {
+            query,
+            predicates,
+            item_capture_id,
+            name_capture_id,
+            context_capture_id,
+        })
+    }
+
+    pub fn capture_names(&self) -> &[&str] {
+        self.query.capture_names()
+    }
+}
+
+/// Item of the structure view produced by outline queries
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutlineItem {
+    pub language_id: LanguageId,
+    pub kind: Box<str>,
+    /// Text of context and name nodes joined with spaces
+    pub name: Box<str>,
+    pub range: ts::Range,
+    pub name_range: ts::Range,
+    /// Number of items enclosing this one
+    pub depth: usize,
+}
+
+/// Outline items of all snapshot layers sorted by start, with depth giving the hierarchy
+pub fn collect_outline_items(snapshot: &SyntaxSnapshot, text: SourceText<'_>) -> Vec<OutlineItem> {
+    let _profile = profiler::call(&snapshot.isolate, "outline");
+    let text_provider = SourceTextProvider::new(text);
+    let mut items = Vec::new();
+    for (_, entry) in snapshot.intersecting_entries(0..text.byte_len(), true) {
+        let SyntaxSnapshotEntryContent::Parsed { language, tree } = &entry.content else {
+            continue;
+        };
+        let query = snapshot.isolate.with_language(*language, |language| {
+            language.parser_info().outline_query.clone()
+        });
+        let Ok(Some(query)) = query else {
+            continue;
+        };
+        let mut cursor = pooled_query_cursor();
+        let mut matches = cursor.matches(
+            &query.query,
+            tree.root_node_with_offset(entry.byte_offset, entry.point_offset),
+            &text_provider,
+        );
+        while let Some(query_match) = matches.next() {
+            if !query
+                .predicates
+                .satisfies_predicates(&text_provider, query_match)
+            {
+                continue;
+            }
+            let (Some(item), Some(name)) = (
+                query_match
+                    .nodes_for_capture_index(query.item_capture_id)
+                    .next(),
+                query_match
+                    .nodes_for_capture_index(query.name_capture_id)
+                    .next(),
+            ) else {
+                continue;
+            };
+            let mut name_nodes: Vec<ts::Node> = query
+                .context_capture_id
+                .map(|capture_id| query_match.nodes_for_capture_index(capture_id).collect())
+                .unwrap_or_default();
+            name_nodes.push(name);
+            name_nodes.sort_by_key(|node| node.start_byte());
+            let name_text = name_nodes
+                .iter()
+                .map(|node| text.text_for_byte_range(node.byte_range()))
+                .collect::<Vec<_>>()
+                .join(" ");
+            let kind = query
+                .predicates
+                .metadata()
+                .pattern(query_match.pattern_index)
+                .value("kind")
+                .unwrap_or(item.kind())
+                .into();
+            items.push(OutlineItem {
+                language_id: *language,
+                kind,
+                name: name_text.into(),
+                range: item.range(),
+                name_range: name.range(),
+                depth: 0,
+            });
+        }
+    }
+    items.sort_by_key(|item| {
+        (
+            item.range.start_byte,
+            std::cmp::Reverse(item.range.end_byte),
+        )
+    });
+    items.dedup_by(|item, other| item.range == other.range && item.name_range == other.name_range);
+    let mut enclosing_ends: Vec<usize> = Vec::new();
+    for item in &mut items {
+        while enclosing_ends
+            .last()
+            .is_some_and(|end| *end <= item.range.start_byte)
+        {
+            enclosing_ends.pop();
+        }
+        item.depth = enclosing_ends.len();
+        enclosing_ends.push(item.range.end_byte);
+    }
+    items
+}
+
+/// Definition tag of the document outline
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutlineSymbol {
+    pub language_id: LanguageId,
+    pub kind: Box<str>,
+    pub name: Box<str>,
+    pub range: ts::Range,
+    pub name_range: ts::Range,
+}
+
+impl OutlineSymbol {
+    fn from_tag(tag: Tag, text: SourceText<'_>) -> Option<Self> {
+        if !tag.is_definition {
+            return None;
+        }
+        Some(OutlineSymbol {
+            language_id: tag.language_id,
+            name: text
+                .text_for_byte_range(tag.name_range.start_byte..tag.name_range.end_byte)
+                .into(),
+            kind: tag.kind,
+            range: tag.range,
+            name_range: tag.name_range,
+        })
+    }
+
+    fn is_same_symbol(&self, other: &OutlineSymbol) -> bool {
+        self.language_id == other.language_id && self.kind == other.kind && self.name == other.name
+    }
+
+    fn intersects(&self, byte_range: &Range<usize>) -> bool {
+        self.range.start_byte <= byte_range.end && self.range.end_byte >= byte_range.start
+    }
+}
+
+/// Changes of outline between snapshots. Symbols located after the edit are not reported, their
+/// positions are shifted by the edit.
+#[derive(Debug, Clone, Default)]
+pub struct OutlineDelta {
+    pub added: Vec<OutlineSymbol>,
+    pub removed: Vec<OutlineSymbol>,
+    /// Old and new version of symbols whose range changed
+    pub moved: Vec<(OutlineSymbol, OutlineSymbol)>,
+}
+
+/// Outline of snapshot, computed once and kept with the snapshot
+pub fn document_outline(snapshot: &SyntaxSnapshot, text: SourceText<'_>) -> Arc<[OutlineSymbol]> {
+    Arc::clone(snapshot.outline.get_or_init(|| {
+        collect_tags(snapshot, text)
+            .into_iter()
+            .filter_map(|tag| OutlineSymbol::from_tag(tag, text))
+            .collect()
+    }))
+}
+
+fn shift_point(point: ts::Point, edit: &ts::InputEdit) -> ts::Point {
+    if point.row == edit.old_end_position.row {
+        ts::Point {
+            row: edit.new_end_position.row,
+            column: point.column - edit.old_end_position.column + edit.new_end_position.column,
+        }
+    } else {
+        ts::Point {
+            row: point.row - edit.old_end_position.row + edit.new_end_position.row,
+            column: point.column,
+        }
+    }
+}
+
+fn shift_range(range: ts::Range, edit: &ts::InputEdit) -> ts::Range {
+    ts::Range {
+        start_byte: range.start_byte - edit.old_end_byte + edit.new_end_byte,
+        end_byte: range.end_byte - edit.old_end_byte + edit.new_end_byte,
+        start_point: shift_point(range.start_point, edit),
+        end_point: shift_point(range.end_point, edit),
+    }
+}
+
+fn shift_symbol(symbol: &OutlineSymbol, edit: &ts::InputEdit) -> OutlineSymbol {
+    OutlineSymbol {
+        range: shift_range(symbol.range, edit),
+        name_range: shift_range(symbol.name_range, edit),
+        ..symbol.clone()
+    }
+}
+
+/// Updates outline of `new_snapshot` from outline of `old_snapshot` re-collecting only symbols
+/// in changed ranges. Returns `None` if `new_snapshot` is not an incremental reparse of
+/// `old_snapshot` or old outline was never computed.
+pub fn update_outline(
+    old_snapshot: &SyntaxSnapshot,
+    new_snapshot: &SyntaxSnapshot,
+    text: SourceText<'_>,
+) -> Option<OutlineDelta> {
+    let origin = new_snapshot
+        .origin
+        .as_ref()
+        .filter(|origin| origin.snapshot_id == old_snapshot.id)?;
+    let old_outline = old_snapshot.outline.get()?;
+    let edit = &origin.edit;
+    let affected_start = origin
+        .changed_ranges
+        .iter()
+        .map(|range| range.start_byte)
+        .fold(edit.start_byte, usize::min);
+    let affected_end = origin
+        .changed_ranges
+        .iter()
+        .map(|range| range.end_byte)
+        .fold(edit.new_end_byte, usize::max);
+    let new_affected = affected_start..affected_end;
+    let old_affected = affected_start..(affected_end - edit.new_end_byte + edit.old_end_byte);
+
+    let mut outline: Vec<OutlineSymbol> = Vec::with_capacity(old_outline.len());
+    let mut removed: Vec<OutlineSymbol> = Vec::new();
+    for symbol in old_outline.iter() {
+        if symbol.intersects(&old_affected) {
+            removed.push(symbol.clone());
+        } else if symbol.range.end_byte <= edit.start_byte {
+            outline.push(symbol.clone());
+        } else {
+            outline.push(shift_symbol(symbol, edit));
+        }
+    }
+
+    let mut delta = OutlineDelta::default();
+    let cursor_range = new_affected.start.saturating_sub(1)..(new_affected.end + 1);
+    for tag in collect_tags_in_range(new_snapshot, text, cursor_range) {
+        let Some(symbol) = OutlineSymbol::from_tag(tag, text) else {
+            continue;
+        };
+        if !symbol.intersects(&new_affected) {
+            continue;
+        }
+        if let Some(idx) = removed.iter().position(|old| old.is_same_symbol(&symbol)) {
+            let old = removed.remove(idx);
+            if old.range != symbol.range || old.name_range != symbol.name_range {
+                delta.moved.push((old, symbol.clone()));
+            }
+        } else {
+            delta.added.push(symbol.clone());
+        }
+        outline.push(symbol);
+    }
+    delta.removed = removed;
+
+    outline.sort_by_key(|symbol| (symbol.range.start_byte, symbol.range.end_byte));
+    let _ = new_snapshot.outline.set(outline.into());
+    Some(delta)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{json_isolate, parse, reparse};
+
+    const TAGS_QUERY: &str = "(pair key: (string (string_content) @name)) @definition.key";
+
+    fn json_snapshot(text: &str) -> SyntaxSnapshot {
+        let (isolate, language_id) = json_isolate();
+        isolate.add_tags_query(language_id, TAGS_QUERY).unwrap();
+        parse(&isolate, language_id, text)
+    }
+
+    fn names(symbols: &[OutlineSymbol]) -> Vec<&str> {
+        symbols.iter().map(|symbol| symbol.name.as_ref()).collect()
+    }
+
+    #[test]
+    fn update_reports_symbols_of_changed_ranges() {
+        let text = "{\n  \"alpha\": 1,\n  \"beta\": 2,\n  \"gamma\": 3\n}";
+        let snapshot = json_snapshot(text);
+        let outline = document_outline(&snapshot, SourceText::Utf8(text));
+        assert_eq!(names(&outline), vec!["alpha", "beta", "gamma"]);
+
+        let beta_end = text.find("beta").unwrap() + 4;
+        let (new_text, new_snapshot) = reparse(&snapshot, text, beta_end..beta_end, "s");
+        let delta = update_outline(&snapshot, &new_snapshot, SourceText::Utf8(&new_text)).unwrap();
+        assert_eq!(names(&delta.added), vec!["betas"]);
+        assert_eq!(names(&delta.removed), vec!["beta"]);
+        assert!(delta.moved.is_empty());
+
+        let fresh_snapshot = json_snapshot(&new_text);
+        assert_eq!(
+            document_outline(&new_snapshot, SourceText::Utf8(&new_text)),
+            document_outline(&fresh_snapshot, SourceText::Utf8(&new_text))
+        );
+    }
+
+    #[test]
+    fn update_shifts_symbols_after_the_edit() {
+        let text = "{\n  \"alpha\": 1,\n  \"beta\": 2\n}";
+        let snapshot = json_snapshot(text);
+        document_outline(&snapshot, SourceText::Utf8(text));
+
+        let alpha_end = text.find("1,").unwrap() + 2;
+        let (new_text, new_snapshot) =
+            reparse(&snapshot, text, alpha_end..alpha_end, "\n  \"new\": 0,");
+        let delta = update_outline(&snapshot, &new_snapshot, SourceText::Utf8(&new_text)).unwrap();
+        assert_eq!(names(&delta.added), vec!["new"]);
+        assert!(delta.removed.is_empty());
+
+        let outline = document_outline(&new_snapshot, SourceText::Utf8(&new_text));
+        assert_eq!(names(&outline), vec!["alpha", "new", "beta"]);
+        let beta = &outline[2];
+        assert_eq!(
+            &new_text[beta.name_range.start_byte..beta.name_range.end_byte],
+            "beta"
+        );
+        assert_eq!(beta.name_range.start_point, ts::Point::new(3, 3));
+    }
+
+    #[test]
+    fn update_needs_outline_of_the_parent_snapshot() {
+        let text = "{\"alpha\": 1}";
+        let snapshot = json_snapshot(text);
+        let (new_text, new_snapshot) = reparse(&snapshot, text, 2..2, "x");
+        assert!(update_outline(&snapshot, &new_snapshot, SourceText::Utf8(&new_text)).is_none());
+        document_outline(&snapshot, SourceText::Utf8(text));
+        let other_snapshot = json_snapshot(&new_text);
+        assert!(update_outline(&snapshot, &other_snapshot, SourceText::Utf8(&new_text)).is_none());
+    }
+}
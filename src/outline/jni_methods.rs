@@ -0,0 +1,262 @@
+use jni::{
+    errors::Result as JNIResult,
+    objects::{AutoLocal, JCharArray, JClass, JMethodID, JObject, JObjectArray, JValue},
+    sys::{jint, jsize},
+    JNIEnv,
+};
+use once_cell::sync::OnceCell as JOnceLock;
+
+use crate::{
+    jni_utils::{read_char_array, throw_exception_from_result, RangeDesc},
+    query::SourceText,
+    syntax_snapshot::SyntaxSnapshotDesc,
+};
+
+use super::{collect_outline_items, document_outline, update_outline, OutlineItem, OutlineSymbol};
+
+static OUTLINE_SYMBOL_CONSTRUCTOR: JOnceLock<JMethodID> = JOnceLock::new();
+static OUTLINE_ITEM_CONSTRUCTOR: JOnceLock<JMethodID> = JOnceLock::new();
+static OUTLINE_DELTA_CONSTRUCTOR: JOnceLock<JMethodID> = JOnceLock::new();
+
+struct OutlineSymbolDesc<'local> {
+    constructor: JMethodID,
+    class: AutoLocal<'local, JClass<'local>>,
+    range_desc: RangeDesc<'local>,
+}
+
+impl<'local> OutlineSymbolDesc<'local> {
+    fn new(env: &mut JNIEnv<'local>) -> JNIResult<OutlineSymbolDesc<'local>> {
+        let range_desc = RangeDesc::new(env)?;
+        let class = env.find_class("com/hulylabs/treesitter/language/OutlineSymbol")?;
+        let constructor = *OUTLINE_SYMBOL_CONSTRUCTOR.get_or_try_init(|| {
+            env.get_method_id(
+                &class,
+                "<init>",
+                "(JLjava/lang/String;Ljava/lang/String;Lcom/hulylabs/treesitter/language/Range;Lcom/hulylabs/treesitter/language/Range;)V",
+            )
+        })?;
+        Ok(OutlineSymbolDesc {
+            constructor,
+            class: env.auto_local(class),
+            range_desc,
+        })
+    }
+
+    fn to_java_object(
+        &self,
+        env: &mut JNIEnv<'local>,
+        symbol: &OutlineSymbol,
+    ) -> JNIResult<JObject<'local>> {
+        let kind: JObject = env.new_string(&symbol.kind)?.into();
+        let kind = env.auto_local(kind);
+        let name: JObject = env.new_string(&symbol.name)?.into();
+        let name = env.auto_local(name);
+        let range_obj = self.range_desc.to_java_object(env, symbol.range)?;
+        let range_obj = env.auto_local(range_obj);
+        let name_range_obj = self.range_desc.to_java_object(env, symbol.name_range)?;
+        let name_range_obj = env.auto_local(name_range_obj);
+        // SAFETY: constructor is valid and derived from class by construction of self
+        unsafe {
+            env.new_object_unchecked(
+                &self.class,
+                self.constructor,
+                &[
+                    JValue::from(symbol.language_id).as_jni(),
+                    JValue::Object(&kind).as_jni(),
+                    JValue::Object(&name).as_jni(),
+                    JValue::Object(&range_obj).as_jni(),
+                    JValue::Object(&name_range_obj).as_jni(),
+                ],
+            )
+        }
+    }
+
+    fn to_java_array<'a>(
+        &self,
+        env: &mut JNIEnv<'local>,
+        symbols: impl ExactSizeIterator<Item = &'a OutlineSymbol>,
+    ) -> JNIResult<JObjectArray<'local>> {
+        let array = env.new_object_array(symbols.len() as jsize, &self.class, JObject::null())?;
+        for (index, symbol) in symbols.enumerate() {
+            let obj = self.to_java_object(env, symbol)?;
+            let obj = env.auto_local(obj);
+            env.set_object_array_element(&array, index as i32, obj)?;
+        }
+        Ok(array)
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeOutlineProvider_nativeGetOutline<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    snapshot: JObject<'local>,
+    text: JCharArray<'local>,
+) -> JObjectArray<'local> {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        snapshot: JObject<'local>,
+        text: JCharArray<'local>,
+    ) -> JNIResult<JObjectArray<'local>> {
+        let snapshot = SyntaxSnapshotDesc::from_java_object(env, snapshot)?;
+        let symbol_desc = OutlineSymbolDesc::new(env)?;
+        let text_buffer = read_char_array(env, &text)?;
+
+        let outline = document_outline(snapshot, SourceText::Utf16(&text_buffer));
+        symbol_desc.to_java_array(env, outline.iter())
+    }
+    let result = inner(&mut env, snapshot, text);
+    throw_exception_from_result(&mut env, result)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeOutlineProvider_nativeGetOutlineDelta<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    old_snapshot: JObject<'local>,
+    new_snapshot: JObject<'local>,
+    text: JCharArray<'local>,
+) -> JObject<'local> {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        old_snapshot: JObject<'local>,
+        new_snapshot: JObject<'local>,
+        text: JCharArray<'local>,
+    ) -> JNIResult<JObject<'local>> {
+        let old_snapshot = SyntaxSnapshotDesc::from_java_object(env, old_snapshot)?;
+        let new_snapshot = SyntaxSnapshotDesc::from_java_object(env, new_snapshot)?;
+        let symbol_desc = OutlineSymbolDesc::new(env)?;
+        let text_buffer = read_char_array(env, &text)?;
+
+        let Some(delta) =
+            update_outline(old_snapshot, new_snapshot, SourceText::Utf16(&text_buffer))
+        else {
+            return Ok(JObject::null());
+        };
+        let added = symbol_desc.to_java_array(env, delta.added.iter())?;
+        let added = env.auto_local(added);
+        let removed = symbol_desc.to_java_array(env, delta.removed.iter())?;
+        let removed = env.auto_local(removed);
+        let moved_old = symbol_desc.to_java_array(env, delta.moved.iter().map(|(old, _)| old))?;
+        let moved_old = env.auto_local(moved_old);
+        let moved_new = symbol_desc.to_java_array(env, delta.moved.iter().map(|(_, new)| new))?;
+        let moved_new = env.auto_local(moved_new);
+
+        let class = env.find_class("com/hulylabs/treesitter/language/OutlineDelta")?;
+        let class = env.auto_local(class);
+        let constructor = *OUTLINE_DELTA_CONSTRUCTOR.get_or_try_init(|| {
+            env.get_method_id(
+                &class,
+                "<init>",
+                "([Lcom/hulylabs/treesitter/language/OutlineSymbol;[Lcom/hulylabs/treesitter/language/OutlineSymbol;[Lcom/hulylabs/treesitter/language/OutlineSymbol;[Lcom/hulylabs/treesitter/language/OutlineSymbol;)V",
+            )
+        })?;
+        // SAFETY: constructor is valid and derived from class
+        unsafe {
+            env.new_object_unchecked(
+                &class,
+                constructor,
+                &[
+                    JValue::Object(&added).as_jni(),
+                    JValue::Object(&removed).as_jni(),
+                    JValue::Object(&moved_old).as_jni(),
+                    JValue::Object(&moved_new).as_jni(),
+                ],
+            )
+        }
+    }
+    let result = inner(&mut env, old_snapshot, new_snapshot, text);
+    throw_exception_from_result(&mut env, result)
+}
+
+struct OutlineItemDesc<'local> {
+    constructor: JMethodID,
+    class: AutoLocal<'local, JClass<'local>>,
+    range_desc: RangeDesc<'local>,
+}
+
+impl<'local> OutlineItemDesc<'local> {
+    fn new(env: &mut JNIEnv<'local>) -> JNIResult<OutlineItemDesc<'local>> {
+        let range_desc = RangeDesc::new(env)?;
+        let class = env.find_class("com/hulylabs/treesitter/language/OutlineItem")?;
+        let constructor = *OUTLINE_ITEM_CONSTRUCTOR.get_or_try_init(|| {
+            env.get_method_id(
+                &class,
+                "<init>",
+                "(JLjava/lang/String;Ljava/lang/String;Lcom/hulylabs/treesitter/language/Range;Lcom/hulylabs/treesitter/language/Range;I)V",
+            )
+        })?;
+        Ok(OutlineItemDesc {
+            constructor,
+            class: env.auto_local(class),
+            range_desc,
+        })
+    }
+
+    fn to_java_object(
+        &self,
+        env: &mut JNIEnv<'local>,
+        item: &OutlineItem,
+    ) -> JNIResult<JObject<'local>> {
+        let kind: JObject = env.new_string(&item.kind)?.into();
+        let kind = env.auto_local(kind);
+        let name: JObject = env.new_string(&item.name)?.into();
+        let name = env.auto_local(name);
+        let range_obj = self.range_desc.to_java_object(env, item.range)?;
+        let range_obj = env.auto_local(range_obj);
+        let name_range_obj = self.range_desc.to_java_object(env, item.name_range)?;
+        let name_range_obj = env.auto_local(name_range_obj);
+        // SAFETY: constructor is valid and derived from class by construction of self
+        unsafe {
+            env.new_object_unchecked(
+                &self.class,
+                self.constructor,
+                &[
+                    JValue::from(item.language_id).as_jni(),
+                    JValue::Object(&kind).as_jni(),
+                    JValue::Object(&name).as_jni(),
+                    JValue::Object(&range_obj).as_jni(),
+                    JValue::Object(&name_range_obj).as_jni(),
+                    JValue::Int(item.depth as jint).as_jni(),
+                ],
+            )
+        }
+    }
+}
+
+/// Structure view items produced by outline queries, in document order with nesting depth
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeOutlineProvider_nativeGetOutlineItems<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    snapshot: JObject<'local>,
+    text: JCharArray<'local>,
+) -> JObjectArray<'local> {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        snapshot: JObject<'local>,
+        text: JCharArray<'local>,
+    ) -> JNIResult<JObjectArray<'local>> {
+        let snapshot = SyntaxSnapshotDesc::from_java_object(env, snapshot)?;
+        let item_desc = OutlineItemDesc::new(env)?;
+        let text_buffer = read_char_array(env, &text)?;
+
+        let items = collect_outline_items(snapshot, SourceText::Utf16(&text_buffer));
+        let array =
+            env.new_object_array(items.len() as jsize, &item_desc.class, JObject::null())?;
+        for (index, item) in items.iter().enumerate() {
+            let obj = item_desc.to_java_object(env, item)?;
+            let obj = env.auto_local(obj);
+            env.set_object_array_element(&array, index as i32, obj)?;
+        }
+        Ok(array)
+    }
+    let result = inner(&mut env, snapshot, text);
+    throw_exception_from_result(&mut env, result)
+}
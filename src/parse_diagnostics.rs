@@ -0,0 +1,131 @@
+use tree_sitter as ts;
+
+use crate::{
+    query::SourceText,
+    syntax_snapshot::{SyntaxSnapshot, SyntaxSnapshotEntryContent},
+    LanguageId,
+};
+
+#[cfg(feature = "jni")]
+mod jni_methods;
+
+/// Longer unexpected text is shortened in messages
+const MAX_UNEXPECTED_TEXT_CHARS: usize = 32;
+
+const BRACKET_PAIRS: &[(&str, &str)] = &[("(", ")"), ("[", "]"), ("{", "}"), ("<", ">")];
+
+#[derive(Debug, Clone)]
+pub struct ParseDiagnostic {
+    pub language_id: LanguageId,
+    pub range: ts::Range,
+    pub message: String,
+    pub related_ranges: Vec<ts::Range>,
+}
+
+/// Unclosed opening bracket for missing closing one, searched among previous siblings
+fn find_opening_bracket(missing: ts::Node<'_>) -> Option<ts::Node<'_>> {
+    let (opening, _) = BRACKET_PAIRS
+        .iter()
+        .find(|(_, closing)| *closing == missing.kind())?;
+    let mut depth = 0usize;
+    let mut sibling = missing.prev_sibling();
+    while let Some(node) = sibling {
+        if node.kind() == missing.kind() && !node.is_missing() {
+            depth += 1;
+        } else if node.kind() == *opening {
+            if depth == 0 {
+                return Some(node);
+            }
+            depth -= 1;
+        }
+        sibling = node.prev_sibling();
+    }
+    None
+}
+
+fn missing_diagnostic(language_id: LanguageId, node: ts::Node) -> ParseDiagnostic {
+    let mut message = format!("missing '{}'", node.kind());
+    let mut related_ranges = Vec::new();
+    if let Some(opening) = find_opening_bracket(node) {
+        let construct = node
+            .parent()
+            .map_or("construct", |parent| parent.kind())
+            .replace('_', " ");
+        message.push_str(&format!(
+            " to close {construct} started at line {}",
+            opening.start_position().row + 1
+        ));
+        related_ranges.push(opening.range());
+    } else if let Some(parent) = node.parent() {
+        message.push_str(&format!(" in {}", parent.kind().replace('_', " ")));
+    }
+    ParseDiagnostic {
+        language_id,
+        range: node.range(),
+        message,
+        related_ranges,
+    }
+}
+
+fn error_diagnostic(language_id: LanguageId, node: ts::Node, text: SourceText) -> ParseDiagnostic {
+    let error_text = text.text_for_byte_range(node.byte_range());
+    let error_text = error_text.trim();
+    let mut message = if error_text.is_empty() {
+        "syntax error".to_string()
+    } else if error_text.chars().count() > MAX_UNEXPECTED_TEXT_CHARS {
+        let shortened: String = error_text.chars().take(MAX_UNEXPECTED_TEXT_CHARS).collect();
+        format!("unexpected '{shortened}...'")
+    } else {
+        format!("unexpected '{error_text}'")
+    };
+    let mut related_ranges = Vec::new();
+    if let Some(parent) = node.parent() {
+        message.push_str(&format!(" in {}", parent.kind().replace('_', " ")));
+        related_ranges.push(parent.range());
+    }
+    ParseDiagnostic {
+        language_id,
+        range: node.range(),
+        message,
+        related_ranges,
+    }
+}
+
+/// Produces human-readable diagnostics for ERROR and MISSING nodes of every parsed layer
+pub fn collect_parse_diagnostics(
+    snapshot: &SyntaxSnapshot,
+    text: SourceText<'_>,
+) -> Vec<ParseDiagnostic> {
+    let mut diagnostics = Vec::new();
+    for entry in &snapshot.entries {
+        let SyntaxSnapshotEntryContent::Parsed { language, tree } = &entry.content else {
+            continue;
+        };
+        let root = tree.root_node_with_offset(entry.byte_offset, entry.point_offset);
+        if !root.has_error() {
+            continue;
+        }
+        let mut cursor = root.walk();
+        'walk: loop {
+            let node = cursor.node();
+            let mut descend = false;
+            if node.is_missing() {
+                diagnostics.push(missing_diagnostic(*language, node));
+            } else if node.is_error() {
+                diagnostics.push(error_diagnostic(*language, node, text));
+            } else {
+                descend = node.has_error();
+            }
+            if descend && cursor.goto_first_child() {
+                continue;
+            }
+            while !cursor.goto_next_sibling() {
+                if !cursor.goto_parent() {
+                    break 'walk;
+                }
+            }
+        }
+    }
+    diagnostics.sort_by_key(|diagnostic| diagnostic.range.start_byte);
+    diagnostics
+}
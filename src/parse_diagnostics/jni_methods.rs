@@ -0,0 +1,112 @@
+use jni::{
+    errors::Result as JNIResult,
+    objects::{AutoLocal, JCharArray, JClass, JMethodID, JObject, JObjectArray, JValue},
+    sys::jsize,
+    JNIEnv,
+};
+use once_cell::sync::OnceCell as JOnceLock;
+
+use crate::{
+    jni_utils::{read_char_array, throw_exception_from_result, RangeDesc},
+    query::SourceText,
+    syntax_snapshot::SyntaxSnapshotDesc,
+};
+
+use super::{collect_parse_diagnostics, ParseDiagnostic};
+
+static PARSE_DIAGNOSTIC_CONSTRUCTOR: JOnceLock<JMethodID> = JOnceLock::new();
+
+struct ParseDiagnosticDesc<'local> {
+    constructor: JMethodID,
+    class: AutoLocal<'local, JClass<'local>>,
+    range_desc: RangeDesc<'local>,
+}
+
+impl<'local> ParseDiagnosticDesc<'local> {
+    fn new(env: &mut JNIEnv<'local>) -> JNIResult<ParseDiagnosticDesc<'local>> {
+        let range_desc = RangeDesc::new(env)?;
+        let class = env.find_class("com/hulylabs/treesitter/language/ParseDiagnostic")?;
+        let constructor = *PARSE_DIAGNOSTIC_CONSTRUCTOR.get_or_try_init(|| {
+            env.get_method_id(
+                &class,
+                "<init>",
+                "(JLcom/hulylabs/treesitter/language/Range;Ljava/lang/String;[Lcom/hulylabs/treesitter/language/Range;)V",
+            )
+        })?;
+        Ok(ParseDiagnosticDesc {
+            constructor,
+            class: env.auto_local(class),
+            range_desc,
+        })
+    }
+
+    fn to_java_object(
+        &self,
+        env: &mut JNIEnv<'local>,
+        diagnostic: ParseDiagnostic,
+    ) -> JNIResult<JObject<'local>> {
+        let range_obj = self.range_desc.to_java_object(env, diagnostic.range)?;
+        let range_obj = env.auto_local(range_obj);
+        let message: JObject = env.new_string(diagnostic.message)?.into();
+        let message = env.auto_local(message);
+        let related_ranges = env.new_object_array(
+            diagnostic.related_ranges.len() as jsize,
+            &self.range_desc.class,
+            JObject::null(),
+        )?;
+        let related_ranges = env.auto_local(related_ranges);
+        for (index, range) in diagnostic.related_ranges.into_iter().enumerate() {
+            let related_range_obj = self.range_desc.to_java_object(env, range)?;
+            let related_range_obj = env.auto_local(related_range_obj);
+            env.set_object_array_element(&related_ranges, index as i32, &related_range_obj)?;
+        }
+        // SAFETY: constructor is valid and derived from class by construction of self
+        unsafe {
+            env.new_object_unchecked(
+                &self.class,
+                self.constructor,
+                &[
+                    JValue::from(diagnostic.language_id).as_jni(),
+                    JValue::Object(&range_obj).as_jni(),
+                    JValue::Object(&message).as_jni(),
+                    JValue::Object(&related_ranges).as_jni(),
+                ],
+            )
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeDiagnosticsProvider_nativeCollectParseDiagnostics<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    snapshot: JObject<'local>,
+    text: JCharArray<'local>,
+) -> JObjectArray<'local> {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        snapshot: JObject<'local>,
+        text: JCharArray<'local>,
+    ) -> JNIResult<JObjectArray<'local>> {
+        let snapshot = SyntaxSnapshotDesc::from_java_object(env, snapshot)?;
+        let diagnostic_desc = ParseDiagnosticDesc::new(env)?;
+        let text_buffer = read_char_array(env, &text)?;
+
+        let diagnostics = collect_parse_diagnostics(snapshot, SourceText::Utf16(&text_buffer));
+        let diagnostics_array = env.new_object_array(
+            diagnostics.len() as jsize,
+            &diagnostic_desc.class,
+            JObject::null(),
+        )?;
+        for (index, diagnostic) in diagnostics.into_iter().enumerate() {
+            let obj = diagnostic_desc.to_java_object(env, diagnostic)?;
+            let obj = env.auto_local(obj);
+            env.set_object_array_element(&diagnostics_array, index as i32, obj)?;
+        }
+        Ok(diagnostics_array)
+    }
+    let result = inner(&mut env, snapshot, text);
+    throw_exception_from_result(&mut env, result)
+}
@@ -1,10 +1,16 @@
-use std::{collections::HashMap, marker::PhantomData, ops::Deref};
+use std::{borrow::Cow, collections::HashMap, fmt, ops::Deref};
 
 use tree_sitter::{
     Node, Query, QueryError, QueryErrorKind, QueryMatch, QueryPredicate, QueryPredicateArg,
     TextProvider,
 };
 
+use lua_pattern::LuaPattern;
+
+use crate::query::{QueryMetadata, SourceTextProvider};
+
+mod lua_pattern;
+
 const fn predicate_error(row: usize, message: String) -> QueryError {
     QueryError {
         row,
@@ -16,26 +22,74 @@ const fn predicate_error(row: usize, message: String) -> QueryError {
 }
 
 pub trait TextProviderPredicate {
+    /// UTF-8 text of the node, recoded for UTF-16 source text
     fn text(&mut self, node: Node) -> &[u8];
+
+    /// Visits UTF-8 text of the node in chunks which never split characters until `f` returns
+    /// false, so predicates scanning text of large nodes do not flatten it
+    fn for_each_chunk(&mut self, node: Node, f: &mut dyn FnMut(&[u8]) -> bool) {
+        f(self.text(node));
+    }
+
+    /// Code units of the node text if source text is UTF-16, so predicates comparing against
+    /// pre-encoded literals avoid recoding it
+    fn utf16_text(&mut self, _node: Node) -> Option<&[u16]> {
+        None
+    }
 }
 
-struct TextProviderPredicateImpl<'a, T: TextProvider<I>, I: AsRef<[u8]>> {
-    text_provider: &'a mut T,
+struct TextProviderPredicateImpl<'a, 'b> {
+    text_provider: &'b SourceTextProvider<'a>,
     buffer: Vec<u8>,
-    _phantom: PhantomData<I>,
+    utf16_buffer: Cow<'a, [u16]>,
+}
+
+impl<'a, 'b> TextProviderPredicateImpl<'a, 'b> {
+    fn new(text_provider: &'b SourceTextProvider<'a>) -> Self {
+        TextProviderPredicateImpl {
+            text_provider,
+            buffer: Vec::with_capacity(64),
+            utf16_buffer: Cow::Borrowed(&[]),
+        }
+    }
 }
 
-impl<T: TextProvider<I>, I: AsRef<[u8]>> TextProviderPredicate
-    for TextProviderPredicateImpl<'_, T, I>
-{
+impl TextProviderPredicate for TextProviderPredicateImpl<'_, '_> {
     fn text(&mut self, node: Node) -> &[u8] {
-        let chunks = self.text_provider.text(node);
+        let chunks = (&mut &*self.text_provider).text(node);
         self.buffer.clear();
         for chunk in chunks {
             self.buffer.extend_from_slice(chunk.as_ref());
         }
         &self.buffer
     }
+
+    fn for_each_chunk(&mut self, node: Node, f: &mut dyn FnMut(&[u8]) -> bool) {
+        for chunk in (&mut &*self.text_provider).text(node) {
+            if !f(chunk.as_ref()) {
+                break;
+            }
+        }
+    }
+
+    fn utf16_text(&mut self, node: Node) -> Option<&[u16]> {
+        self.utf16_buffer = self.text_provider.utf16_units(node)?;
+        Some(&self.utf16_buffer)
+    }
+}
+
+/// Relative cost of checking a predicate, predicates of a pattern are checked cheapest first
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PredicateCost {
+    /// Only inspects nodes of the match
+    Nodes,
+    /// Compares node text with a literal or another capture
+    Compare,
+    /// Searches node text, e.g. for a substring
+    #[default]
+    Search,
+    /// Matches node text against a pattern
+    Pattern,
 }
 
 pub trait Predicate {
@@ -44,6 +98,10 @@ pub trait Predicate {
         mat: &QueryMatch<'_, '_>,
         text: &mut dyn TextProviderPredicate,
     ) -> bool;
+
+    fn cost(&self) -> PredicateCost {
+        PredicateCost::default()
+    }
 }
 
 pub trait PredicateParser {
@@ -77,14 +135,38 @@ impl PredicateParser for HashMap<&'static str, Box<dyn PredicateParser>> {
     }
 }
 
+/// Flag argument of text predicates making them compare text ignoring case,
+/// e.g. `(#contains? @keyword "select" "ignore-case")`
+const IGNORE_CASE_FLAG: &str = "ignore-case";
+
+/// Splits `-ignore-case` variant of the operator, e.g. `contains-ignore-case?`, into the base
+/// operator and the flag
+fn split_ignore_case(operator: &str) -> (String, bool) {
+    match operator.strip_suffix("-ignore-case?") {
+        Some(base) => (format!("{base}?"), true),
+        None => (operator.to_owned(), false),
+    }
+}
+
+/// Lowercases text char by char. Unlike `str::to_lowercase` it does not depend on the context
+/// of a char (final sigma), so literals, whole node texts and their chunks fold alike.
+fn fold_case(text: &str) -> String {
+    text.chars().flat_map(char::to_lowercase).collect()
+}
+
+/// Parser of `#contains?` family of predicates along with their `-ignore-case` variants,
+/// the literal may be followed by `"ignore-case"` flag as well
 #[derive(Clone, Copy)]
 pub struct ContainsPredicateParser;
 
 struct ContainsPredicate {
     capture_id: u32,
     pattern: Box<str>,
+    /// Pattern encoded for comparison with UTF-16 text
+    pattern_utf16: Box<[u16]>,
     is_positive: bool,
     match_all: bool,
+    ignore_case: bool,
 }
 
 impl PredicateParser for ContainsPredicateParser {
@@ -95,7 +177,7 @@ impl PredicateParser for ContainsPredicateParser {
             "any-contains?",
             "any-not-contains?",
         ]
-        .contains(&name)
+        .contains(&split_ignore_case(name).0.deref())
     }
     fn parse_predicate(
         &self,
@@ -103,7 +185,8 @@ impl PredicateParser for ContainsPredicateParser {
         row: usize,
         predicate: &QueryPredicate,
     ) -> Result<Box<dyn Predicate + Send + Sync>, QueryError> {
-        let (is_positive, match_all) = match predicate.operator.deref() {
+        let (operator, mut ignore_case) = split_ignore_case(&predicate.operator);
+        let (is_positive, match_all) = match operator.deref() {
             "contains?" => (true, true),
             "not-contains?" => (false, true),
             "any-contains?" => (true, false),
@@ -115,6 +198,315 @@ impl PredicateParser for ContainsPredicateParser {
                 ));
             }
         };
+        match predicate.args.len() {
+            2 => {}
+            3 if !ignore_case => match &predicate.args[2] {
+                QueryPredicateArg::String(flag) if flag.deref() == IGNORE_CASE_FLAG => {
+                    ignore_case = true;
+                }
+                _ => {
+                    return Err(predicate_error(
+                        row,
+                        format!(
+                            "Third argument to #{} predicate must be \"{}\" flag.",
+                            predicate.operator, IGNORE_CASE_FLAG
+                        ),
+                    ));
+                }
+            },
+            _ => {
+                return Err(predicate_error(
+                    row,
+                    format!(
+                        "Wrong number of arguments to #{} predicate. Expected 2, got {}",
+                        predicate.operator,
+                        predicate.args.len()
+                    ),
+                ));
+            }
+        }
+        let capture_id = match &predicate.args[0] {
+            QueryPredicateArg::Capture(capture_id) => *capture_id,
+            QueryPredicateArg::String(literal) => {
+                return Err(predicate_error(
+                    row,
+                    format!(
+                        "First argument to #{} predicate must be a capture name. Got literal \"{}\".",
+                        predicate.operator, literal
+                    ),
+                ));
+            }
+        };
+        let pattern = match &predicate.args[1] {
+            QueryPredicateArg::Capture(capture_id) => {
+                return Err(predicate_error(
+                    row,
+                    format!(
+                        "Second argument to #{} predicate must be a literal. Got capture @{}.",
+                        predicate.operator,
+                        query.capture_names()[*capture_id as usize]
+                    ),
+                ));
+            }
+            QueryPredicateArg::String(literal) if ignore_case => fold_case(literal).into(),
+            QueryPredicateArg::String(literal) => literal.clone(),
+        };
+
+        Ok(Box::new(ContainsPredicate {
+            capture_id,
+            pattern_utf16: pattern.encode_utf16().collect(),
+            pattern,
+            is_positive,
+            match_all,
+            ignore_case,
+        }))
+    }
+}
+
+impl Predicate for ContainsPredicate {
+    fn check_predicate(
+        &self,
+        mat: &QueryMatch<'_, '_>,
+        texts: &mut dyn TextProviderPredicate,
+    ) -> bool {
+        for node in mat.nodes_for_capture_index(self.capture_id) {
+            let utf16_text = (!self.ignore_case)
+                .then(|| texts.utf16_text(node))
+                .flatten();
+            let does_match = if let Some(text) = utf16_text {
+                self.pattern_utf16.is_empty()
+                    || text
+                        .windows(self.pattern_utf16.len())
+                        .any(|window| *window == *self.pattern_utf16)
+            } else {
+                node_text_contains(texts, node, &self.pattern, self.ignore_case)
+            };
+            if does_match != self.is_positive && self.match_all {
+                return false;
+            }
+            if does_match == self.is_positive && !self.match_all {
+                return true;
+            }
+        }
+        self.match_all
+    }
+}
+
+/// Whether text of the node contains `pattern`, lowercased first if `ignore_case` is set. Text is
+/// searched chunk by chunk keeping the tail of the previous chunks to find matches spanning them.
+fn node_text_contains(
+    texts: &mut dyn TextProviderPredicate,
+    node: Node,
+    pattern: &str,
+    ignore_case: bool,
+) -> bool {
+    if pattern.is_empty() {
+        return true;
+    }
+    let mut window = String::new();
+    let mut found = false;
+    texts.for_each_chunk(node, &mut |chunk| {
+        let chunk = String::from_utf8_lossy(chunk);
+        if ignore_case {
+            window.push_str(&fold_case(&chunk));
+        } else {
+            window.push_str(&chunk);
+        }
+        found = window.contains(pattern);
+        let mut tail_start = window.len().saturating_sub(pattern.len() - 1);
+        while !window.is_char_boundary(tail_start) {
+            tail_start -= 1;
+        }
+        window.drain(..tail_start);
+        !found
+    });
+    found
+}
+
+/// Whether text of the node folded by [`fold_case`] equals `expected`, compared chunk by chunk
+fn node_text_eq_lowercase(
+    texts: &mut dyn TextProviderPredicate,
+    node: Node,
+    expected: &str,
+) -> bool {
+    let mut expected_chars = expected.chars();
+    let mut equal = true;
+    texts.for_each_chunk(node, &mut |chunk| {
+        equal = String::from_utf8_lossy(chunk)
+            .chars()
+            .flat_map(char::to_lowercase)
+            .all(|c| expected_chars.next() == Some(c));
+        equal
+    });
+    equal && expected_chars.next().is_none()
+}
+
+/// Parser of `#eq-ignore-case?` family of predicates comparing a capture with a literal or another
+/// capture ignoring case. Plain `#eq?` is evaluated by tree-sitter, which does not accept a flag.
+#[derive(Clone, Copy)]
+pub struct EqIgnoreCasePredicateParser;
+
+enum EqIgnoreCaseOperand {
+    Literal(Box<str>),
+    Capture(u32),
+}
+
+struct EqIgnoreCasePredicate {
+    capture_id: u32,
+    operand: EqIgnoreCaseOperand,
+    is_positive: bool,
+    match_all: bool,
+}
+
+impl PredicateParser for EqIgnoreCasePredicateParser {
+    fn can_parse_predicate(&self, name: &str) -> bool {
+        [
+            "eq-ignore-case?",
+            "not-eq-ignore-case?",
+            "any-eq-ignore-case?",
+            "any-not-eq-ignore-case?",
+        ]
+        .contains(&name)
+    }
+
+    fn parse_predicate(
+        &self,
+        _query: &Query,
+        row: usize,
+        predicate: &QueryPredicate,
+    ) -> Result<Box<dyn Predicate + Send + Sync>, QueryError> {
+        let (is_positive, match_all) = match predicate.operator.deref() {
+            "eq-ignore-case?" => (true, true),
+            "not-eq-ignore-case?" => (false, true),
+            "any-eq-ignore-case?" => (true, false),
+            "any-not-eq-ignore-case?" => (false, false),
+            _ => {
+                return Err(predicate_error(
+                    row,
+                    format!("Invalid operator {}", predicate.operator),
+                ));
+            }
+        };
+        if predicate.args.len() != 2 {
+            return Err(predicate_error(
+                row,
+                format!(
+                    "Wrong number of arguments to #{} predicate. Expected 2, got {}",
+                    predicate.operator,
+                    predicate.args.len()
+                ),
+            ));
+        }
+        let capture_id = match &predicate.args[0] {
+            QueryPredicateArg::Capture(capture_id) => *capture_id,
+            QueryPredicateArg::String(literal) => {
+                return Err(predicate_error(
+                    row,
+                    format!(
+                        "First argument to #{} predicate must be a capture name. Got literal \"{}\".",
+                        predicate.operator, literal
+                    ),
+                ));
+            }
+        };
+        let operand = match &predicate.args[1] {
+            QueryPredicateArg::Capture(capture_id) => EqIgnoreCaseOperand::Capture(*capture_id),
+            QueryPredicateArg::String(literal) => {
+                EqIgnoreCaseOperand::Literal(fold_case(literal).into())
+            }
+        };
+
+        Ok(Box::new(EqIgnoreCasePredicate {
+            capture_id,
+            operand,
+            is_positive,
+            match_all,
+        }))
+    }
+}
+
+impl Predicate for EqIgnoreCasePredicate {
+    fn cost(&self) -> PredicateCost {
+        PredicateCost::Compare
+    }
+
+    fn check_predicate(
+        &self,
+        mat: &QueryMatch<'_, '_>,
+        texts: &mut dyn TextProviderPredicate,
+    ) -> bool {
+        let expected = match &self.operand {
+            EqIgnoreCaseOperand::Literal(literal) => literal.to_string(),
+            EqIgnoreCaseOperand::Capture(capture_id) => {
+                let Some(node) = mat.nodes_for_capture_index(*capture_id).next() else {
+                    return true;
+                };
+                fold_case(&String::from_utf8_lossy(texts.text(node)))
+            }
+        };
+        for node in mat.nodes_for_capture_index(self.capture_id) {
+            let does_match = match texts.utf16_text(node) {
+                // Only ascii text is compared in place, other characters may fold to ascii ones
+                Some(text) if text.iter().all(|unit| *unit < 0x80) => {
+                    text.len() == expected.len()
+                        && text
+                            .iter()
+                            .zip(expected.bytes())
+                            .all(|(unit, byte)| (*unit as u8).to_ascii_lowercase() == byte)
+                }
+                _ => node_text_eq_lowercase(texts, node, &expected),
+            };
+            if does_match != self.is_positive && self.match_all {
+                return false;
+            }
+            if does_match == self.is_positive && !self.match_all {
+                return true;
+            }
+        }
+        self.match_all
+    }
+}
+
+/// Parser of `#lua-match?` family of predicates of nvim-treesitter queries
+#[derive(Clone, Copy)]
+pub struct LuaMatchPredicateParser;
+
+struct LuaMatchPredicate {
+    capture_id: u32,
+    pattern: LuaPattern,
+    is_positive: bool,
+    match_all: bool,
+}
+
+impl PredicateParser for LuaMatchPredicateParser {
+    fn can_parse_predicate(&self, name: &str) -> bool {
+        [
+            "lua-match?",
+            "not-lua-match?",
+            "any-lua-match?",
+            "any-not-lua-match?",
+        ]
+        .contains(&name)
+    }
+
+    fn parse_predicate(
+        &self,
+        query: &Query,
+        row: usize,
+        predicate: &QueryPredicate,
+    ) -> Result<Box<dyn Predicate + Send + Sync>, QueryError> {
+        let (is_positive, match_all) = match predicate.operator.deref() {
+            "lua-match?" => (true, true),
+            "not-lua-match?" => (false, true),
+            "any-lua-match?" => (true, false),
+            "any-not-lua-match?" => (false, false),
+            _ => {
+                return Err(predicate_error(
+                    row,
+                    format!("Invalid operator {}", predicate.operator),
+                ));
+            }
+        };
         if predicate.args.len() != 2 {
             return Err(predicate_error(
                 row,
@@ -148,10 +540,18 @@ impl PredicateParser for ContainsPredicateParser {
                     ),
                 ));
             }
-            QueryPredicateArg::String(literal) => literal.clone(),
+            QueryPredicateArg::String(literal) => LuaPattern::new(literal).map_err(|err| {
+                predicate_error(
+                    row,
+                    format!(
+                        "Invalid pattern of #{} predicate: {err}",
+                        predicate.operator
+                    ),
+                )
+            })?,
         };
 
-        Ok(Box::new(ContainsPredicate {
+        Ok(Box::new(LuaMatchPredicate {
             capture_id,
             pattern,
             is_positive,
@@ -160,16 +560,18 @@ impl PredicateParser for ContainsPredicateParser {
     }
 }
 
-impl Predicate for ContainsPredicate {
+impl Predicate for LuaMatchPredicate {
+    fn cost(&self) -> PredicateCost {
+        PredicateCost::Pattern
+    }
+
     fn check_predicate(
         &self,
         mat: &QueryMatch<'_, '_>,
         texts: &mut dyn TextProviderPredicate,
     ) -> bool {
         for node in mat.nodes_for_capture_index(self.capture_id) {
-            let text = texts.text(node);
-            let text = String::from_utf8_lossy(text);
-            let does_match = text.contains(self.pattern.deref());
+            let does_match = self.pattern.is_match(texts.text(node));
             if does_match != self.is_positive && self.match_all {
                 return false;
             }
@@ -181,10 +583,161 @@ impl Predicate for ContainsPredicate {
     }
 }
 
+/// Parser of `#has-ancestor?` and `#has-parent?` predicates constraining kinds of nodes around
+/// the capture, e.g. `(#has-ancestor? @self function_item impl_item)`
+#[derive(Clone, Copy)]
+pub struct HasAncestorPredicateParser;
+
+struct HasAncestorPredicate {
+    capture_id: u32,
+    kinds: Box<[Box<str>]>,
+    is_positive: bool,
+    parent_only: bool,
+}
+
+impl PredicateParser for HasAncestorPredicateParser {
+    fn can_parse_predicate(&self, name: &str) -> bool {
+        [
+            "has-ancestor?",
+            "not-has-ancestor?",
+            "has-parent?",
+            "not-has-parent?",
+        ]
+        .contains(&name)
+    }
+
+    fn parse_predicate(
+        &self,
+        _query: &Query,
+        row: usize,
+        predicate: &QueryPredicate,
+    ) -> Result<Box<dyn Predicate + Send + Sync>, QueryError> {
+        let (is_positive, parent_only) = match predicate.operator.deref() {
+            "has-ancestor?" => (true, false),
+            "not-has-ancestor?" => (false, false),
+            "has-parent?" => (true, true),
+            "not-has-parent?" => (false, true),
+            _ => {
+                return Err(predicate_error(
+                    row,
+                    format!("Invalid operator {}", predicate.operator),
+                ));
+            }
+        };
+        if predicate.args.len() < 2 {
+            return Err(predicate_error(
+                row,
+                format!(
+                    "Wrong number of arguments to #{} predicate. Expected at least 2, got {}",
+                    predicate.operator,
+                    predicate.args.len()
+                ),
+            ));
+        }
+        let capture_id = match &predicate.args[0] {
+            QueryPredicateArg::Capture(capture_id) => *capture_id,
+            QueryPredicateArg::String(literal) => {
+                return Err(predicate_error(
+                    row,
+                    format!(
+                        "First argument to #{} predicate must be a capture name. Got literal \"{}\".",
+                        predicate.operator, literal
+                    ),
+                ));
+            }
+        };
+        let kinds = predicate.args[1..]
+            .iter()
+            .map(|arg| match arg {
+                QueryPredicateArg::String(kind) => Ok(kind.clone()),
+                QueryPredicateArg::Capture(_) => Err(predicate_error(
+                    row,
+                    format!(
+                        "Node kinds of #{} predicate must be literals.",
+                        predicate.operator
+                    ),
+                )),
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Box::new(HasAncestorPredicate {
+            capture_id,
+            kinds,
+            is_positive,
+            parent_only,
+        }))
+    }
+}
+
+impl Predicate for HasAncestorPredicate {
+    fn cost(&self) -> PredicateCost {
+        PredicateCost::Nodes
+    }
+
+    fn check_predicate(
+        &self,
+        mat: &QueryMatch<'_, '_>,
+        _texts: &mut dyn TextProviderPredicate,
+    ) -> bool {
+        mat.nodes_for_capture_index(self.capture_id).all(|node| {
+            let mut ancestor = node.parent();
+            let mut found = false;
+            while let Some(current) = ancestor {
+                if self.kinds.iter().any(|kind| kind.deref() == current.kind()) {
+                    found = true;
+                    break;
+                }
+                if self.parent_only {
+                    break;
+                }
+                ancestor = current.parent();
+            }
+            found == self.is_positive
+        })
+    }
+}
+
 type AnyPredicate = Box<dyn Predicate + Send + Sync>;
+type NamedPredicate = (Box<str>, AnyPredicate);
+
+/// Directives which are not evaluated as predicates but read by the query kinds using them
+const QUERY_DIRECTIVES: &[&str] = &["offset!"];
 
+/// How query registration treats general predicates no parser knows about
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PredicateMode {
+    /// Unknown predicates are skipped and reported as warnings
+    #[default]
+    Lenient,
+    /// Query with unknown predicates is rejected
+    Strict,
+}
+
+/// General predicate skipped while matching because no parser knows its operator
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownPredicate {
+    pub pattern_index: usize,
+    /// Row of the pattern start in the query source
+    pub row: usize,
+    pub operator: Box<str>,
+}
+
+impl fmt::Display for UnknownPredicate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{} at row {}", self.operator, self.row)
+    }
+}
+
+/// Predicates tree-sitter leaves to the caller as general predicates. Text predicates are
+/// evaluated by tree-sitter itself while matching: the `#eq?` family (`eq?`, `not-eq?`,
+/// `any-eq?`, `any-not-eq?`) comparing a capture with a literal or another capture, the `#match?`
+/// family and `#any-of?`, so they are not parsed here. `#is?`/`#is-not?` are pattern properties
+/// checked against local bindings while highlighting. Predicates of a pattern parsed here are
+/// checked cheapest first by [`Predicate::cost`], after the ones evaluated by tree-sitter.
 pub struct AdditionalPredicates {
-    predicates: Box<[Box<[AnyPredicate]>]>,
+    predicates: Box<[Box<[NamedPredicate]>]>,
+    unknown_predicates: Box<[UnknownPredicate]>,
+    metadata: QueryMetadata,
 }
 
 impl AdditionalPredicates {
@@ -194,6 +747,7 @@ impl AdditionalPredicates {
         parser: &impl PredicateParser,
     ) -> Result<Self, QueryError> {
         let mut additional_predicates = Vec::with_capacity(query.pattern_count());
+        let mut unknown_predicates = Vec::new();
         for pattern_idx in 0..query.pattern_count() {
             let pattern_start = query.start_byte_for_pattern(pattern_idx);
             let row = source
@@ -205,37 +759,79 @@ impl AdditionalPredicates {
             let mut parsed_predicates = Vec::with_capacity(general_predicates.len());
             for predicate in query.general_predicates(pattern_idx) {
                 if !parser.can_parse_predicate(predicate.operator.deref()) {
+                    if !QUERY_DIRECTIVES.contains(&predicate.operator.deref()) {
+                        unknown_predicates.push(UnknownPredicate {
+                            pattern_index: pattern_idx,
+                            row,
+                            operator: predicate.operator.clone(),
+                        });
+                    }
                     continue;
                 }
-                parsed_predicates.push(parser.parse_predicate(query, row, predicate)?);
+                parsed_predicates.push((
+                    predicate.operator.clone(),
+                    parser.parse_predicate(query, row, predicate)?,
+                ));
             }
+            // Stable, so predicates of the same cost are checked in the query order
+            parsed_predicates.sort_by_key(|(_, predicate)| predicate.cost());
             additional_predicates.push(parsed_predicates.into());
         }
         Ok(Self {
             predicates: additional_predicates.into(),
+            unknown_predicates: unknown_predicates.into(),
+            metadata: QueryMetadata::new(query),
         })
     }
 
-    pub fn satisfies_predicates<I: AsRef<[u8]>>(
+    /// `#set!` properties of the query patterns
+    pub fn metadata(&self) -> &QueryMetadata {
+        &self.metadata
+    }
+
+    /// General predicates of the query which are ignored while matching
+    pub fn unknown_predicates(&self) -> &[UnknownPredicate] {
+        &self.unknown_predicates
+    }
+
+    pub fn satisfies_predicates(
         &self,
-        text_provider: &mut impl TextProvider<I>,
+        text_provider: &SourceTextProvider<'_>,
         query_match: &QueryMatch,
     ) -> bool {
         let Some(predicates) = self.predicates.get(query_match.pattern_index) else {
             return true;
         };
-        let mut predicate_text_provider = TextProviderPredicateImpl {
-            text_provider,
-            buffer: Vec::with_capacity(64),
-            _phantom: PhantomData,
-        };
-        for predicate in predicates {
+        let mut predicate_text_provider = TextProviderPredicateImpl::new(text_provider);
+        for (_, predicate) in predicates {
             if !predicate.check_predicate(query_match, &mut predicate_text_provider) {
                 return false;
             }
         }
         true
     }
+
+    /// Evaluates every predicate of the match pattern without short-circuiting,
+    /// returns operator names along with results in the order predicates are checked
+    pub fn evaluate_predicates(
+        &self,
+        text_provider: &SourceTextProvider<'_>,
+        query_match: &QueryMatch,
+    ) -> Vec<(Box<str>, bool)> {
+        let Some(predicates) = self.predicates.get(query_match.pattern_index) else {
+            return Vec::new();
+        };
+        let mut predicate_text_provider = TextProviderPredicateImpl::new(text_provider);
+        predicates
+            .iter()
+            .map(|(operator, predicate)| {
+                (
+                    operator.clone(),
+                    predicate.check_predicate(query_match, &mut predicate_text_provider),
+                )
+            })
+            .collect()
+    }
 }
 
 thread_local! {
@@ -244,5 +840,165 @@ thread_local! {
         ("not-contains?", Box::new(ContainsPredicateParser) as Box<dyn PredicateParser>),
         ("any-contains?", Box::new(ContainsPredicateParser) as Box<dyn PredicateParser>),
         ("any-not-contains?", Box::new(ContainsPredicateParser) as Box<dyn PredicateParser>),
+        ("contains-ignore-case?", Box::new(ContainsPredicateParser) as Box<dyn PredicateParser>),
+        ("not-contains-ignore-case?", Box::new(ContainsPredicateParser) as Box<dyn PredicateParser>),
+        ("any-contains-ignore-case?", Box::new(ContainsPredicateParser) as Box<dyn PredicateParser>),
+        ("any-not-contains-ignore-case?", Box::new(ContainsPredicateParser) as Box<dyn PredicateParser>),
+        ("eq-ignore-case?", Box::new(EqIgnoreCasePredicateParser) as Box<dyn PredicateParser>),
+        ("not-eq-ignore-case?", Box::new(EqIgnoreCasePredicateParser) as Box<dyn PredicateParser>),
+        ("any-eq-ignore-case?", Box::new(EqIgnoreCasePredicateParser) as Box<dyn PredicateParser>),
+        ("any-not-eq-ignore-case?", Box::new(EqIgnoreCasePredicateParser) as Box<dyn PredicateParser>),
+        ("lua-match?", Box::new(LuaMatchPredicateParser) as Box<dyn PredicateParser>),
+        ("not-lua-match?", Box::new(LuaMatchPredicateParser) as Box<dyn PredicateParser>),
+        ("any-lua-match?", Box::new(LuaMatchPredicateParser) as Box<dyn PredicateParser>),
+        ("any-not-lua-match?", Box::new(LuaMatchPredicateParser) as Box<dyn PredicateParser>),
+        ("has-ancestor?", Box::new(HasAncestorPredicateParser) as Box<dyn PredicateParser>),
+        ("not-has-ancestor?", Box::new(HasAncestorPredicateParser) as Box<dyn PredicateParser>),
+        ("has-parent?", Box::new(HasAncestorPredicateParser) as Box<dyn PredicateParser>),
+        ("not-has-parent?", Box::new(HasAncestorPredicateParser) as Box<dyn PredicateParser>),
     ]);
 }
+
+#[cfg(test)]
+mod tests {
+    use streaming_iterator::StreamingIterator as _;
+
+    use super::*;
+    use crate::query::SourceText;
+
+    /// Node text split into fixed chunks regardless of the node
+    struct ChunkedText {
+        chunks: Vec<&'static str>,
+        text: Vec<u8>,
+    }
+
+    impl ChunkedText {
+        fn new(chunks: &[&'static str]) -> Self {
+            ChunkedText {
+                chunks: chunks.to_vec(),
+                text: chunks.concat().into_bytes(),
+            }
+        }
+    }
+
+    impl TextProviderPredicate for ChunkedText {
+        fn text(&mut self, _node: Node) -> &[u8] {
+            &self.text
+        }
+
+        fn for_each_chunk(&mut self, _node: Node, f: &mut dyn FnMut(&[u8]) -> bool) {
+            for chunk in &self.chunks {
+                if !f(chunk.as_bytes()) {
+                    break;
+                }
+            }
+        }
+    }
+
+    fn contains(chunks: &[&'static str], pattern: &str, ignore_case: bool) -> bool {
+        let mut parser = tree_sitter::Parser::new();
+        parser
+            .set_language(&tree_sitter_json::LANGUAGE.into())
+            .unwrap();
+        let tree = parser.parse("1", None).unwrap();
+        node_text_contains(
+            &mut ChunkedText::new(chunks),
+            tree.root_node(),
+            pattern,
+            ignore_case,
+        )
+    }
+
+    #[test]
+    fn node_text_contains_match_spanning_chunks() {
+        assert!(contains(&["ab", "cd", "ef"], "bcde", false));
+        assert!(contains(&["ab", "c", "d", "ef"], "abcdef", false));
+        assert!(!contains(&["ab", "cd", "ef"], "ace", false));
+        assert!(contains(&["ab"], "", false));
+    }
+
+    #[test]
+    fn node_text_contains_keeps_whole_chars_in_window() {
+        assert!(contains(&["xé", "éy"], "éé", false));
+        assert!(contains(&["aé", "b"], "éb", false));
+        assert!(!contains(&["é", "b"], "ab", false));
+    }
+
+    #[test]
+    fn node_text_contains_ignoring_case() {
+        assert!(contains(&["Ab", "CD"], "bc", true));
+        assert!(!contains(&["Ab", "CD"], "bc", false));
+        // Pattern is folded by the predicate parser, the window per chunk
+        assert!(contains(&["ΟΔ", "ΟΣ"], &fold_case("ΟΔΟΣ"), true));
+    }
+
+    /// Texts of `@number` captures of `pattern` in JSON `text` passing the ancestor predicates
+    fn numbers_with_ancestors(pattern: &str, text: &str) -> Vec<String> {
+        let language = tree_sitter_json::LANGUAGE.into();
+        let mut parser = tree_sitter::Parser::new();
+        parser.set_language(&language).unwrap();
+        let tree = parser.parse(text, None).unwrap();
+        let query = Query::new(&language, pattern).unwrap();
+        let predicates =
+            AdditionalPredicates::parse(&query, pattern, &HasAncestorPredicateParser).unwrap();
+        let text_provider = SourceTextProvider::new(SourceText::Utf8(text));
+        let mut cursor = tree_sitter::QueryCursor::new();
+        let mut matches = cursor.matches(&query, tree.root_node(), &text_provider);
+        let mut numbers = Vec::new();
+        while let Some(query_match) = matches.next() {
+            if predicates.satisfies_predicates(&text_provider, query_match) {
+                numbers.extend(
+                    query_match
+                        .captures
+                        .iter()
+                        .map(|capture| text[capture.node.byte_range()].to_string()),
+                );
+            }
+        }
+        numbers
+    }
+
+    #[test]
+    fn has_ancestor_looks_through_all_ancestors() {
+        let text = r#"{"a": 1, "b": [2, {"c": 3}]}"#;
+        assert_eq!(
+            numbers_with_ancestors("((number) @number (#has-ancestor? @number array))", text),
+            vec!["2", "3"]
+        );
+        assert_eq!(
+            numbers_with_ancestors(
+                "((number) @number (#not-has-ancestor? @number array))",
+                text
+            ),
+            vec!["1"]
+        );
+        assert_eq!(
+            numbers_with_ancestors(
+                "((number) @number (#has-ancestor? @number array document))",
+                text
+            ),
+            vec!["1", "2", "3"]
+        );
+    }
+
+    #[test]
+    fn has_parent_checks_only_the_parent() {
+        let text = r#"{"a": 1, "b": [2, {"c": 3}]}"#;
+        assert_eq!(
+            numbers_with_ancestors("((number) @number (#has-parent? @number array))", text),
+            vec!["2"]
+        );
+        assert_eq!(
+            numbers_with_ancestors("((number) @number (#not-has-parent? @number array))", text),
+            vec!["1", "3"]
+        );
+    }
+
+    #[test]
+    fn has_ancestor_rejects_capture_kinds() {
+        let language = tree_sitter_json::LANGUAGE.into();
+        let pattern = "((number) @number (#has-ancestor? @number @number))";
+        let query = Query::new(&language, pattern).unwrap();
+        assert!(AdditionalPredicates::parse(&query, pattern, &HasAncestorPredicateParser).is_err());
+    }
+}
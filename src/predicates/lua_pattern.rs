@@ -0,0 +1,318 @@
+//! Lua pattern matching as in `string.find`, used by nvim-treesitter queries. Captures only
+//! group items and back references are not supported.
+
+const ESCAPE: u8 = b'%';
+
+pub struct LuaPattern {
+    pattern: Box<[u8]>,
+}
+
+impl LuaPattern {
+    pub fn new(pattern: &str) -> Result<Self, String> {
+        let pattern = pattern.as_bytes();
+        let mut p = 0;
+        while p < pattern.len() {
+            match pattern[p] {
+                ESCAPE => match pattern.get(p + 1) {
+                    None => return Err("pattern ends with '%'".into()),
+                    Some(b'b') => {
+                        if p + 3 >= pattern.len() {
+                            return Err("missing arguments to '%b'".into());
+                        }
+                        p += 4;
+                    }
+                    Some(b'f') => {
+                        if pattern.get(p + 2) != Some(&b'[') {
+                            return Err("missing '[' after '%f' in pattern".into());
+                        }
+                        p = checked_class_end(pattern, p + 2)?;
+                    }
+                    Some(c) if c.is_ascii_digit() => {
+                        return Err("back references are not supported".into());
+                    }
+                    Some(_) => p += 2,
+                },
+                b'[' => p = checked_class_end(pattern, p)?,
+                _ => p += 1,
+            }
+        }
+        Ok(LuaPattern {
+            pattern: pattern.into(),
+        })
+    }
+
+    /// Whether pattern matches any part of `subject`, or its start if pattern starts with `^`
+    pub fn is_match(&self, subject: &[u8]) -> bool {
+        let (anchored, start) = match self.pattern.first() {
+            Some(b'^') => (true, 1),
+            _ => (false, 0),
+        };
+        let matcher = Matcher {
+            pattern: &self.pattern,
+            subject,
+        };
+        for s in 0..=subject.len() {
+            if matcher.do_match(s, start).is_some() {
+                return true;
+            }
+            if anchored {
+                break;
+            }
+        }
+        false
+    }
+}
+
+/// End of the set starting at `p`, fails on unterminated sets
+fn checked_class_end(pattern: &[u8], p: usize) -> Result<usize, String> {
+    let mut p = p + 1;
+    if pattern.get(p) == Some(&b'^') {
+        p += 1;
+    }
+    // First character of the set is never its end, so `[]]` is a valid set
+    loop {
+        let Some(&c) = pattern.get(p) else {
+            return Err("malformed pattern (missing ']')".into());
+        };
+        p += 1;
+        if c == ESCAPE {
+            p += 1;
+        }
+        if pattern.get(p) == Some(&b']') {
+            return Ok(p + 1);
+        }
+    }
+}
+
+fn match_class(c: u8, class: u8) -> bool {
+    let result = match class.to_ascii_lowercase() {
+        b'a' => c.is_ascii_alphabetic(),
+        b'c' => c.is_ascii_control(),
+        b'd' => c.is_ascii_digit(),
+        b'g' => c.is_ascii_graphic(),
+        b'l' => c.is_ascii_lowercase(),
+        b'p' => c.is_ascii_punctuation(),
+        b's' => c.is_ascii_whitespace() || c == 0x0b,
+        b'u' => c.is_ascii_uppercase(),
+        b'w' => c.is_ascii_alphanumeric(),
+        b'x' => c.is_ascii_hexdigit(),
+        _ => return class == c,
+    };
+    if class.is_ascii_uppercase() {
+        !result
+    } else {
+        result
+    }
+}
+
+struct Matcher<'a> {
+    pattern: &'a [u8],
+    subject: &'a [u8],
+}
+
+impl Matcher<'_> {
+    /// End of the single character class starting at `p`, pattern is validated on creation
+    fn class_end(&self, p: usize) -> usize {
+        match self.pattern[p] {
+            ESCAPE => p + 2,
+            b'[' => checked_class_end(self.pattern, p).expect("pattern is validated"),
+            _ => p + 1,
+        }
+    }
+
+    /// Whether `c` is in the set between `[` at `p` and `]` at `end`
+    fn match_bracket_class(&self, c: u8, p: usize, end: usize) -> bool {
+        let mut p = p + 1;
+        let mut is_positive = true;
+        if self.pattern[p] == b'^' {
+            is_positive = false;
+            p += 1;
+        }
+        while p < end {
+            if self.pattern[p] == ESCAPE {
+                p += 1;
+                if match_class(c, self.pattern[p]) {
+                    return is_positive;
+                }
+                p += 1;
+            } else if self.pattern[p + 1] == b'-' && p + 2 < end {
+                if self.pattern[p] <= c && c <= self.pattern[p + 2] {
+                    return is_positive;
+                }
+                p += 3;
+            } else {
+                if self.pattern[p] == c {
+                    return is_positive;
+                }
+                p += 1;
+            }
+        }
+        !is_positive
+    }
+
+    fn single_match(&self, s: usize, p: usize, class_end: usize) -> bool {
+        let Some(&c) = self.subject.get(s) else {
+            return false;
+        };
+        match self.pattern[p] {
+            b'.' => true,
+            ESCAPE => match_class(c, self.pattern[p + 1]),
+            b'[' => self.match_bracket_class(c, p, class_end - 1),
+            pattern_char => pattern_char == c,
+        }
+    }
+
+    fn match_balance(&self, s: usize, p: usize) -> Option<usize> {
+        let (open, close) = (self.pattern[p], self.pattern[p + 1]);
+        if self.subject.get(s) != Some(&open) {
+            return None;
+        }
+        let mut depth = 1;
+        for (i, c) in self.subject.iter().enumerate().skip(s + 1) {
+            if *c == close {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+            } else if *c == open {
+                depth += 1;
+            }
+        }
+        None
+    }
+
+    fn max_expand(&self, s: usize, p: usize, class_end: usize) -> Option<usize> {
+        let mut count = 0;
+        while self.single_match(s + count, p, class_end) {
+            count += 1;
+        }
+        (0..=count)
+            .rev()
+            .find_map(|count| self.do_match(s + count, class_end + 1))
+    }
+
+    fn min_expand(&self, mut s: usize, p: usize, class_end: usize) -> Option<usize> {
+        loop {
+            if let Some(end) = self.do_match(s, class_end + 1) {
+                return Some(end);
+            }
+            if !self.single_match(s, p, class_end) {
+                return None;
+            }
+            s += 1;
+        }
+    }
+
+    /// End of the match of pattern from `p` at subject offset `s`
+    fn do_match(&self, mut s: usize, mut p: usize) -> Option<usize> {
+        while p < self.pattern.len() {
+            match self.pattern[p] {
+                // Captures only group items
+                b'(' | b')' => p += 1,
+                b'$' if p + 1 == self.pattern.len() => {
+                    return (s == self.subject.len()).then_some(s);
+                }
+                ESCAPE if self.pattern[p + 1] == b'b' => {
+                    s = self.match_balance(s, p + 2)?;
+                    p += 4;
+                }
+                ESCAPE if self.pattern[p + 1] == b'f' => {
+                    p += 2;
+                    let class_end = self.class_end(p);
+                    let previous = s.checked_sub(1).map_or(0, |s| self.subject[s]);
+                    let current = self.subject.get(s).copied().unwrap_or(0);
+                    if self.match_bracket_class(previous, p, class_end - 1)
+                        || !self.match_bracket_class(current, p, class_end - 1)
+                    {
+                        return None;
+                    }
+                    p = class_end;
+                }
+                _ => {
+                    let class_end = self.class_end(p);
+                    let is_match = self.single_match(s, p, class_end);
+                    match self.pattern.get(class_end) {
+                        Some(b'?') => {
+                            if is_match {
+                                if let Some(end) = self.do_match(s + 1, class_end + 1) {
+                                    return Some(end);
+                                }
+                            }
+                            p = class_end + 1;
+                        }
+                        Some(b'+') => {
+                            return if is_match {
+                                self.max_expand(s + 1, p, class_end)
+                            } else {
+                                None
+                            };
+                        }
+                        Some(b'*') => return self.max_expand(s, p, class_end),
+                        Some(b'-') => return self.min_expand(s, p, class_end),
+                        _ => {
+                            if !is_match {
+                                return None;
+                            }
+                            s += 1;
+                            p = class_end;
+                        }
+                    }
+                }
+            }
+        }
+        Some(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_match(pattern: &str, subject: &str) -> bool {
+        LuaPattern::new(pattern)
+            .unwrap()
+            .is_match(subject.as_bytes())
+    }
+
+    #[test]
+    fn anchors_and_classes() {
+        assert!(is_match("^%a+$", "abc"));
+        assert!(!is_match("^%a+$", "ab1"));
+        assert!(is_match("%d+", "x12"));
+        assert!(!is_match("^%d", "x12"));
+        assert!(is_match("^%u%l*$", "Name"));
+        assert!(!is_match("^%U", "Name"));
+        assert!(is_match("^[%w_]+$", "snake_case1"));
+        assert!(is_match("^[^a-z]+$", "ABC-1"));
+        assert!(!is_match("^[^a-z]+$", "ABc"));
+        assert!(is_match("[]]", "a]"));
+        assert!(is_match("^a$b", "a$b"));
+        assert!(is_match("^$", ""));
+    }
+
+    #[test]
+    fn repetitions() {
+        assert!(is_match("^colou?r$", "color"));
+        assert!(is_match("^colou?r$", "colour"));
+        assert!(is_match("^a.-b$", "axxbxb"));
+        assert!(is_match("^a.*b$", "axxbxb"));
+        assert!(!is_match("^a.+b$", "ab"));
+        assert!(is_match("^%s*$", " \t\n"));
+    }
+
+    #[test]
+    fn balances_frontiers_and_captures() {
+        assert!(is_match("^f%b()$", "f(a(b)c)"));
+        assert!(!is_match("^f%b()$", "f(a(b)c"));
+        assert!(is_match("%f[%w]foo", "a foo"));
+        assert!(!is_match("%f[%w]foo", "afoo"));
+        assert!(is_match("^(%d+)-(%d+)$", "10-20"));
+    }
+
+    #[test]
+    fn malformed_patterns_are_rejected() {
+        for pattern in ["%", "[a", "[]", "%b(", "%fx", "%1"] {
+            assert!(LuaPattern::new(pattern).is_err(), "{pattern}");
+        }
+    }
+}
@@ -0,0 +1,184 @@
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    fmt::Write as _,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{Isolate, LanguageId};
+
+#[cfg(feature = "jni")]
+mod jni_methods;
+
+/// Oldest call profiles are dropped when more calls are recorded before dump
+const MAX_RECORDED_CALLS: usize = 1024;
+
+thread_local! {
+    static CURRENT_CALL: RefCell<Option<CallProfile>> = const { RefCell::new(None) };
+}
+
+struct PhaseTiming {
+    phase: &'static str,
+    language_id: Option<LanguageId>,
+    duration: Duration,
+}
+
+struct CallProfile {
+    call: &'static str,
+    started: Instant,
+    duration: Duration,
+    phases: Vec<PhaseTiming>,
+}
+
+/// Calls recorded on an isolate while profiling is enabled for it
+#[derive(Default)]
+pub(crate) struct Recorder {
+    enabled: AtomicBool,
+    calls: Mutex<VecDeque<CallProfile>>,
+}
+
+impl Recorder {
+    pub(crate) fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+        if !enabled {
+            self.calls.lock().unwrap().clear();
+        }
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+}
+
+/// Records the call from creation until drop on the isolate, nested calls are recorded as part
+/// of outer one
+pub struct CallGuard {
+    isolate: Option<Arc<Isolate>>,
+}
+
+pub fn call(isolate: &Arc<Isolate>, call: &'static str) -> CallGuard {
+    if !isolate.profiler.is_enabled() {
+        return CallGuard { isolate: None };
+    }
+    let active = CURRENT_CALL.with_borrow_mut(|current| {
+        if current.is_some() {
+            return false;
+        }
+        *current = Some(CallProfile {
+            call,
+            started: Instant::now(),
+            duration: Duration::ZERO,
+            phases: Vec::new(),
+        });
+        true
+    });
+    CallGuard {
+        isolate: active.then(|| Arc::clone(isolate)),
+    }
+}
+
+impl Drop for CallGuard {
+    fn drop(&mut self) {
+        let Some(isolate) = self.isolate.take() else {
+            return;
+        };
+        let Some(mut profile) = CURRENT_CALL.with_borrow_mut(Option::take) else {
+            return;
+        };
+        profile.duration = profile.started.elapsed();
+        let mut recorded_calls = isolate.profiler.calls.lock().unwrap();
+        if recorded_calls.len() >= MAX_RECORDED_CALLS {
+            recorded_calls.pop_front();
+        }
+        recorded_calls.push_back(profile);
+    }
+}
+
+/// Records phase of the current call from creation until drop
+pub struct PhaseGuard {
+    phase: Option<(&'static str, Option<LanguageId>, Instant)>,
+}
+
+pub fn phase(phase: &'static str, language_id: Option<LanguageId>) -> PhaseGuard {
+    if !CURRENT_CALL.with_borrow(Option::is_some) {
+        return PhaseGuard { phase: None };
+    }
+    PhaseGuard {
+        phase: Some((phase, language_id, Instant::now())),
+    }
+}
+
+impl Drop for PhaseGuard {
+    fn drop(&mut self) {
+        let Some((phase, language_id, started)) = self.phase.take() else {
+            return;
+        };
+        let duration = started.elapsed();
+        CURRENT_CALL.with_borrow_mut(|current| {
+            if let Some(profile) = current {
+                profile.phases.push(PhaseTiming {
+                    phase,
+                    language_id,
+                    duration,
+                });
+            }
+        });
+    }
+}
+
+/// Returns calls recorded on the isolate as JSON and clears them:
+/// `{"calls":[{"call":"parse","total_us":120,"phases":[{"phase":"parse.base","language":0,"us":80}]}]}`
+pub fn take_json(isolate: &Isolate) -> String {
+    let calls = std::mem::take(&mut *isolate.profiler.calls.lock().unwrap());
+    let mut json = String::from("{\"calls\":[");
+    for (call_idx, profile) in calls.iter().enumerate() {
+        if call_idx > 0 {
+            json.push(',');
+        }
+        let _ = write!(
+            json,
+            "{{\"call\":\"{}\",\"total_us\":{},\"phases\":[",
+            profile.call,
+            profile.duration.as_micros()
+        );
+        for (phase_idx, phase) in profile.phases.iter().enumerate() {
+            if phase_idx > 0 {
+                json.push(',');
+            }
+            let _ = write!(json, "{{\"phase\":\"{}\"", phase.phase);
+            if let Some(language_id) = phase.language_id {
+                let _ = write!(json, ",\"language\":{}", i64::from(language_id));
+            }
+            let _ = write!(json, ",\"us\":{}}}", phase.duration.as_micros());
+        }
+        json.push_str("]}");
+    }
+    json.push_str("]}");
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{json_isolate, parse};
+
+    #[test]
+    fn calls_are_recorded_on_their_isolate() {
+        let (profiled, language_id) = json_isolate();
+        profiled.set_option("profiler.enabled", "true").unwrap();
+        let (other, other_language_id) = json_isolate();
+        parse(&profiled, language_id, "[1]");
+        parse(&other, other_language_id, "[1]");
+        let json = take_json(&profiled);
+        assert!(
+            json.starts_with("{\"calls\":[{\"call\":\"parse\""),
+            "{json}"
+        );
+        assert_eq!(take_json(&profiled), "{\"calls\":[]}");
+        assert_eq!(take_json(&other), "{\"calls\":[]}");
+    }
+}
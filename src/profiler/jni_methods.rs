@@ -0,0 +1,58 @@
+use jni::{
+    errors::Result as JNIResult,
+    objects::{JClass, JString},
+    sys::jboolean,
+    JNIEnv,
+};
+
+use crate::{jni_utils::throw_exception_from_result, Isolate, IsolateId};
+
+use super::take_json;
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeProfiler_nativeSetEnabled<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    isolate_id: IsolateId,
+    enabled: jboolean,
+) {
+    match Isolate::get(isolate_id) {
+        Ok(isolate) => isolate.profiler.set_enabled(enabled != 0),
+        Err(err) => env
+            .throw_new(
+                "java/lang/IllegalStateException",
+                format!("Failed to enable profiler: {err}"),
+            )
+            .unwrap(),
+    }
+}
+
+/// Returns calls recorded on the isolate since previous dump, see [`take_json`] for format
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeProfiler_nativeDumpJson<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    isolate_id: IsolateId,
+) -> JString<'local> {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        isolate_id: IsolateId,
+    ) -> JNIResult<JString<'local>> {
+        match Isolate::get(isolate_id) {
+            Ok(isolate) => env.new_string(take_json(&isolate)),
+            Err(err) => {
+                env.throw_new(
+                    "java/lang/IllegalStateException",
+                    format!("Failed to dump profile: {err}"),
+                )?;
+                Ok(JString::default())
+            }
+        }
+    }
+    let result = inner(&mut env, isolate_id);
+    throw_exception_from_result(&mut env, result)
+}
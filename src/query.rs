@@ -1,4 +1,476 @@
-use tree_sitter::{Node, Range, TextProvider};
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    ops::{Deref, DerefMut, Range as StdRange},
+};
+
+use tree_sitter::{Node, Point, Query, QueryCursor, Range, TextProvider};
+
+const MAX_POOLED_QUERY_CURSORS: usize = 8;
+
+thread_local! {
+    static QUERY_CURSOR_POOL: RefCell<Vec<QueryCursor>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Query cursor taken from the thread local pool, returned to the pool on drop with its ranges
+/// and limits reset, so hot paths do not allocate a cursor per query run
+pub(crate) struct PooledQueryCursor(Option<QueryCursor>);
+
+pub(crate) fn pooled_query_cursor() -> PooledQueryCursor {
+    let cursor = QUERY_CURSOR_POOL
+        .try_with(|pool| pool.borrow_mut().pop())
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    PooledQueryCursor(Some(cursor))
+}
+
+impl Deref for PooledQueryCursor {
+    type Target = QueryCursor;
+
+    fn deref(&self) -> &QueryCursor {
+        self.0.as_ref().expect("cursor is taken only on drop")
+    }
+}
+
+impl DerefMut for PooledQueryCursor {
+    fn deref_mut(&mut self) -> &mut QueryCursor {
+        self.0.as_mut().expect("cursor is taken only on drop")
+    }
+}
+
+impl Drop for PooledQueryCursor {
+    fn drop(&mut self) {
+        let Some(mut cursor) = self.0.take() else {
+            return;
+        };
+        cursor.set_byte_range(0..usize::MAX);
+        cursor.set_point_range(Point::new(0, 0)..Point::new(usize::MAX, usize::MAX));
+        cursor.set_match_limit(u32::MAX);
+        cursor.set_timeout_micros(0);
+        let _ = QUERY_CURSOR_POOL.try_with(|pool| {
+            let mut pool = pool.borrow_mut();
+            if pool.len() < MAX_POOLED_QUERY_CURSORS {
+                pool.push(cursor);
+            }
+        });
+    }
+}
+
+/// Document text in one of the encodings supported by tree-sitter. Byte offsets of trees parsed
+/// from `Utf16` and `Utf16Chunks` text are twice the offsets in code units, for `Utf8` they are
+/// equal.
+#[derive(Debug, Clone, Copy)]
+pub enum SourceText<'a> {
+    Utf16(&'a [u16]),
+    Utf16Chunks(&'a Utf16Chunks<'a>),
+    Utf8(&'a str),
+}
+
+/// UTF-16 text split into chunks, e.g. leaves of an editor rope, which is read in place instead
+/// of being copied into a contiguous buffer. Surrogate pairs may be split between chunks.
+#[derive(Debug, Clone, Default)]
+pub struct Utf16Chunks<'a> {
+    chunks: Vec<&'a [u16]>,
+    /// Offset of each chunk start in code units
+    chunk_starts: Vec<usize>,
+    len: usize,
+}
+
+impl<'a> Utf16Chunks<'a> {
+    pub fn new(chunks: impl IntoIterator<Item = &'a [u16]>) -> Self {
+        let mut text = Utf16Chunks::default();
+        for chunk in chunks.into_iter().filter(|chunk| !chunk.is_empty()) {
+            text.chunks.push(chunk);
+            text.chunk_starts.push(text.len);
+            text.len += chunk.len();
+        }
+        text
+    }
+
+    /// Length of text in code units
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn unit(&self, index: usize) -> u16 {
+        let chunk_idx = self.chunk_index(index);
+        self.chunks[chunk_idx][index - self.chunk_starts[chunk_idx]]
+    }
+
+    fn chunk_index(&self, index: usize) -> usize {
+        self.chunk_starts
+            .partition_point(|start| *start <= index)
+            .saturating_sub(1)
+    }
+
+    /// Part of the chunk containing `start` which lies before `end`
+    fn slice_from(&self, start: usize, end: usize) -> &'a [u16] {
+        if start >= end.min(self.len) {
+            return &[];
+        }
+        let chunk_idx = self.chunk_index(start);
+        let chunk = self.chunks[chunk_idx];
+        let offset = start - self.chunk_starts[chunk_idx];
+        &chunk[offset..chunk.len().min(offset + end - start)]
+    }
+
+    /// Code units of `range`
+    pub fn units(&self, range: StdRange<usize>) -> impl Iterator<Item = u16> + use<'a, '_> {
+        let mut start = range.start;
+        std::iter::from_fn(move || {
+            let slice = self.slice_from(start, range.end);
+            start += slice.len();
+            (!slice.is_empty()).then_some(slice)
+        })
+        .flatten()
+        .copied()
+    }
+}
+
+impl<'a> SourceText<'a> {
+    /// Number of tree-sitter bytes in one code unit of text
+    pub fn unit_size(&self) -> usize {
+        match self {
+            SourceText::Utf16(_) | SourceText::Utf16Chunks(_) => 2,
+            SourceText::Utf8(_) => 1,
+        }
+    }
+
+    /// Length of text in code units
+    pub fn len(&self) -> usize {
+        match self {
+            SourceText::Utf16(text) => text.len(),
+            SourceText::Utf16Chunks(text) => text.len(),
+            SourceText::Utf8(text) => text.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn byte_len(&self) -> usize {
+        self.len() * self.unit_size()
+    }
+
+    /// Code unit at `index`, bytes are widened for `Utf8` text
+    pub fn unit(&self, index: usize) -> u16 {
+        match self {
+            SourceText::Utf16(text) => text[index],
+            SourceText::Utf16Chunks(text) => text.unit(index),
+            SourceText::Utf8(text) => text.as_bytes()[index] as u16,
+        }
+    }
+
+    pub fn text_for_byte_range(&self, byte_range: StdRange<usize>) -> Cow<'a, str> {
+        match self {
+            SourceText::Utf16(text) => Cow::Owned(String::from_utf16_lossy(
+                &text[(byte_range.start / 2)..(byte_range.end / 2)],
+            )),
+            SourceText::Utf16Chunks(text) => Cow::Owned(
+                char::decode_utf16(text.units((byte_range.start / 2)..(byte_range.end / 2)))
+                    .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                    .collect(),
+            ),
+            SourceText::Utf8(text) => String::from_utf8_lossy(&text.as_bytes()[byte_range]),
+        }
+    }
+
+    /// Position of `byte` with column in bytes, same as tree-sitter points
+    pub fn point_for_byte(&self, byte: usize) -> Point {
+        let unit_size = self.unit_size();
+        let unit = (byte / unit_size).min(self.len());
+        let mut row = 0;
+        let mut line_start = 0;
+        for idx in 0..unit {
+            if self.unit(idx) == '\n' as u16 {
+                row += 1;
+                line_start = idx + 1;
+            }
+        }
+        Point {
+            row,
+            column: (unit - line_start) * unit_size,
+        }
+    }
+
+    /// Position of `to_byte` given the position `point` of `from_byte`, found by walking the units
+    /// between them instead of counting lines from the start of the text
+    pub fn shift_point(&self, point: Point, from_byte: usize, to_byte: usize) -> Point {
+        let unit_size = self.unit_size();
+        let (from_unit, to_unit) = (from_byte / unit_size, to_byte / unit_size);
+        if to_unit >= from_unit {
+            return (from_unit..to_unit).fold(point, |point, idx| {
+                if self.unit(idx) == '\n' as u16 {
+                    Point {
+                        row: point.row + 1,
+                        column: 0,
+                    }
+                } else {
+                    Point {
+                        row: point.row,
+                        column: point.column + unit_size,
+                    }
+                }
+            });
+        }
+        let rows_back = (to_unit..from_unit)
+            .filter(|idx| self.unit(*idx) == '\n' as u16)
+            .count();
+        if rows_back == 0 {
+            return Point {
+                row: point.row,
+                column: point
+                    .column
+                    .saturating_sub((from_unit - to_unit) * unit_size),
+            };
+        }
+        let line_start_unit = (0..to_unit)
+            .rev()
+            .find(|idx| self.unit(*idx) == '\n' as u16)
+            .map_or(0, |idx| idx + 1);
+        Point {
+            row: point.row.saturating_sub(rows_back),
+            column: (to_unit - line_start_unit) * unit_size,
+        }
+    }
+
+    /// Characters of `byte_range` with their byte offsets, invalid sequences are replaced
+    pub fn chars_in_byte_range(&self, byte_range: StdRange<usize>) -> Vec<(usize, char)> {
+        match self {
+            SourceText::Utf16(text) => decode_utf16_with_offsets(
+                byte_range.start,
+                text[(byte_range.start / 2)..(byte_range.end / 2)]
+                    .iter()
+                    .copied(),
+            ),
+            SourceText::Utf16Chunks(text) => decode_utf16_with_offsets(
+                byte_range.start,
+                text.units((byte_range.start / 2)..(byte_range.end / 2)),
+            ),
+            SourceText::Utf8(text) => text
+                .get(byte_range.clone())
+                .unwrap_or_default()
+                .char_indices()
+                .map(|(idx, c)| (byte_range.start + idx, c))
+                .collect(),
+        }
+    }
+
+    pub fn parse(
+        &self,
+        parser: &mut tree_sitter::Parser,
+        byte_range: StdRange<usize>,
+        old_tree: Option<&tree_sitter::Tree>,
+    ) -> Option<tree_sitter::Tree> {
+        match self {
+            SourceText::Utf16(text) => parser.parse_utf16(
+                &text[(byte_range.start / 2)..(byte_range.end / 2)],
+                old_tree,
+            ),
+            SourceText::Utf16Chunks(text) => {
+                let (start, end) = (byte_range.start / 2, byte_range.end / 2);
+                parser.parse_utf16_with(
+                    &mut |offset, _| text.slice_from(start + offset, end),
+                    old_tree,
+                )
+            }
+            SourceText::Utf8(text) => parser.parse(&text.as_bytes()[byte_range], old_tree),
+        }
+    }
+}
+
+fn decode_utf16_with_offsets(
+    start_offset: usize,
+    units: impl Iterator<Item = u16>,
+) -> Vec<(usize, char)> {
+    let mut offset = start_offset;
+    char::decode_utf16(units)
+        .map(|c| {
+            let c_offset = offset;
+            let c = c.unwrap_or(char::REPLACEMENT_CHARACTER);
+            offset += c.len_utf16() * 2;
+            (c_offset, c)
+        })
+        .collect()
+}
+
+impl<'a> From<&'a [u16]> for SourceText<'a> {
+    fn from(text: &'a [u16]) -> Self {
+        SourceText::Utf16(text)
+    }
+}
+
+impl<'a> From<&'a Utf16Chunks<'a>> for SourceText<'a> {
+    fn from(text: &'a Utf16Chunks<'a>) -> Self {
+        SourceText::Utf16Chunks(text)
+    }
+}
+
+impl<'a> From<&'a str> for SourceText<'a> {
+    fn from(text: &'a str) -> Self {
+        SourceText::Utf8(text)
+    }
+}
+
+/// Line starts of a text, so rows and points map to offsets by binary search instead of scanning
+/// the text from its start on every lookup
+pub struct LineIndex {
+    /// Unit offsets of line starts, the first line starts at 0
+    line_starts: Box<[usize]>,
+    len: usize,
+    unit_size: usize,
+}
+
+impl LineIndex {
+    pub fn new(text: SourceText<'_>) -> Self {
+        let line_breaks: Vec<usize> = match text {
+            SourceText::Utf16(text) => line_breaks(text.iter().copied()),
+            SourceText::Utf16Chunks(text) => line_breaks(text.units(0..text.len())),
+            SourceText::Utf8(text) => line_breaks(text.bytes().map(u16::from)),
+        };
+        LineIndex {
+            line_starts: std::iter::once(0)
+                .chain(line_breaks.into_iter().map(|idx| idx + 1))
+                .collect(),
+            len: text.len(),
+            unit_size: text.unit_size(),
+        }
+    }
+
+    /// Unit range of the line `row` without the line break
+    fn line_range(&self, row: usize) -> Option<StdRange<usize>> {
+        let line_start = *self.line_starts.get(row)?;
+        let line_end = self
+            .line_starts
+            .get(row + 1)
+            .map_or(self.len, |next_line_start| next_line_start - 1);
+        Some(line_start..line_end)
+    }
+
+    /// Byte offset of `point` with column in bytes, columns past the end of the line are clamped
+    /// to it and rows past the end of the text map to the end of the text
+    pub fn byte_for_point(&self, point: Point) -> usize {
+        let Some(line_range) = self.line_range(point.row) else {
+            return self.len * self.unit_size;
+        };
+        (line_range.start + point.column / self.unit_size).min(line_range.end) * self.unit_size
+    }
+
+    /// Byte range of the line `row` without the line break, `None` if text has fewer lines
+    pub fn line_byte_range(&self, row: usize) -> Option<StdRange<usize>> {
+        self.line_range(row)
+            .map(|range| (range.start * self.unit_size)..(range.end * self.unit_size))
+    }
+}
+
+fn line_breaks(units: impl Iterator<Item = u16>) -> Vec<usize> {
+    units
+        .enumerate()
+        .filter(|(_, unit)| *unit == '\n' as u16)
+        .map(|(idx, _)| idx)
+        .collect()
+}
+
+/// Text provider for queries over `SourceText`, recodes only UTF-16 text
+pub enum SourceTextProvider<'a> {
+    Utf16(RecodingUtf16TextProvider<'a>),
+    Utf16Chunks(&'a Utf16Chunks<'a>),
+    Utf8(&'a [u8]),
+}
+
+impl<'a> SourceTextProvider<'a> {
+    pub fn new(text: SourceText<'a>) -> Self {
+        match text {
+            SourceText::Utf16(text) => {
+                SourceTextProvider::Utf16(RecodingUtf16TextProvider::new(text))
+            }
+            SourceText::Utf16Chunks(text) => SourceTextProvider::Utf16Chunks(text),
+            SourceText::Utf8(text) => SourceTextProvider::Utf8(text.as_bytes()),
+        }
+    }
+
+    /// Code units of the node text without recoding, borrowed unless the node spans several
+    /// chunks. `None` for UTF-8 text.
+    pub fn utf16_units(&self, node: Node) -> Option<Cow<'a, [u16]>> {
+        let (start, end) = (node.start_byte() / 2, node.end_byte() / 2);
+        match self {
+            SourceTextProvider::Utf16(provider) => Some(Cow::Borrowed(&provider.text[start..end])),
+            SourceTextProvider::Utf16Chunks(text) => {
+                let slice = text.slice_from(start, end);
+                Some(if slice.len() == end - start {
+                    Cow::Borrowed(slice)
+                } else {
+                    Cow::Owned(text.units(start..end).collect())
+                })
+            }
+            SourceTextProvider::Utf8(_) => None,
+        }
+    }
+}
+
+pub enum SourceTextProviderIterator<'a> {
+    Utf16(RecodingUtf16TextProviderIterator<'a>),
+    Utf16Chunks(RecodingUtf16ChunksIterator<'a>),
+    Utf8(Option<&'a [u8]>),
+}
+
+/// Node text chunk, recoded for UTF-16 text and borrowed for `Utf8`
+#[allow(clippy::large_enum_variant)] // recoded chunks are inline to avoid allocation per node
+pub enum SourceTextChunk<'a> {
+    Recoded(RecodedChunk),
+    Borrowed(&'a [u8]),
+}
+
+impl AsRef<[u8]> for SourceTextChunk<'_> {
+    fn as_ref(&self) -> &[u8] {
+        match self {
+            SourceTextChunk::Recoded(chunk) => chunk.as_ref(),
+            SourceTextChunk::Borrowed(chunk) => chunk,
+        }
+    }
+}
+
+impl<'a> Iterator for SourceTextProviderIterator<'a> {
+    type Item = SourceTextChunk<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            SourceTextProviderIterator::Utf16(iter) => iter.next().map(SourceTextChunk::Recoded),
+            SourceTextProviderIterator::Utf16Chunks(iter) => {
+                iter.next().map(SourceTextChunk::Recoded)
+            }
+            SourceTextProviderIterator::Utf8(chunk) => chunk.take().map(SourceTextChunk::Borrowed),
+        }
+    }
+}
+
+impl<'a> TextProvider<SourceTextChunk<'a>> for &SourceTextProvider<'a> {
+    type I = SourceTextProviderIterator<'a>;
+
+    fn text(&mut self, node: Node) -> Self::I {
+        match self {
+            SourceTextProvider::Utf16(provider) => {
+                SourceTextProviderIterator::Utf16((&mut &*provider).text(node))
+            }
+            SourceTextProvider::Utf16Chunks(text) => {
+                SourceTextProviderIterator::Utf16Chunks(RecodingUtf16ChunksIterator {
+                    text,
+                    start_offset: node.start_byte() / 2,
+                    end_offset: node.end_byte() / 2,
+                })
+            }
+            SourceTextProvider::Utf8(text) => {
+                SourceTextProviderIterator::Utf8(Some(&text[node.byte_range()]))
+            }
+        }
+    }
+}
 
 pub struct RecodingUtf16TextProvider<'a> {
     text: &'a [u16],
@@ -10,39 +482,134 @@ impl<'a> RecodingUtf16TextProvider<'a> {
     }
 }
 
+/// Capacity of a recoded chunk, nodes with longer text are yielded in several chunks
+const RECODED_CHUNK_SIZE: usize = 256;
+
+/// UTF-8 text of up to [`RECODED_CHUNK_SIZE`] bytes, stored inline so recoding does not allocate
+pub struct RecodedChunk {
+    buf: [u8; RECODED_CHUNK_SIZE],
+    len: usize,
+}
+
+impl AsRef<[u8]> for RecodedChunk {
+    fn as_ref(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
 pub struct RecodingUtf16TextProviderIterator<'a> {
     text: &'a [u16],
     start_offset: usize,
     end_offset: usize,
-    ended: bool,
+}
+
+impl RecodedChunk {
+    fn new() -> Self {
+        RecodedChunk {
+            buf: [0; RECODED_CHUNK_SIZE],
+            len: 0,
+        }
+    }
+
+    /// Appends recoded prefix of `text` which fits into the chunk, returns its length in code
+    /// units
+    fn recode(&mut self, text: &[u16]) -> usize {
+        let mut pos = 0;
+        while pos < text.len() {
+            // Expect mostly ascii, copy runs of it without decoding
+            let ascii_len = text[pos..]
+                .iter()
+                .take(RECODED_CHUNK_SIZE - self.len)
+                .take_while(|unit| **unit < 0x80)
+                .count();
+            for (dst, unit) in self.buf[self.len..(self.len + ascii_len)]
+                .iter_mut()
+                .zip(&text[pos..(pos + ascii_len)])
+            {
+                *dst = *unit as u8;
+            }
+            self.len += ascii_len;
+            pos += ascii_len;
+            // Characters are never split between chunks
+            if pos == text.len() || RECODED_CHUNK_SIZE - self.len < 4 {
+                break;
+            }
+            let (c, c_units) = match char::decode_utf16(text[pos..].iter().copied()).next() {
+                Some(Ok(c)) => (c, c.len_utf16()),
+                _ => (char::REPLACEMENT_CHARACTER, 1),
+            };
+            self.len += c.encode_utf8(&mut self.buf[self.len..]).len();
+            pos += c_units;
+        }
+        pos
+    }
 }
 
 impl Iterator for RecodingUtf16TextProviderIterator<'_> {
-    type Item = Vec<u8>;
+    type Item = RecodedChunk;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.ended {
+        if self.start_offset >= self.end_offset {
             return None;
         }
-        // Expect mostly ascii
-        let mut buf = Vec::with_capacity(self.end_offset - self.start_offset);
-        let mut char_buf = [0u8; 4];
-        for c in char::decode_utf16(
-            self.text[self.start_offset..self.end_offset]
-                .iter()
-                .copied(),
-        ) {
-            let c = c.unwrap_or(char::REPLACEMENT_CHARACTER);
-            let c_len = c.len_utf8();
-            c.encode_utf8(&mut char_buf);
-            buf.extend_from_slice(&char_buf[0..c_len]);
+        let mut chunk = RecodedChunk::new();
+        self.start_offset += chunk.recode(&self.text[self.start_offset..self.end_offset]);
+        Some(chunk)
+    }
+}
+
+pub struct RecodingUtf16ChunksIterator<'a> {
+    text: &'a Utf16Chunks<'a>,
+    start_offset: usize,
+    end_offset: usize,
+}
+
+impl Iterator for RecodingUtf16ChunksIterator<'_> {
+    type Item = RecodedChunk;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.start_offset >= self.end_offset {
+            return None;
+        }
+        let mut chunk = RecodedChunk::new();
+        while self.start_offset < self.end_offset {
+            let mut slice = self.text.slice_from(self.start_offset, self.end_offset);
+            if slice.is_empty() {
+                self.start_offset = self.end_offset;
+                break;
+            }
+            // Surrogate pair split between text chunks is recoded separately
+            let next_offset = self.start_offset + slice.len();
+            let split_pair = slice
+                .last()
+                .is_some_and(|unit| (0xD800..0xDC00).contains(unit))
+                && next_offset < self.end_offset
+                && (0xDC00..0xE000).contains(&self.text.unit(next_offset));
+            if split_pair {
+                slice = &slice[..(slice.len() - 1)];
+            }
+            let recoded = chunk.recode(slice);
+            self.start_offset += recoded;
+            if recoded < slice.len() {
+                break;
+            }
+            if split_pair {
+                let pair = [
+                    self.text.unit(self.start_offset),
+                    self.text.unit(self.start_offset + 1),
+                ];
+                let recoded = chunk.recode(&pair);
+                if recoded == 0 {
+                    break;
+                }
+                self.start_offset += recoded;
+            }
         }
-        self.ended = true;
-        Some(buf)
+        Some(chunk)
     }
 }
 
-impl<'a> TextProvider<Vec<u8>> for &RecodingUtf16TextProvider<'a> {
+impl<'a> TextProvider<RecodedChunk> for &RecodingUtf16TextProvider<'a> {
     type I = RecodingUtf16TextProviderIterator<'a>;
 
     fn text(&mut self, node: Node) -> Self::I {
@@ -53,11 +620,82 @@ impl<'a> TextProvider<Vec<u8>> for &RecodingUtf16TextProvider<'a> {
             text: self.text,
             start_offset,
             end_offset,
-            ended: false,
         }
     }
 }
 
+/// Property set by `(#set! key value)` or `(#set! @capture key value)` directive of a pattern
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatchProperty {
+    pub key: Box<str>,
+    pub value: Option<Box<str>>,
+    pub capture_id: Option<u32>,
+}
+
+/// `#set!` properties of a pattern in the order of the directives
+#[derive(Debug, Clone, Default)]
+pub struct PatternMetadata {
+    properties: Box<[MatchProperty]>,
+}
+
+impl PatternMetadata {
+    pub fn properties(&self) -> &[MatchProperty] {
+        &self.properties
+    }
+
+    /// First property with the `key`
+    pub fn property(&self, key: &str) -> Option<&MatchProperty> {
+        self.properties
+            .iter()
+            .find(|property| property.key.deref() == key)
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.property(key).is_some()
+    }
+
+    /// Value of the first property with the `key`, `None` if it is not set or has no value
+    pub fn value(&self, key: &str) -> Option<&str> {
+        self.property(key)?.value.as_deref()
+    }
+}
+
+/// Pattern properties of a query collected once when it is parsed, so consumers look them up by
+/// key instead of scanning property settings of each match
+#[derive(Debug, Clone, Default)]
+pub struct QueryMetadata {
+    patterns: Box<[PatternMetadata]>,
+}
+
+impl QueryMetadata {
+    pub fn new(query: &Query) -> Self {
+        let patterns = (0..query.pattern_count())
+            .map(|pattern_index| PatternMetadata {
+                properties: query
+                    .property_settings(pattern_index)
+                    .iter()
+                    .map(|property| MatchProperty {
+                        key: property.key.clone(),
+                        value: property.value.clone(),
+                        capture_id: property.capture_id.map(|id| id as u32),
+                    })
+                    .collect(),
+            })
+            .collect();
+        QueryMetadata { patterns }
+    }
+
+    pub fn pattern(&self, pattern_index: usize) -> &PatternMetadata {
+        &self.patterns[pattern_index]
+    }
+
+    /// Whether any pattern of the query sets the `key`
+    pub fn has_property(&self, key: &str) -> bool {
+        self.patterns.iter().any(|pattern| pattern.contains(key))
+    }
+}
+
+/// Offsets of capture range in code units, as written in `#offset!` directive
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub struct CaptureOffset {
     start_offset: i32,
@@ -72,24 +710,108 @@ impl CaptureOffset {
         }
     }
 
-    pub fn apply_to_range(&self, range: &Range) -> Range {
-        let start_byte = ((range.start_byte as i32) + self.start_offset) as usize;
-        let end_byte = ((range.end_byte as i32) + self.start_offset) as usize;
-        let start_point = range.start_point;
-        let start_point = tree_sitter::Point {
-            row: start_point.row,
-            column: ((start_point.column as i32) + self.start_offset) as usize,
-        };
-        let end_point = range.end_point;
-        let end_point = tree_sitter::Point {
-            row: end_point.row,
-            column: ((end_point.column as i32) + self.end_offset) as usize,
+    pub fn start_offset(&self) -> i32 {
+        self.start_offset
+    }
+
+    pub fn end_offset(&self) -> i32 {
+        self.end_offset
+    }
+
+    /// Range moved by the offsets. Bytes are clamped to the text with the end kept at or after the
+    /// start, points follow the bytes across line breaks.
+    pub fn apply_to_range(&self, range: &Range, text: SourceText<'_>) -> Range {
+        let unit_size = text.unit_size() as i64;
+        let shift = |byte: usize, offset: i32| {
+            (byte as i64 + offset as i64 * unit_size).clamp(0, text.byte_len() as i64) as usize
         };
+        let start_byte = shift(range.start_byte, self.start_offset);
+        let end_byte = shift(range.end_byte, self.end_offset).max(start_byte);
         Range {
             start_byte,
             end_byte,
-            start_point,
-            end_point,
+            start_point: text.shift_point(range.start_point, range.start_byte, start_byte),
+            end_point: text.shift_point(range.end_point, range.end_byte, end_byte),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn byte_for_point_in_utf8_text() {
+        let text = LineIndex::new(SourceText::Utf8("ab\ncd"));
+        assert_eq!(text.byte_for_point(Point { row: 0, column: 1 }), 1);
+        assert_eq!(text.byte_for_point(Point { row: 1, column: 1 }), 4);
+        // Column past the end of the line is clamped to it
+        assert_eq!(text.byte_for_point(Point { row: 0, column: 10 }), 2);
+        // Row past the end of the text maps to its end
+        assert_eq!(text.byte_for_point(Point { row: 5, column: 0 }), 5);
+    }
+
+    #[test]
+    fn byte_for_point_in_utf16_text() {
+        let units: Vec<u16> = "ab\ncd".encode_utf16().collect();
+        let text = LineIndex::new(SourceText::Utf16(&units));
+        assert_eq!(text.byte_for_point(Point { row: 1, column: 2 }), 8);
+        assert_eq!(text.byte_for_point(Point { row: 1, column: 10 }), 10);
+        assert_eq!(text.byte_for_point(Point { row: 2, column: 0 }), 10);
+    }
+
+    #[test]
+    fn line_byte_ranges_exclude_line_breaks() {
+        let units: Vec<u16> = "ab\n\ncd\n".encode_utf16().collect();
+        let chunks = Utf16Chunks::new([&units[..4], &units[4..]]);
+        let text = LineIndex::new(SourceText::Utf16Chunks(&chunks));
+        assert_eq!(text.line_byte_range(0), Some(0..4));
+        assert_eq!(text.line_byte_range(1), Some(6..6));
+        assert_eq!(text.line_byte_range(2), Some(8..12));
+        assert_eq!(text.line_byte_range(3), Some(14..14));
+        assert_eq!(text.line_byte_range(4), None);
+    }
+
+    #[test]
+    fn capture_offset_shifts_range_by_units() {
+        let units: Vec<u16> = "line\n0123456789abcdef".encode_utf16().collect();
+        let range = Range {
+            start_byte: 18,
+            end_byte: 38,
+            start_point: Point { row: 1, column: 8 },
+            end_point: Point { row: 1, column: 28 },
+        };
+        let shifted = CaptureOffset::new(1, -1).apply_to_range(&range, SourceText::Utf16(&units));
+        assert_eq!(shifted.start_byte, 20);
+        assert_eq!(shifted.end_byte, 36);
+        assert_eq!(shifted.start_point, Point { row: 1, column: 10 });
+        assert_eq!(shifted.end_point, Point { row: 1, column: 26 });
+        assert_eq!(
+            CaptureOffset::new(0, 0).apply_to_range(&range, SourceText::Utf16(&units)),
+            range
+        );
+    }
+    #[test]
+    fn capture_offset_moves_points_across_line_breaks() {
+        let text = SourceText::Utf8("ab\ncd\nef");
+        // Node ending with the line break, so its end is at column 0 of the next line
+        let range = Range {
+            start_byte: 3,
+            end_byte: 6,
+            start_point: Point { row: 1, column: 0 },
+            end_point: Point { row: 2, column: 0 },
+        };
+        let trimmed = CaptureOffset::new(0, -1).apply_to_range(&range, text);
+        assert_eq!((trimmed.start_byte, trimmed.end_byte), (3, 5));
+        assert_eq!(trimmed.end_point, Point { row: 1, column: 2 });
+        let widened = CaptureOffset::new(-1, 1).apply_to_range(&range, text);
+        assert_eq!((widened.start_byte, widened.end_byte), (2, 7));
+        assert_eq!(widened.start_point, Point { row: 0, column: 2 });
+        assert_eq!(widened.end_point, Point { row: 2, column: 1 });
+        // Offsets past the text are clamped to it
+        let clamped = CaptureOffset::new(-10, 10).apply_to_range(&range, text);
+        assert_eq!((clamped.start_byte, clamped.end_byte), (0, 8));
+        assert_eq!(clamped.start_point, Point { row: 0, column: 0 });
+        assert_eq!(clamped.end_point, Point { row: 2, column: 2 });
+    }
+}
@@ -0,0 +1,158 @@
+use std::{
+    cell::Cell,
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
+
+use tree_sitter::QueryCursor;
+
+use crate::Isolate;
+
+#[cfg(feature = "jni")]
+mod jni_methods;
+
+thread_local! {
+    static IN_CALL: Cell<bool> = const { Cell::new(false) };
+    /// Match limit of the isolate the current call runs on, 0 for tree-sitter default
+    static MATCH_LIMIT: Cell<u32> = const { Cell::new(0) };
+    static EXEMPT: Cell<bool> = const { Cell::new(false) };
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+    static TRUNCATED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Limits queries of the call from creation until drop by the match limit and time budget of the
+/// isolate, nested calls share budget of the outer one
+pub struct BudgetGuard {
+    active: bool,
+}
+
+pub fn call(isolate: &Isolate) -> BudgetGuard {
+    if IN_CALL.replace(true) {
+        return BudgetGuard { active: false };
+    }
+    TRUNCATED.set(false);
+    MATCH_LIMIT.set(isolate.options.match_limit.load(Ordering::Relaxed));
+    let budget = isolate.options.time_budget_micros.load(Ordering::Relaxed);
+    DEADLINE.set((budget > 0).then(|| Instant::now() + Duration::from_micros(budget)));
+    BudgetGuard { active: true }
+}
+
+impl Drop for BudgetGuard {
+    fn drop(&mut self) {
+        if self.active {
+            IN_CALL.set(false);
+            MATCH_LIMIT.set(0);
+            DEADLINE.set(None);
+        }
+    }
+}
+
+/// Lifts match limit and time budget for queries of the scope from creation until drop
+pub struct ExemptGuard {
+    outer_in_call: bool,
+    outer_deadline: Option<Instant>,
+    outer_exempt: bool,
+}
+
+/// Runs queries of the scope without match limit and time budget and without touching the
+/// truncated flag. Used while building snapshots: injections dropped for the budget would not be
+/// rediscovered by incremental reparses, which only query the changed ranges.
+pub(crate) fn exempt() -> ExemptGuard {
+    ExemptGuard {
+        outer_in_call: IN_CALL.replace(true),
+        outer_deadline: DEADLINE.take(),
+        outer_exempt: EXEMPT.replace(true),
+    }
+}
+
+impl Drop for ExemptGuard {
+    fn drop(&mut self) {
+        IN_CALL.set(self.outer_in_call);
+        DEADLINE.set(self.outer_deadline);
+        EXEMPT.set(self.outer_exempt);
+    }
+}
+
+/// Whether the time budget of the current call is spent, further queries are skipped then
+pub(crate) fn is_exhausted() -> bool {
+    let exhausted = DEADLINE
+        .get()
+        .is_some_and(|deadline| Instant::now() >= deadline);
+    if exhausted {
+        TRUNCATED.set(true);
+    }
+    exhausted
+}
+
+/// Applies match limit and the time left of the current call to the cursor before it is run
+pub(crate) fn prepare_cursor(cursor: &mut QueryCursor) {
+    let limit = MATCH_LIMIT.get();
+    cursor.set_match_limit(if limit == 0 || EXEMPT.get() {
+        u32::MAX
+    } else {
+        limit
+    });
+    let timeout = DEADLINE.get().map_or(0, |deadline| {
+        // Zero timeout disables it, so the spent budget still leaves a microsecond
+        (deadline
+            .saturating_duration_since(Instant::now())
+            .as_micros() as u64)
+            .max(1)
+    });
+    cursor.set_timeout_micros(timeout);
+}
+
+/// Records whether the cursor run was cut short by the match limit or the time budget, returns
+/// true if it was
+pub(crate) fn finish_cursor(cursor: &QueryCursor) -> bool {
+    let exceeded_match_limit = cursor.did_exceed_match_limit();
+    if exceeded_match_limit {
+        TRUNCATED.set(true);
+    }
+    is_exhausted() || exceeded_match_limit
+}
+
+/// Whether results of the last call on this thread are partial because of the match limit or
+/// the time budget
+pub fn last_call_truncated() -> bool {
+    TRUNCATED.get()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn match_limit_of_the_calling_isolate() {
+        let (limited, unlimited) = (Isolate::create(), Isolate::create());
+        limited.set_option("query.match_limit", "8").unwrap();
+        let mut cursor = QueryCursor::new();
+        {
+            let _budget = call(&limited);
+            prepare_cursor(&mut cursor);
+            assert_eq!(cursor.match_limit(), 8);
+            // Nested call keeps limits of the outer one
+            let _nested = call(&unlimited);
+            prepare_cursor(&mut cursor);
+            assert_eq!(cursor.match_limit(), 8);
+        }
+        let _budget = call(&unlimited);
+        prepare_cursor(&mut cursor);
+        assert_eq!(cursor.match_limit(), u32::MAX);
+    }
+
+    #[test]
+    fn exempt_scope_lifts_match_limit() {
+        let isolate = Isolate::create();
+        isolate.set_option("query.match_limit", "8").unwrap();
+        let _budget = call(&isolate);
+        let mut cursor = QueryCursor::new();
+        {
+            let _exempt = exempt();
+            prepare_cursor(&mut cursor);
+            assert_eq!(cursor.match_limit(), u32::MAX);
+        }
+        prepare_cursor(&mut cursor);
+        assert_eq!(cursor.match_limit(), 8);
+    }
+}
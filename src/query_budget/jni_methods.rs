@@ -0,0 +1,14 @@
+use jni::{objects::JClass, sys::jboolean, JNIEnv};
+
+use super::last_call_truncated;
+
+/// Whether results of the last highlights, ranges or injections call on this thread are partial
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeQueryBudget_nativeLastCallTruncated<
+    'local,
+>(
+    _env: JNIEnv<'local>,
+    _class: JClass<'local>,
+) -> jboolean {
+    last_call_truncated().into()
+}
@@ -1,24 +1,27 @@
-use std::{char, collections::HashMap, ops::Range, sync::Arc, usize};
-
-use jni::{
-    errors::Result as JNIResult,
-    objects::{AutoLocal, JCharArray, JClass, JMethodID, JObject, JObjectArray, JValue},
-    strings::JNIString,
-    sys::{jboolean, jint, jsize},
-    JNIEnv,
+use std::{
+    cmp::Reverse,
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    hash::{Hash, Hasher},
+    ops::Range,
+    sync::Arc,
 };
+
 use streaming_iterator::StreamingIterator;
-use tree_sitter::QueryCursor;
 
 use crate::{
-    jni_utils::{throw_exception_from_result, RangeDesc},
-    language_registry::with_language,
+    invariants,
     predicates::AdditionalPredicates,
-    query::RecodingUtf16TextProvider,
-    syntax_snapshot::{SyntaxSnapshot, SyntaxSnapshotDesc, SyntaxSnapshotEntryContent},
+    profiler,
+    query::{pooled_query_cursor, CaptureOffset, SourceText, SourceTextProvider},
+    query_budget,
+    syntax_snapshot::{SyntaxSnapshot, SyntaxSnapshotEntryContent},
     Language, LanguageId,
 };
-use once_cell::sync::OnceCell as JOnceLock;
+
+#[cfg(feature = "jni")]
+mod jni_methods;
+#[cfg(feature = "jni")]
+pub(crate) use jni_methods::fold_ranges_to_java_array;
 
 #[derive(thiserror::Error, Debug)]
 pub enum RangesQueryError {
@@ -26,6 +29,8 @@ pub enum RangesQueryError {
     NoRequiredCaptures,
     #[error("duplicate captures found")]
     DuplicateCapture,
+    #[error("invalid predicate \"{1}\" for pattern {0}")]
+    InvalidPredicate(usize, Box<str>),
 }
 
 pub struct RangesQuery {
@@ -34,6 +39,10 @@ pub struct RangesQuery {
     main_capture_id: u32,
     start_capture_id: Option<u32>,
     end_capture_id: Option<u32>,
+    /// Names of patterns set by `name` property, the main capture name by default
+    pattern_names: Vec<Box<str>>,
+    /// Adjustments of capture ranges set by `#offset!` directives of each pattern
+    offsets: Vec<HashMap<u32, CaptureOffset>>,
 }
 
 impl RangesQuery {
@@ -64,29 +73,90 @@ impl RangesQuery {
             }
         }
 
+        let pattern_names = (0..query.pattern_count())
+            .map(|pattern_index| {
+                predicates
+                    .metadata()
+                    .pattern(pattern_index)
+                    .value("name")
+                    .unwrap_or(main_capture_name)
+                    .into()
+            })
+            .collect();
+
+        let mut offsets = Vec::with_capacity(query.pattern_count());
+        for pattern_index in 0..query.pattern_count() {
+            let mut pattern_offsets = HashMap::new();
+            for predicate in query.general_predicates(pattern_index) {
+                if predicate.operator.as_ref() != "offset!" {
+                    continue;
+                }
+                let [tree_sitter::QueryPredicateArg::Capture(capture_id), tree_sitter::QueryPredicateArg::String(start), tree_sitter::QueryPredicateArg::String(end)] =
+                    predicate.args.as_ref()
+                else {
+                    return Err(RangesQueryError::InvalidPredicate(
+                        pattern_index,
+                        predicate.operator.clone(),
+                    ));
+                };
+                let (Ok(start), Ok(end)) = (start.parse::<i32>(), end.parse::<i32>()) else {
+                    return Err(RangesQueryError::InvalidPredicate(
+                        pattern_index,
+                        predicate.operator.clone(),
+                    ));
+                };
+                pattern_offsets.insert(*capture_id, CaptureOffset::new(start, end));
+            }
+            offsets.push(pattern_offsets);
+        }
+
         Ok(RangesQuery {
             query,
             predicates,
             main_capture_id: main_capture_id.ok_or(RangesQueryError::NoRequiredCaptures)?,
             start_capture_id,
             end_capture_id,
+            pattern_names,
+            offsets,
         })
     }
+
+    pub fn capture_names(&self) -> &[&str] {
+        self.query.capture_names()
+    }
+
+    pub fn pattern_name(&self, pattern_index: usize) -> &str {
+        &self.pattern_names[pattern_index]
+    }
 }
 
-fn collect_ranges(
+/// Range captured by a ranges query of one of the snapshot layers
+pub struct QueryRange {
+    pub language_id: LanguageId,
+    pub pattern_index: usize,
+    /// Depth of the injection layer, 0 for the base language
+    pub depth: usize,
+    /// Kind of the first node of the main capture
+    pub anchor_kind_id: u16,
+    pub range: tree_sitter::Range,
+    /// Start of the node following the range, used to combine adjacent ranges
+    pub next_byte: usize,
+}
+
+pub fn collect_ranges(
     snapshot: &SyntaxSnapshot,
     query_selector: impl Fn(&Language) -> Option<Arc<RangesQuery>>,
     query_cache: &mut HashMap<LanguageId, Arc<RangesQuery>>,
-    text: &[u16],
+    text: SourceText<'_>,
     byte_range: Range<usize>,
     use_inner: bool,
-) -> Vec<((LanguageId, usize), tree_sitter::Range, usize)> {
+) -> Vec<QueryRange> {
+    let _budget = query_budget::call(&snapshot.isolate);
     let mut ranges = Vec::new();
-    let text_provider = RecodingUtf16TextProvider::new(text);
-    for entry in &snapshot.entries {
-        if byte_range.start >= entry.byte_range.end || byte_range.end <= entry.byte_range.start {
-            continue;
+    let text_provider = SourceTextProvider::new(text);
+    for (_, entry) in snapshot.intersecting_entries(byte_range.clone(), false) {
+        if query_budget::is_exhausted() {
+            break;
         }
         let SyntaxSnapshotEntryContent::Parsed { language, tree } = &entry.content else {
             continue;
@@ -94,14 +164,24 @@ fn collect_ranges(
         let query = if let Some(query) = query_cache.get(language) {
             query
         } else {
-            let Ok(Some(query)) = with_language(*language, |language| query_selector(language))
+            let Ok(Some(query)) = snapshot
+                .isolate
+                .with_language(*language, |language| query_selector(language))
             else {
                 continue;
             };
             query_cache.entry(*language).or_insert(query)
         };
-        let mut cursor = QueryCursor::new();
-        cursor.set_byte_range(entry.byte_range.clone());
+        let _phase = profiler::phase("ranges.query", Some(*language));
+        let mut cursor = pooled_query_cursor();
+        // Empty range still has to match constructs around its offset
+        let start_byte = byte_range.start.max(entry.byte_range.start);
+        let end_byte = byte_range
+            .end
+            .max(byte_range.start + 1)
+            .min(entry.byte_range.end);
+        cursor.set_byte_range(start_byte..end_byte.max(start_byte));
+        query_budget::prepare_cursor(&mut cursor);
         let mut matches = cursor.matches(
             &query.query,
             tree.root_node_with_offset(entry.byte_offset, entry.point_offset),
@@ -110,7 +190,7 @@ fn collect_ranges(
         while let Some(query_match) = matches.next() {
             if !query
                 .predicates
-                .satisfies_predicates(&mut &text_provider, query_match)
+                .satisfies_predicates(&text_provider, query_match)
             {
                 continue;
             }
@@ -119,15 +199,24 @@ fn collect_ranges(
             let mut next_byte: Option<usize> = None;
             let mut start_point: Option<tree_sitter::Point> = None;
             let mut end_point: Option<tree_sitter::Point> = None;
+            let mut anchor_kind_id: Option<u16> = None;
+            let offsets = &query.offsets[query_match.pattern_index];
+            let capture_range =
+                |capture_id: u32, node: tree_sitter::Node| match offsets.get(&capture_id) {
+                    Some(offset) => offset.apply_to_range(&node.range(), text),
+                    None => node.range(),
+                };
             let nodes = query_match.nodes_for_capture_index(query.main_capture_id);
             for node in nodes {
-                if start_byte.is_none_or(|b| node.start_byte() < b) {
-                    start_byte = Some(node.start_byte());
-                    start_point = Some(node.start_position());
+                anchor_kind_id.get_or_insert(node.kind_id());
+                let range = capture_range(query.main_capture_id, node);
+                if start_byte.is_none_or(|b| range.start_byte < b) {
+                    start_byte = Some(range.start_byte);
+                    start_point = Some(range.start_point);
                 }
-                if end_byte.is_none_or(|b| node.end_byte() > b) {
-                    end_byte = Some(node.end_byte());
-                    end_point = Some(node.end_position());
+                if end_byte.is_none_or(|b| range.end_byte > b) {
+                    end_byte = Some(range.end_byte);
+                    end_point = Some(range.end_point);
                 }
                 if let Some(next_node) = node.next_sibling() {
                     if next_byte.is_none_or(|b| next_node.start_byte() > b) {
@@ -139,27 +228,28 @@ fn collect_ranges(
             }
             let use_inner = use_inner
                 || query
-                    .query
-                    .property_settings(query_match.pattern_index)
-                    .iter()
-                    .any(|p| p.key.as_ref() == "range.inner");
+                    .predicates
+                    .metadata()
+                    .pattern(query_match.pattern_index)
+                    .contains("range.inner");
             for capture in query_match.captures {
+                let range = capture_range(capture.index, capture.node);
                 if Some(capture.index) == query.start_capture_id {
                     if use_inner {
-                        start_byte = Some(capture.node.end_byte());
-                        start_point = Some(capture.node.end_position());
+                        start_byte = Some(range.end_byte);
+                        start_point = Some(range.end_point);
                     } else {
-                        start_byte = Some(capture.node.start_byte());
-                        start_point = Some(capture.node.start_position());
+                        start_byte = Some(range.start_byte);
+                        start_point = Some(range.start_point);
                     }
                 } else if Some(capture.index) == query.end_capture_id {
                     if use_inner {
-                        end_byte = Some(capture.node.start_byte());
-                        end_point = Some(capture.node.start_position());
-                        next_byte = Some(capture.node.start_byte());
+                        end_byte = Some(range.start_byte);
+                        end_point = Some(range.start_point);
+                        next_byte = Some(range.start_byte);
                     } else {
-                        end_byte = Some(capture.node.end_byte());
-                        end_point = Some(capture.node.end_position());
+                        end_byte = Some(range.end_byte);
+                        end_point = Some(range.end_point);
                         if let Some(next_node) = capture.node.next_sibling() {
                             next_byte = Some(next_node.start_byte())
                         } else {
@@ -176,258 +266,729 @@ fn collect_ranges(
                 Some(next_byte),
             ) = (start_byte, end_byte, start_point, end_point, next_byte)
             {
-                ranges.push((
-                    (*language, query_match.pattern_index),
-                    tree_sitter::Range {
+                // Offsets may not move the range out of the text or turn it inside out
+                if start_byte > end_byte || end_byte > text.byte_len() {
+                    continue;
+                }
+                ranges.push(QueryRange {
+                    language_id: *language,
+                    pattern_index: query_match.pattern_index,
+                    depth: entry.depth,
+                    anchor_kind_id: anchor_kind_id.unwrap_or_default(),
+                    range: tree_sitter::Range {
                         start_byte,
                         end_byte,
                         start_point,
                         end_point,
                     },
                     next_byte,
-                ));
+                });
             }
         }
+        query_budget::finish_cursor(&cursor);
     }
+    invariants::check_ranges(
+        &snapshot.isolate,
+        ranges.iter().map(|query_range| &query_range.range),
+        text.byte_len(),
+        "ranges query",
+    );
     ranges
 }
 
-#[no_mangle]
-pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeRangesProvider_nativeGetIndentRanges<
-    'local,
->(
-    mut env: JNIEnv<'local>,
-    _class: JClass<'local>,
-    snapshot: JObject<'local>,
-    text: JCharArray<'local>,
-    start_offset: jint,
-    end_offset: jint,
-    use_inner: jboolean,
-) -> JObjectArray<'local> {
-    fn inner<'local>(
-        env: &mut JNIEnv<'local>,
-        snapshot: JObject<'local>,
-        text: JCharArray<'local>,
-        start_offset: jint,
-        end_offset: jint,
-        use_inner: jboolean,
-    ) -> JNIResult<JObjectArray<'local>> {
-        let snapshot = SyntaxSnapshotDesc::from_java_object(env, snapshot)?;
-        let range_desc = RangeDesc::new(env)?;
-        let text_length = env.get_array_length(&text)?;
-        let mut text_buffer = vec![0u16; text_length as usize];
-        env.get_char_array_region(&text, 0, &mut text_buffer)?;
-
-        let use_inner = use_inner != 0;
-        let mut query_cache = HashMap::new();
-        let ranges = collect_ranges(
-            snapshot,
-            |l| l.parser_info().indents_query.clone(),
-            &mut query_cache,
-            &text_buffer,
-            ((start_offset * 2) as usize)..((end_offset * 2) as usize),
-            use_inner,
-        );
+/// Sorts ranges by start with enclosing ranges first and drops duplicates, of the items with the
+/// same range the first one is kept
+fn sort_ranges<T>(items: &mut Vec<T>, range: impl Fn(&T) -> &tree_sitter::Range) {
+    items.sort_by_key(|item| (range(item).start_byte, Reverse(range(item).end_byte)));
+    items.dedup_by(|item, other| {
+        (range(item).start_byte, range(item).end_byte)
+            == (range(other).start_byte, range(other).end_byte)
+    });
+}
 
-        let ranges_array =
-            env.new_object_array(ranges.len() as jsize, &range_desc.class, JObject::null())?;
-        for (index, (_, range, _)) in ranges.into_iter().enumerate() {
-            let range_obj = range_desc.to_java_object(env, range)?;
-            let range_obj = env.auto_local(range_obj);
-            env.set_object_array_element(&ranges_array, index as i32, range_obj)?;
-        }
-        Ok(ranges_array)
-    }
-    let result = inner(
-        &mut env,
+/// Range along with the name of the pattern it is matched by
+#[derive(Debug, Clone)]
+pub struct PatternRange {
+    pub range: tree_sitter::Range,
+    /// Value of `name` pattern property or the main capture name
+    pub pattern_name: Box<str>,
+}
+
+/// Indent ranges sorted by start with enclosing ranges first, without duplicates
+pub fn collect_indent_ranges(
+    snapshot: &SyntaxSnapshot,
+    text: SourceText<'_>,
+    byte_range: Range<usize>,
+    use_inner: bool,
+) -> Vec<PatternRange> {
+    let _profile = profiler::call(&snapshot.isolate, "indents");
+    let mut query_cache = HashMap::new();
+    let ranges = collect_ranges(
         snapshot,
+        |l| l.parser_info().ranges_query("indents"),
+        &mut query_cache,
         text,
-        start_offset,
-        end_offset,
+        byte_range,
         use_inner,
     );
-    throw_exception_from_result(&mut env, result)
+    let mut ranges = ranges
+        .into_iter()
+        .map(|query_range| PatternRange {
+            range: query_range.range,
+            pattern_name: query_cache[&query_range.language_id]
+                .pattern_name(query_range.pattern_index)
+                .into(),
+        })
+        .collect();
+    sort_ranges(&mut ranges, |range| &range.range);
+    ranges
 }
 
-static FOLD_RANGE_CONSTRUCTOR: JOnceLock<JMethodID> = JOnceLock::new();
+/// Ranges of the query registered under `name` by [`crate::Isolate::add_ranges_query`], sorted
+/// the same way as [`collect_indent_ranges`]
+pub fn collect_named_ranges(
+    snapshot: &SyntaxSnapshot,
+    text: SourceText<'_>,
+    name: &str,
+    byte_range: Range<usize>,
+    use_inner: bool,
+) -> Vec<tree_sitter::Range> {
+    let _profile = profiler::call(&snapshot.isolate, "ranges");
+    let mut query_cache = HashMap::new();
+    let mut ranges = collect_ranges(
+        snapshot,
+        |l| l.parser_info().ranges_query(name),
+        &mut query_cache,
+        text,
+        byte_range,
+        use_inner,
+    )
+    .into_iter()
+    .map(|query_range| query_range.range)
+    .collect();
+    sort_ranges(&mut ranges, |range| range);
+    ranges
+}
+
+/// Statements captured as `@statement` by statement queries intersecting `byte_range`, sorted by
+/// start with enclosing statements first. Statements nested into another statement within
+/// `byte_range` are skipped, so the result consists of top-level statements of the range and
+/// statements enclosing it.
+pub fn collect_statement_ranges(
+    snapshot: &SyntaxSnapshot,
+    text: SourceText<'_>,
+    byte_range: Range<usize>,
+) -> Vec<tree_sitter::Range> {
+    let _profile = profiler::call(&snapshot.isolate, "statements");
+    let mut query_cache = HashMap::new();
+    let mut statements: Vec<tree_sitter::Range> = collect_ranges(
+        snapshot,
+        |l| l.parser_info().ranges_query("statements"),
+        &mut query_cache,
+        text,
+        byte_range.clone(),
+        false,
+    )
+    .into_iter()
+    .map(|query_range| query_range.range)
+    .filter(|range| range.start_byte <= byte_range.end && range.end_byte >= byte_range.start)
+    .collect();
+    sort_ranges(&mut statements, |range| range);
+    let within_range = |range: &tree_sitter::Range| {
+        byte_range.start <= range.start_byte && range.end_byte <= byte_range.end
+    };
+    let mut top_level_end: Option<usize> = None;
+    statements.retain(|range| {
+        if top_level_end.is_some_and(|end| range.end_byte <= end) {
+            return false;
+        }
+        if within_range(range) {
+            top_level_end = Some(range.end_byte);
+        }
+        true
+    });
+    statements
+}
 
-struct FoldRangeDesc<'local> {
-    constructor: JMethodID,
-    class: AutoLocal<'local, JClass<'local>>,
-    range_desc: RangeDesc<'local>,
+#[derive(Debug, Clone)]
+pub struct FoldRange {
+    pub range: tree_sitter::Range,
+    pub collapsed_text: Option<Box<str>>,
+    pub collapsed_by_default: bool,
+    /// Value of `fold.collapsed`, e.g. `imports`, so the editor may decide by user settings
+    /// whether folds of the category are collapsed by default
+    pub collapsed_category: Option<Box<str>>,
+    /// Language of the layer the fold comes from
+    pub language_id: LanguageId,
+    /// Depth of the injection layer the fold comes from, 0 for the base language
+    pub depth: usize,
+    /// Identity of the fold kept by edits outside of the line it starts on, see [`fold_id`]
+    pub id: u64,
+    /// Value of `name` pattern property or `fold`
+    pub pattern_name: Box<str>,
 }
 
-impl<'local> FoldRangeDesc<'local> {
-    fn new(env: &mut JNIEnv<'local>) -> JNIResult<FoldRangeDesc<'local>> {
-        let range_desc = RangeDesc::new(env)?;
-        let class = env.find_class("com/hulylabs/treesitter/language/FoldRange")?;
-        let constructor = *FOLD_RANGE_CONSTRUCTOR.get_or_try_init(|| {
-            env.get_method_id(
-                &class,
-                "<init>",
-                "(Lcom/hulylabs/treesitter/language/Range;Ljava/lang/String;Z)V",
-            )
-        })?;
+/// Units of the line taken into [`fold_id`] on each side of the fold start
+const FOLD_ID_LINE_WINDOW: usize = 128;
 
-        Ok(FoldRangeDesc {
-            constructor,
-            class: env.auto_local(class),
-            range_desc,
-        })
-    }
+/// Hash of the pattern, layer and kind of the anchor node of the fold along with the text of the
+/// line it starts on, so the editor may keep collapsed state of folds across edits. Only
+/// [`FOLD_ID_LINE_WINDOW`] units around the start are hashed, so long lines are not copied per fold
+fn fold_id(text: SourceText<'_>, query_range: &QueryRange) -> u64 {
+    let unit_size = text.unit_size();
+    let start_unit = query_range.range.start_byte / unit_size;
+    let line_start_unit = start_unit - query_range.range.start_point.column / unit_size;
+    let line_start_unit = line_start_unit.max(start_unit.saturating_sub(FOLD_ID_LINE_WINDOW));
+    let window_end_unit = text.len().min(start_unit + FOLD_ID_LINE_WINDOW);
+    let line_end_unit = (start_unit..window_end_unit)
+        .find(|idx| text.unit(*idx) == '\n' as u16)
+        .unwrap_or(window_end_unit);
+    let line = text.text_for_byte_range((line_start_unit * unit_size)..(line_end_unit * unit_size));
+    let mut hasher = DefaultHasher::new();
+    query_range.language_id.hash(&mut hasher);
+    query_range.pattern_index.hash(&mut hasher);
+    query_range.depth.hash(&mut hasher);
+    query_range.anchor_kind_id.hash(&mut hasher);
+    line.trim().hash(&mut hasher);
+    hasher.finish()
+}
 
-    fn to_java_object(
-        &self,
-        env: &mut JNIEnv<'local>,
-        range: tree_sitter::Range,
-        collapsed_text: Option<impl Into<JNIString>>,
-        collapsed_by_default: bool,
-    ) -> JNIResult<JObject<'local>> {
-        let range_obj = self.range_desc.to_java_object(env, range)?;
-        let range_obj = env.auto_local(range_obj);
-        let collapsed_text: JObject = if let Some(collapsed_text) = collapsed_text {
-            env.new_string(collapsed_text)?.into()
-        } else {
-            JObject::null()
-        };
-        let collapsed_text = env.auto_local(collapsed_text);
-        unsafe {
-            env.new_object_unchecked(
-                &self.class,
-                self.constructor,
-                &[
-                    JValue::Object(&range_obj).as_jni(),
-                    JValue::Object(&collapsed_text).as_jni(),
-                    JValue::from(collapsed_by_default).as_jni(),
-                ],
-            )
-        }
-    }
+/// Fold of one or several consecutive ranges combined by `fold.combined-lines`
+struct CombinedFold<'a> {
+    id: u64,
+    language_id: LanguageId,
+    depth: usize,
+    range: tree_sitter::Range,
+    collapsed_by_default: bool,
+    collapsed_category: Option<&'a str>,
+    collapsed_text: Option<&'a str>,
+    pattern_name: &'a str,
+    next_byte: usize,
+    /// Code units added to the range bounds by `fold.offset.start` and `fold.offset.end`
+    offset: CaptureOffset,
 }
 
-#[no_mangle]
-pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeRangesProvider_nativeGetFoldRanges<
-    'local,
->(
-    mut env: JNIEnv<'local>,
-    _class: JClass<'local>,
-    snapshot: JObject<'local>,
-    text: JCharArray<'local>,
-    start_offset: jint,
-    end_offset: jint,
-    use_inner: jboolean,
-) -> JObjectArray<'local> {
-    fn inner<'local>(
-        env: &mut JNIEnv<'local>,
-        snapshot: JObject<'local>,
-        text: JCharArray<'local>,
-        start_offset: jint,
-        end_offset: jint,
-        use_inner: jboolean,
-    ) -> JNIResult<JObjectArray<'local>> {
-        let snapshot = SyntaxSnapshotDesc::from_java_object(env, snapshot)?;
-        let fold_range_desc = FoldRangeDesc::new(env)?;
-        let text_length = env.get_array_length(&text)?;
-        let mut text_buffer = vec![0u16; text_length as usize];
-        env.get_char_array_region(&text, 0, &mut text_buffer)?;
-
-        let use_inner = use_inner != 0;
-        let mut query_cache = HashMap::new();
-        let ranges = collect_ranges(
-            snapshot,
-            |l| l.parser_info().folds_query.clone(),
-            &mut query_cache,
-            &text_buffer,
-            ((start_offset * 2) as usize)..((end_offset * 2) as usize),
-            use_inner,
-        );
-        let mut combined_ranges: Vec<(usize, tree_sitter::Range, bool, Option<&str>, usize)> =
-            Vec::new();
-        let mut last_combined_idx: HashMap<usize, usize> = HashMap::new();
-        'outer: for ((language_id, pattern_id), range, next_byte) in ranges {
-            let query = query_cache
-                .get(&language_id)
-                .expect("query exists in cache if returned from collect_ranges");
-            let mut collapsed_text = None;
-            let mut collapsed_by_default = false;
-            let properties = query.query.property_settings(pattern_id as usize);
-            for property in properties {
-                if property.key.as_ref() == "fold.text" {
-                    collapsed_text = property.value.as_ref().map(|t| t.as_ref());
-                }
-                if property.key.as_ref() == "fold.collapsed" {
-                    collapsed_by_default = true;
-                }
-                if property.key.as_ref() == "fold.combined-lines" {
-                    if let Some((_, last_range, _, _, last_next_byte)) = last_combined_idx
-                        .get(&pattern_id)
-                        .and_then(|idx| combined_ranges.get_mut(*idx))
+/// Folds sorted by start with enclosing folds first. Of the folds with the same range only the
+/// first matched one is kept.
+pub fn collect_fold_ranges(
+    snapshot: &SyntaxSnapshot,
+    text: SourceText<'_>,
+    byte_range: Range<usize>,
+    use_inner: bool,
+) -> Vec<FoldRange> {
+    let _profile = profiler::call(&snapshot.isolate, "folds");
+    let mut query_cache = HashMap::new();
+    let ranges = collect_ranges(
+        snapshot,
+        |l| l.parser_info().ranges_query("folds"),
+        &mut query_cache,
+        text,
+        byte_range,
+        use_inner,
+    );
+    let mut combined_ranges: Vec<CombinedFold> = Vec::new();
+    // Pattern indices are only unique within the query of one language
+    let mut last_combined_idx: HashMap<(LanguageId, usize), usize> = HashMap::new();
+    'outer: for query_range in ranges {
+        let id = fold_id(text, &query_range);
+        let QueryRange {
+            language_id,
+            pattern_index: pattern_id,
+            depth,
+            range,
+            next_byte,
+            ..
+        } = query_range;
+        let query = query_cache
+            .get(&language_id)
+            .expect("query exists in cache if returned from collect_ranges");
+        let mut collapsed_text = None;
+        let mut collapsed_by_default = false;
+        let mut collapsed_category = None;
+        let mut start_offset = 0;
+        let mut end_offset = 0;
+        let properties = query.predicates.metadata().pattern(pattern_id).properties();
+        for property in properties {
+            let offset = || {
+                property
+                    .value
+                    .as_ref()
+                    .and_then(|value| value.parse::<i32>().ok())
+                    .unwrap_or(0)
+            };
+            if property.key.as_ref() == "fold.offset.start" {
+                start_offset = offset();
+            }
+            if property.key.as_ref() == "fold.offset.end" {
+                end_offset = offset();
+            }
+            if property.key.as_ref() == "fold.text" {
+                collapsed_text = property.value.as_ref().map(|t| t.as_ref());
+            }
+            if property.key.as_ref() == "fold.collapsed" {
+                collapsed_by_default = true;
+                collapsed_category = property.value.as_deref();
+            }
+            if property.key.as_ref() == "fold.combined-lines" {
+                if let Some(last) = last_combined_idx
+                    .get(&(language_id, pattern_id))
+                    .and_then(|idx| combined_ranges.get_mut(*idx))
+                {
+                    if last.next_byte == range.start_byte
+                        && range.start_point.column == last.range.start_point.column
+                        && (last.range.end_point.row + 1 == range.start_point.row
+                            || last.range.end_point.row == range.start_point.row)
                     {
-                        if *last_next_byte == range.start_byte
-                            && range.start_point.column == last_range.start_point.column
-                            && (last_range.end_point.row + 1 == range.start_point.row
-                                || last_range.end_point.row == range.start_point.row)
-                        {
-                            last_range.end_byte = range.end_byte;
-                            last_range.end_point = range.end_point;
-                            *last_next_byte = next_byte;
-                            continue 'outer;
-                        }
+                        last.range.end_byte = range.end_byte;
+                        last.range.end_point = range.end_point;
+                        last.next_byte = next_byte;
+                        continue 'outer;
                     }
-                    last_combined_idx.insert(pattern_id, combined_ranges.len());
                 }
+                last_combined_idx.insert((language_id, pattern_id), combined_ranges.len());
             }
-            combined_ranges.push((
-                pattern_id,
-                range,
+        }
+        combined_ranges.push(CombinedFold {
+            id,
+            pattern_name: query.pattern_name(pattern_id),
+            language_id,
+            depth,
+            range,
+            collapsed_by_default,
+            collapsed_category,
+            collapsed_text,
+            next_byte,
+            offset: CaptureOffset::new(start_offset, end_offset),
+        });
+    }
+    let unit_size = text.unit_size();
+    let mut folds: Vec<FoldRange> = combined_ranges
+        .into_iter()
+        .filter_map(|fold| {
+            let CombinedFold {
+                id,
+                pattern_name,
+                language_id,
+                depth,
+                mut range,
                 collapsed_by_default,
+                collapsed_category,
                 collapsed_text,
-                next_byte,
-            ));
-        }
-        let ranges_array = env.new_object_array(
-            combined_ranges.len() as jsize,
-            &fold_range_desc.class,
-            JObject::null(),
-        )?;
-        for (index, (_, mut range, collapsed_by_default, collapsed_text, _)) in
-            combined_ranges.into_iter().enumerate()
-        {
+                offset,
+                ..
+            } = fold;
+            // Offsets may not move the range out of the text or turn it inside out
+            let start_byte =
+                range.start_byte as i64 + offset.start_offset() as i64 * unit_size as i64;
+            let end_byte = range.end_byte as i64 + offset.end_offset() as i64 * unit_size as i64;
+            if start_byte < 0 || end_byte > text.byte_len() as i64 || start_byte >= end_byte {
+                return None;
+            }
+            range = offset.apply_to_range(&range, text);
             // Some nodes may include newline at the end, but folds should not end with newline
-            if text_buffer[range.end_byte / 2 - 1] == '\n' as u16 {
-                range.end_byte -= 1;
+            let end_unit = range.end_byte / unit_size;
+            if end_unit > 0 && text.unit(end_unit - 1) == '\n' as u16 {
+                let line_end_unit = end_unit - 1;
+                let line_start_unit = (0..line_end_unit)
+                    .rev()
+                    .find(|idx| text.unit(*idx) == '\n' as u16)
+                    .map_or(0, |idx| idx + 1);
+                range.end_byte = line_end_unit * unit_size;
                 range.end_point.row -= 1;
-                let line_end_offset = range.end_byte / 2 - 1;
-                let mut offset = line_end_offset;
-                let line_start_offset = loop {
-                    let new_offset = offset.saturating_sub(1);
-                    if text_buffer[new_offset] == ('\n' as u16) || new_offset == 0 {
-                        break offset;
-                    }
-                    offset = new_offset;
-                };
-                range.end_point.column = char::decode_utf16(
-                    text_buffer[line_start_offset..line_start_offset]
-                        .iter()
-                        .copied(),
-                )
-                .count();
+                range.end_point.column = (line_end_unit - line_start_unit) * unit_size;
+            }
+            Some(FoldRange {
+                range,
+                collapsed_text: collapsed_text.map(Into::into),
+                collapsed_by_default,
+                collapsed_category: collapsed_category.map(Into::into),
+                language_id,
+                depth,
+                id,
+                pattern_name: pattern_name.into(),
+            })
+        })
+        .collect();
+    // Stable sort keeps folds of the same range in the order they were matched. Folds of the same
+    // range from different layers are all kept, so the editor sees the language of each of them
+    folds.sort_by_key(|fold| (fold.range.start_byte, Reverse(fold.range.end_byte)));
+    folds.dedup_by(|fold, other| {
+        (
+            fold.range.start_byte,
+            fold.range.end_byte,
+            fold.language_id,
+            fold.depth,
+        ) == (
+            other.range.start_byte,
+            other.range.end_byte,
+            other.language_id,
+            other.depth,
+        )
+    });
+    folds
+}
+
+/// Folds intersecting any of `changed_byte_ranges`, so the editor may update only the folds of
+/// dirty regions and match them to the previous ones by [`FoldRange::id`]
+pub fn collect_changed_fold_ranges(
+    snapshot: &SyntaxSnapshot,
+    text: SourceText<'_>,
+    changed_byte_ranges: &[Range<usize>],
+) -> Vec<FoldRange> {
+    let mut changed: Vec<Range<usize>> = changed_byte_ranges.to_vec();
+    changed.sort_by_key(|range| range.start);
+    let mut merged: Vec<Range<usize>> = Vec::with_capacity(changed.len());
+    for range in changed {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    let mut folds: Vec<FoldRange> = Vec::new();
+    let mut seen: HashSet<(usize, usize, u64)> = HashSet::new();
+    for range in merged {
+        for fold in collect_fold_ranges(snapshot, text, range.clone(), false) {
+            let intersects =
+                fold.range.start_byte <= range.end && fold.range.end_byte >= range.start;
+            if intersects && seen.insert((fold.range.start_byte, fold.range.end_byte, fold.id)) {
+                folds.push(fold);
             }
-            let obj =
-                fold_range_desc.to_java_object(env, range, collapsed_text, collapsed_by_default)?;
-            let obj = env.auto_local(obj);
-            env.set_object_array_element(&ranges_array, index as i32, obj)?;
         }
+    }
+    folds.sort_by_key(|fold| (fold.range.start_byte, Reverse(fold.range.end_byte)));
+    folds
+}
+
+/// Construct enclosing an offset, e.g. a function shown in sticky scroll header
+#[derive(Debug, Clone)]
+pub struct ContextRange {
+    pub language_id: LanguageId,
+    pub range: tree_sitter::Range,
+    /// Part of the construct identifying it: up to the start of `@end` capture if there is one,
+    /// the first line of the construct otherwise
+    pub header: tree_sitter::Range,
+}
+
+/// End of the first line of the node, or of the node if it fits into one line
+fn first_line_end(text: SourceText<'_>, node: tree_sitter::Node) -> (usize, tree_sitter::Point) {
+    let unit_size = text.unit_size();
+    let start_unit = node.start_byte() / unit_size;
+    (start_unit..(node.end_byte() / unit_size))
+        .find(|idx| text.unit(*idx) == '\n' as u16)
+        .map_or((node.end_byte(), node.end_position()), |idx| {
+            (
+                idx * unit_size,
+                tree_sitter::Point {
+                    row: node.start_position().row,
+                    column: node.start_position().column + (idx - start_unit) * unit_size,
+                },
+            )
+        })
+}
+
+/// Constructs captured as `@context` by context queries of all layers which enclose
+/// `byte_offset`, from the outermost one
+pub fn collect_context_ranges(
+    snapshot: &SyntaxSnapshot,
+    text: SourceText<'_>,
+    byte_offset: usize,
+) -> Vec<ContextRange> {
+    let _profile = profiler::call(&snapshot.isolate, "contexts");
+    collect_enclosing_ranges(snapshot, text, "contexts", "contexts.query", byte_offset)
+}
+
+/// Code block enclosing an offset, e.g. for breadcrumbs or highlighting of the current scope
+#[derive(Debug, Clone)]
+pub struct CodeBlock {
+    pub language_id: LanguageId,
+    pub range: tree_sitter::Range,
+    /// Text of the block header with whitespace runs collapsed to single spaces
+    pub label: Box<str>,
+}
+
+/// Blocks captured as `@block` by block queries of all layers which enclose `byte_offset`, from
+/// the outermost one. Headers are determined the same way as for [`collect_context_ranges`].
+pub fn collect_code_blocks(
+    snapshot: &SyntaxSnapshot,
+    text: SourceText<'_>,
+    byte_offset: usize,
+) -> Vec<CodeBlock> {
+    let _profile = profiler::call(&snapshot.isolate, "blocks");
+    collect_enclosing_ranges(snapshot, text, "blocks", "blocks.query", byte_offset)
+        .into_iter()
+        .map(|context| {
+            let header =
+                text.text_for_byte_range(context.header.start_byte..context.header.end_byte);
+            CodeBlock {
+                language_id: context.language_id,
+                range: context.range,
+                label: header
+                    .split_whitespace()
+                    .collect::<Vec<_>>()
+                    .join(" ")
+                    .into(),
+            }
+        })
+        .collect()
+}
 
-        Ok(ranges_array)
+/// Ranges of the query registered under `query_name` enclosing `byte_offset` along with their
+/// headers, from the outermost one
+fn collect_enclosing_ranges(
+    snapshot: &SyntaxSnapshot,
+    text: SourceText<'_>,
+    query_name: &str,
+    phase: &'static str,
+    byte_offset: usize,
+) -> Vec<ContextRange> {
+    let text_provider = SourceTextProvider::new(text);
+    let mut contexts = Vec::new();
+    for (_, entry) in snapshot.intersecting_entries(byte_offset..(byte_offset + 1), false) {
+        let SyntaxSnapshotEntryContent::Parsed { language, tree } = &entry.content else {
+            continue;
+        };
+        let Ok(Some(query)) = snapshot.isolate.with_language(*language, |language| {
+            language.parser_info().ranges_query(query_name)
+        }) else {
+            continue;
+        };
+        let _phase = profiler::phase(phase, Some(*language));
+        let mut cursor = pooled_query_cursor();
+        cursor.set_byte_range(byte_offset..(byte_offset + 1));
+        let mut matches = cursor.matches(
+            &query.query,
+            tree.root_node_with_offset(entry.byte_offset, entry.point_offset),
+            &text_provider,
+        );
+        while let Some(query_match) = matches.next() {
+            if !query
+                .predicates
+                .satisfies_predicates(&text_provider, query_match)
+            {
+                continue;
+            }
+            for node in query_match.nodes_for_capture_index(query.main_capture_id) {
+                if node.start_byte() > byte_offset || node.end_byte() <= byte_offset {
+                    continue;
+                }
+                let (end_byte, end_point) = query
+                    .end_capture_id
+                    .and_then(|end_capture_id| {
+                        query_match.nodes_for_capture_index(end_capture_id).next()
+                    })
+                    .filter(|end| end.start_byte() >= node.start_byte())
+                    .map_or_else(
+                        || first_line_end(text, node),
+                        |end| (end.start_byte(), end.start_position()),
+                    );
+                contexts.push(ContextRange {
+                    language_id: *language,
+                    range: node.range(),
+                    header: tree_sitter::Range {
+                        start_byte: node.start_byte(),
+                        end_byte,
+                        start_point: node.start_position(),
+                        end_point,
+                    },
+                });
+            }
+        }
     }
-    let result = inner(
-        &mut env,
+    contexts.sort_by_key(|context| (context.range.start_byte, Reverse(context.range.end_byte)));
+    contexts.dedup_by_key(|context| (context.range.start_byte, context.range.end_byte));
+    contexts
+}
+
+/// Boundary points are known only when taken from a unit node
+struct FormatRange {
+    start: usize,
+    start_point: Option<tree_sitter::Point>,
+    end: usize,
+    end_point: Option<tree_sitter::Point>,
+}
+
+/// Expands changed ranges to complete formatting units captured as `@format`, so the result
+/// never cuts through a unit. Boundary not covered by any unit is left as is.
+pub fn collect_format_ranges(
+    snapshot: &SyntaxSnapshot,
+    text: SourceText<'_>,
+    changed_byte_ranges: &[Range<usize>],
+) -> Vec<tree_sitter::Range> {
+    let (Some(min_byte), Some(max_byte)) = (
+        changed_byte_ranges.iter().map(|r| r.start).min(),
+        changed_byte_ranges.iter().map(|r| r.end).max(),
+    ) else {
+        return Vec::new();
+    };
+    let _profile = profiler::call(&snapshot.isolate, "formats");
+    let mut query_cache = HashMap::new();
+    let units: Vec<tree_sitter::Range> = collect_ranges(
         snapshot,
+        |l| l.parser_info().ranges_query("formats"),
+        &mut query_cache,
         text,
-        start_offset,
-        end_offset,
-        use_inner,
-    );
-    throw_exception_from_result(&mut env, result)
+        min_byte..(max_byte + 1),
+        false,
+    )
+    .into_iter()
+    .map(|query_range| query_range.range)
+    .collect();
+
+    let mut expanded: Vec<FormatRange> = Vec::with_capacity(changed_byte_ranges.len());
+    for changed in changed_byte_ranges {
+        let (mut start, mut start_point) = (changed.start, None);
+        let (mut end, mut end_point) = (changed.end, None);
+        // Smallest units around each boundary
+        if let Some(unit) = units
+            .iter()
+            .filter(|u| u.start_byte <= start && start < u.end_byte)
+            .min_by_key(|u| u.end_byte - u.start_byte)
+        {
+            start = unit.start_byte;
+            start_point = Some(unit.start_point);
+            if unit.end_byte > end {
+                end = unit.end_byte;
+                end_point = Some(unit.end_point);
+            }
+        }
+        if let Some(unit) = units
+            .iter()
+            .filter(|u| u.start_byte < end && end <= u.end_byte)
+            .min_by_key(|u| u.end_byte - u.start_byte)
+        {
+            if unit.start_byte < start {
+                start = unit.start_byte;
+                start_point = Some(unit.start_point);
+            }
+            end = unit.end_byte;
+            end_point = Some(unit.end_point);
+        }
+        // Units cutting through one of the boundaries are included until none is left
+        loop {
+            let cutting = units.iter().find(|u| {
+                let contains_start = u.start_byte < start && start < u.end_byte;
+                let contains_end = u.start_byte < end && end < u.end_byte;
+                contains_start != contains_end
+            });
+            let Some(unit) = cutting else {
+                break;
+            };
+            if unit.start_byte < start {
+                start = unit.start_byte;
+                start_point = Some(unit.start_point);
+            }
+            if unit.end_byte > end {
+                end = unit.end_byte;
+                end_point = Some(unit.end_point);
+            }
+        }
+        expanded.push(FormatRange {
+            start,
+            start_point,
+            end,
+            end_point,
+        });
+    }
+
+    expanded.sort_by_key(|range| (range.start, range.end));
+    let mut merged: Vec<FormatRange> = Vec::with_capacity(expanded.len());
+    for range in expanded {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => {
+                if range.end > last.end {
+                    last.end = range.end;
+                    last.end_point = range.end_point;
+                }
+            }
+            _ => merged.push(range),
+        }
+    }
+    merged
+        .into_iter()
+        .map(|range| tree_sitter::Range {
+            start_byte: range.start,
+            end_byte: range.end,
+            start_point: range
+                .start_point
+                .unwrap_or_else(|| text.point_for_byte(range.start)),
+            end_point: range
+                .end_point
+                .unwrap_or_else(|| text.point_for_byte(range.end)),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{json_isolate, parse, reparse};
+
+    #[test]
+    fn fold_end_offset_moves_end_point_to_previous_line() {
+        let (isolate, language_id) = json_isolate();
+        isolate
+            .add_fold_query(language_id, "((object) @fold (#set! fold.offset.end -2))")
+            .unwrap();
+        let text = "{\n \"a\": 1\n}";
+        let snapshot = parse(&isolate, language_id, text);
+        let text = SourceText::Utf8(text);
+        let folds = collect_fold_ranges(&snapshot, text, 0..text.byte_len(), false);
+        assert_eq!(folds.len(), 1);
+        let range = folds[0].range;
+        assert_eq!((range.start_byte, range.end_byte), (0, 9));
+        assert_eq!(range.end_point, tree_sitter::Point { row: 1, column: 7 });
+    }
+
+    fn fold_ids(snapshot: &SyntaxSnapshot, text: &str) -> Vec<(usize, u64)> {
+        let text = SourceText::Utf8(text);
+        collect_fold_ranges(snapshot, text, 0..text.byte_len(), false)
+            .into_iter()
+            .map(|fold| (fold.range.start_point.row, fold.id))
+            .collect()
+    }
+
+    #[test]
+    fn fold_ids_are_kept_by_edits_outside_of_their_start_line() {
+        let (isolate, language_id) = json_isolate();
+        isolate
+            .add_fold_query(language_id, "(pair value: (array) @fold)")
+            .unwrap();
+        let text = "{\n \"a\": [\n  1\n ],\n \"b\": [\n  2\n ]\n}";
+        let snapshot = parse(&isolate, language_id, text);
+        let ids = fold_ids(&snapshot, text);
+        assert_eq!(ids.len(), 2);
+        assert_ne!(ids[0].1, ids[1].1);
+
+        let (new_text, new_snapshot) = reparse(&snapshot, text, 1..1, "\n \"z\": 0,");
+        let new_ids = fold_ids(&new_snapshot, &new_text);
+        assert_eq!(new_ids, vec![(2, ids[0].1), (5, ids[1].1)]);
+
+        let two = new_text.find('2').unwrap();
+        let (new_text, new_snapshot) = reparse(&new_snapshot, &new_text, two..(two + 1), "22");
+        assert_eq!(fold_ids(&new_snapshot, &new_text), new_ids);
+
+        let b = new_text.find('b').unwrap();
+        let (new_text, new_snapshot) = reparse(&new_snapshot, &new_text, b..(b + 1), "c");
+        let renamed_ids = fold_ids(&new_snapshot, &new_text);
+        assert_eq!(renamed_ids[0], new_ids[0]);
+        assert_ne!(renamed_ids[1].1, new_ids[1].1);
+    }
+
+    #[test]
+    fn changed_fold_ranges_intersect_changed_ranges() {
+        let (isolate, language_id) = json_isolate();
+        isolate
+            .add_fold_query(language_id, "(pair value: (array) @fold)")
+            .unwrap();
+        let text = "{\n \"a\": [\n  1\n ],\n \"b\": [\n  2\n ]\n}";
+        let snapshot = parse(&isolate, language_id, text);
+        let two = text.find('2').unwrap();
+        let folds = collect_changed_fold_ranges(
+            &snapshot,
+            SourceText::Utf8(text),
+            &[two..(two + 1), two..two],
+        );
+        assert_eq!(folds.len(), 1);
+        assert_eq!(folds[0].range.start_point.row, 4);
+    }
 }
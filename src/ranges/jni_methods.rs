@@ -0,0 +1,656 @@
+use jni::{
+    errors::Result as JNIResult,
+    objects::{
+        AutoLocal, JCharArray, JClass, JIntArray, JMethodID, JObject, JObjectArray, JString, JValue,
+    },
+    sys::{jboolean, jint, jsize},
+    JNIEnv,
+};
+use once_cell::sync::OnceCell as JOnceLock;
+
+use crate::{
+    jni_utils::{
+        read_char_array, throw_exception_from_result, JavaTextRange, PointDesc, RangeDesc,
+    },
+    query::SourceText,
+    syntax_snapshot::SyntaxSnapshotDesc,
+};
+
+use super::{
+    collect_changed_fold_ranges, collect_code_blocks, collect_context_ranges, collect_fold_ranges,
+    collect_format_ranges, collect_indent_ranges, collect_named_ranges, collect_statement_ranges,
+    CodeBlock, ContextRange, FoldRange, PatternRange,
+};
+
+static PATTERN_RANGE_CONSTRUCTOR: JOnceLock<JMethodID> = JOnceLock::new();
+
+struct PatternRangeDesc<'local> {
+    constructor: JMethodID,
+    class: AutoLocal<'local, JClass<'local>>,
+    range_desc: RangeDesc<'local>,
+}
+
+impl<'local> PatternRangeDesc<'local> {
+    fn new(env: &mut JNIEnv<'local>) -> JNIResult<PatternRangeDesc<'local>> {
+        let range_desc = RangeDesc::new(env)?;
+        let class = env.find_class("com/hulylabs/treesitter/language/PatternRange")?;
+        let constructor = *PATTERN_RANGE_CONSTRUCTOR.get_or_try_init(|| {
+            env.get_method_id(
+                &class,
+                "<init>",
+                "(Lcom/hulylabs/treesitter/language/Range;Ljava/lang/String;)V",
+            )
+        })?;
+        Ok(PatternRangeDesc {
+            constructor,
+            class: env.auto_local(class),
+            range_desc,
+        })
+    }
+
+    fn to_java_object(
+        &self,
+        env: &mut JNIEnv<'local>,
+        pattern_range: &PatternRange,
+    ) -> JNIResult<JObject<'local>> {
+        let range_obj = self.range_desc.to_java_object(env, pattern_range.range)?;
+        let range_obj = env.auto_local(range_obj);
+        let pattern_name: JObject = env.new_string(&pattern_range.pattern_name)?.into();
+        let pattern_name = env.auto_local(pattern_name);
+        // SAFETY: constructor is valid and derived from class by construction of self
+        unsafe {
+            env.new_object_unchecked(
+                &self.class,
+                self.constructor,
+                &[
+                    JValue::Object(&range_obj).as_jni(),
+                    JValue::Object(&pattern_name).as_jni(),
+                ],
+            )
+        }
+    }
+}
+
+/// Indent ranges with names of their patterns
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeRangesProvider_nativeGetIndentRanges<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    snapshot: JObject<'local>,
+    text: JCharArray<'local>,
+    start_offset: jint,
+    end_offset: jint,
+    use_inner: jboolean,
+) -> JObjectArray<'local> {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        snapshot: JObject<'local>,
+        text: JCharArray<'local>,
+        start_offset: jint,
+        end_offset: jint,
+        use_inner: jboolean,
+    ) -> JNIResult<JObjectArray<'local>> {
+        let snapshot = SyntaxSnapshotDesc::from_java_object(env, snapshot)?;
+        let pattern_range_desc = PatternRangeDesc::new(env)?;
+        let text_buffer = read_char_array(env, &text)?;
+
+        let ranges = collect_indent_ranges(
+            snapshot,
+            SourceText::Utf16(&text_buffer),
+            ((start_offset * 2) as usize)..((end_offset * 2) as usize),
+            use_inner != 0,
+        );
+
+        let ranges_array = env.new_object_array(
+            ranges.len() as jsize,
+            &pattern_range_desc.class,
+            JObject::null(),
+        )?;
+        for (index, range) in ranges.iter().enumerate() {
+            let range_obj = pattern_range_desc.to_java_object(env, range)?;
+            let range_obj = env.auto_local(range_obj);
+            env.set_object_array_element(&ranges_array, index as i32, range_obj)?;
+        }
+        Ok(ranges_array)
+    }
+    let result = inner(
+        &mut env,
+        snapshot,
+        text,
+        start_offset,
+        end_offset,
+        use_inner,
+    );
+    throw_exception_from_result(&mut env, result)
+}
+
+fn get_named_ranges<'local>(
+    env: &mut JNIEnv<'local>,
+    snapshot: JObject<'local>,
+    text: JCharArray<'local>,
+    name: JString<'local>,
+    range: JavaTextRange,
+    use_inner: jboolean,
+) -> JNIResult<JObjectArray<'local>> {
+    let snapshot = SyntaxSnapshotDesc::from_java_object(env, snapshot)?;
+    let range_desc = RangeDesc::new(env)?;
+    let name: String = env.get_string(&name)?.into();
+    let text_buffer = read_char_array(env, &text)?;
+    let text = SourceText::Utf16(&text_buffer);
+    let unit_range = range.unit_range(snapshot, text);
+
+    let ranges = collect_named_ranges(
+        snapshot,
+        text,
+        &name,
+        (unit_range.start * 2)..(unit_range.end * 2),
+        use_inner != 0,
+    );
+
+    let ranges_array =
+        env.new_object_array(ranges.len() as jsize, &range_desc.class, JObject::null())?;
+    for (index, range) in ranges.into_iter().enumerate() {
+        let range_obj = range_desc.to_java_object(env, range)?;
+        let range_obj = env.auto_local(range_obj);
+        env.set_object_array_element(&ranges_array, index as i32, range_obj)?;
+    }
+    Ok(ranges_array)
+}
+
+/// Ranges of the query registered by `nativeAddRangesQuery` under `name`
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeRangesProvider_nativeGetRanges<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    snapshot: JObject<'local>,
+    text: JCharArray<'local>,
+    name: JString<'local>,
+    start_offset: jint,
+    end_offset: jint,
+    use_inner: jboolean,
+) -> JObjectArray<'local> {
+    let result = get_named_ranges(
+        &mut env,
+        snapshot,
+        text,
+        name,
+        JavaTextRange::Offsets(start_offset, end_offset),
+        use_inner,
+    );
+    throw_exception_from_result(&mut env, result)
+}
+
+/// Same as `nativeGetRanges`, range is given by `com.hulylabs.treesitter.language.Point`
+/// positions with columns in chars, columns past the end of the line are clamped to it
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeRangesProvider_nativeGetRangesForPoints<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    snapshot: JObject<'local>,
+    text: JCharArray<'local>,
+    name: JString<'local>,
+    start_point: JObject<'local>,
+    end_point: JObject<'local>,
+    use_inner: jboolean,
+) -> JObjectArray<'local> {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        snapshot: JObject<'local>,
+        text: JCharArray<'local>,
+        name: JString<'local>,
+        start_point: JObject<'local>,
+        end_point: JObject<'local>,
+        use_inner: jboolean,
+    ) -> JNIResult<JObjectArray<'local>> {
+        let start_point = PointDesc::from_java_object(env, &start_point)?;
+        let end_point = PointDesc::from_java_object(env, &end_point)?;
+        get_named_ranges(
+            env,
+            snapshot,
+            text,
+            name,
+            JavaTextRange::Points(start_point, end_point),
+            use_inner,
+        )
+    }
+    let result = inner(
+        &mut env,
+        snapshot,
+        text,
+        name,
+        start_point,
+        end_point,
+        use_inner,
+    );
+    throw_exception_from_result(&mut env, result)
+}
+
+/// Top-level statements of the range and statements enclosing it, for Move Statement actions
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeRangesProvider_nativeGetStatementRanges<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    snapshot: JObject<'local>,
+    text: JCharArray<'local>,
+    start_offset: jint,
+    end_offset: jint,
+) -> JObjectArray<'local> {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        snapshot: JObject<'local>,
+        text: JCharArray<'local>,
+        start_offset: jint,
+        end_offset: jint,
+    ) -> JNIResult<JObjectArray<'local>> {
+        let snapshot = SyntaxSnapshotDesc::from_java_object(env, snapshot)?;
+        let range_desc = RangeDesc::new(env)?;
+        let text_buffer = read_char_array(env, &text)?;
+
+        let ranges = collect_statement_ranges(
+            snapshot,
+            SourceText::Utf16(&text_buffer),
+            ((start_offset * 2) as usize)..((end_offset * 2) as usize),
+        );
+
+        let ranges_array =
+            env.new_object_array(ranges.len() as jsize, &range_desc.class, JObject::null())?;
+        for (index, range) in ranges.into_iter().enumerate() {
+            let range_obj = range_desc.to_java_object(env, range)?;
+            let range_obj = env.auto_local(range_obj);
+            env.set_object_array_element(&ranges_array, index as i32, range_obj)?;
+        }
+        Ok(ranges_array)
+    }
+    let result = inner(&mut env, snapshot, text, start_offset, end_offset);
+    throw_exception_from_result(&mut env, result)
+}
+
+static FOLD_RANGE_CONSTRUCTOR: JOnceLock<JMethodID> = JOnceLock::new();
+
+struct FoldRangeDesc<'local> {
+    constructor: JMethodID,
+    class: AutoLocal<'local, JClass<'local>>,
+    range_desc: RangeDesc<'local>,
+}
+
+impl<'local> FoldRangeDesc<'local> {
+    fn new(env: &mut JNIEnv<'local>) -> JNIResult<FoldRangeDesc<'local>> {
+        let range_desc = RangeDesc::new(env)?;
+        let class = env.find_class("com/hulylabs/treesitter/language/FoldRange")?;
+        let constructor = *FOLD_RANGE_CONSTRUCTOR.get_or_try_init(|| {
+            env.get_method_id(
+                &class,
+                "<init>",
+                "(Lcom/hulylabs/treesitter/language/Range;Ljava/lang/String;ZLjava/lang/String;JIJLjava/lang/String;)V",
+            )
+        })?;
+
+        Ok(FoldRangeDesc {
+            constructor,
+            class: env.auto_local(class),
+            range_desc,
+        })
+    }
+
+    fn to_java_object(
+        &self,
+        env: &mut JNIEnv<'local>,
+        fold_range: &FoldRange,
+    ) -> JNIResult<JObject<'local>> {
+        let range_obj = self.range_desc.to_java_object(env, fold_range.range)?;
+        let range_obj = env.auto_local(range_obj);
+        let collapsed_text: JObject = if let Some(collapsed_text) = &fold_range.collapsed_text {
+            env.new_string(collapsed_text)?.into()
+        } else {
+            JObject::null()
+        };
+        let collapsed_text = env.auto_local(collapsed_text);
+        let collapsed_category: JObject =
+            if let Some(collapsed_category) = &fold_range.collapsed_category {
+                env.new_string(collapsed_category)?.into()
+            } else {
+                JObject::null()
+            };
+        let collapsed_category = env.auto_local(collapsed_category);
+        let pattern_name: JObject = env.new_string(&fold_range.pattern_name)?.into();
+        let pattern_name = env.auto_local(pattern_name);
+        unsafe {
+            env.new_object_unchecked(
+                &self.class,
+                self.constructor,
+                &[
+                    JValue::Object(&range_obj).as_jni(),
+                    JValue::Object(&collapsed_text).as_jni(),
+                    JValue::from(fold_range.collapsed_by_default).as_jni(),
+                    JValue::Object(&collapsed_category).as_jni(),
+                    JValue::from(fold_range.language_id).as_jni(),
+                    JValue::Int(fold_range.depth as jint).as_jni(),
+                    JValue::Long(fold_range.id as i64).as_jni(),
+                    JValue::Object(&pattern_name).as_jni(),
+                ],
+            )
+        }
+    }
+}
+
+pub(crate) fn fold_ranges_to_java_array<'local>(
+    env: &mut JNIEnv<'local>,
+    fold_ranges: &[FoldRange],
+) -> JNIResult<JObjectArray<'local>> {
+    let fold_range_desc = FoldRangeDesc::new(env)?;
+    let ranges_array = env.new_object_array(
+        fold_ranges.len() as jsize,
+        &fold_range_desc.class,
+        JObject::null(),
+    )?;
+    for (index, fold_range) in fold_ranges.iter().enumerate() {
+        let obj = fold_range_desc.to_java_object(env, fold_range)?;
+        let obj = env.auto_local(obj);
+        env.set_object_array_element(&ranges_array, index as i32, obj)?;
+    }
+    Ok(ranges_array)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeRangesProvider_nativeGetFoldRanges<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    snapshot: JObject<'local>,
+    text: JCharArray<'local>,
+    start_offset: jint,
+    end_offset: jint,
+    use_inner: jboolean,
+) -> JObjectArray<'local> {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        snapshot: JObject<'local>,
+        text: JCharArray<'local>,
+        start_offset: jint,
+        end_offset: jint,
+        use_inner: jboolean,
+    ) -> JNIResult<JObjectArray<'local>> {
+        let snapshot = SyntaxSnapshotDesc::from_java_object(env, snapshot)?;
+        let text_buffer = read_char_array(env, &text)?;
+
+        let fold_ranges = collect_fold_ranges(
+            snapshot,
+            SourceText::Utf16(&text_buffer),
+            ((start_offset * 2) as usize)..((end_offset * 2) as usize),
+            use_inner != 0,
+        );
+        fold_ranges_to_java_array(env, &fold_ranges)
+    }
+    let result = inner(
+        &mut env,
+        snapshot,
+        text,
+        start_offset,
+        end_offset,
+        use_inner,
+    );
+    throw_exception_from_result(&mut env, result)
+}
+
+/// Folds intersecting changed ranges passed as flat start/end offset pairs, with ids stable
+/// across edits
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeRangesProvider_nativeGetFoldRangesIncremental<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    snapshot: JObject<'local>,
+    text: JCharArray<'local>,
+    changed_offsets: JIntArray<'local>,
+) -> JObjectArray<'local> {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        snapshot: JObject<'local>,
+        text: JCharArray<'local>,
+        changed_offsets: JIntArray<'local>,
+    ) -> JNIResult<JObjectArray<'local>> {
+        let snapshot = SyntaxSnapshotDesc::from_java_object(env, snapshot)?;
+        let text_buffer = read_char_array(env, &text)?;
+        let offsets_length = env.get_array_length(&changed_offsets)?;
+        let mut offsets = vec![0; offsets_length as usize];
+        env.get_int_array_region(&changed_offsets, 0, &mut offsets)?;
+        let changed_ranges: Vec<_> = offsets
+            .chunks_exact(2)
+            .map(|pair| ((pair[0] * 2) as usize)..((pair[1] * 2) as usize))
+            .collect();
+
+        let fold_ranges =
+            collect_changed_fold_ranges(snapshot, SourceText::Utf16(&text_buffer), &changed_ranges);
+        fold_ranges_to_java_array(env, &fold_ranges)
+    }
+    let result = inner(&mut env, snapshot, text, changed_offsets);
+    throw_exception_from_result(&mut env, result)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeRangesProvider_nativeGetFormatRanges<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    snapshot: JObject<'local>,
+    text: JCharArray<'local>,
+    changed_offsets: JIntArray<'local>,
+) -> JObjectArray<'local> {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        snapshot: JObject<'local>,
+        text: JCharArray<'local>,
+        changed_offsets: JIntArray<'local>,
+    ) -> JNIResult<JObjectArray<'local>> {
+        let snapshot = SyntaxSnapshotDesc::from_java_object(env, snapshot)?;
+        let range_desc = RangeDesc::new(env)?;
+        let text_buffer = read_char_array(env, &text)?;
+        // Changed ranges are passed as flat start/end offset pairs
+        let offsets_length = env.get_array_length(&changed_offsets)?;
+        let mut offsets = vec![0; offsets_length as usize];
+        env.get_int_array_region(&changed_offsets, 0, &mut offsets)?;
+        let changed_ranges: Vec<_> = offsets
+            .chunks_exact(2)
+            .map(|pair| ((pair[0] * 2) as usize)..((pair[1] * 2) as usize))
+            .collect();
+
+        let ranges =
+            collect_format_ranges(snapshot, SourceText::Utf16(&text_buffer), &changed_ranges);
+
+        let ranges_array =
+            env.new_object_array(ranges.len() as jsize, &range_desc.class, JObject::null())?;
+        for (index, range) in ranges.into_iter().enumerate() {
+            let range_obj = range_desc.to_java_object(env, range)?;
+            let range_obj = env.auto_local(range_obj);
+            env.set_object_array_element(&ranges_array, index as i32, range_obj)?;
+        }
+        Ok(ranges_array)
+    }
+    let result = inner(&mut env, snapshot, text, changed_offsets);
+    throw_exception_from_result(&mut env, result)
+}
+
+static CONTEXT_RANGE_CONSTRUCTOR: JOnceLock<JMethodID> = JOnceLock::new();
+
+struct ContextRangeDesc<'local> {
+    constructor: JMethodID,
+    class: AutoLocal<'local, JClass<'local>>,
+    range_desc: RangeDesc<'local>,
+}
+
+impl<'local> ContextRangeDesc<'local> {
+    fn new(env: &mut JNIEnv<'local>) -> JNIResult<ContextRangeDesc<'local>> {
+        let range_desc = RangeDesc::new(env)?;
+        let class = env.find_class("com/hulylabs/treesitter/language/ContextRange")?;
+        let constructor = *CONTEXT_RANGE_CONSTRUCTOR.get_or_try_init(|| {
+            env.get_method_id(
+                &class,
+                "<init>",
+                "(JLcom/hulylabs/treesitter/language/Range;Lcom/hulylabs/treesitter/language/Range;)V",
+            )
+        })?;
+        Ok(ContextRangeDesc {
+            constructor,
+            class: env.auto_local(class),
+            range_desc,
+        })
+    }
+
+    fn to_java_object(
+        &self,
+        env: &mut JNIEnv<'local>,
+        context: &ContextRange,
+    ) -> JNIResult<JObject<'local>> {
+        let range = self.range_desc.to_java_object(env, context.range)?;
+        let range = env.auto_local(range);
+        let header = self.range_desc.to_java_object(env, context.header)?;
+        let header = env.auto_local(header);
+        // SAFETY: constructor is valid and derived from class by construction of self
+        unsafe {
+            env.new_object_unchecked(
+                &self.class,
+                self.constructor,
+                &[
+                    JValue::from(context.language_id).as_jni(),
+                    JValue::Object(&range).as_jni(),
+                    JValue::Object(&header).as_jni(),
+                ],
+            )
+        }
+    }
+}
+
+/// Enclosing constructs from the outermost one, for sticky scroll headers
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeRangesProvider_nativeGetContextRangesAtOffset<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    snapshot: JObject<'local>,
+    text: JCharArray<'local>,
+    offset: jint,
+) -> JObjectArray<'local> {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        snapshot: JObject<'local>,
+        text: JCharArray<'local>,
+        offset: jint,
+    ) -> JNIResult<JObjectArray<'local>> {
+        let snapshot = SyntaxSnapshotDesc::from_java_object(env, snapshot)?;
+        let text_buffer = read_char_array(env, &text)?;
+        let contexts = collect_context_ranges(
+            snapshot,
+            SourceText::Utf16(&text_buffer),
+            (offset * 2) as usize,
+        );
+        let desc = ContextRangeDesc::new(env)?;
+        let array = env.new_object_array(contexts.len() as jsize, &desc.class, JObject::null())?;
+        for (index, context) in contexts.iter().enumerate() {
+            let obj = desc.to_java_object(env, context)?;
+            let obj = env.auto_local(obj);
+            env.set_object_array_element(&array, index as i32, obj)?;
+        }
+        Ok(array)
+    }
+    let result = inner(&mut env, snapshot, text, offset);
+    throw_exception_from_result(&mut env, result)
+}
+
+static CODE_BLOCK_CONSTRUCTOR: JOnceLock<JMethodID> = JOnceLock::new();
+
+struct CodeBlockDesc<'local> {
+    constructor: JMethodID,
+    class: AutoLocal<'local, JClass<'local>>,
+    range_desc: RangeDesc<'local>,
+}
+
+impl<'local> CodeBlockDesc<'local> {
+    fn new(env: &mut JNIEnv<'local>) -> JNIResult<CodeBlockDesc<'local>> {
+        let range_desc = RangeDesc::new(env)?;
+        let class = env.find_class("com/hulylabs/treesitter/language/CodeBlock")?;
+        let constructor = *CODE_BLOCK_CONSTRUCTOR.get_or_try_init(|| {
+            env.get_method_id(
+                &class,
+                "<init>",
+                "(JLcom/hulylabs/treesitter/language/Range;Ljava/lang/String;)V",
+            )
+        })?;
+        Ok(CodeBlockDesc {
+            constructor,
+            class: env.auto_local(class),
+            range_desc,
+        })
+    }
+
+    fn to_java_object(
+        &self,
+        env: &mut JNIEnv<'local>,
+        block: &CodeBlock,
+    ) -> JNIResult<JObject<'local>> {
+        let range = self.range_desc.to_java_object(env, block.range)?;
+        let range = env.auto_local(range);
+        let label: JObject = env.new_string(&block.label)?.into();
+        let label = env.auto_local(label);
+        // SAFETY: constructor is valid and derived from class by construction of self
+        unsafe {
+            env.new_object_unchecked(
+                &self.class,
+                self.constructor,
+                &[
+                    JValue::from(block.language_id).as_jni(),
+                    JValue::Object(&range).as_jni(),
+                    JValue::Object(&label).as_jni(),
+                ],
+            )
+        }
+    }
+}
+
+/// Enclosing code blocks with their header labels from the outermost one, for breadcrumbs
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeRangesProvider_nativeGetCodeBlockRanges<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    snapshot: JObject<'local>,
+    text: JCharArray<'local>,
+    offset: jint,
+) -> JObjectArray<'local> {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        snapshot: JObject<'local>,
+        text: JCharArray<'local>,
+        offset: jint,
+    ) -> JNIResult<JObjectArray<'local>> {
+        let snapshot = SyntaxSnapshotDesc::from_java_object(env, snapshot)?;
+        let text_buffer = read_char_array(env, &text)?;
+        let blocks = collect_code_blocks(
+            snapshot,
+            SourceText::Utf16(&text_buffer),
+            (offset * 2) as usize,
+        );
+        let desc = CodeBlockDesc::new(env)?;
+        let array = env.new_object_array(blocks.len() as jsize, &desc.class, JObject::null())?;
+        for (index, block) in blocks.iter().enumerate() {
+            let obj = desc.to_java_object(env, block)?;
+            let obj = env.auto_local(obj);
+            env.set_object_array_element(&array, index as i32, obj)?;
+        }
+        Ok(array)
+    }
+    let result = inner(&mut env, snapshot, text, offset);
+    throw_exception_from_result(&mut env, result)
+}
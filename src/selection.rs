@@ -0,0 +1,151 @@
+use tree_sitter as ts;
+
+use crate::{query::SourceText, syntax_snapshot::SyntaxSnapshot};
+
+#[cfg(feature = "jni")]
+mod jni_methods;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CharClass {
+    Word,
+    Whitespace,
+    Punctuation,
+}
+
+impl CharClass {
+    fn of(c: char) -> Self {
+        if c.is_alphanumeric() || c == '_' {
+            CharClass::Word
+        } else if c.is_whitespace() {
+            CharClass::Whitespace
+        } else {
+            CharClass::Punctuation
+        }
+    }
+}
+
+/// Sub-word boundaries: `_`/`-` separators, lower to upper case transitions, last upper case
+/// letter of an acronym followed by lower case and letter/digit transitions
+fn is_sub_word_boundary(prev: char, c: char, next: Option<char>) -> bool {
+    let is_separator = |c: char| c == '_' || c == '-';
+    if is_separator(prev) != is_separator(c) {
+        return true;
+    }
+    if prev.is_lowercase() && c.is_uppercase() {
+        return true;
+    }
+    if prev.is_uppercase() && c.is_uppercase() && next.is_some_and(char::is_lowercase) {
+        return true;
+    }
+    prev.is_numeric() != c.is_numeric()
+}
+
+/// Point of char at `idx` of `node` chars, or node end point for index past the last char
+fn point_at_char(
+    node: &ts::Node,
+    chars: &[(usize, char)],
+    idx: usize,
+    unit_size: usize,
+) -> ts::Point {
+    let Some((byte, _)) = chars.get(idx) else {
+        return node.end_position();
+    };
+    let mut point = node.start_position();
+    let mut line_start_byte = node.start_byte();
+    for (offset, c) in &chars[..idx] {
+        if *c == '\n' {
+            point.row += 1;
+            point.column = 0;
+            line_start_byte = offset + unit_size;
+        }
+    }
+    point.column += byte - line_start_byte;
+    point
+}
+
+/// Range of word at `byte_offset`. Words never cross boundaries of the innermost syntax node, so
+/// identifiers, operators and words inside string contents or comments are selected separately.
+/// With `sub_word`, camelCase and snake_case parts of the word are selected instead.
+pub fn word_range_at(
+    snapshot: &SyntaxSnapshot,
+    text: SourceText<'_>,
+    byte_offset: usize,
+    sub_word: bool,
+) -> Option<ts::Range> {
+    let (_, node) = snapshot.innermost_node_at(byte_offset);
+    let chars = text.chars_in_byte_range(node.byte_range());
+    let char_idx = chars
+        .iter()
+        .rposition(|(offset, _)| *offset <= byte_offset)?;
+    let class = CharClass::of(chars[char_idx].1);
+    let mut start = char_idx;
+    while start > 0 && CharClass::of(chars[start - 1].1) == class {
+        start -= 1;
+    }
+    let mut end = char_idx + 1;
+    while end < chars.len() && CharClass::of(chars[end].1) == class {
+        end += 1;
+    }
+    if sub_word && class == CharClass::Word {
+        let mut sub_start = char_idx;
+        while sub_start > start
+            && !is_sub_word_boundary(
+                chars[sub_start - 1].1,
+                chars[sub_start].1,
+                chars.get(sub_start + 1).map(|(_, c)| *c),
+            )
+        {
+            sub_start -= 1;
+        }
+        let mut sub_end = char_idx + 1;
+        while sub_end < end
+            && !is_sub_word_boundary(
+                chars[sub_end - 1].1,
+                chars[sub_end].1,
+                chars.get(sub_end + 1).map(|(_, c)| *c),
+            )
+        {
+            sub_end += 1;
+        }
+        start = sub_start;
+        end = sub_end;
+    }
+
+    let point_at = |idx: usize| point_at_char(&node, &chars, idx, text.unit_size());
+    Some(ts::Range {
+        start_byte: chars[start].0,
+        end_byte: chars
+            .get(end)
+            .map_or(node.end_byte(), |(offset, _)| *offset),
+        start_point: point_at(start),
+        end_point: point_at(end),
+    })
+}
+
+/// Up to `count` progressively larger ranges of nodes around `byte_offset` for expanding the
+/// selection, starting from the innermost node and crossing injection boundaries. Nodes with the
+/// same range as the previous one are skipped.
+pub fn selection_ranges(
+    snapshot: &SyntaxSnapshot,
+    byte_offset: usize,
+    count: usize,
+) -> Vec<ts::Range> {
+    // Caret at the end of the text or of a line selects the node before it
+    let mut node = snapshot
+        .node_at_offset(byte_offset)
+        .or_else(|| snapshot.node_at_offset(byte_offset.checked_sub(1)?));
+    let mut ranges: Vec<ts::Range> = Vec::new();
+    while let Some(current) = node {
+        if ranges.len() >= count {
+            break;
+        }
+        let range = current.node.range();
+        if ranges.last().is_none_or(|last| {
+            last.start_byte != range.start_byte || last.end_byte != range.end_byte
+        }) {
+            ranges.push(range);
+        }
+        node = snapshot.node_parent(&current);
+    }
+    ranges
+}
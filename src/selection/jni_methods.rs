@@ -0,0 +1,86 @@
+use jni::{
+    errors::Result as JNIResult,
+    objects::{JCharArray, JClass, JObject, JObjectArray},
+    sys::{jboolean, jint, jsize},
+    JNIEnv,
+};
+
+use crate::{
+    jni_utils::{read_char_array, throw_exception_from_result, RangeDesc},
+    query::SourceText,
+    syntax_snapshot::SyntaxSnapshotDesc,
+};
+
+use super::{selection_ranges, word_range_at};
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeSelectionProvider_nativeGetWordRange<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    snapshot: JObject<'local>,
+    text: JCharArray<'local>,
+    offset: jint,
+    sub_word: jboolean,
+) -> JObject<'local> {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        snapshot: JObject<'local>,
+        text: JCharArray<'local>,
+        offset: jint,
+        sub_word: jboolean,
+    ) -> JNIResult<JObject<'local>> {
+        let snapshot = SyntaxSnapshotDesc::from_java_object(env, snapshot)?;
+        let range_desc = RangeDesc::new(env)?;
+        let text_buffer = read_char_array(env, &text)?;
+
+        let range = word_range_at(
+            snapshot,
+            SourceText::Utf16(&text_buffer),
+            (offset * 2) as usize,
+            sub_word != 0,
+        );
+        match range {
+            Some(range) => range_desc.to_java_object(env, range),
+            None => Ok(JObject::null()),
+        }
+    }
+    let result = inner(&mut env, snapshot, text, offset, sub_word);
+    throw_exception_from_result(&mut env, result)
+}
+
+/// Ranges for Extend Selection from the innermost node around `offset`, at most `count` of them
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeSelectionProvider_nativeGetSelectionRanges<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    snapshot: JObject<'local>,
+    offset: jint,
+    count: jint,
+) -> JObjectArray<'local> {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        snapshot: JObject<'local>,
+        offset: jint,
+        count: jint,
+    ) -> JNIResult<JObjectArray<'local>> {
+        let snapshot = SyntaxSnapshotDesc::from_java_object(env, snapshot)?;
+        let range_desc = RangeDesc::new(env)?;
+
+        let ranges = selection_ranges(snapshot, (offset * 2) as usize, count.max(0) as usize);
+
+        let ranges_array =
+            env.new_object_array(ranges.len() as jsize, &range_desc.class, JObject::null())?;
+        for (index, range) in ranges.into_iter().enumerate() {
+            let range_obj = range_desc.to_java_object(env, range)?;
+            let range_obj = env.auto_local(range_obj);
+            env.set_object_array_element(&ranges_array, index as i32, range_obj)?;
+        }
+        Ok(ranges_array)
+    }
+    let result = inner(&mut env, snapshot, offset, count);
+    throw_exception_from_result(&mut env, result)
+}
@@ -0,0 +1,246 @@
+use streaming_iterator::StreamingIterator as _;
+use tree_sitter::{self as ts, QueryCursor};
+
+use crate::{
+    predicates::AdditionalPredicates,
+    query::{SourceText, SourceTextProvider},
+    syntax_snapshot::{SyntaxSnapshot, SyntaxSnapshotEntryContent},
+};
+
+#[cfg(feature = "jni")]
+mod jni_methods;
+
+#[derive(thiserror::Error, Debug)]
+pub enum SplitQueryError {
+    #[error("required captures not found")]
+    NoRequiredCaptures,
+}
+
+/// Query capturing constructs which may be split on enter as `@split`. Pattern properties:
+/// - `split.prefix`/`split.suffix` text inserted before/after the line break;
+/// - `split.position` `"before"` to split before separator tokens instead of after them.
+///
+/// Constructs without children (e.g. string contents) are split at the caret.
+pub struct SplitQuery {
+    query: ts::Query,
+    predicates: AdditionalPredicates,
+    split_capture_id: u32,
+}
+
+impl SplitQuery {
+    pub fn new(
+        query: ts::Query,
+        predicates: AdditionalPredicates,
+    ) -> Result<Self, SplitQueryError> {
+        let split_capture_id = query
+            .capture_index_for_name("split")
+            .ok_or(SplitQueryError::NoRequiredCaptures)?;
+        Ok(SplitQuery {
+            query,
+            predicates,
+            split_capture_id,
+        })
+    }
+
+    pub fn capture_names(&self) -> &[&str] {
+        self.query.capture_names()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SplitPoint {
+    pub byte_offset: usize,
+    pub point: ts::Point,
+    pub prefix: Option<Box<str>>,
+    pub suffix: Option<Box<str>>,
+}
+
+struct SplitConstruct<'tree> {
+    node: ts::Node<'tree>,
+    prefix: Option<Box<str>>,
+    suffix: Option<Box<str>>,
+    split_before: bool,
+}
+
+/// Boundaries of separator tokens inside of construct node
+fn split_positions(node: ts::Node, split_before: bool) -> Vec<(usize, ts::Point)> {
+    let mut cursor = node.walk();
+    let children: Vec<_> = node.children(&mut cursor).collect();
+    // Leaf constructs, e.g. strings lexed as a single token, have nothing to split between
+    if children.len() < 2 {
+        return Vec::new();
+    }
+    let inner_children = &children[1..(children.len() - 1)];
+    let mut positions: Vec<_> = inner_children
+        .iter()
+        .filter(|child| !child.is_named())
+        .map(|child| {
+            if split_before {
+                (child.start_byte(), child.start_position())
+            } else {
+                (child.end_byte(), child.end_position())
+            }
+        })
+        .collect();
+    if positions.is_empty() {
+        // No separator tokens, construct is split between its named parts
+        positions = children
+            .iter()
+            .skip(1)
+            .filter(|child| child.is_named())
+            .map(|child| (child.start_byte(), child.start_position()))
+            .collect();
+    }
+    positions
+}
+
+/// Positions where innermost splittable construct around `byte_offset` may be broken into lines,
+/// along with text required to keep the construct valid
+pub fn collect_split_points(
+    snapshot: &SyntaxSnapshot,
+    text: SourceText<'_>,
+    byte_offset: usize,
+) -> Vec<SplitPoint> {
+    let text_provider = SourceTextProvider::new(text);
+    let mut innermost: Option<SplitConstruct> = None;
+    for (_, entry) in snapshot.intersecting_entries(byte_offset..(byte_offset + 1), false) {
+        if byte_offset <= entry.byte_range.start || byte_offset >= entry.byte_range.end {
+            continue;
+        }
+        let SyntaxSnapshotEntryContent::Parsed { language, tree } = &entry.content else {
+            continue;
+        };
+        let query = snapshot.isolate.with_language(*language, |language| {
+            language.parser_info().splits_query.clone()
+        });
+        let Ok(Some(query)) = query else {
+            continue;
+        };
+        let mut cursor = QueryCursor::new();
+        cursor.set_byte_range(byte_offset..(byte_offset + 1));
+        let mut matches = cursor.matches(
+            &query.query,
+            tree.root_node_with_offset(entry.byte_offset, entry.point_offset),
+            &text_provider,
+        );
+        while let Some(query_match) = matches.next() {
+            if !query
+                .predicates
+                .satisfies_predicates(&text_provider, query_match)
+            {
+                continue;
+            }
+            for node in query_match.nodes_for_capture_index(query.split_capture_id) {
+                if node.start_byte() >= byte_offset || node.end_byte() <= byte_offset {
+                    continue;
+                }
+                if innermost
+                    .as_ref()
+                    .is_some_and(|other| other.node.byte_range().len() <= node.byte_range().len())
+                {
+                    continue;
+                }
+                let mut prefix = None;
+                let mut suffix = None;
+                let mut split_before = false;
+                let metadata = query
+                    .predicates
+                    .metadata()
+                    .pattern(query_match.pattern_index);
+                for property in metadata.properties() {
+                    match property.key.as_ref() {
+                        "split.prefix" => prefix = property.value.clone(),
+                        "split.suffix" => suffix = property.value.clone(),
+                        "split.position" => {
+                            split_before = property.value.as_deref() == Some("before")
+                        }
+                        _ => {}
+                    }
+                }
+                innermost = Some(SplitConstruct {
+                    node,
+                    prefix,
+                    suffix,
+                    split_before,
+                });
+            }
+        }
+    }
+    let Some(SplitConstruct {
+        node,
+        prefix,
+        suffix,
+        split_before,
+    }) = innermost
+    else {
+        return Vec::new();
+    };
+    let positions = if node.child_count() == 0 {
+        // Leaf constructs such as string contents are split right at the caret
+        let unit_size = text.unit_size();
+        let line_start_unit = (0..(byte_offset / unit_size))
+            .rev()
+            .find(|idx| text.unit(*idx) == '\n' as u16)
+            .map_or(0, |idx| idx + 1);
+        let row = node.start_position().row
+            + (node.start_byte() / unit_size..byte_offset / unit_size)
+                .filter(|idx| text.unit(*idx) == '\n' as u16)
+                .count();
+        vec![(
+            byte_offset,
+            ts::Point {
+                row,
+                column: byte_offset - line_start_unit * unit_size,
+            },
+        )]
+    } else {
+        split_positions(node, split_before)
+    };
+    positions
+        .into_iter()
+        .map(|(byte_offset, point)| SplitPoint {
+            byte_offset,
+            point,
+            prefix: prefix.clone(),
+            suffix: suffix.clone(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn json_tree(text: &str) -> ts::Tree {
+        let mut parser = ts::Parser::new();
+        parser
+            .set_language(&tree_sitter_json::LANGUAGE.into())
+            .unwrap();
+        parser.parse(text, None).unwrap()
+    }
+
+    #[test]
+    fn split_positions_of_leaf_node() {
+        let tree = json_tree("\"abc\"");
+        let string = tree.root_node().child(0).unwrap();
+        let content = string.named_child(0).unwrap();
+        assert_eq!(content.child_count(), 0);
+        assert!(split_positions(content, false).is_empty());
+    }
+
+    #[test]
+    fn split_positions_after_separators() {
+        let tree = json_tree("[1, 2, 3]");
+        let array = tree.root_node().child(0).unwrap();
+        let offsets: Vec<usize> = split_positions(array, false)
+            .into_iter()
+            .map(|(byte, _)| byte)
+            .collect();
+        assert_eq!(offsets, vec![3, 6]);
+        let offsets: Vec<usize> = split_positions(array, true)
+            .into_iter()
+            .map(|(byte, _)| byte)
+            .collect();
+        assert_eq!(offsets, vec![2, 5]);
+    }
+}
@@ -0,0 +1,115 @@
+use jni::{
+    errors::Result as JNIResult,
+    objects::{AutoLocal, JCharArray, JClass, JMethodID, JObject, JObjectArray, JValue},
+    sys::{jint, jsize},
+    JNIEnv,
+};
+use once_cell::sync::OnceCell as JOnceLock;
+
+use crate::{
+    jni_utils::{read_char_array, throw_exception_from_result, PointDesc},
+    query::SourceText,
+    syntax_snapshot::SyntaxSnapshotDesc,
+};
+
+use super::{collect_split_points, SplitPoint};
+
+static SPLIT_POINT_CONSTRUCTOR: JOnceLock<JMethodID> = JOnceLock::new();
+
+struct SplitPointDesc<'local> {
+    constructor: JMethodID,
+    class: AutoLocal<'local, JClass<'local>>,
+    point_desc: PointDesc<'local>,
+}
+
+impl<'local> SplitPointDesc<'local> {
+    fn new(env: &mut JNIEnv<'local>) -> JNIResult<SplitPointDesc<'local>> {
+        let point_desc = PointDesc::new(env)?;
+        let class = env.find_class("com/hulylabs/treesitter/language/SplitPoint")?;
+        let constructor = *SPLIT_POINT_CONSTRUCTOR.get_or_try_init(|| {
+            env.get_method_id(
+                &class,
+                "<init>",
+                "(ILcom/hulylabs/treesitter/language/Point;Ljava/lang/String;Ljava/lang/String;)V",
+            )
+        })?;
+        Ok(SplitPointDesc {
+            constructor,
+            class: env.auto_local(class),
+            point_desc,
+        })
+    }
+
+    fn to_java_object(
+        &self,
+        env: &mut JNIEnv<'local>,
+        split_point: SplitPoint,
+    ) -> JNIResult<JObject<'local>> {
+        let point_obj = self.point_desc.to_java_object(env, &split_point.point)?;
+        let point_obj = env.auto_local(point_obj);
+        let prefix = match split_point.prefix {
+            Some(prefix) => env.new_string(prefix)?.into(),
+            None => JObject::null(),
+        };
+        let prefix = env.auto_local(prefix);
+        let suffix = match split_point.suffix {
+            Some(suffix) => env.new_string(suffix)?.into(),
+            None => JObject::null(),
+        };
+        let suffix = env.auto_local(suffix);
+        // SAFETY: constructor is valid and derived from class by construction of self
+        unsafe {
+            env.new_object_unchecked(
+                &self.class,
+                self.constructor,
+                &[
+                    JValue::Int((split_point.byte_offset / 2) as i32).as_jni(),
+                    JValue::Object(&point_obj).as_jni(),
+                    JValue::Object(&prefix).as_jni(),
+                    JValue::Object(&suffix).as_jni(),
+                ],
+            )
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeSmartEnterProvider_nativeGetSplitPoints<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    snapshot: JObject<'local>,
+    text: JCharArray<'local>,
+    offset: jint,
+) -> JObjectArray<'local> {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        snapshot: JObject<'local>,
+        text: JCharArray<'local>,
+        offset: jint,
+    ) -> JNIResult<JObjectArray<'local>> {
+        let snapshot = SyntaxSnapshotDesc::from_java_object(env, snapshot)?;
+        let split_point_desc = SplitPointDesc::new(env)?;
+        let text_buffer = read_char_array(env, &text)?;
+
+        let split_points = collect_split_points(
+            snapshot,
+            SourceText::Utf16(&text_buffer),
+            (offset * 2) as usize,
+        );
+        let split_points_array = env.new_object_array(
+            split_points.len() as jsize,
+            &split_point_desc.class,
+            JObject::null(),
+        )?;
+        for (index, split_point) in split_points.into_iter().enumerate() {
+            let obj = split_point_desc.to_java_object(env, split_point)?;
+            let obj = env.auto_local(obj);
+            env.set_object_array_element(&split_points_array, index as i32, obj)?;
+        }
+        Ok(split_points_array)
+    }
+    let result = inner(&mut env, snapshot, text, offset);
+    throw_exception_from_result(&mut env, result)
+}
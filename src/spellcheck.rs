@@ -0,0 +1,107 @@
+use std::ops::Range;
+
+use streaming_iterator::StreamingIterator as _;
+use tree_sitter::{self as ts, QueryCursor};
+
+use crate::{
+    profiler,
+    query::{SourceText, SourceTextProvider},
+    syntax_snapshot::{SyntaxSnapshot, SyntaxSnapshotEntryContent},
+};
+
+#[cfg(feature = "jni")]
+mod jni_methods;
+
+/// Removes `excluded` from sorted non-overlapping `ranges`
+fn subtract_ranges(ranges: Vec<ts::Range>, excluded: &[ts::Range]) -> Vec<ts::Range> {
+    let mut result = Vec::new();
+    for range in ranges {
+        let mut rest = range;
+        for excluded in excluded {
+            if excluded.end_byte <= rest.start_byte || excluded.start_byte >= rest.end_byte {
+                continue;
+            }
+            if excluded.start_byte > rest.start_byte {
+                result.push(ts::Range {
+                    end_byte: excluded.start_byte,
+                    end_point: excluded.start_point,
+                    ..rest
+                });
+            }
+            if excluded.end_byte >= rest.end_byte {
+                rest.start_byte = rest.end_byte;
+                break;
+            }
+            rest.start_byte = excluded.end_byte;
+            rest.start_point = excluded.end_point;
+        }
+        if rest.start_byte < rest.end_byte {
+            result.push(rest);
+        }
+    }
+    result
+}
+
+/// Ranges where natural language spell checking should run, captured as `@spell` by highlight
+/// queries (comments, strings) without the nested `@nospell` ranges. Ranges intersecting
+/// `byte_range` are returned sorted and merged.
+pub fn collect_spellcheck_ranges(
+    snapshot: &SyntaxSnapshot,
+    text: SourceText<'_>,
+    byte_range: Range<usize>,
+) -> Vec<ts::Range> {
+    let _profile = profiler::call(&snapshot.isolate, "spellcheck");
+    let text_provider = SourceTextProvider::new(text);
+    let mut spell_ranges = Vec::new();
+    let mut nospell_ranges = Vec::new();
+    for (_, entry) in snapshot.intersecting_entries(byte_range.clone(), false) {
+        let SyntaxSnapshotEntryContent::Parsed { language, tree } = &entry.content else {
+            continue;
+        };
+        let query = snapshot.isolate.with_language(*language, |language| {
+            language.parser_info().highlights_query.clone()
+        });
+        let Ok(Some(query)) = query else {
+            continue;
+        };
+        let spell_capture_id = query.0.capture_index_for_name("spell");
+        let nospell_capture_id = query.0.capture_index_for_name("nospell");
+        if spell_capture_id.is_none() {
+            continue;
+        }
+        let mut cursor = QueryCursor::new();
+        cursor.set_byte_range(byte_range.clone());
+        let mut matches = cursor.matches(
+            &query.0,
+            tree.root_node_with_offset(entry.byte_offset, entry.point_offset),
+            &text_provider,
+        );
+        while let Some(query_match) = matches.next() {
+            if !query.1.satisfies_predicates(&text_provider, query_match) {
+                continue;
+            }
+            for capture in query_match.captures {
+                if Some(capture.index) == spell_capture_id {
+                    spell_ranges.push(capture.node.range());
+                } else if Some(capture.index) == nospell_capture_id {
+                    nospell_ranges.push(capture.node.range());
+                }
+            }
+        }
+    }
+    spell_ranges.sort_by_key(|range| (range.start_byte, range.end_byte));
+    let mut merged: Vec<ts::Range> = Vec::with_capacity(spell_ranges.len());
+    for range in spell_ranges {
+        match merged.last_mut() {
+            Some(last) if range.start_byte <= last.end_byte => {
+                if range.end_byte > last.end_byte {
+                    last.end_byte = range.end_byte;
+                    last.end_point = range.end_point;
+                }
+            }
+            _ => merged.push(range),
+        }
+    }
+    nospell_ranges.sort_by_key(|range| (range.start_byte, range.end_byte));
+    subtract_ranges(merged, &nospell_ranges)
+}
@@ -0,0 +1,55 @@
+use jni::{
+    errors::Result as JNIResult,
+    objects::{JCharArray, JClass, JObject, JObjectArray},
+    sys::{jint, jsize},
+    JNIEnv,
+};
+
+use crate::{
+    jni_utils::{read_char_array, throw_exception_from_result, RangeDesc},
+    query::SourceText,
+    syntax_snapshot::SyntaxSnapshotDesc,
+};
+
+use super::collect_spellcheck_ranges;
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeRangesProvider_nativeGetSpellcheckRanges<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    snapshot: JObject<'local>,
+    text: JCharArray<'local>,
+    start_offset: jint,
+    end_offset: jint,
+) -> JObjectArray<'local> {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        snapshot: JObject<'local>,
+        text: JCharArray<'local>,
+        start_offset: jint,
+        end_offset: jint,
+    ) -> JNIResult<JObjectArray<'local>> {
+        let snapshot = SyntaxSnapshotDesc::from_java_object(env, snapshot)?;
+        let range_desc = RangeDesc::new(env)?;
+        let text_buffer = read_char_array(env, &text)?;
+
+        let ranges = collect_spellcheck_ranges(
+            snapshot,
+            SourceText::Utf16(&text_buffer),
+            ((start_offset * 2) as usize)..((end_offset * 2) as usize),
+        );
+
+        let ranges_array =
+            env.new_object_array(ranges.len() as jsize, &range_desc.class, JObject::null())?;
+        for (index, range) in ranges.into_iter().enumerate() {
+            let range_obj = range_desc.to_java_object(env, range)?;
+            let range_obj = env.auto_local(range_obj);
+            env.set_object_array_element(&ranges_array, index as i32, range_obj)?;
+        }
+        Ok(ranges_array)
+    }
+    let result = inner(&mut env, snapshot, text, start_offset, end_offset);
+    throw_exception_from_result(&mut env, result)
+}
@@ -0,0 +1,454 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+};
+
+use tree_sitter as ts;
+
+use crate::{
+    query::SourceText,
+    syntax_snapshot::{SyntaxSnapshot, SyntaxSnapshotEntry, SyntaxSnapshotEntryContent},
+    LanguageId,
+};
+
+#[cfg(feature = "jni")]
+mod jni_methods;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyntaxChangeKind {
+    Inserted,
+    Deleted,
+    Changed,
+    Moved,
+}
+
+/// Node level difference between two snapshots. Inserted changes have only `new_range`,
+/// deleted only `old_range`, changed and moved have both.
+#[derive(Debug, Clone)]
+pub struct SyntaxChange {
+    pub kind: SyntaxChangeKind,
+    pub language_id: LanguageId,
+    pub old_range: Option<ts::Range>,
+    pub new_range: Option<ts::Range>,
+}
+
+struct DiffSide<'a> {
+    /// Without text leaves are compared by kind only
+    text: Option<SourceText<'a>>,
+    hashes: HashMap<usize, u64>,
+}
+
+impl DiffSide<'_> {
+    /// Hash of node kind and text of its leaves, equal hashes are considered same subtrees.
+    /// Subtree is walked with an explicit stack, so deeply nested trees do not overflow the
+    /// native stack.
+    fn subtree_hash(&mut self, root: ts::Node) -> u64 {
+        if let Some(hash) = self.hashes.get(&root.id()) {
+            return *hash;
+        }
+        let mut cursor = root.walk();
+        // Nodes are hashed after their children, flag tells that children are already pushed
+        let mut stack = vec![(root, false)];
+        while let Some((node, children_pushed)) = stack.pop() {
+            if self.hashes.contains_key(&node.id()) {
+                continue;
+            }
+            if !children_pushed && node.child_count() > 0 {
+                stack.push((node, true));
+                stack.extend(
+                    node.children(&mut cursor)
+                        .filter(|child| !self.hashes.contains_key(&child.id()))
+                        .map(|child| (child, false)),
+                );
+                continue;
+            }
+            let mut hasher = DefaultHasher::new();
+            node.kind_id().hash(&mut hasher);
+            if node.child_count() == 0 {
+                if let Some(text) = self.text {
+                    text.text_for_byte_range(node.byte_range())
+                        .hash(&mut hasher);
+                }
+            } else {
+                for child in node.children(&mut cursor) {
+                    self.hashes[&child.id()].hash(&mut hasher);
+                }
+            }
+            self.hashes.insert(node.id(), hasher.finish());
+        }
+        self.hashes[&root.id()]
+    }
+}
+
+struct LayerDiff<'a> {
+    language_id: LanguageId,
+    old: DiffSide<'a>,
+    new: DiffSide<'a>,
+    inserted: Vec<(u64, ts::Range)>,
+    deleted: Vec<(u64, ts::Range)>,
+    changed: Vec<(ts::Range, ts::Range)>,
+}
+
+/// Size of the length table above which sequences are aligned by their unique hashes instead, so
+/// long sibling lists (e.g. top-level items of large files) do not take quadratic memory
+const MAX_SUBSEQUENCE_CELLS: usize = 1 << 22;
+
+/// Longest common subsequence of two hash sequences, returns matched index pairs. Common prefix
+/// and suffix are matched first, sequences left too long for the length table are aligned by
+/// [`unique_common_subsequence`].
+fn common_subsequence(old: &[u64], new: &[u64]) -> Vec<(usize, usize)> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_middle = &old[prefix..(old.len() - suffix)];
+    let new_middle = &new[prefix..(new.len() - suffix)];
+    let middle_pairs =
+        if (old_middle.len() + 1).saturating_mul(new_middle.len() + 1) > MAX_SUBSEQUENCE_CELLS {
+            unique_common_subsequence(old_middle, new_middle)
+        } else {
+            table_common_subsequence(old_middle, new_middle)
+        };
+    let mut pairs: Vec<(usize, usize)> = (0..prefix).map(|i| (i, i)).collect();
+    pairs.extend(
+        middle_pairs
+            .into_iter()
+            .map(|(i, j)| (prefix + i, prefix + j)),
+    );
+    pairs.extend((0..suffix).map(|k| (old.len() - suffix + k, new.len() - suffix + k)));
+    pairs
+}
+
+/// Exact longest common subsequence by the length table of `old.len() * new.len()` cells
+fn table_common_subsequence(old: &[u64], new: &[u64]) -> Vec<(usize, usize)> {
+    let columns = new.len() + 1;
+    let mut lengths = vec![0usize; (old.len() + 1) * columns];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lengths[i * columns + j] = if old[i] == new[j] {
+                lengths[(i + 1) * columns + j + 1] + 1
+            } else {
+                lengths[(i + 1) * columns + j].max(lengths[i * columns + j + 1])
+            };
+        }
+    }
+    let mut pairs = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            pairs.push((i, j));
+            i += 1;
+            j += 1;
+        } else if lengths[(i + 1) * columns + j] >= lengths[i * columns + j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    pairs
+}
+
+/// Approximate common subsequence in linear memory: hashes occurring exactly once in both
+/// sequences are matched, keeping the longest run of them which is in order on both sides.
+/// Repeated subtrees are left unmatched and reported as changed, inserted or deleted.
+fn unique_common_subsequence(old: &[u64], new: &[u64]) -> Vec<(usize, usize)> {
+    // Occurrences in old, occurrences in new and index in old of each hash
+    let mut occurrences: HashMap<u64, (usize, usize, usize)> = HashMap::new();
+    for (i, hash) in old.iter().enumerate() {
+        let entry = occurrences.entry(*hash).or_default();
+        entry.0 += 1;
+        entry.2 = i;
+    }
+    for hash in new {
+        if let Some(entry) = occurrences.get_mut(hash) {
+            entry.1 += 1;
+        }
+    }
+    let candidates: Vec<(usize, usize)> = new
+        .iter()
+        .enumerate()
+        .filter_map(|(j, hash)| match occurrences.get(hash) {
+            Some((1, 1, i)) => Some((*i, j)),
+            _ => None,
+        })
+        .collect();
+    // Candidates are ordered by new index, longest subsequence increasing in old index is taken
+    let mut tails: Vec<usize> = Vec::new();
+    let mut previous: Vec<Option<usize>> = vec![None; candidates.len()];
+    for (k, (i, _)) in candidates.iter().enumerate() {
+        let position = tails.partition_point(|tail| candidates[*tail].0 < *i);
+        if position > 0 {
+            previous[k] = Some(tails[position - 1]);
+        }
+        if position == tails.len() {
+            tails.push(k);
+        } else {
+            tails[position] = k;
+        }
+    }
+    let mut pairs = Vec::with_capacity(tails.len());
+    let mut next = tails.last().copied();
+    while let Some(k) = next {
+        pairs.push(candidates[k]);
+        next = previous[k];
+    }
+    pairs.reverse();
+    pairs
+}
+
+impl LayerDiff<'_> {
+    /// Pairs of nodes to align are kept on an explicit stack, so deeply nested trees do not
+    /// overflow the native stack
+    fn diff_nodes(&mut self, old_root: ts::Node, new_root: ts::Node) {
+        let mut pending = vec![(old_root, new_root)];
+        while let Some((old_node, new_node)) = pending.pop() {
+            self.diff_children(old_node, new_node, &mut pending);
+        }
+    }
+
+    /// Reports difference of the node pair, pairs of children to be diffed further are pushed to
+    /// `pending`
+    fn diff_children<'tree>(
+        &mut self,
+        old_node: ts::Node<'tree>,
+        new_node: ts::Node<'tree>,
+        pending: &mut Vec<(ts::Node<'tree>, ts::Node<'tree>)>,
+    ) {
+        if self.old.subtree_hash(old_node) == self.new.subtree_hash(new_node) {
+            return;
+        }
+        if old_node.kind_id() != new_node.kind_id()
+            || old_node.child_count() == 0
+            || new_node.child_count() == 0
+        {
+            self.changed.push((old_node.range(), new_node.range()));
+            return;
+        }
+        let mut cursor = old_node.walk();
+        let old_children: Vec<_> = old_node.children(&mut cursor).collect();
+        let mut cursor = new_node.walk();
+        let new_children: Vec<_> = new_node.children(&mut cursor).collect();
+        let old_hashes: Vec<_> = old_children
+            .iter()
+            .map(|child| self.old.subtree_hash(*child))
+            .collect();
+        let new_hashes: Vec<_> = new_children
+            .iter()
+            .map(|child| self.new.subtree_hash(*child))
+            .collect();
+        let mut matched = common_subsequence(&old_hashes, &new_hashes);
+        matched.push((old_children.len(), new_children.len()));
+
+        let mut aligned = Vec::new();
+        let (mut old_idx, mut new_idx) = (0, 0);
+        for (old_matched, new_matched) in matched {
+            // Unmatched children between two matched ones are aligned pairwise while
+            // kinds agree, rest are reported as inserted or deleted
+            let mut old_gap = old_idx..old_matched;
+            let mut new_gap = new_idx..new_matched;
+            while !old_gap.is_empty()
+                && !new_gap.is_empty()
+                && old_children[old_gap.start].kind_id() == new_children[new_gap.start].kind_id()
+            {
+                aligned.push((old_children[old_gap.start], new_children[new_gap.start]));
+                old_gap.start += 1;
+                new_gap.start += 1;
+            }
+            for idx in old_gap {
+                self.deleted
+                    .push((old_hashes[idx], old_children[idx].range()));
+            }
+            for idx in new_gap {
+                self.inserted
+                    .push((new_hashes[idx], new_children[idx].range()));
+            }
+            old_idx = old_matched + 1;
+            new_idx = new_matched + 1;
+        }
+        // Reversed so pairs are popped in document order
+        pending.extend(aligned.into_iter().rev());
+    }
+
+    fn into_changes(self, changes: &mut Vec<SyntaxChange>) {
+        let language_id = self.language_id;
+        let mut deleted_by_hash: HashMap<u64, Vec<ts::Range>> = HashMap::new();
+        for (hash, range) in self.deleted {
+            deleted_by_hash.entry(hash).or_default().push(range);
+        }
+        for (hash, new_range) in self.inserted {
+            let old_range = deleted_by_hash
+                .get_mut(&hash)
+                .and_then(|ranges| ranges.pop());
+            changes.push(SyntaxChange {
+                kind: if old_range.is_some() {
+                    SyntaxChangeKind::Moved
+                } else {
+                    SyntaxChangeKind::Inserted
+                },
+                language_id,
+                old_range,
+                new_range: Some(new_range),
+            });
+        }
+        for old_range in deleted_by_hash.into_values().flatten() {
+            changes.push(SyntaxChange {
+                kind: SyntaxChangeKind::Deleted,
+                language_id,
+                old_range: Some(old_range),
+                new_range: None,
+            });
+        }
+        for (old_range, new_range) in self.changed {
+            changes.push(SyntaxChange {
+                kind: SyntaxChangeKind::Changed,
+                language_id,
+                old_range: Some(old_range),
+                new_range: Some(new_range),
+            });
+        }
+    }
+}
+
+fn parsed_layers(
+    snapshot: &SyntaxSnapshot,
+) -> HashMap<(usize, LanguageId), Vec<&SyntaxSnapshotEntry>> {
+    let mut layers: HashMap<_, Vec<_>> = HashMap::new();
+    for entry in &snapshot.entries {
+        if let SyntaxSnapshotEntryContent::Parsed { language, .. } = &entry.content {
+            layers
+                .entry((entry.depth, *language))
+                .or_default()
+                .push(entry);
+        }
+    }
+    layers
+}
+
+fn entry_root(entry: &SyntaxSnapshotEntry) -> ts::Node<'_> {
+    let SyntaxSnapshotEntryContent::Parsed { tree, .. } = &entry.content else {
+        unreachable!("only parsed entries are diffed")
+    };
+    tree.root_node_with_offset(entry.byte_offset, entry.point_offset)
+}
+
+/// Aligns two snapshots of related texts and reports node spans which were inserted, deleted,
+/// changed in place or moved. Layers are paired by injection depth and language in order of
+/// appearance; layers without a pair are reported as whole inserted or deleted.
+/// Changes are ordered by new range, deleted ones by their old range.
+pub fn diff_snapshots(
+    old_snapshot: &SyntaxSnapshot,
+    old_text: SourceText<'_>,
+    new_snapshot: &SyntaxSnapshot,
+    new_text: SourceText<'_>,
+) -> Vec<SyntaxChange> {
+    diff_layers(old_snapshot, Some(old_text), new_snapshot, Some(new_text))
+}
+
+/// Same as [`diff_snapshots`] comparing only kinds of nodes, so edits of token text are not
+/// reported. Gives subtrees whose structure changed without access to the texts, e.g. to
+/// invalidate folds precisely within changed ranges of an incremental parse.
+pub fn diff_snapshot_structure(
+    old_snapshot: &SyntaxSnapshot,
+    new_snapshot: &SyntaxSnapshot,
+) -> Vec<SyntaxChange> {
+    diff_layers(old_snapshot, None, new_snapshot, None)
+}
+
+fn diff_layers(
+    old_snapshot: &SyntaxSnapshot,
+    old_text: Option<SourceText<'_>>,
+    new_snapshot: &SyntaxSnapshot,
+    new_text: Option<SourceText<'_>>,
+) -> Vec<SyntaxChange> {
+    let mut changes = Vec::new();
+    let mut old_layers = parsed_layers(old_snapshot);
+    for ((depth, language_id), new_entries) in parsed_layers(new_snapshot) {
+        let old_entries = old_layers.remove(&(depth, language_id)).unwrap_or_default();
+        let mut layer_diff = LayerDiff {
+            language_id,
+            old: DiffSide {
+                text: old_text,
+                hashes: HashMap::new(),
+            },
+            new: DiffSide {
+                text: new_text,
+                hashes: HashMap::new(),
+            },
+            inserted: Vec::new(),
+            deleted: Vec::new(),
+            changed: Vec::new(),
+        };
+        for (idx, new_entry) in new_entries.iter().enumerate() {
+            let new_root = entry_root(new_entry);
+            match old_entries.get(idx) {
+                Some(old_entry) => layer_diff.diff_nodes(entry_root(old_entry), new_root),
+                None => {
+                    let hash = layer_diff.new.subtree_hash(new_root);
+                    layer_diff.inserted.push((hash, new_root.range()));
+                }
+            }
+        }
+        for old_entry in old_entries.iter().skip(new_entries.len()) {
+            let old_root = entry_root(old_entry);
+            let hash = layer_diff.old.subtree_hash(old_root);
+            layer_diff.deleted.push((hash, old_root.range()));
+        }
+        layer_diff.into_changes(&mut changes);
+    }
+    for ((_, language_id), old_entries) in old_layers {
+        for old_entry in old_entries {
+            changes.push(SyntaxChange {
+                kind: SyntaxChangeKind::Deleted,
+                language_id,
+                old_range: Some(entry_root(old_entry).range()),
+                new_range: None,
+            });
+        }
+    }
+    changes.sort_by_key(|change| {
+        change
+            .new_range
+            .or(change.old_range)
+            .map(|range| (range.start_byte, range.end_byte))
+    });
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn common_subsequence_of_equal_sequences() {
+        assert_eq!(
+            common_subsequence(&[1, 2, 3], &[1, 2, 3]),
+            vec![(0, 0), (1, 1), (2, 2)]
+        );
+    }
+
+    #[test]
+    fn common_subsequence_skips_replaced_items() {
+        assert_eq!(
+            common_subsequence(&[1, 2, 3, 4], &[1, 5, 3, 4]),
+            vec![(0, 0), (2, 2), (3, 3)]
+        );
+        assert_eq!(
+            common_subsequence(&[1, 2, 3], &[2, 4, 3, 1]),
+            vec![(1, 0), (2, 2)]
+        );
+        assert_eq!(common_subsequence(&[], &[1]), vec![]);
+    }
+
+    #[test]
+    fn common_subsequence_of_long_sequences_aligns_unique_items() {
+        // Moving the first item to the end leaves a middle too long for the length table
+        let old: Vec<u64> = (0..3000).collect();
+        let new: Vec<u64> = (1..3000).chain([0]).collect();
+        assert!(old.len() * new.len() > MAX_SUBSEQUENCE_CELLS);
+        let expected: Vec<(usize, usize)> = (1..3000).map(|i| (i, i - 1)).collect();
+        assert_eq!(common_subsequence(&old, &new), expected);
+    }
+}
@@ -0,0 +1,153 @@
+use jni::{
+    errors::Result as JNIResult,
+    objects::{AutoLocal, JCharArray, JClass, JMethodID, JObject, JObjectArray, JValue},
+    sys::jsize,
+    JNIEnv,
+};
+use once_cell::sync::OnceCell as JOnceLock;
+
+use crate::{
+    jni_utils::{read_char_array, throw_exception_from_result, RangeDesc},
+    query::SourceText,
+    syntax_snapshot::SyntaxSnapshotDesc,
+};
+
+use super::{diff_snapshot_structure, diff_snapshots, SyntaxChange, SyntaxChangeKind};
+
+static SYNTAX_CHANGE_CONSTRUCTOR: JOnceLock<JMethodID> = JOnceLock::new();
+
+struct SyntaxChangeDesc<'local> {
+    constructor: JMethodID,
+    class: AutoLocal<'local, JClass<'local>>,
+    range_desc: RangeDesc<'local>,
+}
+
+impl<'local> SyntaxChangeDesc<'local> {
+    fn new(env: &mut JNIEnv<'local>) -> JNIResult<SyntaxChangeDesc<'local>> {
+        let range_desc = RangeDesc::new(env)?;
+        let class = env.find_class("com/hulylabs/treesitter/language/SyntaxChange")?;
+        let constructor = *SYNTAX_CHANGE_CONSTRUCTOR.get_or_try_init(|| {
+            env.get_method_id(
+                &class,
+                "<init>",
+                "(IJLcom/hulylabs/treesitter/language/Range;Lcom/hulylabs/treesitter/language/Range;)V",
+            )
+        })?;
+        Ok(SyntaxChangeDesc {
+            constructor,
+            class: env.auto_local(class),
+            range_desc,
+        })
+    }
+
+    fn to_java_object(
+        &self,
+        env: &mut JNIEnv<'local>,
+        change: SyntaxChange,
+    ) -> JNIResult<JObject<'local>> {
+        let kind = match change.kind {
+            SyntaxChangeKind::Inserted => 0,
+            SyntaxChangeKind::Deleted => 1,
+            SyntaxChangeKind::Changed => 2,
+            SyntaxChangeKind::Moved => 3,
+        };
+        let old_range = match change.old_range {
+            Some(range) => self.range_desc.to_java_object(env, range)?,
+            None => JObject::null(),
+        };
+        let old_range = env.auto_local(old_range);
+        let new_range = match change.new_range {
+            Some(range) => self.range_desc.to_java_object(env, range)?,
+            None => JObject::null(),
+        };
+        let new_range = env.auto_local(new_range);
+        // SAFETY: constructor is valid and derived from class by construction of self
+        unsafe {
+            env.new_object_unchecked(
+                &self.class,
+                self.constructor,
+                &[
+                    JValue::Int(kind).as_jni(),
+                    JValue::from(change.language_id).as_jni(),
+                    JValue::Object(&old_range).as_jni(),
+                    JValue::Object(&new_range).as_jni(),
+                ],
+            )
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeStructuralDiff_nativeDiff<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    old_snapshot: JObject<'local>,
+    old_text: JCharArray<'local>,
+    new_snapshot: JObject<'local>,
+    new_text: JCharArray<'local>,
+) -> JObjectArray<'local> {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        old_snapshot: JObject<'local>,
+        old_text: JCharArray<'local>,
+        new_snapshot: JObject<'local>,
+        new_text: JCharArray<'local>,
+    ) -> JNIResult<JObjectArray<'local>> {
+        let old_snapshot = SyntaxSnapshotDesc::from_java_object(env, old_snapshot)?;
+        let new_snapshot = SyntaxSnapshotDesc::from_java_object(env, new_snapshot)?;
+        let change_desc = SyntaxChangeDesc::new(env)?;
+        let old_text_buffer = read_char_array(env, &old_text)?;
+        let new_text_buffer = read_char_array(env, &new_text)?;
+
+        let changes = diff_snapshots(
+            old_snapshot,
+            SourceText::Utf16(&old_text_buffer),
+            new_snapshot,
+            SourceText::Utf16(&new_text_buffer),
+        );
+        let changes_array =
+            env.new_object_array(changes.len() as jsize, &change_desc.class, JObject::null())?;
+        for (index, change) in changes.into_iter().enumerate() {
+            let change_obj = change_desc.to_java_object(env, change)?;
+            let change_obj = env.auto_local(change_obj);
+            env.set_object_array_element(&changes_array, index as i32, change_obj)?;
+        }
+        Ok(changes_array)
+    }
+    let result = inner(&mut env, old_snapshot, old_text, new_snapshot, new_text);
+    throw_exception_from_result(&mut env, result)
+}
+
+/// Same as `nativeDiff` comparing only node kinds, texts of the snapshots are not needed
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeStructuralDiff_nativeDiffSnapshots<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    old_snapshot: JObject<'local>,
+    new_snapshot: JObject<'local>,
+) -> JObjectArray<'local> {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        old_snapshot: JObject<'local>,
+        new_snapshot: JObject<'local>,
+    ) -> JNIResult<JObjectArray<'local>> {
+        let old_snapshot = SyntaxSnapshotDesc::from_java_object(env, old_snapshot)?;
+        let new_snapshot = SyntaxSnapshotDesc::from_java_object(env, new_snapshot)?;
+        let change_desc = SyntaxChangeDesc::new(env)?;
+        let changes = diff_snapshot_structure(old_snapshot, new_snapshot);
+        let changes_array =
+            env.new_object_array(changes.len() as jsize, &change_desc.class, JObject::null())?;
+        for (index, change) in changes.into_iter().enumerate() {
+            let change_obj = change_desc.to_java_object(env, change)?;
+            let change_obj = env.auto_local(change_obj);
+            env.set_object_array_element(&changes_array, index as i32, change_obj)?;
+        }
+        Ok(changes_array)
+    }
+    let result = inner(&mut env, old_snapshot, new_snapshot);
+    throw_exception_from_result(&mut env, result)
+}
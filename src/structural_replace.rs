@@ -0,0 +1,138 @@
+use std::ops::Range;
+
+use crate::{
+    query::SourceText,
+    syntax_snapshot::SyntaxSnapshot,
+    user_query::{execute_query, UserQuery},
+};
+
+#[cfg(feature = "jni")]
+mod jni_methods;
+
+/// Capture which range is replaced, if pattern does not have it whole match range is replaced
+const REPLACE_CAPTURE_NAME: &str = "replace";
+
+#[derive(thiserror::Error, Debug)]
+pub enum TemplateError {
+    #[error("unknown capture @{0} in template")]
+    UnknownCapture(Box<str>),
+    #[error("unterminated capture reference at {0}")]
+    UnterminatedCapture(usize),
+}
+
+#[derive(Debug)]
+enum TemplatePart {
+    Text(Box<str>),
+    Capture(u32),
+}
+
+/// Replacement text with `{@name}` references to captures of the query
+#[derive(Debug)]
+pub struct ReplacementTemplate {
+    parts: Vec<TemplatePart>,
+}
+
+impl ReplacementTemplate {
+    pub fn parse(query: &UserQuery, template: &str) -> Result<Self, TemplateError> {
+        let mut parts = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find("{@") {
+            if start > 0 {
+                parts.push(TemplatePart::Text(rest[..start].into()));
+            }
+            let reference = &rest[(start + 2)..];
+            let Some(end) = reference.find('}') else {
+                return Err(TemplateError::UnterminatedCapture(
+                    template.len() - rest.len() + start,
+                ));
+            };
+            let capture_name = &reference[..end];
+            let capture_id = query
+                .query()
+                .capture_index_for_name(capture_name)
+                .ok_or_else(|| TemplateError::UnknownCapture(capture_name.into()))?;
+            parts.push(TemplatePart::Capture(capture_id));
+            rest = &reference[(end + 1)..];
+        }
+        if !rest.is_empty() {
+            parts.push(TemplatePart::Text(rest.into()));
+        }
+        Ok(ReplacementTemplate { parts })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TextEdit {
+    pub range: tree_sitter::Range,
+    pub replacement: String,
+}
+
+/// Computes edits replacing every match of `query` in `byte_range` by expanded `template`.
+/// Edits are ordered by position, matches overlapping already replaced range are skipped.
+pub fn compute_replacements(
+    snapshot: &SyntaxSnapshot,
+    text: SourceText<'_>,
+    query: &UserQuery,
+    template: &ReplacementTemplate,
+    byte_range: Range<usize>,
+) -> Vec<TextEdit> {
+    let replace_capture_id = query.query().capture_index_for_name(REPLACE_CAPTURE_NAME);
+    let mut matches = execute_query(snapshot, text, query, byte_range);
+    matches.retain(|query_match| !query_match.captures.is_empty());
+    let mut edits: Vec<TextEdit> = Vec::with_capacity(matches.len());
+    for query_match in matches {
+        let range = if let Some(capture) = query_match
+            .captures
+            .iter()
+            .find(|capture| Some(capture.capture_id) == replace_capture_id)
+        {
+            capture.range
+        } else {
+            let first = query_match
+                .captures
+                .iter()
+                .min_by_key(|capture| capture.range.start_byte)
+                .expect("captures are not empty");
+            let last = query_match
+                .captures
+                .iter()
+                .max_by_key(|capture| capture.range.end_byte)
+                .expect("captures are not empty");
+            tree_sitter::Range {
+                start_byte: first.range.start_byte,
+                start_point: first.range.start_point,
+                end_byte: last.range.end_byte,
+                end_point: last.range.end_point,
+            }
+        };
+        let mut replacement = String::new();
+        for part in &template.parts {
+            match part {
+                TemplatePart::Text(part_text) => replacement.push_str(part_text),
+                TemplatePart::Capture(capture_id) => {
+                    let captured: Vec<_> = query_match
+                        .captures
+                        .iter()
+                        .filter(|capture| capture.capture_id == *capture_id)
+                        .collect();
+                    if let (Some(first), Some(last)) = (captured.first(), captured.last()) {
+                        replacement.push_str(
+                            &text.text_for_byte_range(first.range.start_byte..last.range.end_byte),
+                        );
+                    }
+                }
+            }
+        }
+        edits.push(TextEdit { range, replacement });
+    }
+    edits.sort_by_key(|edit| (edit.range.start_byte, edit.range.end_byte));
+    let mut last_end = 0;
+    edits.retain(|edit| {
+        if edit.range.start_byte < last_end {
+            return false;
+        }
+        last_end = edit.range.end_byte;
+        true
+    });
+    edits
+}
@@ -0,0 +1,161 @@
+use std::borrow::Cow;
+
+use jni::{
+    errors::{Error as JNIError, Result as JNIResult},
+    objects::{AutoLocal, JCharArray, JClass, JMethodID, JObject, JObjectArray, JString, JValue},
+    sys::{jint, jsize},
+    JNIEnv,
+};
+use once_cell::sync::OnceCell as JOnceLock;
+
+use crate::{
+    jni_utils::{read_char_array, RangeDesc},
+    language_registry::QueryParseError,
+    query::SourceText,
+    syntax_snapshot::SyntaxSnapshotDesc,
+    LanguageId,
+};
+
+use super::{compute_replacements, ReplacementTemplate, TemplateError, TextEdit};
+
+#[derive(thiserror::Error, Debug)]
+enum StructuralReplaceError {
+    #[error(transparent)]
+    QueryError(#[from] QueryParseError),
+    #[error(transparent)]
+    TemplateError(#[from] TemplateError),
+}
+
+impl From<JNIError> for StructuralReplaceError {
+    fn from(value: JNIError) -> Self {
+        StructuralReplaceError::QueryError(value.into())
+    }
+}
+
+static TEXT_EDIT_CONSTRUCTOR: JOnceLock<JMethodID> = JOnceLock::new();
+
+struct TextEditDesc<'local> {
+    constructor: JMethodID,
+    class: AutoLocal<'local, JClass<'local>>,
+    range_desc: RangeDesc<'local>,
+}
+
+impl<'local> TextEditDesc<'local> {
+    fn new(env: &mut JNIEnv<'local>) -> JNIResult<TextEditDesc<'local>> {
+        let range_desc = RangeDesc::new(env)?;
+        let class = env.find_class("com/hulylabs/treesitter/language/TextEdit")?;
+        let constructor = *TEXT_EDIT_CONSTRUCTOR.get_or_try_init(|| {
+            env.get_method_id(
+                &class,
+                "<init>",
+                "(Lcom/hulylabs/treesitter/language/Range;Ljava/lang/String;)V",
+            )
+        })?;
+        Ok(TextEditDesc {
+            constructor,
+            class: env.auto_local(class),
+            range_desc,
+        })
+    }
+
+    fn to_java_object(
+        &self,
+        env: &mut JNIEnv<'local>,
+        edit: TextEdit,
+    ) -> JNIResult<JObject<'local>> {
+        let range_obj = self.range_desc.to_java_object(env, edit.range)?;
+        let range_obj = env.auto_local(range_obj);
+        let replacement: JObject = env.new_string(edit.replacement)?.into();
+        let replacement = env.auto_local(replacement);
+        // SAFETY: constructor is valid and derived from class by construction of self
+        unsafe {
+            env.new_object_unchecked(
+                &self.class,
+                self.constructor,
+                &[
+                    JValue::Object(&range_obj).as_jni(),
+                    JValue::Object(&replacement).as_jni(),
+                ],
+            )
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeQueryExecutor_nativeComputeReplacements<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    snapshot: JObject<'local>,
+    text: JCharArray<'local>,
+    language_id: LanguageId,
+    query_source: JString<'local>,
+    template: JString<'local>,
+    start_offset: jint,
+    end_offset: jint,
+) -> JObjectArray<'local> {
+    #[allow(clippy::too_many_arguments)]
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        snapshot: JObject<'local>,
+        text: JCharArray<'local>,
+        language_id: LanguageId,
+        query_source: JString<'local>,
+        template: JString<'local>,
+        start_offset: jint,
+        end_offset: jint,
+    ) -> Result<JObjectArray<'local>, StructuralReplaceError> {
+        let snapshot = SyntaxSnapshotDesc::from_java_object(env, snapshot)?;
+        let query_source = env.get_string(&query_source)?;
+        let query_source: Cow<'_, str> = (&query_source).into();
+        let query = snapshot
+            .isolate
+            .compile_user_query(language_id, &query_source)?;
+        let template = env.get_string(&template)?;
+        let template: Cow<'_, str> = (&template).into();
+        let template = ReplacementTemplate::parse(&query, &template)?;
+        let edit_desc = TextEditDesc::new(env)?;
+        let text_buffer = read_char_array(env, &text)?;
+
+        let edits = compute_replacements(
+            snapshot,
+            SourceText::Utf16(&text_buffer),
+            &query,
+            &template,
+            ((start_offset * 2) as usize)..((end_offset * 2) as usize),
+        );
+        let edits_array =
+            env.new_object_array(edits.len() as jsize, &edit_desc.class, JObject::null())?;
+        for (index, edit) in edits.into_iter().enumerate() {
+            let edit_obj = edit_desc.to_java_object(env, edit)?;
+            let edit_obj = env.auto_local(edit_obj);
+            env.set_object_array_element(&edits_array, index as i32, edit_obj)?;
+        }
+        Ok(edits_array)
+    }
+    let result = inner(
+        &mut env,
+        snapshot,
+        text,
+        language_id,
+        query_source,
+        template,
+        start_offset,
+        end_offset,
+    );
+    match result {
+        Ok(edits) => edits,
+        Err(StructuralReplaceError::QueryError(QueryParseError::JNIError(
+            JNIError::JavaException,
+        ))) => JObjectArray::default(),
+        Err(err) => {
+            env.throw_new(
+                "java/lang/RuntimeException",
+                format!("Failed to compute replacements: {err}"),
+            )
+            .unwrap();
+            JObjectArray::default()
+        }
+    }
+}
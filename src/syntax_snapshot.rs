@@ -1,45 +1,164 @@
 use std::{
     borrow::Cow,
-    collections::BinaryHeap,
+    collections::{BinaryHeap, HashSet},
     ops::Range,
-    sync::{Arc, LazyLock, Mutex},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    time::{Duration, Instant},
 };
 
 use crate::{
+    highlighting_lexer::query::HighlightCache,
     injections::InjectionMatch,
-    language_registry::{with_language, with_unknown_language, LanguageId, UnknownLanguage},
+    invariants,
+    isolate::Isolate,
+    language_registry::{LanguageId, UnknownLanguage},
+    locals::LocalsCache,
+    logging::{log, LogLevel},
+    outline::OutlineSymbol,
+    profiler,
+    query::{LineIndex, SourceText},
+    query_budget,
 };
 
+#[cfg(feature = "jni")]
 mod jni_methods;
+#[cfg(feature = "jni")]
 pub use jni_methods::SyntaxSnapshotDesc;
 use tree_sitter as ts;
 
 #[derive(Default)]
-struct ParsersPool {
-    pool: Arc<Mutex<Vec<ts::Parser>>>,
+pub(crate) struct ParsersPool {
+    /// Parsers along with the language last set to them
+    pool: Arc<Mutex<Vec<(LanguageId, ts::Parser)>>>,
 }
 
-static PARSERS_POOL: LazyLock<ParsersPool> = LazyLock::new(ParsersPool::default);
-
 impl ParsersPool {
-    fn with_parser<T, F: FnOnce(&mut ts::Parser) -> T>(&self, func: F) -> T {
-        let mut parser = {
-            let mut guard = self.pool.lock().unwrap();
-            guard.pop().unwrap_or_default()
+    /// Runs `func` with a parser set to the language, parsers last used with the same language are
+    /// preferred as setting the language reinitializes the lexer. `None` if the language could not
+    /// be set.
+    pub(crate) fn with_parser<T, F: FnOnce(&mut ts::Parser) -> T>(
+        &self,
+        language_id: LanguageId,
+        ts_language: &ts::Language,
+        func: F,
+    ) -> Option<T> {
+        self.with_parser_counted(&mut ParseStats::default(), language_id, ts_language, func)
+    }
+
+    /// Same as [`Self::with_parser`], counts whether the pool had a parser of the language
+    fn with_parser_counted<T, F: FnOnce(&mut ts::Parser) -> T>(
+        &self,
+        stats: &mut ParseStats,
+        language_id: LanguageId,
+        ts_language: &ts::Language,
+        func: F,
+    ) -> Option<T> {
+        let pooled = {
+            let mut pool = self.pool.lock().unwrap();
+            match pool
+                .iter()
+                .rposition(|(language, _)| *language == language_id)
+            {
+                Some(idx) => Some(pool.swap_remove(idx)),
+                None => pool.pop(),
+            }
+        };
+        let mut parser = match pooled {
+            Some((language, parser)) if language == language_id => {
+                stats.parser_pool_hits += 1;
+                parser
+            }
+            pooled => {
+                stats.parser_pool_misses += 1;
+                let (pooled_language, mut parser) = match pooled {
+                    Some((language, parser)) => (Some(language), parser),
+                    None => (None, ts::Parser::new()),
+                };
+                if parser.set_language(ts_language).is_err() {
+                    // Parser keeps its previous language, so it goes back to the pool as is
+                    if let Some(pooled_language) = pooled_language {
+                        self.pool.lock().unwrap().push((pooled_language, parser));
+                    }
+                    return None;
+                }
+                parser
+            }
         };
         let result = func(&mut parser);
         parser.reset();
-        let mut guard = self.pool.lock().unwrap();
-        guard.push(parser);
-        result
+        // Included ranges are kept by the parser otherwise
+        parser
+            .set_included_ranges(&[])
+            .expect("empty included ranges are valid");
+        self.pool.lock().unwrap().push((language_id, parser));
+        Some(result)
     }
 }
 
-fn with_parser<T, F: FnOnce(&mut ts::Parser) -> T>(func: F) -> T {
-    PARSERS_POOL.with_parser(func)
+const MAX_POOLED_BUFFERS: usize = 8;
+
+/// Scratch buffers of a single parse
+#[derive(Default)]
+struct ParseBuffers {
+    parse_queue: BinaryHeap<ParseCommand>,
+    included_ranges: Vec<ts::Range>,
+    /// Commands already taken from the queue, combined injection patterns may produce the same
+    /// injection several times
+    parsed_commands: HashSet<ParseCommandKey>,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+/// Allocations reused between parses, so bursts of incremental parses of documents with many
+/// injections do not reallocate queues and entry vectors every time. Entry vectors are returned
+/// to the pool when their snapshot is dropped.
+#[derive(Default)]
+pub(crate) struct ParseBuffersPool {
+    buffers: Mutex<Vec<ParseBuffers>>,
+    entries: Mutex<Vec<Vec<SyntaxSnapshotEntry>>>,
+}
+
+impl ParseBuffersPool {
+    fn take(&self) -> ParseBuffers {
+        self.buffers.lock().unwrap().pop().unwrap_or_default()
+    }
+
+    fn put(&self, mut buffers: ParseBuffers) {
+        buffers.parse_queue.clear();
+        buffers.included_ranges.clear();
+        buffers.parsed_commands.clear();
+        let mut pool = self.buffers.lock().unwrap();
+        if pool.len() < MAX_POOLED_BUFFERS {
+            pool.push(buffers);
+        }
+    }
+
+    fn take_entries(&self, capacity: usize) -> Vec<SyntaxSnapshotEntry> {
+        let mut entries = self.entries.lock().unwrap().pop().unwrap_or_default();
+        entries.reserve(capacity);
+        entries
+    }
+
+    /// Returns buffers and entries of a parse which is given up before producing a snapshot
+    fn put_abandoned(&self, buffers: ParseBuffers, entries: Vec<SyntaxSnapshotEntry>) {
+        self.put(buffers);
+        self.put_entries(entries);
+    }
+
+    fn put_entries(&self, mut entries: Vec<SyntaxSnapshotEntry>) {
+        if entries.capacity() == 0 {
+            return;
+        }
+        entries.clear();
+        let mut pool = self.entries.lock().unwrap();
+        if pool.len() < MAX_POOLED_BUFFERS {
+            pool.push(entries);
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 enum ParseCommandLanguage {
     Known(LanguageId),
     Unknown(UnknownLanguage),
@@ -53,15 +172,17 @@ struct ParseCommand {
     byte_range: std::ops::Range<usize>,
     byte_offset: usize,
     point_offset: ts::Point,
+    /// Id of the old snapshot entry the command is carried over from
+    entry_id: Option<u64>,
 }
 
 impl ParseCommand {
-    fn source_language(&self) -> Cow<'_, UnknownLanguage> {
+    fn source_language(&self, isolate: &Isolate) -> Cow<'_, UnknownLanguage> {
         match &self.language {
             ParseCommandLanguage::Known(language_id) => {
-                let language_name: Box<str> =
-                    with_language(*language_id, |language| language.name().into())
-                        .unwrap_or_else(|_| format!("Language({language_id:?})").into());
+                let language_name: Box<str> = isolate
+                    .with_language(*language_id, |language| language.name().into())
+                    .unwrap_or_else(|_| format!("Language({language_id:?})").into());
                 Cow::Owned(UnknownLanguage::LanguageName(language_name))
             }
             ParseCommandLanguage::Unknown(unknown_language) => Cow::Borrowed(unknown_language),
@@ -74,11 +195,47 @@ impl ParseCommand {
         }
     }
 
-    fn from_injection(injection: InjectionMatch, depth: usize) -> Self {
-        let language = with_unknown_language(&injection.language, |language| {
-            ParseCommandLanguage::Known(language.id())
-        })
-        .unwrap_or(ParseCommandLanguage::Unknown(injection.language));
+    /// Whether command parses injection larger than `max_size`
+    fn exceeds_size(&self, max_size: Option<usize>) -> bool {
+        let Some(max_size) = max_size else {
+            return false;
+        };
+        self.depth > 0
+            && self
+                .included_ranges
+                .iter()
+                .map(|range| range.end_byte - range.start_byte)
+                .sum::<usize>()
+                > max_size
+    }
+
+    fn key(&self) -> ParseCommandKey {
+        ParseCommandKey {
+            language: self.language.clone(),
+            included_ranges: self
+                .included_ranges
+                .iter()
+                .map(|range| range.start_byte..range.end_byte)
+                .collect(),
+        }
+    }
+
+    fn from_injection(isolate: &Isolate, mut injection: InjectionMatch, depth: usize) -> Self {
+        if merge_included_ranges(&mut injection.included_ranges) {
+            log(
+                LogLevel::Warn,
+                format_args!(
+                    "injection of {:?} has overlapping or unordered ranges, check the injection \
+                     query",
+                    injection.language
+                ),
+            );
+        }
+        let language = isolate
+            .with_unknown_language(&injection.language, |language| {
+                ParseCommandLanguage::Known(language.id())
+            })
+            .unwrap_or(ParseCommandLanguage::Unknown(injection.language));
         let injection_start = injection
             .included_ranges
             .first()
@@ -92,10 +249,30 @@ impl ParseCommand {
             byte_range: injection.enclosing_byte_range,
             byte_offset,
             point_offset,
+            entry_id: None,
+        }
+    }
+
+    /// Whether entry could be produced by the command, ignoring its ranges
+    fn language_matches(&self, isolate: &Isolate, entry: &SyntaxSnapshotEntry) -> bool {
+        match &entry.content {
+            SyntaxSnapshotEntryContent::Parsed { language, .. } => {
+                self.language_id() == Some(*language)
+            }
+            SyntaxSnapshotEntryContent::Unparsed(unknown_language) => {
+                *self.source_language(isolate) == *unknown_language
+            }
         }
     }
 }
 
+/// Commands with equal keys produce the same tree
+#[derive(PartialEq, Eq, Hash)]
+struct ParseCommandKey {
+    language: ParseCommandLanguage,
+    included_ranges: Box<[Range<usize>]>,
+}
+
 impl PartialOrd for ParseCommand {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         Some(self.cmp(other))
@@ -111,8 +288,133 @@ impl Ord for ParseCommand {
     }
 }
 
+/// Flag aborting parses which use it once cancelled, shared between the caller and the parse
+#[derive(Debug, Clone, Default)]
+pub struct ParseCancellation(Arc<AtomicUsize>);
+
+impl ParseCancellation {
+    pub fn cancel(&self) {
+        self.0.store(1, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed) != 0
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+    pub cancellation: Option<ParseCancellation>,
+    /// Time budget of the whole parse. Injections which could not be parsed within it become
+    /// unparsed entries, parse fails if base language tree could not be parsed within it
+    pub timeout: Option<Duration>,
+}
+
+impl ParseOptions {
+    fn is_cancelled(&self) -> bool {
+        self.cancellation
+            .as_ref()
+            .is_some_and(ParseCancellation::is_cancelled)
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        self.timeout.map(|timeout| Instant::now() + timeout)
+    }
+
+    /// Runs parse with options applied to the parser, they are unset before the parser is
+    /// returned to the pool
+    fn parse(
+        &self,
+        parser: &mut ts::Parser,
+        deadline: Option<Instant>,
+        parse: impl FnOnce(&mut ts::Parser) -> Option<ts::Tree>,
+    ) -> Option<ts::Tree> {
+        if let Some(deadline) = deadline {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return None;
+            }
+            // Zero timeout disables it in tree-sitter
+            parser.set_timeout_micros((remaining.as_micros() as u64).max(1));
+        }
+        let flag = self
+            .cancellation
+            .as_ref()
+            .map(|cancellation| &*cancellation.0);
+        // SAFETY: flag is owned by self which outlives the parse, it is unset right after it
+        unsafe { parser.set_cancellation_flag(flag) };
+        let tree = parse(parser);
+        // SAFETY: unsetting the flag is always valid
+        unsafe { parser.set_cancellation_flag(None) };
+        parser.set_timeout_micros(0);
+        tree
+    }
+}
+
+/// Metrics of the parse which produced a snapshot
+#[derive(Debug, Clone, Default)]
+pub struct ParseStats {
+    pub duration: Duration,
+    /// Time spent in tree-sitter parses per language, in order of the first parse of the language
+    pub language_durations: Vec<(LanguageId, Duration)>,
+    pub entry_count: usize,
+    /// Number of entries at each injection depth, base language entry is at depth 0
+    pub depth_histogram: Vec<usize>,
+    /// Parses which got a pooled parser already set to their language
+    pub parser_pool_hits: usize,
+    pub parser_pool_misses: usize,
+    /// Entries parsed incrementally using trees of the old snapshot
+    pub reused_trees: usize,
+}
+
+impl ParseStats {
+    fn add_language_duration(&mut self, language_id: LanguageId, duration: Duration) {
+        match self
+            .language_durations
+            .iter_mut()
+            .find(|(language, _)| *language == language_id)
+        {
+            Some((_, total)) => *total += duration,
+            None => self.language_durations.push((language_id, duration)),
+        }
+    }
+
+    fn finish(&mut self, started: Instant, entries: &[SyntaxSnapshotEntry]) {
+        self.duration = started.elapsed();
+        self.entry_count = entries.len();
+        for entry in entries {
+            if self.depth_histogram.len() <= entry.depth {
+                self.depth_histogram.resize(entry.depth + 1, 0);
+            }
+            self.depth_histogram[entry.depth] += 1;
+        }
+    }
+}
+
+static SNAPSHOT_ID_COUNTER: AtomicU64 = AtomicU64::new(0);
+static ENTRY_ID_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Edit which produced snapshot from the previous one
+#[derive(Debug, Clone)]
+pub(crate) struct SnapshotOrigin {
+    pub(crate) snapshot_id: u64,
+    pub(crate) edit: ts::InputEdit,
+    pub(crate) changed_ranges: Box<[ts::Range]>,
+    /// Sorted ids of entries having changed ranges
+    pub(crate) changed_entry_ids: Box<[u64]>,
+}
+
 pub struct SyntaxSnapshot {
+    pub(crate) isolate: Arc<Isolate>,
     pub(crate) entries: Vec<SyntaxSnapshotEntry>,
+    pub(crate) id: u64,
+    pub(crate) origin: Option<SnapshotOrigin>,
+    pub(crate) outline: OnceLock<Arc<[OutlineSymbol]>>,
+    entry_index: OnceLock<EntryIndex>,
+    line_index: OnceLock<Arc<LineIndex>>,
+    pub(crate) highlight_cache: HighlightCache,
+    pub(crate) locals_cache: LocalsCache,
+    stats: ParseStats,
 }
 
 #[derive(Debug, Clone)]
@@ -121,11 +423,14 @@ pub(crate) enum SyntaxSnapshotEntryContent {
         language: LanguageId,
         tree: ts::Tree,
     },
-    Unparsed(#[allow(dead_code)] UnknownLanguage),
+    Unparsed(UnknownLanguage),
 }
 
 #[derive(Debug, Clone)]
 pub struct SyntaxSnapshotEntry {
+    /// Identity of the entry, kept by incremental parses while the entry stays at the same place
+    /// of the edited text with the same language
+    pub(crate) id: u64,
     pub(crate) depth: usize,
     pub(crate) content: SyntaxSnapshotEntryContent,
     pub(crate) byte_range: Range<usize>,
@@ -134,11 +439,12 @@ pub struct SyntaxSnapshotEntry {
 }
 
 impl SyntaxSnapshotEntry {
-    fn new_unparsed(parse_command: &ParseCommand) -> Self {
+    fn new_unparsed(isolate: &Isolate, parse_command: &ParseCommand, id: u64) -> Self {
         Self {
+            id,
             depth: parse_command.depth,
             content: SyntaxSnapshotEntryContent::Unparsed(
-                parse_command.source_language().into_owned(),
+                parse_command.source_language(isolate).into_owned(),
             ),
             byte_range: parse_command.byte_range.clone(),
             byte_offset: parse_command.byte_offset,
@@ -147,6 +453,24 @@ impl SyntaxSnapshotEntry {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InjectionLanguage {
+    Parsed(LanguageId),
+    /// Language is not registered or its text could not be parsed
+    Unparsed(UnknownLanguage),
+}
+
+/// Region of the document covered by one snapshot entry
+#[derive(Debug, Clone)]
+pub struct InjectionRange {
+    /// Id of the entry, see [`SyntaxSnapshot::changed_entry_ids`]
+    pub id: u64,
+    pub depth: usize,
+    pub language: InjectionLanguage,
+    /// Ranges included into the entry, whole entry range if they are unknown
+    pub byte_ranges: Vec<Range<usize>>,
+}
+
 fn sub_point(point1: &ts::Point, point2: &ts::Point) -> ts::Point {
     if point1.row == point2.row {
         ts::Point {
@@ -161,7 +485,391 @@ fn sub_point(point1: &ts::Point, point2: &ts::Point) -> ts::Point {
     }
 }
 
+/// Entries sorted by start with running maximum of their ends, so entries intersecting a range
+/// are found by two binary searches instead of scanning all entries
+struct SortedEntries {
+    by_start: Box<[usize]>,
+    max_end: Box<[usize]>,
+}
+
+impl SortedEntries {
+    fn new(entries: &[SyntaxSnapshotEntry], mut indices: Vec<usize>) -> Self {
+        indices.sort_by_key(|idx| entries[*idx].byte_range.start);
+        let max_end = indices
+            .iter()
+            .scan(0, |max_end, idx| {
+                *max_end = entries[*idx].byte_range.end.max(*max_end);
+                Some(*max_end)
+            })
+            .collect();
+        SortedEntries {
+            by_start: indices.into(),
+            max_end,
+        }
+    }
+
+    /// Indices of entries intersecting `byte_range` ordered by their start
+    fn intersecting<'a>(
+        &'a self,
+        entries: &'a [SyntaxSnapshotEntry],
+        byte_range: Range<usize>,
+        inclusive: bool,
+    ) -> impl Iterator<Item = usize> + 'a {
+        let upper = self.by_start.partition_point(|idx| {
+            let start = entries[*idx].byte_range.start;
+            start < byte_range.end || (inclusive && start == byte_range.end)
+        });
+        let lower = self.max_end[..upper].partition_point(|end| {
+            *end < byte_range.start || (!inclusive && *end == byte_range.start)
+        });
+        self.by_start[lower..upper]
+            .iter()
+            .copied()
+            .filter(move |idx| {
+                let end = entries[*idx].byte_range.end;
+                end > byte_range.start || (inclusive && end == byte_range.start)
+            })
+    }
+
+    /// Indices of entries inside of `byte_range` ordered by their start
+    fn within<'a>(
+        &'a self,
+        entries: &'a [SyntaxSnapshotEntry],
+        byte_range: Range<usize>,
+    ) -> impl Iterator<Item = usize> + 'a {
+        let lower = self
+            .by_start
+            .partition_point(|idx| entries[*idx].byte_range.start < byte_range.start);
+        let upper = self
+            .by_start
+            .partition_point(|idx| entries[*idx].byte_range.start <= byte_range.end);
+        self.by_start[lower..upper.max(lower)]
+            .iter()
+            .copied()
+            .filter(move |idx| entries[*idx].byte_range.end <= byte_range.end)
+    }
+}
+
+/// Index of all entries along with indices of entries of each depth, the latter let cursor
+/// descent skip entries of enclosing layers which span the whole document
+struct EntryIndex {
+    all: SortedEntries,
+    by_depth: Box<[SortedEntries]>,
+}
+
+impl EntryIndex {
+    fn new(entries: &[SyntaxSnapshotEntry]) -> Self {
+        let max_depth = entries.iter().map(|entry| entry.depth).max().unwrap_or(0);
+        let mut depth_indices = vec![Vec::new(); max_depth + 1];
+        for (idx, entry) in entries.iter().enumerate() {
+            depth_indices[entry.depth].push(idx);
+        }
+        EntryIndex {
+            all: SortedEntries::new(entries, (0..entries.len()).collect()),
+            by_depth: depth_indices
+                .into_iter()
+                .map(|indices| SortedEntries::new(entries, indices))
+                .collect(),
+        }
+    }
+}
+
+fn add_point(point: &ts::Point, offset: &ts::Point) -> ts::Point {
+    if point.row == 0 {
+        ts::Point {
+            row: offset.row,
+            column: offset.column + point.column,
+        }
+    } else {
+        ts::Point {
+            row: offset.row + point.row,
+            column: point.column,
+        }
+    }
+}
+
+/// Maps byte of text before `edit` to text after it, bytes inside of replaced range can't be
+/// mapped
+fn map_byte_through_edit(byte: usize, edit: &ts::InputEdit) -> Option<usize> {
+    if byte <= edit.start_byte {
+        Some(byte)
+    } else if byte >= edit.old_end_byte {
+        Some(byte - edit.old_end_byte + edit.new_end_byte)
+    } else {
+        None
+    }
+}
+
+/// Sorts included ranges and merges overlapping ones, which parser rejects. Returns whether the
+/// ranges had to be fixed.
+fn merge_included_ranges(ranges: &mut Vec<ts::Range>) -> bool {
+    let is_valid = ranges
+        .windows(2)
+        .all(|pair| pair[0].end_byte <= pair[1].start_byte);
+    if is_valid {
+        return false;
+    }
+    ranges.sort_unstable_by_key(|range| (range.start_byte, range.end_byte));
+    let mut merged: Vec<ts::Range> = Vec::with_capacity(ranges.len());
+    for range in ranges.drain(..) {
+        match merged.last_mut() {
+            Some(last) if range.start_byte < last.end_byte => {
+                if range.end_byte > last.end_byte {
+                    last.end_byte = range.end_byte;
+                    last.end_point = range.end_point;
+                }
+            }
+            _ => merged.push(range),
+        }
+    }
+    *ranges = merged;
+    true
+}
+
+/// Sorts ranges, merges overlapping and adjacent ones and clamps them to the text
+fn normalize_ranges(ranges: &mut Vec<ts::Range>, text: SourceText<'_>) {
+    let byte_len = text.byte_len();
+    ranges.sort_unstable_by_key(|range| (range.start_byte, range.end_byte));
+    let mut merged: Vec<ts::Range> = Vec::with_capacity(ranges.len());
+    for mut range in ranges.drain(..) {
+        if range.start_byte >= byte_len {
+            range.start_byte = byte_len;
+            range.start_point = text.point_for_byte(byte_len);
+        }
+        if range.end_byte >= byte_len {
+            range.end_byte = byte_len;
+            range.end_point = text.point_for_byte(byte_len);
+        }
+        match merged.last_mut() {
+            Some(last) if range.start_byte <= last.end_byte => {
+                if range.end_byte > last.end_byte {
+                    last.end_byte = range.end_byte;
+                    last.end_point = range.end_point;
+                }
+            }
+            _ => merged.push(range),
+        }
+    }
+    *ranges = merged;
+}
+
+/// Maps point of text before `edit` to text after it, same as [`map_byte_through_edit`]
+fn map_point_through_edit(point: ts::Point, edit: &ts::InputEdit) -> Option<ts::Point> {
+    if point <= edit.start_position {
+        Some(point)
+    } else if point >= edit.old_end_position {
+        let column = if point.row == edit.old_end_position.row {
+            point.column - edit.old_end_position.column + edit.new_end_position.column
+        } else {
+            point.column
+        };
+        Some(ts::Point {
+            row: point.row - edit.old_end_position.row + edit.new_end_position.row,
+            column,
+        })
+    } else {
+        None
+    }
+}
+
+/// Old snapshot entry of the same language which occupies the same place as `parse_command`
+/// after `edit`
+fn matching_old_entry<'a>(
+    old_snapshot: &'a SyntaxSnapshot,
+    edit: &ts::InputEdit,
+    parse_command: &ParseCommand,
+) -> Option<(usize, &'a SyntaxSnapshotEntry)> {
+    let new_start = parse_command.byte_range.start;
+    let old_start = if new_start <= edit.start_byte {
+        new_start
+    } else if new_start >= edit.new_end_byte {
+        new_start - edit.new_end_byte + edit.old_end_byte
+    } else {
+        return None;
+    };
+    old_snapshot
+        .intersecting_entries(old_start..old_start, true)
+        .find(|(_, entry)| {
+            entry.depth == parse_command.depth
+                && entry.byte_range.start == old_start
+                && map_byte_through_edit(entry.byte_range.end, edit)
+                    == Some(parse_command.byte_range.end)
+                && parse_command.language_matches(&old_snapshot.isolate, entry)
+        })
+}
+
+/// Tree of old snapshot entry matching a parse command, edited to be used as old tree of the parse
+fn reusable_old_tree(old_entry: &SyntaxSnapshotEntry, edit: &ts::InputEdit) -> Option<ts::Tree> {
+    let SyntaxSnapshotEntryContent::Parsed { tree, .. } = &old_entry.content else {
+        return None;
+    };
+    let mut tree = tree.clone();
+    if (edit.start_byte < old_entry.byte_range.start
+        && edit.old_end_byte <= old_entry.byte_range.start)
+        || edit.start_byte >= old_entry.byte_range.end
+    {
+        // Edit is outside of the entry, its text is only shifted
+        return Some(tree);
+    }
+    if edit.start_byte < old_entry.byte_offset {
+        return None;
+    }
+    tree.edit(&ts::InputEdit {
+        start_byte: edit.start_byte - old_entry.byte_offset,
+        old_end_byte: edit.old_end_byte - old_entry.byte_offset,
+        new_end_byte: edit.new_end_byte - old_entry.byte_offset,
+        start_position: sub_point(&edit.start_position, &old_entry.point_offset),
+        old_end_position: sub_point(&edit.old_end_position, &old_entry.point_offset),
+        new_end_position: sub_point(&edit.new_end_position, &old_entry.point_offset),
+    });
+    Some(tree)
+}
+
+/// Injections of old snapshot entry which lie away from `changed_ranges` of its new tree, moved
+/// through `edit`. They are kept as is instead of being rediscovered by the injection query.
+/// `None` if some of them can't be recreated, so the whole entry has to be queried again.
+fn carried_injections(
+    old_snapshot: &SyntaxSnapshot,
+    old_entry_idx: usize,
+    edit: &ts::InputEdit,
+    changed_ranges: &[Range<usize>],
+    unit_size: usize,
+) -> Option<Vec<ParseCommand>> {
+    let old_entry = &old_snapshot.entries[old_entry_idx];
+    let mut commands = Vec::new();
+    for (_, child) in
+        old_snapshot.entries_at_depth_within(old_entry.depth + 1, old_entry.byte_range.clone())
+    {
+        // Included ranges of unparsed entries are not kept
+        let SyntaxSnapshotEntryContent::Parsed { language, tree } = &child.content else {
+            return None;
+        };
+        let included_ranges: Option<Vec<ts::Range>> = tree
+            .included_ranges()
+            .into_iter()
+            .map(|range| {
+                Some(ts::Range {
+                    start_byte: map_byte_through_edit(range.start_byte + child.byte_offset, edit)?,
+                    end_byte: map_byte_through_edit(range.end_byte + child.byte_offset, edit)?,
+                    start_point: map_point_through_edit(
+                        add_point(&range.start_point, &child.point_offset),
+                        edit,
+                    )?,
+                    end_point: map_point_through_edit(
+                        add_point(&range.end_point, &child.point_offset),
+                        edit,
+                    )?,
+                })
+            })
+            .collect();
+        let Some(included_ranges) = included_ranges else {
+            continue;
+        };
+        let (Some(first), Some(last)) = (included_ranges.first(), included_ranges.last()) else {
+            continue;
+        };
+        let byte_range = first.start_byte..last.end_byte;
+        // Injection query is run over changed ranges extended by a unit
+        if changed_ranges.iter().any(|changed| {
+            byte_range.start <= changed.end + unit_size
+                && changed.start <= byte_range.end + unit_size
+        }) {
+            continue;
+        }
+        commands.push(ParseCommand {
+            depth: child.depth,
+            language: ParseCommandLanguage::Known(*language),
+            byte_offset: first.start_byte,
+            point_offset: first.start_point,
+            byte_range,
+            included_ranges,
+            entry_id: Some(child.id),
+        });
+    }
+    Some(commands)
+}
+
+impl Clone for SyntaxSnapshot {
+    fn clone(&self) -> Self {
+        SyntaxSnapshot {
+            isolate: Arc::clone(&self.isolate),
+            entries: self.entries.clone(),
+            id: self.id,
+            origin: self.origin.clone(),
+            outline: self.outline.clone(),
+            entry_index: OnceLock::new(),
+            line_index: self.line_index.clone(),
+            highlight_cache: HighlightCache::default(),
+            locals_cache: LocalsCache::default(),
+            stats: self.stats.clone(),
+        }
+    }
+}
+
+impl Drop for SyntaxSnapshot {
+    fn drop(&mut self) {
+        let entries = std::mem::take(&mut self.entries);
+        self.isolate.parse_buffers.put_entries(entries);
+    }
+}
+
 impl SyntaxSnapshot {
+    /// Entries intersecting `byte_range` in snapshot order. With `inclusive` entries which only
+    /// touch the range boundary are included as well.
+    pub(crate) fn intersecting_entries(
+        &self,
+        byte_range: Range<usize>,
+        inclusive: bool,
+    ) -> impl Iterator<Item = (usize, &SyntaxSnapshotEntry)> {
+        let mut indices: Vec<usize> = self
+            .entry_index()
+            .all
+            .intersecting(&self.entries, byte_range, inclusive)
+            .collect();
+        indices.sort_unstable();
+        indices.into_iter().map(|idx| (idx, &self.entries[idx]))
+    }
+
+    fn entry_index(&self) -> &EntryIndex {
+        self.entry_index
+            .get_or_init(|| EntryIndex::new(&self.entries))
+    }
+
+    /// Line starts of `text`, which must be the text the snapshot was parsed from, built on first
+    /// use so per-line calls do not scan the text from its start
+    pub(crate) fn line_index(&self, text: SourceText<'_>) -> &LineIndex {
+        self.line_index
+            .get_or_init(|| Arc::new(LineIndex::new(text)))
+    }
+
+    /// Entries of `depth` intersecting `byte_range` ordered by start, boundaries are inclusive
+    pub(crate) fn intersecting_entries_at_depth(
+        &self,
+        depth: usize,
+        byte_range: Range<usize>,
+    ) -> impl Iterator<Item = (usize, &SyntaxSnapshotEntry)> {
+        self.entry_index()
+            .by_depth
+            .get(depth)
+            .into_iter()
+            .flat_map(move |index| index.intersecting(&self.entries, byte_range.clone(), true))
+            .map(|idx| (idx, &self.entries[idx]))
+    }
+
+    /// Entries of `depth` inside of `byte_range` ordered by start, e.g. injections into a node
+    pub(crate) fn entries_at_depth_within(
+        &self,
+        depth: usize,
+        byte_range: Range<usize>,
+    ) -> impl Iterator<Item = (usize, &SyntaxSnapshotEntry)> {
+        self.entry_index()
+            .by_depth
+            .get(depth)
+            .into_iter()
+            .flat_map(move |index| index.within(&self.entries, byte_range.clone()))
+            .map(|idx| (idx, &self.entries[idx]))
+    }
+
     pub fn base_language(&self) -> LanguageId {
         match &self
             .entries
@@ -186,61 +894,290 @@ impl SyntaxSnapshot {
         }
     }
 
-    fn parse(base_language_id: LanguageId, text: &[u16]) -> Option<Self> {
-        let mut entries: Vec<SyntaxSnapshotEntry> = Vec::new();
-        let mut parse_queue: BinaryHeap<ParseCommand> = BinaryHeap::new();
-        parse_queue.push(ParseCommand {
+    /// Reparses unparsed entries whose language got registered after the snapshot was parsed,
+    /// returns the new snapshot along with ranges which changed. `None` if there is nothing to
+    /// resolve or the parse failed.
+    pub fn resolve_unparsed_languages(
+        &self,
+        text: SourceText<'_>,
+    ) -> Option<(Self, Vec<ts::Range>)> {
+        let resolvable = self.entries.iter().any(|entry| {
+            matches!(
+                &entry.content,
+                SyntaxSnapshotEntryContent::Unparsed(language)
+                    if self.isolate.with_unknown_language(language, |_| ()).is_ok()
+            )
+        });
+        if !resolvable {
+            return None;
+        }
+        // Empty edit keeps trees of all parsed entries, injections are looked up again only in
+        // entries hosting unparsed ones
+        let (snapshot, mut changed_ranges) = Self::parse_incremental_text(
+            text,
+            self,
+            ts::InputEdit {
+                start_byte: 0,
+                old_end_byte: 0,
+                new_end_byte: 0,
+                start_position: ts::Point::default(),
+                old_end_position: ts::Point::default(),
+                new_end_position: ts::Point::default(),
+            },
+        )?;
+        changed_ranges.retain(|range| range.start_byte < range.end_byte);
+        Some((snapshot, changed_ranges))
+    }
+
+    /// Metrics of the parse which produced the snapshot
+    pub fn parse_stats(&self) -> &ParseStats {
+        &self.stats
+    }
+
+    /// Sorted ids of entries with ranges changed by the incremental parse producing the snapshot.
+    /// Empty for snapshots parsed from scratch.
+    ///
+    /// Ids are kept by entries while they stay at the same place with the same language, so state
+    /// attached to an injected region survives edits of the text around or inside of it.
+    pub fn changed_entry_ids(&self) -> &[u64] {
+        self.origin
+            .as_ref()
+            .map_or(&[], |origin| &origin.changed_entry_ids)
+    }
+
+    /// Language layout of the document, one range per entry in snapshot order
+    pub fn injection_ranges(&self) -> Vec<InjectionRange> {
+        self.entries
+            .iter()
+            .map(|entry| {
+                let (language, byte_ranges) = match &entry.content {
+                    SyntaxSnapshotEntryContent::Parsed { language, tree } => {
+                        // Trees parsed without included ranges report single unbounded range
+                        let byte_ranges = tree
+                            .included_ranges()
+                            .into_iter()
+                            .map(|range| {
+                                let start = (range.start_byte.saturating_add(entry.byte_offset))
+                                    .max(entry.byte_range.start);
+                                let end = (range.end_byte.saturating_add(entry.byte_offset))
+                                    .min(entry.byte_range.end);
+                                start..end
+                            })
+                            .filter(|range| !range.is_empty())
+                            .collect();
+                        (InjectionLanguage::Parsed(*language), byte_ranges)
+                    }
+                    SyntaxSnapshotEntryContent::Unparsed(unknown_language) => (
+                        InjectionLanguage::Unparsed(unknown_language.clone()),
+                        vec![entry.byte_range.clone()],
+                    ),
+                };
+                InjectionRange {
+                    id: entry.id,
+                    depth: entry.depth,
+                    language,
+                    byte_ranges,
+                }
+            })
+            .collect()
+    }
+
+    /// Nodes of all layers intersecting `byte_range` in document order, injected trees follow
+    /// the nodes they are injected into
+    pub fn iter_nodes(&self, byte_range: Range<usize>) -> SnapshotNodesIter<'_> {
+        SnapshotNodesIter {
+            cursor: SyntaxSnapshotTreeCursor::walk(self),
+            byte_range,
+            depth: 0,
+            state: SnapshotNodesIterState::Start,
+        }
+    }
+
+    /// Smallest node of any snapshot layer which contains `byte_offset`, with its layer language
+    pub fn innermost_node_at(&self, byte_offset: usize) -> (LanguageId, ts::Node<'_>) {
+        let mut tree_cursor = SyntaxSnapshotTreeCursor::walk(self);
+        let mut innermost = (tree_cursor.language(), tree_cursor.node());
+        while tree_cursor.goto_first_child_for_byte(byte_offset).is_some() {
+            let node = tree_cursor.node();
+            if node.start_byte() > byte_offset {
+                break;
+            }
+            innermost = (tree_cursor.language(), node);
+        }
+        innermost
+    }
+
+    pub fn parse(
+        isolate: Arc<Isolate>,
+        base_language_id: LanguageId,
+        text: &[u16],
+    ) -> Option<Self> {
+        Self::parse_text(isolate, base_language_id, SourceText::Utf16(text))
+    }
+
+    pub fn parse_str(
+        isolate: Arc<Isolate>,
+        base_language_id: LanguageId,
+        text: &str,
+    ) -> Option<Self> {
+        Self::parse_text(isolate, base_language_id, SourceText::Utf8(text))
+    }
+
+    /// Parses UTF-8 bytes, offsets of the snapshot are byte offsets. `None` if text is not valid
+    /// UTF-8, so hosts passing arbitrary bytes (e.g. fuzzers) don't need to validate it upfront.
+    pub fn parse_utf8(
+        isolate: Arc<Isolate>,
+        base_language_id: LanguageId,
+        text: &[u8],
+    ) -> Option<Self> {
+        Self::parse_str(isolate, base_language_id, std::str::from_utf8(text).ok()?)
+    }
+
+    pub fn parse_text(
+        isolate: Arc<Isolate>,
+        base_language_id: LanguageId,
+        text: SourceText<'_>,
+    ) -> Option<Self> {
+        Self::parse_text_with_options(isolate, base_language_id, text, &ParseOptions::default())
+    }
+
+    /// Parses text, returns `None` if base language could not be parsed (including timing out) or
+    /// parse was cancelled
+    pub fn parse_text_with_options(
+        isolate: Arc<Isolate>,
+        base_language_id: LanguageId,
+        text: SourceText<'_>,
+        options: &ParseOptions,
+    ) -> Option<Self> {
+        let _profile = profiler::call(&isolate, "parse");
+        let _budget = query_budget::exempt();
+        let started = Instant::now();
+        let mut stats = ParseStats::default();
+        let deadline = options.deadline();
+        let mut buffers = isolate.parse_buffers.take();
+        let mut entries = isolate.parse_buffers.take_entries(1);
+        buffers.parse_queue.push(ParseCommand {
             depth: 0,
             language: ParseCommandLanguage::Known(base_language_id),
-            byte_range: 0..text.len() * 2,
+            byte_range: 0..text.byte_len(),
             included_ranges: Vec::new(),
             byte_offset: 0,
             point_offset: ts::Point::default(),
+            entry_id: None,
         });
-        while let Some(parse_command) = parse_queue.pop() {
+        while let Some(parse_command) = buffers.parse_queue.pop() {
+            if options.is_cancelled() {
+                isolate.parse_buffers.put_abandoned(buffers, entries);
+                return None;
+            }
+            if !buffers.parsed_commands.insert(parse_command.key()) {
+                continue;
+            }
             let Some(language_id) = parse_command.language_id() else {
-                entries.push(SyntaxSnapshotEntry::new_unparsed(&parse_command));
+                if let ParseCommandLanguage::Unknown(language) = &parse_command.language {
+                    isolate.notify_unknown_language(
+                        language,
+                        parse_command.byte_range.clone(),
+                        text.unit_size(),
+                    );
+                }
+                entries.push(SyntaxSnapshotEntry::new_unparsed(
+                    &isolate,
+                    &parse_command,
+                    ENTRY_ID_COUNTER.fetch_add(1, Ordering::Relaxed),
+                ));
                 continue;
             };
-            let (ts_language, injections_query) = with_language(language_id, |language| {
+            let language_info = isolate.with_language(language_id, |language| {
+                let parser_info = language.parser_info();
                 (
                     language.ts_language(),
-                    language.parser_info().injections_query.clone(),
+                    parser_info.injections_query.clone(),
+                    parser_info.max_injection_size,
                 )
-            })
-            .ok()?;
-            let mut included_ranges = parse_command.included_ranges.clone();
-            for range in &mut included_ranges {
-                range.start_byte -= parse_command.byte_offset;
-                range.start_point = sub_point(&range.start_point, &parse_command.point_offset);
-                range.end_byte -= parse_command.byte_offset;
-                range.end_point = sub_point(&range.end_point, &parse_command.point_offset);
-            }
-            let tree = with_parser(|parser| {
-                parser.set_language(&ts_language).ok()?;
-                parser.set_included_ranges(&included_ranges).ok()?;
-                let text_slice =
-                    &text[(parse_command.byte_range.start / 2)..(parse_command.byte_range.end / 2)];
-                parser.parse_utf16(text_slice, None)
             });
+            let Ok((ts_language, injections_query, max_injection_size)) = language_info else {
+                isolate.parse_buffers.put_abandoned(buffers, entries);
+                return None;
+            };
+            if parse_command.exceeds_size(max_injection_size) {
+                log(
+                    LogLevel::Debug,
+                    format_args!("injection of language {language_id:?} is too large to parse"),
+                );
+                entries.push(SyntaxSnapshotEntry::new_unparsed(
+                    &isolate,
+                    &parse_command,
+                    ENTRY_ID_COUNTER.fetch_add(1, Ordering::Relaxed),
+                ));
+                continue;
+            }
+            let included_ranges = &mut buffers.included_ranges;
+            included_ranges.clear();
+            included_ranges.extend(parse_command.included_ranges.iter().map(|range| ts::Range {
+                start_byte: range.start_byte - parse_command.byte_offset,
+                start_point: sub_point(&range.start_point, &parse_command.point_offset),
+                end_byte: range.end_byte - parse_command.byte_offset,
+                end_point: sub_point(&range.end_point, &parse_command.point_offset),
+            }));
+            let tree = {
+                let _phase = profiler::phase(
+                    if parse_command.depth == 0 {
+                        "parse.base"
+                    } else {
+                        "parse.injection"
+                    },
+                    Some(language_id),
+                );
+                let parse_started = Instant::now();
+                let tree = isolate
+                    .parsers_pool
+                    .with_parser_counted(&mut stats, language_id, &ts_language, |parser| {
+                        parser.set_included_ranges(included_ranges).ok()?;
+                        options.parse(parser, deadline, |parser| {
+                            text.parse(parser, parse_command.byte_range.clone(), None)
+                        })
+                    })
+                    .flatten();
+                stats.add_language_duration(language_id, parse_started.elapsed());
+                tree
+            };
             let Some(tree) = tree else {
-                entries.push(SyntaxSnapshotEntry::new_unparsed(&parse_command));
+                if options.is_cancelled() {
+                    isolate.parse_buffers.put_abandoned(buffers, entries);
+                    return None;
+                }
+                if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                    log(
+                        LogLevel::Debug,
+                        format_args!("parse of language {language_id:?} timed out"),
+                    );
+                }
+                entries.push(SyntaxSnapshotEntry::new_unparsed(
+                    &isolate,
+                    &parse_command,
+                    ENTRY_ID_COUNTER.fetch_add(1, Ordering::Relaxed),
+                ));
                 continue;
             };
             if let Some(injections_query) = injections_query {
                 let node = tree
                     .root_node_with_offset(parse_command.byte_offset, parse_command.point_offset);
+                let _phase = profiler::phase("injections.query", Some(language_id));
                 let injections = injections_query.collect_injections(
                     node,
                     text,
-                    &[parse_command.byte_range.clone()],
+                    std::slice::from_ref(&parse_command.byte_range),
                 );
-                parse_queue.extend(injections.into_iter().map(|injection| {
-                    ParseCommand::from_injection(injection, parse_command.depth + 1)
-                }));
+                buffers
+                    .parse_queue
+                    .extend(injections.into_iter().map(|injection| {
+                        ParseCommand::from_injection(&isolate, injection, parse_command.depth + 1)
+                    }));
             }
 
             let entry = SyntaxSnapshotEntry {
+                id: ENTRY_ID_COUNTER.fetch_add(1, Ordering::Relaxed),
                 depth: parse_command.depth,
                 content: SyntaxSnapshotEntryContent::Parsed {
                     language: language_id,
@@ -252,6 +1189,7 @@ impl SyntaxSnapshot {
             };
             entries.push(entry);
         }
+        isolate.parse_buffers.put(buffers);
         if !entries.is_empty()
             && matches!(
                 entries.first(),
@@ -261,107 +1199,288 @@ impl SyntaxSnapshot {
                 })
             )
         {
-            Some(SyntaxSnapshot { entries })
+            stats.finish(started, &entries);
+            let snapshot = SyntaxSnapshot {
+                isolate,
+                entries,
+                id: SNAPSHOT_ID_COUNTER.fetch_add(1, Ordering::Relaxed),
+                origin: None,
+                outline: OnceLock::new(),
+                entry_index: OnceLock::new(),
+                line_index: OnceLock::new(),
+                highlight_cache: HighlightCache::default(),
+                locals_cache: LocalsCache::default(),
+                stats,
+            };
+            invariants::check_snapshot(&snapshot, text.byte_len(), "parse");
+            Some(snapshot)
         } else {
+            isolate.parse_buffers.put_entries(entries);
             None
         }
     }
 
-    fn parse_incremental(
+    pub fn parse_incremental(
         text: &[u16],
         old_snapshot: &SyntaxSnapshot,
         edit: ts::InputEdit,
     ) -> Option<(Self, Vec<ts::Range>)> {
+        Self::parse_incremental_text(SourceText::Utf16(text), old_snapshot, edit)
+    }
+
+    pub fn parse_incremental_str(
+        text: &str,
+        old_snapshot: &SyntaxSnapshot,
+        edit: ts::InputEdit,
+    ) -> Option<(Self, Vec<ts::Range>)> {
+        Self::parse_incremental_text(SourceText::Utf8(text), old_snapshot, edit)
+    }
+
+    /// Same as [`Self::parse_utf8`] for incremental parse
+    pub fn parse_incremental_utf8(
+        text: &[u8],
+        old_snapshot: &SyntaxSnapshot,
+        edit: ts::InputEdit,
+    ) -> Option<(Self, Vec<ts::Range>)> {
+        Self::parse_incremental_str(std::str::from_utf8(text).ok()?, old_snapshot, edit)
+    }
+
+    pub fn parse_incremental_text(
+        text: SourceText<'_>,
+        old_snapshot: &SyntaxSnapshot,
+        edit: ts::InputEdit,
+    ) -> Option<(Self, Vec<ts::Range>)> {
+        Self::parse_incremental_text_with_options(
+            text,
+            old_snapshot,
+            edit,
+            &ParseOptions::default(),
+        )
+    }
+
+    pub fn parse_incremental_text_with_options(
+        text: SourceText<'_>,
+        old_snapshot: &SyntaxSnapshot,
+        edit: ts::InputEdit,
+        options: &ParseOptions,
+    ) -> Option<(Self, Vec<ts::Range>)> {
+        let _profile = profiler::call(&old_snapshot.isolate, "parse_incremental");
+        let _budget = query_budget::exempt();
+        let started = Instant::now();
+        let mut stats = ParseStats::default();
+        let deadline = options.deadline();
+        let isolate = Arc::clone(&old_snapshot.isolate);
         let base_language_id = old_snapshot.base_language();
-        let mut entries: Vec<SyntaxSnapshotEntry> = Vec::new();
-        let mut parse_queue: BinaryHeap<ParseCommand> = BinaryHeap::new();
+        let mut buffers = isolate.parse_buffers.take();
+        let mut entries = isolate
+            .parse_buffers
+            .take_entries(old_snapshot.entries.len());
         let mut changed_ranges: Vec<ts::Range> = Vec::new();
+        let mut changed_entry_ids: Vec<u64> = Vec::new();
         changed_ranges.push(ts::Range {
             start_byte: edit.start_byte,
             end_byte: edit.new_end_byte,
             start_point: edit.start_position,
             end_point: edit.new_end_position,
         });
-        parse_queue.push(ParseCommand {
+        // Base entry keeps its id as it always covers the whole text
+        changed_entry_ids.push(old_snapshot.entries[0].id);
+        buffers.parse_queue.push(ParseCommand {
             depth: 0,
             language: ParseCommandLanguage::Known(base_language_id),
-            byte_range: 0..text.len() * 2,
+            byte_range: 0..text.byte_len(),
             included_ranges: Vec::new(),
             byte_offset: 0,
             point_offset: ts::Point::default(),
+            entry_id: None,
         });
-        while let Some(parse_command) = parse_queue.pop() {
+        while let Some(parse_command) = buffers.parse_queue.pop() {
+            if options.is_cancelled() {
+                isolate.parse_buffers.put_abandoned(buffers, entries);
+                return None;
+            }
+            if !buffers.parsed_commands.insert(parse_command.key()) {
+                continue;
+            }
+            let old_entry = matching_old_entry(old_snapshot, &edit, &parse_command);
+            let entry_id = old_entry
+                .map(|(_, old_entry)| old_entry.id)
+                .or(parse_command.entry_id)
+                .unwrap_or_else(|| ENTRY_ID_COUNTER.fetch_add(1, Ordering::Relaxed));
             let Some(language_id) = parse_command.language_id() else {
-                entries.push(SyntaxSnapshotEntry::new_unparsed(&parse_command));
+                if let ParseCommandLanguage::Unknown(language) = &parse_command.language {
+                    isolate.notify_unknown_language(
+                        language,
+                        parse_command.byte_range.clone(),
+                        text.unit_size(),
+                    );
+                }
+                entries.push(SyntaxSnapshotEntry::new_unparsed(
+                    &isolate,
+                    &parse_command,
+                    entry_id,
+                ));
                 continue;
             };
-            let (ts_language, injections_query) = with_language(language_id, |language| {
+            let language_info = isolate.with_language(language_id, |language| {
+                let parser_info = language.parser_info();
                 (
                     language.ts_language(),
-                    language.parser_info().injections_query.clone(),
+                    parser_info.injections_query.clone(),
+                    parser_info.max_injection_size,
                 )
-            })
-            .ok()?;
-            let mut old_tree: Option<ts::Tree> = None;
-            if parse_command.depth == 0 {
-                let old_entry = &old_snapshot.entries[0];
-                if old_entry.byte_range.end >= edit.old_end_byte
-                    && (old_entry.byte_range.end - edit.old_end_byte) + edit.new_end_byte
-                        == parse_command.byte_range.end
-                {
-                    old_tree = if let SyntaxSnapshotEntryContent::Parsed { language, tree } =
-                        &old_entry.content
-                    {
-                        if *language == language_id {
-                            let mut tree = tree.clone();
-                            tree.edit(&edit);
-                            Some(tree)
-                        } else {
-                            None
-                        }
-                    } else {
-                        None
-                    };
-                }
-            }
-            let mut included_ranges = parse_command.included_ranges.clone();
-            for range in &mut included_ranges {
-                range.start_byte -= parse_command.byte_offset;
-                range.start_point = sub_point(&range.start_point, &parse_command.point_offset);
-                range.end_byte -= parse_command.byte_offset;
-                range.end_point = sub_point(&range.end_point, &parse_command.point_offset);
-            }
-            let tree = with_parser(|parser| {
-                parser.set_language(&ts_language).ok()?;
-                parser.set_included_ranges(&included_ranges).ok()?;
-                let text_slice =
-                    &text[(parse_command.byte_range.start / 2)..(parse_command.byte_range.end / 2)];
-                parser.parse_utf16(text_slice, old_tree.as_ref())
             });
+            let Ok((ts_language, injections_query, max_injection_size)) = language_info else {
+                isolate.parse_buffers.put_abandoned(buffers, entries);
+                return None;
+            };
+            if parse_command.exceeds_size(max_injection_size) {
+                log(
+                    LogLevel::Debug,
+                    format_args!("injection of language {language_id:?} is too large to parse"),
+                );
+                entries.push(SyntaxSnapshotEntry::new_unparsed(
+                    &isolate,
+                    &parse_command,
+                    entry_id,
+                ));
+                continue;
+            }
+            let (old_entry_idx, old_tree) =
+                match old_entry.and_then(|(old_entry_idx, old_entry)| {
+                    Some((old_entry_idx, reusable_old_tree(old_entry, &edit)?))
+                }) {
+                    Some((old_entry_idx, old_tree)) => (Some(old_entry_idx), Some(old_tree)),
+                    None => (None, None),
+                };
+            let included_ranges = &mut buffers.included_ranges;
+            included_ranges.clear();
+            included_ranges.extend(parse_command.included_ranges.iter().map(|range| ts::Range {
+                start_byte: range.start_byte - parse_command.byte_offset,
+                start_point: sub_point(&range.start_point, &parse_command.point_offset),
+                end_byte: range.end_byte - parse_command.byte_offset,
+                end_point: sub_point(&range.end_point, &parse_command.point_offset),
+            }));
+            let tree = {
+                let _phase = profiler::phase(
+                    if parse_command.depth == 0 {
+                        "parse.base"
+                    } else {
+                        "parse.injection"
+                    },
+                    Some(language_id),
+                );
+                let parse_started = Instant::now();
+                let tree = isolate
+                    .parsers_pool
+                    .with_parser_counted(&mut stats, language_id, &ts_language, |parser| {
+                        parser.set_included_ranges(included_ranges).ok()?;
+                        options.parse(parser, deadline, |parser| {
+                            text.parse(parser, parse_command.byte_range.clone(), old_tree.as_ref())
+                        })
+                    })
+                    .flatten();
+                stats.add_language_duration(language_id, parse_started.elapsed());
+                tree
+            };
             let Some(tree) = tree else {
-                entries.push(SyntaxSnapshotEntry::new_unparsed(&parse_command));
+                if options.is_cancelled() {
+                    isolate.parse_buffers.put_abandoned(buffers, entries);
+                    return None;
+                }
+                if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                    log(
+                        LogLevel::Debug,
+                        format_args!("parse of language {language_id:?} timed out"),
+                    );
+                }
+                entries.push(SyntaxSnapshotEntry::new_unparsed(
+                    &isolate,
+                    &parse_command,
+                    entry_id,
+                ));
                 continue;
             };
+            // Ranges of the entry where injections have to be looked up again
+            let mut entry_changed_ranges: Vec<Range<usize>> = Vec::new();
+            let first_changed_range = changed_ranges.len();
             if let Some(old_tree) = old_tree {
+                stats.reused_trees += 1;
                 let new_changed_ranges = old_tree.changed_ranges(&tree);
-                changed_ranges.extend(new_changed_ranges);
+                changed_ranges.extend(new_changed_ranges.map(|range| ts::Range {
+                    start_byte: range.start_byte + parse_command.byte_offset,
+                    start_point: add_point(&range.start_point, &parse_command.point_offset),
+                    end_byte: range.end_byte + parse_command.byte_offset,
+                    end_point: add_point(&range.end_point, &parse_command.point_offset),
+                }));
+                entry_changed_ranges.extend(
+                    changed_ranges[first_changed_range..]
+                        .iter()
+                        .map(|range| range.start_byte..range.end_byte),
+                );
+                let edit_range = edit.start_byte..edit.new_end_byte;
+                if edit_range.start <= parse_command.byte_range.end
+                    && parse_command.byte_range.start <= edit_range.end
+                {
+                    entry_changed_ranges.push(edit_range);
+                }
+            } else if parse_command.included_ranges.is_empty() {
+                changed_ranges.push(ts::Range {
+                    start_byte: 0,
+                    end_byte: text.byte_len(),
+                    start_point: ts::Point::default(),
+                    end_point: text.point_for_byte(text.byte_len()),
+                });
             } else {
-                changed_ranges.extend(included_ranges);
+                changed_ranges.extend_from_slice(&parse_command.included_ranges);
+            }
+            if changed_ranges.len() > first_changed_range {
+                changed_entry_ids.push(entry_id);
             }
             if let Some(injections_query) = injections_query {
                 let node = tree
                     .root_node_with_offset(parse_command.byte_offset, parse_command.point_offset);
+                let _phase = profiler::phase("injections.query", Some(language_id));
+                let carried = old_entry_idx.and_then(|old_entry_idx| {
+                    carried_injections(
+                        old_snapshot,
+                        old_entry_idx,
+                        &edit,
+                        &entry_changed_ranges,
+                        text.unit_size(),
+                    )
+                });
                 let injections = injections_query.collect_injections(
                     node,
                     text,
-                    &[parse_command.byte_range.clone()],
+                    if carried.is_some() {
+                        &entry_changed_ranges
+                    } else {
+                        std::slice::from_ref(&parse_command.byte_range)
+                    },
                 );
-                parse_queue.extend(injections.into_iter().map(|injection| {
-                    ParseCommand::from_injection(injection, parse_command.depth + 1)
-                }));
+                let rediscovered: Vec<Range<usize>> = injections
+                    .iter()
+                    .map(|injection| injection.enclosing_byte_range.clone())
+                    .collect();
+                buffers
+                    .parse_queue
+                    .extend(injections.into_iter().map(|injection| {
+                        ParseCommand::from_injection(&isolate, injection, parse_command.depth + 1)
+                    }));
+                // Injections found again replace the carried ones at the same place
+                buffers
+                    .parse_queue
+                    .extend(carried.into_iter().flatten().filter(|command| {
+                        !rediscovered.iter().any(|range| {
+                            range.start < command.byte_range.end
+                                && command.byte_range.start < range.end
+                        })
+                    }));
             }
 
             let entry = SyntaxSnapshotEntry {
+                id: entry_id,
                 depth: parse_command.depth,
                 content: SyntaxSnapshotEntryContent::Parsed {
                     language: language_id,
@@ -373,6 +1492,7 @@ impl SyntaxSnapshot {
             };
             entries.push(entry);
         }
+        isolate.parse_buffers.put(buffers);
         if !entries.is_empty()
             && matches!(
                 entries.first(),
@@ -382,13 +1502,107 @@ impl SyntaxSnapshot {
                 })
             )
         {
-            Some((SyntaxSnapshot { entries }, changed_ranges))
+            stats.finish(started, &entries);
+            normalize_ranges(&mut changed_ranges, text);
+            changed_entry_ids.sort_unstable();
+            changed_entry_ids.dedup();
+            let changed_from = changed_ranges
+                .iter()
+                .map(|range| range.start_byte)
+                .min()
+                .unwrap_or(edit.start_byte);
+            let locals_cache = old_snapshot.locals_cache.carry(&entries, changed_from);
+            let origin = SnapshotOrigin {
+                snapshot_id: old_snapshot.id,
+                edit,
+                changed_ranges: changed_ranges.clone().into(),
+                changed_entry_ids: changed_entry_ids.into(),
+            };
+            let snapshot = SyntaxSnapshot {
+                isolate,
+                entries,
+                id: SNAPSHOT_ID_COUNTER.fetch_add(1, Ordering::Relaxed),
+                origin: Some(origin),
+                outline: OnceLock::new(),
+                entry_index: OnceLock::new(),
+                line_index: OnceLock::new(),
+                highlight_cache: HighlightCache::default(),
+                locals_cache,
+                stats,
+            };
+            invariants::check_snapshot(&snapshot, text.byte_len(), "incremental parse");
+            invariants::check_ranges(
+                &snapshot.isolate,
+                &changed_ranges,
+                text.byte_len(),
+                "incremental parse",
+            );
+            Some((snapshot, changed_ranges))
         } else {
+            isolate.parse_buffers.put_entries(entries);
             None
         }
     }
 }
 
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SnapshotNodesIterState {
+    Start,
+    Descend,
+    Skip,
+    Done,
+}
+
+/// Depth-first traversal of the combined snapshot tree, yields nodes with their layer language
+/// and depth in the combined tree
+pub struct SnapshotNodesIter<'snapshot> {
+    cursor: SyntaxSnapshotTreeCursor<'snapshot>,
+    byte_range: Range<usize>,
+    depth: usize,
+    state: SnapshotNodesIterState,
+}
+
+impl<'snapshot> Iterator for SnapshotNodesIter<'snapshot> {
+    type Item = (LanguageId, ts::Node<'snapshot>, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.state {
+                SnapshotNodesIterState::Start => {}
+                SnapshotNodesIterState::Done => return None,
+                SnapshotNodesIterState::Descend if self.cursor.goto_first_child() => {
+                    self.depth += 1;
+                }
+                SnapshotNodesIterState::Descend | SnapshotNodesIterState::Skip => {
+                    while !self.cursor.goto_next_sibling() {
+                        if !self.cursor.goto_parent() {
+                            self.state = SnapshotNodesIterState::Done;
+                            return None;
+                        }
+                        self.depth -= 1;
+                    }
+                }
+            }
+            let node = self.cursor.node();
+            let range = &self.byte_range;
+            if node.start_byte() > range.end
+                || (node.start_byte() == range.end && !range.is_empty())
+            {
+                self.state = SnapshotNodesIterState::Done;
+                return None;
+            }
+            if node.end_byte() < range.start
+                || (node.end_byte() == range.start && node.start_byte() < node.end_byte())
+            {
+                self.state = SnapshotNodesIterState::Skip;
+                continue;
+            }
+            self.state = SnapshotNodesIterState::Descend;
+            return Some((self.cursor.language(), node, self.depth));
+        }
+    }
+}
+
 pub struct SyntaxSnapshotTreeCursor<'cursor> {
     snapshot: &'cursor SyntaxSnapshot,
     entry_stack: Vec<(usize, ts::TreeCursor<'cursor>)>,
@@ -429,12 +1643,10 @@ impl<'cursor> SyntaxSnapshotTreeCursor<'cursor> {
             return Some(child);
         } else {
             let node_range = cursor.node().byte_range();
-            let candidate_entry = self.snapshot.entries.iter().enumerate().find(|(_, e)| {
-                e.depth == entry.depth + 1
-                    && e.byte_range.start >= node_range.start
-                    && e.byte_range.end <= node_range.end
-                    && index < entry.byte_range.end
-            });
+            let candidate_entry = self
+                .snapshot
+                .entries_at_depth_within(entry.depth + 1, node_range)
+                .next();
             if let Some((idx, entry)) = candidate_entry {
                 if let SyntaxSnapshotEntryContent::Parsed { language: _, tree } = &entry.content {
                     let new_root =
@@ -448,6 +1660,38 @@ impl<'cursor> SyntaxSnapshotTreeCursor<'cursor> {
         None
     }
 
+    /// Same as [`Self::goto_first_child_for_byte`] for a point, injected entries are entered
+    /// using their point offsets
+    pub fn goto_first_child_for_point(&mut self, point: ts::Point) -> Option<usize> {
+        let (entry_idx, cursor) = self.entry_stack.last_mut().expect("stack is never empty");
+        let entry = &self.snapshot.entries[*entry_idx];
+        let SyntaxSnapshotEntryContent::Parsed { tree, .. } = &entry.content else {
+            unreachable!("unparsed entries do not appear on stack")
+        };
+        let root = tree.root_node_with_offset(entry.byte_offset, entry.point_offset);
+        if point < root.start_position() || point >= root.end_position() {
+            return None;
+        }
+        if let Some(child) = cursor.goto_first_child_for_point(point) {
+            return Some(child);
+        }
+        let node_range = cursor.node().byte_range();
+        let candidate_root = self
+            .snapshot
+            .entries_at_depth_within(entry.depth + 1, node_range)
+            .find_map(|(idx, e)| {
+                let SyntaxSnapshotEntryContent::Parsed { tree, .. } = &e.content else {
+                    return None;
+                };
+                let root = tree.root_node_with_offset(e.byte_offset, e.point_offset);
+                (root.start_position() <= point && point < root.end_position())
+                    .then_some((idx, root))
+            });
+        let (idx, root) = candidate_root?;
+        self.entry_stack.push((idx, root.walk()));
+        Some(0)
+    }
+
     pub fn goto_first_child(&mut self) -> bool {
         let (entry_idx, cursor) = self.entry_stack.last_mut().expect("stack is never empty");
         if cursor.goto_first_child() {
@@ -455,11 +1699,10 @@ impl<'cursor> SyntaxSnapshotTreeCursor<'cursor> {
         }
         let node_range = cursor.node().byte_range();
         let entry = &self.snapshot.entries[*entry_idx];
-        let candidate_entry = self.snapshot.entries.iter().enumerate().find(|(_, e)| {
-            e.depth == entry.depth + 1
-                && e.byte_range.start >= node_range.start
-                && e.byte_range.end <= node_range.end
-        });
+        let candidate_entry = self
+            .snapshot
+            .entries_at_depth_within(entry.depth + 1, node_range)
+            .next();
         if let Some((idx, entry)) = candidate_entry {
             if let SyntaxSnapshotEntryContent::Parsed { language: _, tree } = &entry.content {
                 let new_root = tree.root_node_with_offset(entry.byte_offset, entry.point_offset);
@@ -473,12 +1716,55 @@ impl<'cursor> SyntaxSnapshotTreeCursor<'cursor> {
 
     pub fn goto_previous_sibling(&mut self) -> bool {
         let (_entry_idx, cursor) = self.entry_stack.last_mut().expect("stack is never empty");
-        cursor.goto_previous_sibling()
+        cursor.goto_previous_sibling() || self.goto_sibling_entry(false)
     }
 
     pub fn goto_next_sibling(&mut self) -> bool {
         let (_entry_idx, cursor) = self.entry_stack.last_mut().expect("stack is never empty");
-        cursor.goto_next_sibling()
+        cursor.goto_next_sibling() || self.goto_sibling_entry(true)
+    }
+
+    /// Moves from the root of an injected tree to the root of the neighbour entry injected into
+    /// the same host node
+    fn goto_sibling_entry(&mut self, forward: bool) -> bool {
+        let [.., (_, host_cursor), (entry_idx, cursor)] = self.entry_stack.as_slice() else {
+            return false;
+        };
+        if cursor.depth() > 0 {
+            return false;
+        }
+        let host_range = host_cursor.node().byte_range();
+        let entry = &self.snapshot.entries[*entry_idx];
+        let siblings = self
+            .snapshot
+            .entries_at_depth_within(entry.depth, host_range)
+            .filter(|(_, e)| matches!(e.content, SyntaxSnapshotEntryContent::Parsed { .. }));
+        let sibling = if forward {
+            siblings
+                .filter(|(_, e)| e.byte_range.start >= entry.byte_range.end)
+                .min_by_key(|(_, e)| e.byte_range.start)
+        } else {
+            siblings
+                .filter(|(_, e)| e.byte_range.end <= entry.byte_range.start)
+                .max_by_key(|(_, e)| e.byte_range.start)
+        };
+        let Some((
+            idx,
+            SyntaxSnapshotEntry {
+                content: SyntaxSnapshotEntryContent::Parsed { tree, .. },
+                byte_offset,
+                point_offset,
+                ..
+            },
+        )) = sibling
+        else {
+            return false;
+        };
+        let tree_cursor = tree
+            .root_node_with_offset(*byte_offset, *point_offset)
+            .walk();
+        *self.entry_stack.last_mut().expect("stack is never empty") = (idx, tree_cursor);
+        true
     }
 
     pub fn goto_parent(&mut self) -> bool {
@@ -493,3 +1779,72 @@ impl<'cursor> SyntaxSnapshotTreeCursor<'cursor> {
         false
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::json_isolate;
+
+    fn entries(byte_ranges: &[Range<usize>]) -> Vec<SyntaxSnapshotEntry> {
+        byte_ranges
+            .iter()
+            .enumerate()
+            .map(|(id, byte_range)| SyntaxSnapshotEntry {
+                id: id as u64,
+                depth: 0,
+                content: SyntaxSnapshotEntryContent::Unparsed(UnknownLanguage::LanguageName(
+                    "test".into(),
+                )),
+                byte_range: byte_range.clone(),
+                byte_offset: byte_range.start,
+                point_offset: ts::Point::default(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sorted_entries_intersecting() {
+        let entries = entries(&[40..50, 0..100, 15..18, 30..40, 10..20]);
+        let sorted = SortedEntries::new(&entries, (0..entries.len()).collect());
+        let intersecting = |byte_range, inclusive| -> Vec<usize> {
+            sorted
+                .intersecting(&entries, byte_range, inclusive)
+                .collect()
+        };
+        assert_eq!(intersecting(18..30, false), vec![1, 4]);
+        assert_eq!(intersecting(18..30, true), vec![1, 4, 2, 3]);
+        assert_eq!(intersecting(100..120, false), Vec::<usize>::new());
+        assert_eq!(intersecting(100..120, true), vec![1]);
+        assert_eq!(intersecting(45..46, false), vec![1, 0]);
+    }
+
+    #[test]
+    fn sorted_entries_within() {
+        let entries = entries(&[40..50, 0..100, 15..18, 30..40, 10..20]);
+        let sorted = SortedEntries::new(&entries, (0..entries.len()).collect());
+        let within = |byte_range| -> Vec<usize> { sorted.within(&entries, byte_range).collect() };
+        assert_eq!(within(10..40), vec![4, 2, 3]);
+        assert_eq!(within(0..100), vec![1, 4, 2, 3, 0]);
+        assert_eq!(within(19..29), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn given_up_parse_returns_buffers_to_the_pool() {
+        let (isolate, language_id) = json_isolate();
+        let cancellation = ParseCancellation::default();
+        cancellation.cancel();
+        let options = ParseOptions {
+            cancellation: Some(cancellation),
+            timeout: None,
+        };
+        let snapshot = SyntaxSnapshot::parse_text_with_options(
+            Arc::clone(&isolate),
+            language_id,
+            SourceText::Utf8("[1]"),
+            &options,
+        );
+        assert!(snapshot.is_none());
+        assert_eq!(isolate.parse_buffers.buffers.lock().unwrap().len(), 1);
+        assert_eq!(isolate.parse_buffers.entries.lock().unwrap().len(), 1);
+    }
+}
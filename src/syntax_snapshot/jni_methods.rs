@@ -1,19 +1,28 @@
+use std::time::Duration;
+
 use once_cell::sync::OnceCell as JOnceLock;
 
 use jni::{
     errors::{Error as JNIError, Result as JNIResult},
-    objects::{AutoLocal, JCharArray, JClass, JFieldID, JMethodID, JObject, JValue},
+    objects::{
+        AutoLocal, JCharArray, JClass, JFieldID, JLongArray, JMethodID, JObject, JObjectArray,
+        JValue,
+    },
     signature::{Primitive, ReturnType},
+    sys::{jboolean, jint, jlong, jsize},
     JNIEnv,
 };
 
 use crate::{
-    jni_utils::{throw_exception_from_result, PointDesc, RangeDesc},
-    language_registry::LanguageId,
+    isolate::{Isolate, IsolateId},
+    jni_utils::{throw_exception_from_result, with_java_text, JavaText, PointDesc, RangeDesc},
+    language_registry::{LanguageId, UnknownLanguage},
     syntax_snapshot::SyntaxSnapshotTreeCursor,
 };
 
-use super::SyntaxSnapshot;
+use super::{
+    InjectionLanguage, InjectionRange, ParseCancellation, ParseOptions, ParseStats, SyntaxSnapshot,
+};
 
 struct SyntaxSnapshotDescInner {
     constructor: JMethodID,
@@ -45,6 +54,12 @@ impl<'local> SyntaxSnapshotDesc<'local> {
         })
     }
 
+    pub fn new(env: &mut JNIEnv<'local>) -> JNIResult<SyntaxSnapshotDesc<'local>> {
+        let class =
+            env.find_class("com/hulylabs/treesitter/rusty/TreeSitterNativeSyntaxSnapshot")?;
+        SyntaxSnapshotDesc::from_class(env, class)
+    }
+
     fn from_obj_class(
         env: &mut JNIEnv<'local>,
         obj: &JObject<'local>,
@@ -106,30 +121,101 @@ impl<'local> SyntaxSnapshotDesc<'local> {
     }
 }
 
+fn parse<'local>(
+    env: &mut JNIEnv<'local>,
+    class: JClass<'local>,
+    isolate_id: IsolateId,
+    text: JavaText<'local>,
+    base_language_id: LanguageId,
+    options: &ParseOptions,
+) -> JNIResult<JObject<'local>> {
+    let Ok(isolate) = Isolate::get(isolate_id) else {
+        env.throw_new("java/lang/IllegalStateException", "unknown isolate")?;
+        return Ok(JObject::null());
+    };
+    let snapshot = with_java_text(env, &text, |_, text| {
+        Ok(SyntaxSnapshot::parse_text_with_options(
+            isolate,
+            base_language_id,
+            text,
+            options,
+        ))
+    })?;
+    let Some(snapshot) = snapshot else {
+        return Ok(JObject::null());
+    };
+    SyntaxSnapshotDesc::from_class(env, class)?.to_java_object(env, base_language_id, snapshot)
+}
+
 #[no_mangle]
 pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeSyntaxSnapshot_nativeParse<
     'local,
 >(
     mut env: JNIEnv<'local>,
     class: JClass<'local>,
+    isolate_id: IsolateId,
     text: JCharArray<'local>,
     base_language_id: LanguageId,
 ) -> JObject<'local> {
-    fn inner<'local>(
-        env: &mut JNIEnv<'local>,
-        class: JClass<'local>,
-        text: JCharArray<'local>,
-        base_language_id: LanguageId,
-    ) -> JNIResult<JObject<'local>> {
-        let text_length = env.get_array_length(&text)? as usize;
-        let mut text_buffer = vec![0u16; text_length];
-        env.get_char_array_region(&text, 0, &mut text_buffer)?;
-        let Some(snapshot) = SyntaxSnapshot::parse(base_language_id, &text_buffer) else {
-            return Ok(JObject::null());
-        };
-        SyntaxSnapshotDesc::from_class(env, class)?.to_java_object(env, base_language_id, snapshot)
-    }
-    let result = inner(&mut env, class, text, base_language_id);
+    let result = parse(
+        &mut env,
+        class,
+        isolate_id,
+        JavaText::Array(text),
+        base_language_id,
+        &ParseOptions::default(),
+    );
+    throw_exception_from_result(&mut env, result)
+}
+
+/// Same as `nativeParse`, returns null if parse was cancelled through `cancellation_handle` or
+/// base language could not be parsed within `timeout_micros`
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeSyntaxSnapshot_nativeParseCancellable<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    class: JClass<'local>,
+    isolate_id: IsolateId,
+    text: JCharArray<'local>,
+    base_language_id: LanguageId,
+    cancellation_handle: jlong,
+    timeout_micros: jlong,
+) -> JObject<'local> {
+    let options = parse_options(cancellation_handle, timeout_micros);
+    let result = parse(
+        &mut env,
+        class,
+        isolate_id,
+        JavaText::Array(text),
+        base_language_id,
+        &options,
+    );
+    throw_exception_from_result(&mut env, result)
+}
+
+/// Same as `nativeParseCancellable`, text is passed as `char[][]` chunks
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeSyntaxSnapshot_nativeParseChunked<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    class: JClass<'local>,
+    isolate_id: IsolateId,
+    text_chunks: JObjectArray<'local>,
+    base_language_id: LanguageId,
+    cancellation_handle: jlong,
+    timeout_micros: jlong,
+) -> JObject<'local> {
+    let options = parse_options(cancellation_handle, timeout_micros);
+    let result = parse(
+        &mut env,
+        class,
+        isolate_id,
+        JavaText::Chunks(text_chunks),
+        base_language_id,
+        &options,
+    );
     throw_exception_from_result(&mut env, result)
 }
 
@@ -178,53 +264,207 @@ impl<'local> PairDesc<'local> {
     }
 }
 
+fn parse_with_old<'local>(
+    env: &mut JNIEnv<'local>,
+    class: JClass<'local>,
+    text: JavaText<'local>,
+    old_snapshot: JObject<'local>,
+    edit: JObject<'local>,
+    options: &ParseOptions,
+) -> JNIResult<JObject<'local>> {
+    let desc = SyntaxSnapshotDesc::from_class(env, class)?;
+    let old_snapshot = desc.ref_from_java_object_impl(env, old_snapshot)?;
+    let edit = InputEditMethods::from_java_object(env, &edit)?;
+    let result = with_java_text(env, &text, |_, text| {
+        Ok(SyntaxSnapshot::parse_incremental_text_with_options(
+            text,
+            old_snapshot,
+            edit,
+            options,
+        ))
+    })?;
+    let Some((snapshot, changed_ranges)) = result else {
+        return Ok(JObject::null());
+    };
+    snapshot_with_ranges_to_java_object(env, &desc, snapshot, changed_ranges)
+}
+
+/// Pair of the snapshot and array of the ranges
+fn snapshot_with_ranges_to_java_object<'local>(
+    env: &mut JNIEnv<'local>,
+    desc: &SyntaxSnapshotDesc<'local>,
+    snapshot: SyntaxSnapshot,
+    changed_ranges: Vec<tree_sitter::Range>,
+) -> JNIResult<JObject<'local>> {
+    let range_desc = RangeDesc::new(env)?;
+    let array = env.new_object_array(
+        changed_ranges.len() as i32,
+        &range_desc.class,
+        JObject::null(),
+    )?;
+    for (idx, range) in changed_ranges.into_iter().enumerate() {
+        let range_obj = range_desc.to_java_object(env, range)?;
+        let range_obj = env.auto_local(range_obj);
+        env.set_object_array_element(&array, idx as i32, &range_obj)?;
+    }
+    let pair_desc = PairDesc::new(env)?;
+    let snapshot = desc.to_java_object(env, snapshot.base_language(), snapshot)?;
+    pair_desc.to_java_object(env, (snapshot, array.into()))
+}
+
+/// Returns null if there are no unparsed entries with registered languages, otherwise pair of the
+/// new snapshot and ranges to rehighlight, same as `nativeParseWithOld`
 #[no_mangle]
-pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeSyntaxSnapshot_nativeParseWithOld<
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeSyntaxSnapshot_nativeResolveUnparsedLanguages<
     'local,
 >(
     mut env: JNIEnv<'local>,
     class: JClass<'local>,
+    snapshot: JObject<'local>,
     text: JCharArray<'local>,
-    old_snapshot: JObject<'local>,
-    edit: JObject<'local>,
 ) -> JObject<'local> {
     fn inner<'local>(
         env: &mut JNIEnv<'local>,
         class: JClass<'local>,
+        snapshot: JObject<'local>,
         text: JCharArray<'local>,
-        old_snapshot: JObject<'local>,
-        edit: JObject<'local>,
     ) -> JNIResult<JObject<'local>> {
         let desc = SyntaxSnapshotDesc::from_class(env, class)?;
-        let old_snapshot = desc.ref_from_java_object_impl(env, old_snapshot)?;
-        let text_length = env.get_array_length(&text)? as usize;
-        let mut text_buffer = vec![0u16; text_length];
-        env.get_char_array_region(&text, 0, &mut text_buffer)?;
-        let edit = InputEditMethods::from_java_object(env, &edit)?;
-        let Some((snapshot, changed_ranges)) =
-            SyntaxSnapshot::parse_incremental(&text_buffer, old_snapshot, edit)
-        else {
+        let snapshot = desc.ref_from_java_object_impl(env, snapshot)?;
+        let result = with_java_text(env, &JavaText::Array(text), |_, text| {
+            Ok(snapshot.resolve_unparsed_languages(text))
+        })?;
+        let Some((snapshot, changed_ranges)) = result else {
             return Ok(JObject::null());
         };
-        let range_desc = RangeDesc::new(env)?;
-        let array = env.new_object_array(
-            changed_ranges.len() as i32,
-            &range_desc.class,
-            JObject::null(),
-        )?;
-        for (idx, range) in changed_ranges.into_iter().enumerate() {
-            let range_obj = range_desc.to_java_object(env, range)?;
-            let range_obj = env.auto_local(range_obj);
-            env.set_object_array_element(&array, idx as i32, &range_obj)?;
-        }
-        let pair_desc = PairDesc::new(env)?;
-        let snapshot = desc.to_java_object(env, snapshot.base_language(), snapshot)?;
-        pair_desc.to_java_object(env, (snapshot, array.into()))
+        snapshot_with_ranges_to_java_object(env, &desc, snapshot, changed_ranges)
     }
-    let result = inner(&mut env, class, text, old_snapshot, edit);
+    let result = inner(&mut env, class, snapshot, text);
     throw_exception_from_result(&mut env, result)
 }
 
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeSyntaxSnapshot_nativeParseWithOld<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    class: JClass<'local>,
+    text: JCharArray<'local>,
+    old_snapshot: JObject<'local>,
+    edit: JObject<'local>,
+) -> JObject<'local> {
+    let result = parse_with_old(
+        &mut env,
+        class,
+        JavaText::Array(text),
+        old_snapshot,
+        edit,
+        &ParseOptions::default(),
+    );
+    throw_exception_from_result(&mut env, result)
+}
+
+/// Same as `nativeParseWithOld`, returns null if parse was cancelled through `cancellation_handle`
+/// or base language could not be parsed within `timeout_micros`
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeSyntaxSnapshot_nativeParseWithOldCancellable<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    class: JClass<'local>,
+    text: JCharArray<'local>,
+    old_snapshot: JObject<'local>,
+    edit: JObject<'local>,
+    cancellation_handle: jlong,
+    timeout_micros: jlong,
+) -> JObject<'local> {
+    let options = parse_options(cancellation_handle, timeout_micros);
+    let result = parse_with_old(
+        &mut env,
+        class,
+        JavaText::Array(text),
+        old_snapshot,
+        edit,
+        &options,
+    );
+    throw_exception_from_result(&mut env, result)
+}
+
+/// Same as `nativeParseWithOldCancellable`, text is passed as `char[][]` chunks
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeSyntaxSnapshot_nativeParseWithOldChunked<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    class: JClass<'local>,
+    text_chunks: JObjectArray<'local>,
+    old_snapshot: JObject<'local>,
+    edit: JObject<'local>,
+    cancellation_handle: jlong,
+    timeout_micros: jlong,
+) -> JObject<'local> {
+    let options = parse_options(cancellation_handle, timeout_micros);
+    let result = parse_with_old(
+        &mut env,
+        class,
+        JavaText::Chunks(text_chunks),
+        old_snapshot,
+        edit,
+        &options,
+    );
+    throw_exception_from_result(&mut env, result)
+}
+
+/// Handle 0 means parse can't be cancelled, non-positive timeout means no timeout
+fn parse_options(cancellation_handle: jlong, timeout_micros: jlong) -> ParseOptions {
+    let cancellation = cancellation_handle as *const ParseCancellation;
+    // SAFETY: non-null handle is created by nativeCreate and destroyed only after parses using it
+    let cancellation = unsafe { cancellation.as_ref() }.cloned();
+    ParseOptions {
+        cancellation,
+        timeout: (timeout_micros > 0).then(|| Duration::from_micros(timeout_micros as u64)),
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeParseCancellation_nativeCreate<
+    'local,
+>(
+    _env: JNIEnv<'local>,
+    _class: JClass<'local>,
+) -> jlong {
+    Box::into_raw(Box::new(ParseCancellation::default())) as jlong
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeParseCancellation_nativeCancel<
+    'local,
+>(
+    _env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+) {
+    let cancellation = handle as *const ParseCancellation;
+    // SAFETY: handle is created by nativeCreate and not destroyed yet
+    if let Some(cancellation) = unsafe { cancellation.as_ref() } {
+        cancellation.cancel();
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeParseCancellation_nativeDestroy<
+    'local,
+>(
+    _env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+) {
+    let ptr = handle as *mut ParseCancellation;
+    // SAFETY: handle is created from Box::into_raw, called by java when no other reference to it
+    // exists; parses keep their own reference to the flag
+    std::mem::drop(unsafe { Box::from_raw(ptr) });
+}
+
 #[no_mangle]
 pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeSyntaxSnapshot_nativeDestroy<
     'local,
@@ -336,7 +576,7 @@ pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeSyntax
         let snapshot = SyntaxSnapshotDesc::from_java_object(env, snapshot)?;
         let mut cursor = SyntaxSnapshotTreeCursor::walk(snapshot);
         let byte_offset = (offset as usize) * 2;
-        while let Some(_) = cursor.goto_first_child_for_byte(byte_offset) {}
+        while cursor.goto_first_child_for_byte(byte_offset).is_some() {}
 
         while cursor.node().start_byte() > byte_offset {
             if !cursor.goto_previous_sibling() {
@@ -356,3 +596,375 @@ pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeSyntax
     let result = inner(&mut env, snapshot, offset);
     throw_exception_from_result(&mut env, result)
 }
+
+static INJECTION_RANGE_CONSTRUCTOR: JOnceLock<JMethodID> = JOnceLock::new();
+
+struct InjectionRangeDesc<'local> {
+    constructor: JMethodID,
+    class: AutoLocal<'local, JClass<'local>>,
+}
+
+impl<'local> InjectionRangeDesc<'local> {
+    fn new(env: &mut JNIEnv<'local>) -> JNIResult<InjectionRangeDesc<'local>> {
+        let class = env.find_class(
+            "com/hulylabs/treesitter/rusty/TreeSitterNativeSyntaxSnapshot$InjectionRange",
+        )?;
+        let constructor = *INJECTION_RANGE_CONSTRUCTOR.get_or_try_init(|| {
+            env.get_method_id(
+                &class,
+                "<init>",
+                "(JIJLjava/lang/String;Ljava/lang/String;[I)V",
+            )
+        })?;
+        Ok(InjectionRangeDesc {
+            constructor,
+            class: env.auto_local(class),
+        })
+    }
+
+    /// Unparsed entries get unknown language id along with the name or mimetype they were
+    /// injected with, ranges are passed as start/end offset pairs
+    fn to_java_object(
+        &self,
+        env: &mut JNIEnv<'local>,
+        injection_range: &InjectionRange,
+    ) -> JNIResult<JObject<'local>> {
+        let (language_id, name, mimetype) = match &injection_range.language {
+            InjectionLanguage::Parsed(language_id) => (*language_id, None, None),
+            InjectionLanguage::Unparsed(UnknownLanguage::LanguageName(name)) => {
+                (LanguageId::UNKNOWN, Some(name), None)
+            }
+            InjectionLanguage::Unparsed(UnknownLanguage::LanguageMimetype(mimetype)) => {
+                (LanguageId::UNKNOWN, None, Some(mimetype))
+            }
+        };
+        let name = match name {
+            Some(name) => env.new_string(name)?.into(),
+            None => JObject::null(),
+        };
+        let name = env.auto_local(name);
+        let mimetype = match mimetype {
+            Some(mimetype) => env.new_string(mimetype)?.into(),
+            None => JObject::null(),
+        };
+        let mimetype = env.auto_local(mimetype);
+        let offsets: Vec<jint> = injection_range
+            .byte_ranges
+            .iter()
+            .flat_map(|range| [(range.start / 2) as jint, (range.end / 2) as jint])
+            .collect();
+        let ranges = env.new_int_array(offsets.len() as jsize)?;
+        env.set_int_array_region(&ranges, 0, &offsets)?;
+        let ranges = env.auto_local(ranges);
+        // SAFETY: constructor is valid and derived from class by construction of self
+        unsafe {
+            env.new_object_unchecked(
+                &self.class,
+                self.constructor,
+                &[
+                    JValue::Long(injection_range.id as jlong).as_jni(),
+                    JValue::Int(injection_range.depth as jint).as_jni(),
+                    JValue::from(language_id).as_jni(),
+                    JValue::Object(&name).as_jni(),
+                    JValue::Object(&mimetype).as_jni(),
+                    JValue::Object(&ranges).as_jni(),
+                ],
+            )
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeSyntaxSnapshot_nativeGetInjectionRanges<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    snapshot: JObject<'local>,
+) -> JObjectArray<'local> {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        snapshot: JObject<'local>,
+    ) -> JNIResult<JObjectArray<'local>> {
+        let snapshot = SyntaxSnapshotDesc::from_java_object(env, snapshot)?;
+        let injection_ranges = snapshot.injection_ranges();
+        let desc = InjectionRangeDesc::new(env)?;
+        let array = env.new_object_array(
+            injection_ranges.len() as jsize,
+            &desc.class,
+            JObject::null(),
+        )?;
+        for (index, injection_range) in injection_ranges.iter().enumerate() {
+            let obj = desc.to_java_object(env, injection_range)?;
+            let obj = env.auto_local(obj);
+            env.set_object_array_element(&array, index as jsize, obj)?;
+        }
+        Ok(array)
+    }
+    let result = inner(&mut env, snapshot);
+    throw_exception_from_result(&mut env, result)
+}
+
+static PARSE_STATS_CONSTRUCTOR: JOnceLock<JMethodID> = JOnceLock::new();
+
+struct ParseStatsDesc<'local> {
+    constructor: JMethodID,
+    class: AutoLocal<'local, JClass<'local>>,
+}
+
+impl<'local> ParseStatsDesc<'local> {
+    fn new(env: &mut JNIEnv<'local>) -> JNIResult<ParseStatsDesc<'local>> {
+        let class = env.find_class(
+            "com/hulylabs/treesitter/rusty/TreeSitterNativeSyntaxSnapshot$ParseStats",
+        )?;
+        let constructor = *PARSE_STATS_CONSTRUCTOR
+            .get_or_try_init(|| env.get_method_id(&class, "<init>", "(J[J[JI[IIII)V"))?;
+        Ok(ParseStatsDesc {
+            constructor,
+            class: env.auto_local(class),
+        })
+    }
+
+    /// Durations are passed in nanoseconds, per language durations as parallel arrays of
+    /// language ids and durations
+    fn to_java_object(
+        &self,
+        env: &mut JNIEnv<'local>,
+        stats: &ParseStats,
+    ) -> JNIResult<JObject<'local>> {
+        let (language_ids, language_durations): (Vec<jlong>, Vec<jlong>) = stats
+            .language_durations
+            .iter()
+            .map(|(language_id, duration)| (i64::from(*language_id), duration.as_nanos() as jlong))
+            .unzip();
+        let language_ids_array = env.new_long_array(language_ids.len() as jsize)?;
+        env.set_long_array_region(&language_ids_array, 0, &language_ids)?;
+        let language_ids_array = env.auto_local(language_ids_array);
+        let language_durations_array = env.new_long_array(language_durations.len() as jsize)?;
+        env.set_long_array_region(&language_durations_array, 0, &language_durations)?;
+        let language_durations_array = env.auto_local(language_durations_array);
+        let depth_histogram: Vec<jint> = stats
+            .depth_histogram
+            .iter()
+            .map(|count| *count as jint)
+            .collect();
+        let depth_histogram_array = env.new_int_array(depth_histogram.len() as jsize)?;
+        env.set_int_array_region(&depth_histogram_array, 0, &depth_histogram)?;
+        let depth_histogram_array = env.auto_local(depth_histogram_array);
+        // SAFETY: constructor is valid and derived from class by construction of self
+        unsafe {
+            env.new_object_unchecked(
+                &self.class,
+                self.constructor,
+                &[
+                    JValue::Long(stats.duration.as_nanos() as jlong).as_jni(),
+                    JValue::Object(&language_ids_array).as_jni(),
+                    JValue::Object(&language_durations_array).as_jni(),
+                    JValue::Int(stats.entry_count as jint).as_jni(),
+                    JValue::Object(&depth_histogram_array).as_jni(),
+                    JValue::Int(stats.parser_pool_hits as jint).as_jni(),
+                    JValue::Int(stats.parser_pool_misses as jint).as_jni(),
+                    JValue::Int(stats.reused_trees as jint).as_jni(),
+                ],
+            )
+        }
+    }
+}
+
+/// Metrics of the parse which produced the snapshot
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeSyntaxSnapshot_nativeGetLastParseStats<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    snapshot: JObject<'local>,
+) -> JObject<'local> {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        snapshot: JObject<'local>,
+    ) -> JNIResult<JObject<'local>> {
+        let snapshot = SyntaxSnapshotDesc::from_java_object(env, snapshot)?;
+        ParseStatsDesc::new(env)?.to_java_object(env, snapshot.parse_stats())
+    }
+    let result = inner(&mut env, snapshot);
+    throw_exception_from_result(&mut env, result)
+}
+
+/// Sorted ids of entries with ranges changed by `nativeParseWithOld` which produced the snapshot
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeSyntaxSnapshot_nativeGetChangedEntryIds<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    snapshot: JObject<'local>,
+) -> JLongArray<'local> {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        snapshot: JObject<'local>,
+    ) -> JNIResult<JLongArray<'local>> {
+        let snapshot = SyntaxSnapshotDesc::from_java_object(env, snapshot)?;
+        let ids: Vec<jlong> = snapshot
+            .changed_entry_ids()
+            .iter()
+            .map(|id| *id as jlong)
+            .collect();
+        let array = env.new_long_array(ids.len() as jsize)?;
+        env.set_long_array_region(&array, 0, &ids)?;
+        Ok(array)
+    }
+    let result = inner(&mut env, snapshot);
+    throw_exception_from_result(&mut env, result)
+}
+
+/// Cursor handed to java, owns a copy of its snapshot so it stays valid after the java snapshot
+/// object is collected
+struct CursorHandle {
+    // Declared before the snapshot it borrows, so it is dropped first
+    cursor: SyntaxSnapshotTreeCursor<'static>,
+    _snapshot: Box<SyntaxSnapshot>,
+}
+
+impl CursorHandle {
+    fn new(snapshot: &SyntaxSnapshot) -> Box<CursorHandle> {
+        let snapshot = Box::new(snapshot.clone());
+        // SAFETY: snapshot is boxed so it does not move, it is never mutated and outlives cursor
+        let snapshot_ref: &'static SyntaxSnapshot = unsafe { &*(&*snapshot as *const _) };
+        Box::new(CursorHandle {
+            cursor: SyntaxSnapshotTreeCursor::walk(snapshot_ref),
+            _snapshot: snapshot,
+        })
+    }
+
+    /// # Safety
+    /// `handle` is created by `nativeCursorCreate` and not destroyed yet, java does not use it
+    /// from several threads at once
+    unsafe fn from_handle<'a>(handle: jlong) -> &'a mut SyntaxSnapshotTreeCursor<'static> {
+        &mut (*(handle as *mut CursorHandle)).cursor
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeSyntaxSnapshot_nativeCursorCreate<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    snapshot: JObject<'local>,
+) -> jlong {
+    fn inner<'local>(env: &mut JNIEnv<'local>, snapshot: JObject<'local>) -> JNIResult<jlong> {
+        let snapshot = SyntaxSnapshotDesc::from_java_object(env, snapshot)?;
+        Ok(Box::into_raw(CursorHandle::new(snapshot)) as jlong)
+    }
+    let result = inner(&mut env, snapshot);
+    throw_exception_from_result(&mut env, result)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeSyntaxSnapshot_nativeCursorGotoFirstChild<
+    'local,
+>(
+    _env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+) -> jboolean {
+    // SAFETY: handle is passed by java cursor object which is not destroyed yet
+    let cursor = unsafe { CursorHandle::from_handle(handle) };
+    cursor.goto_first_child().into()
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeSyntaxSnapshot_nativeCursorGotoNextSibling<
+    'local,
+>(
+    _env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+) -> jboolean {
+    // SAFETY: handle is passed by java cursor object which is not destroyed yet
+    let cursor = unsafe { CursorHandle::from_handle(handle) };
+    cursor.goto_next_sibling().into()
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeSyntaxSnapshot_nativeCursorGotoPreviousSibling<
+    'local,
+>(
+    _env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+) -> jboolean {
+    // SAFETY: handle is passed by java cursor object which is not destroyed yet
+    let cursor = unsafe { CursorHandle::from_handle(handle) };
+    cursor.goto_previous_sibling().into()
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeSyntaxSnapshot_nativeCursorGotoParent<
+    'local,
+>(
+    _env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+) -> jboolean {
+    // SAFETY: handle is passed by java cursor object which is not destroyed yet
+    let cursor = unsafe { CursorHandle::from_handle(handle) };
+    cursor.goto_parent().into()
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeSyntaxSnapshot_nativeCursorNodeKind<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+) -> JObject<'local> {
+    // SAFETY: handle is passed by java cursor object which is not destroyed yet
+    let cursor = unsafe { CursorHandle::from_handle(handle) };
+    let result = env.new_string(cursor.node().kind()).map(JObject::from);
+    throw_exception_from_result(&mut env, result)
+}
+
+/// Language of the tree cursor is currently in
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeSyntaxSnapshot_nativeCursorLanguage<
+    'local,
+>(
+    _env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+) -> jlong {
+    // SAFETY: handle is passed by java cursor object which is not destroyed yet
+    let cursor = unsafe { CursorHandle::from_handle(handle) };
+    cursor.language().into()
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeSyntaxSnapshot_nativeCursorNodeRange<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+) -> JObject<'local> {
+    // SAFETY: handle is passed by java cursor object which is not destroyed yet
+    let cursor = unsafe { CursorHandle::from_handle(handle) };
+    let range = cursor.node().range();
+    let result = RangeDesc::new(&mut env).and_then(|desc| desc.to_java_object(&mut env, range));
+    throw_exception_from_result(&mut env, result)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeSyntaxSnapshot_nativeCursorDestroy<
+    'local,
+>(
+    _env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+) {
+    let ptr = handle as *mut CursorHandle;
+    // SAFETY: handle is created from Box::into_raw, called by java when the cursor is closed
+    std::mem::drop(unsafe { Box::from_raw(ptr) });
+}
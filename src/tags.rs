@@ -0,0 +1,182 @@
+use std::{collections::HashMap, ops::Range, sync::Arc};
+
+use streaming_iterator::StreamingIterator;
+use tree_sitter::QueryCursor;
+
+use crate::{
+    predicates::AdditionalPredicates,
+    query::{SourceText, SourceTextProvider},
+    syntax_snapshot::{SyntaxSnapshot, SyntaxSnapshotEntryContent},
+    LanguageId,
+};
+
+#[cfg(feature = "jni")]
+mod jni_methods;
+
+#[derive(thiserror::Error, Debug)]
+pub enum TagsQueryError {
+    #[error("required captures not found")]
+    NoRequiredCaptures,
+    #[error("duplicate captures found")]
+    DuplicateCapture,
+}
+
+struct TagCapture {
+    kind: Box<str>,
+    is_definition: bool,
+}
+
+/// Query following tree-sitter tags convention: `@name` of the symbol, tag node captured as
+/// `@definition.<kind>` or `@reference.<kind>` and optional `@doc` comments.
+pub struct TagsQuery {
+    query: tree_sitter::Query,
+    predicates: AdditionalPredicates,
+    name_capture_id: u32,
+    doc_capture_id: Option<u32>,
+    tag_captures: HashMap<u32, TagCapture>,
+}
+
+impl TagsQuery {
+    pub fn new(
+        query: tree_sitter::Query,
+        predicates: AdditionalPredicates,
+    ) -> Result<TagsQuery, TagsQueryError> {
+        let mut name_capture_id: Option<u32> = None;
+        let mut doc_capture_id: Option<u32> = None;
+        let mut tag_captures = HashMap::new();
+        for (idx, capture_name) in query.capture_names().iter().enumerate() {
+            if *capture_name == "name" {
+                if name_capture_id.replace(idx as u32).is_some() {
+                    return Err(TagsQueryError::DuplicateCapture);
+                }
+            } else if *capture_name == "doc" {
+                if doc_capture_id.replace(idx as u32).is_some() {
+                    return Err(TagsQueryError::DuplicateCapture);
+                }
+            } else if let Some(kind) = capture_name.strip_prefix("definition.") {
+                tag_captures.insert(
+                    idx as u32,
+                    TagCapture {
+                        kind: kind.into(),
+                        is_definition: true,
+                    },
+                );
+            } else if let Some(kind) = capture_name.strip_prefix("reference.") {
+                tag_captures.insert(
+                    idx as u32,
+                    TagCapture {
+                        kind: kind.into(),
+                        is_definition: false,
+                    },
+                );
+            }
+        }
+        if tag_captures.is_empty() {
+            return Err(TagsQueryError::NoRequiredCaptures);
+        }
+
+        Ok(TagsQuery {
+            query,
+            predicates,
+            name_capture_id: name_capture_id.ok_or(TagsQueryError::NoRequiredCaptures)?,
+            doc_capture_id,
+            tag_captures,
+        })
+    }
+
+    pub fn capture_names(&self) -> &[&str] {
+        self.query.capture_names()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Tag {
+    pub language_id: LanguageId,
+    pub kind: Box<str>,
+    pub is_definition: bool,
+    pub range: tree_sitter::Range,
+    pub name_range: tree_sitter::Range,
+    pub docs_range: Option<tree_sitter::Range>,
+}
+
+pub fn collect_tags(snapshot: &SyntaxSnapshot, text: SourceText<'_>) -> Vec<Tag> {
+    collect_tags_in_range(snapshot, text, 0..text.byte_len())
+}
+
+/// Tags of matches intersecting `byte_range`
+pub fn collect_tags_in_range(
+    snapshot: &SyntaxSnapshot,
+    text: SourceText<'_>,
+    byte_range: Range<usize>,
+) -> Vec<Tag> {
+    let mut tags = Vec::new();
+    let mut query_cache: HashMap<LanguageId, Option<Arc<TagsQuery>>> = HashMap::new();
+    let text_provider = SourceTextProvider::new(text);
+    for (_, entry) in snapshot.intersecting_entries(byte_range.clone(), true) {
+        let SyntaxSnapshotEntryContent::Parsed { language, tree } = &entry.content else {
+            continue;
+        };
+        let query = query_cache.entry(*language).or_insert_with(|| {
+            snapshot
+                .isolate
+                .with_language(*language, |language| {
+                    language.parser_info().tags_query.clone()
+                })
+                .ok()
+                .flatten()
+        });
+        let Some(query) = query else {
+            continue;
+        };
+        let mut cursor = QueryCursor::new();
+        cursor.set_byte_range(
+            byte_range.start.max(entry.byte_range.start)..byte_range.end.min(entry.byte_range.end),
+        );
+        let mut matches = cursor.matches(
+            &query.query,
+            tree.root_node_with_offset(entry.byte_offset, entry.point_offset),
+            &text_provider,
+        );
+        while let Some(query_match) = matches.next() {
+            if !query
+                .predicates
+                .satisfies_predicates(&text_provider, query_match)
+            {
+                continue;
+            }
+            let mut tag: Option<(&TagCapture, tree_sitter::Range)> = None;
+            let mut name_range: Option<tree_sitter::Range> = None;
+            let mut docs_range: Option<tree_sitter::Range> = None;
+            for capture in query_match.captures {
+                let range = capture.node.range();
+                if capture.index == query.name_capture_id {
+                    name_range.get_or_insert(range);
+                } else if Some(capture.index) == query.doc_capture_id {
+                    docs_range = Some(match docs_range {
+                        Some(docs_range) => tree_sitter::Range {
+                            start_byte: docs_range.start_byte,
+                            start_point: docs_range.start_point,
+                            end_byte: range.end_byte,
+                            end_point: range.end_point,
+                        },
+                        None => range,
+                    });
+                } else if let Some(tag_capture) = query.tag_captures.get(&capture.index) {
+                    tag.get_or_insert((tag_capture, range));
+                }
+            }
+            let (Some((tag_capture, range)), Some(name_range)) = (tag, name_range) else {
+                continue;
+            };
+            tags.push(Tag {
+                language_id: *language,
+                kind: tag_capture.kind.clone(),
+                is_definition: tag_capture.is_definition,
+                range,
+                name_range,
+                docs_range,
+            });
+        }
+    }
+    tags
+}
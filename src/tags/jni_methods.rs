@@ -0,0 +1,105 @@
+use jni::{
+    errors::Result as JNIResult,
+    objects::{AutoLocal, JCharArray, JClass, JMethodID, JObject, JObjectArray, JValue},
+    sys::jsize,
+    JNIEnv,
+};
+use once_cell::sync::OnceCell as JOnceLock;
+
+use crate::{
+    jni_utils::{read_char_array, throw_exception_from_result, RangeDesc},
+    query::SourceText,
+    syntax_snapshot::SyntaxSnapshotDesc,
+};
+
+use super::{collect_tags, Tag};
+
+static TAG_CONSTRUCTOR: JOnceLock<JMethodID> = JOnceLock::new();
+
+struct TagDesc<'local> {
+    constructor: JMethodID,
+    class: AutoLocal<'local, JClass<'local>>,
+    range_desc: RangeDesc<'local>,
+}
+
+impl<'local> TagDesc<'local> {
+    fn new(env: &mut JNIEnv<'local>) -> JNIResult<TagDesc<'local>> {
+        let range_desc = RangeDesc::new(env)?;
+        let class = env.find_class("com/hulylabs/treesitter/language/Tag")?;
+        let constructor = *TAG_CONSTRUCTOR.get_or_try_init(|| {
+            env.get_method_id(
+                &class,
+                "<init>",
+                "(Lcom/hulylabs/treesitter/language/Range;Lcom/hulylabs/treesitter/language/Range;Lcom/hulylabs/treesitter/language/Range;Ljava/lang/String;ZJ)V",
+            )
+        })?;
+
+        Ok(TagDesc {
+            constructor,
+            class: env.auto_local(class),
+            range_desc,
+        })
+    }
+
+    fn to_java_object(&self, env: &mut JNIEnv<'local>, tag: Tag) -> JNIResult<JObject<'local>> {
+        let range_obj = self.range_desc.to_java_object(env, tag.range)?;
+        let range_obj = env.auto_local(range_obj);
+        let name_range_obj = self.range_desc.to_java_object(env, tag.name_range)?;
+        let name_range_obj = env.auto_local(name_range_obj);
+        let docs_range_obj = if let Some(docs_range) = tag.docs_range {
+            self.range_desc.to_java_object(env, docs_range)?
+        } else {
+            JObject::null()
+        };
+        let docs_range_obj = env.auto_local(docs_range_obj);
+        let kind: JObject = env.new_string(tag.kind)?.into();
+        let kind = env.auto_local(kind);
+        // SAFETY: constructor is valid and derived from class by construction of self
+        unsafe {
+            env.new_object_unchecked(
+                &self.class,
+                self.constructor,
+                &[
+                    JValue::Object(&range_obj).as_jni(),
+                    JValue::Object(&name_range_obj).as_jni(),
+                    JValue::Object(&docs_range_obj).as_jni(),
+                    JValue::Object(&kind).as_jni(),
+                    JValue::from(tag.is_definition).as_jni(),
+                    JValue::from(tag.language_id).as_jni(),
+                ],
+            )
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeTagsProvider_nativeCollectTags<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    snapshot: JObject<'local>,
+    text: JCharArray<'local>,
+) -> JObjectArray<'local> {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        snapshot: JObject<'local>,
+        text: JCharArray<'local>,
+    ) -> JNIResult<JObjectArray<'local>> {
+        let snapshot = SyntaxSnapshotDesc::from_java_object(env, snapshot)?;
+        let tag_desc = TagDesc::new(env)?;
+        let text_buffer = read_char_array(env, &text)?;
+
+        let tags = collect_tags(snapshot, SourceText::Utf16(&text_buffer));
+        let tags_array =
+            env.new_object_array(tags.len() as jsize, &tag_desc.class, JObject::null())?;
+        for (index, tag) in tags.into_iter().enumerate() {
+            let tag_obj = tag_desc.to_java_object(env, tag)?;
+            let tag_obj = env.auto_local(tag_obj);
+            env.set_object_array_element(&tags_array, index as i32, tag_obj)?;
+        }
+        Ok(tags_array)
+    }
+    let result = inner(&mut env, snapshot, text);
+    throw_exception_from_result(&mut env, result)
+}
@@ -0,0 +1,48 @@
+//! Fixtures for unit tests needing a real grammar
+
+use std::sync::Arc;
+
+use crate::{syntax_snapshot::SyntaxSnapshot, Isolate, LanguageId};
+
+/// Fresh isolate with the JSON grammar registered as `json`
+pub(crate) fn json_isolate() -> (Arc<Isolate>, LanguageId) {
+    let isolate = Isolate::create();
+    let language_id = isolate.register_language("json", tree_sitter_json::LANGUAGE.into());
+    (isolate, language_id)
+}
+
+/// Snapshot of UTF-8 `text` parsed as the base language
+pub(crate) fn parse(isolate: &Arc<Isolate>, language_id: LanguageId, text: &str) -> SyntaxSnapshot {
+    SyntaxSnapshot::parse_str(Arc::clone(isolate), language_id, text).expect("text is parsed")
+}
+
+fn point_at(text: &str, byte: usize) -> tree_sitter::Point {
+    let line_start = text[..byte].rfind('\n').map_or(0, |idx| idx + 1);
+    tree_sitter::Point::new(text[..byte].matches('\n').count(), byte - line_start)
+}
+
+/// Text with `byte_range` replaced by `replacement` and its incremental reparse from `snapshot`
+pub(crate) fn reparse(
+    snapshot: &SyntaxSnapshot,
+    text: &str,
+    byte_range: std::ops::Range<usize>,
+    replacement: &str,
+) -> (String, SyntaxSnapshot) {
+    let new_text = format!(
+        "{}{replacement}{}",
+        &text[..byte_range.start],
+        &text[byte_range.end..]
+    );
+    let new_end = byte_range.start + replacement.len();
+    let edit = tree_sitter::InputEdit {
+        start_byte: byte_range.start,
+        old_end_byte: byte_range.end,
+        new_end_byte: new_end,
+        start_position: point_at(text, byte_range.start),
+        old_end_position: point_at(text, byte_range.end),
+        new_end_position: point_at(&new_text, new_end),
+    };
+    let (new_snapshot, _) =
+        SyntaxSnapshot::parse_incremental_str(&new_text, snapshot, edit).expect("text is parsed");
+    (new_text, new_snapshot)
+}
@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+
+use crate::{isolate::Isolate, HighlightToken, LanguageId};
+
+#[cfg(feature = "jni")]
+mod jni_methods;
+
+/// Scope id of tokens whose capture has no TextMate scope
+pub const NO_SCOPE: u16 = u16::MAX;
+
+/// Translation of capture names into TextMate scope chains (space separated scopes, outermost
+/// first). Scope chains are interned, tokens refer to them by id.
+#[derive(Default)]
+pub struct TextMateScopes {
+    scope_chains: Vec<Box<str>>,
+    scope_ids: HashMap<Box<str>, u16>,
+    capture_scopes: HashMap<Box<str>, u16>,
+}
+
+impl TextMateScopes {
+    fn intern(&mut self, scope_chain: &str) -> u16 {
+        if let Some(scope_id) = self.scope_ids.get(scope_chain) {
+            return *scope_id;
+        }
+        let scope_id = self.scope_chains.len() as u16;
+        self.scope_chains.push(scope_chain.into());
+        self.scope_ids.insert(scope_chain.into(), scope_id);
+        scope_id
+    }
+
+    /// Scope of capture, captures without own mapping use scope of their parent capture name,
+    /// e.g. `function.method` falls back to `function`
+    pub fn scope_for_capture(&self, capture_name: &str) -> u16 {
+        let mut name = capture_name;
+        loop {
+            if let Some(scope_id) = self.capture_scopes.get(name) {
+                return *scope_id;
+            }
+            let Some((parent, _)) = name.rsplit_once('.') else {
+                return NO_SCOPE;
+            };
+            name = parent;
+        }
+    }
+
+    pub fn scope_chain(&self, scope_id: u16) -> Option<&str> {
+        self.scope_chains.get(scope_id as usize).map(AsRef::as_ref)
+    }
+
+    pub fn scope_chains(&self) -> &[Box<str>] {
+        &self.scope_chains
+    }
+}
+
+impl Isolate {
+    /// Adds or replaces mapping of captures to scope chains, returns scope ids of the chains
+    pub fn register_textmate_scopes<'a>(
+        &self,
+        mapping: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) -> Vec<u16> {
+        let mut scopes = self.textmate_scopes.write().unwrap();
+        mapping
+            .into_iter()
+            .map(|(capture_name, scope_chain)| {
+                let scope_id = scopes.intern(scope_chain);
+                scopes.capture_scopes.insert(capture_name.into(), scope_id);
+                scope_id
+            })
+            .collect()
+    }
+
+    pub fn textmate_scope_chains(&self) -> Vec<Box<str>> {
+        self.textmate_scopes.read().unwrap().scope_chains().to_vec()
+    }
+}
+
+/// Scope ids for highlight tokens, tokens without capture or mapping get [`NO_SCOPE`]
+pub fn highlight_token_scopes(isolate: &Isolate, tokens: &[HighlightToken]) -> Vec<u16> {
+    let scopes = isolate.textmate_scopes.read().unwrap();
+    let mut capture_tables: HashMap<LanguageId, Vec<u16>> = HashMap::new();
+    tokens
+        .iter()
+        .map(|token| {
+            let table = capture_tables.entry(token.language_id).or_insert_with(|| {
+                isolate
+                    .with_language(token.language_id, |language| {
+                        language
+                            .parser_info()
+                            .highlights_query
+                            .as_ref()
+                            .map(|query| {
+                                query
+                                    .0
+                                    .capture_names()
+                                    .iter()
+                                    .map(|name| scopes.scope_for_capture(name))
+                                    .collect()
+                            })
+                    })
+                    .ok()
+                    .flatten()
+                    .unwrap_or_default()
+            });
+            table
+                .get(token.capture_id as usize)
+                .copied()
+                .unwrap_or(NO_SCOPE)
+        })
+        .collect()
+}
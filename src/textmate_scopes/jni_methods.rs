@@ -0,0 +1,78 @@
+use jni::{
+    errors::Result as JNIResult,
+    objects::{JClass, JObjectArray, JString},
+    sys::jsize,
+    JNIEnv,
+};
+
+use crate::{
+    isolate::{Isolate, IsolateId},
+    jni_utils::{read_string_array, throw_exception_from_result},
+};
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeIsolate_nativeRegisterTextMateScopes<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    isolate_id: IsolateId,
+    capture_names: JObjectArray<'local>,
+    scope_chains: JObjectArray<'local>,
+) {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        isolate_id: IsolateId,
+        capture_names: JObjectArray<'local>,
+        scope_chains: JObjectArray<'local>,
+    ) -> JNIResult<()> {
+        let Ok(isolate) = Isolate::get(isolate_id) else {
+            env.throw_new("java/lang/IllegalStateException", "unknown isolate")?;
+            return Ok(());
+        };
+        let capture_names = read_string_array(env, &capture_names)?;
+        let scope_chains = read_string_array(env, &scope_chains)?;
+        isolate.register_textmate_scopes(
+            capture_names
+                .iter()
+                .map(AsRef::as_ref)
+                .zip(scope_chains.iter().map(AsRef::as_ref)),
+        );
+        Ok(())
+    }
+    let result = inner(&mut env, isolate_id, capture_names, scope_chains);
+    throw_exception_from_result(&mut env, result)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeIsolate_nativeGetTextMateScopeChains<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    isolate_id: IsolateId,
+) -> JObjectArray<'local> {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        isolate_id: IsolateId,
+    ) -> JNIResult<JObjectArray<'local>> {
+        let Ok(isolate) = Isolate::get(isolate_id) else {
+            env.throw_new("java/lang/IllegalStateException", "unknown isolate")?;
+            return Ok(JObjectArray::default());
+        };
+        let scope_chains = isolate.textmate_scope_chains();
+        let scope_chains_array = env.new_object_array(
+            scope_chains.len() as jsize,
+            "java/lang/String",
+            JString::default(),
+        )?;
+        for (index, scope_chain) in scope_chains.iter().enumerate() {
+            let scope_chain = env.new_string(scope_chain)?;
+            let scope_chain = env.auto_local(scope_chain);
+            env.set_object_array_element(&scope_chains_array, index as i32, &scope_chain)?;
+        }
+        Ok(scope_chains_array)
+    }
+    let result = inner(&mut env, isolate_id);
+    throw_exception_from_result(&mut env, result)
+}
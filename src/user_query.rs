@@ -0,0 +1,334 @@
+use std::{
+    collections::HashMap,
+    ops::Range,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use streaming_iterator::StreamingIterator;
+use tree_sitter::QueryCursor;
+
+use crate::{
+    isolate::Isolate,
+    language_registry::{parse_query, QueryParseError},
+    predicates::AdditionalPredicates,
+    query::{pooled_query_cursor, SourceText, SourceTextProvider},
+    query_budget,
+    syntax_snapshot::{SyntaxSnapshot, SyntaxSnapshotEntryContent},
+    LanguageId,
+};
+
+#[cfg(feature = "jni")]
+mod jni_methods;
+
+/// Compiled queries are kept per isolate, the least recently used one is evicted past this size
+const USER_QUERY_CACHE_LIMIT: usize = 64;
+
+type UserQueryKey = (LanguageId, Box<str>);
+
+/// Compiled user queries of an isolate along with the tick they were last used at
+#[derive(Default)]
+pub struct UserQueryCache {
+    queries: HashMap<UserQueryKey, (Arc<UserQuery>, u64)>,
+    tick: u64,
+}
+
+impl UserQueryCache {
+    fn get(&mut self, key: &UserQueryKey) -> Option<Arc<UserQuery>> {
+        self.tick += 1;
+        let (query, last_used) = self.queries.get_mut(key)?;
+        *last_used = self.tick;
+        Some(Arc::clone(query))
+    }
+
+    fn insert(&mut self, key: UserQueryKey, query: Arc<UserQuery>) {
+        if self.queries.len() >= USER_QUERY_CACHE_LIMIT && !self.queries.contains_key(&key) {
+            let least_recent = self
+                .queries
+                .iter()
+                .min_by_key(|(_, (_, last_used))| *last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(least_recent) = least_recent {
+                self.queries.remove(&least_recent);
+            }
+        }
+        self.tick += 1;
+        self.queries.insert(key, (query, self.tick));
+    }
+}
+
+/// Query provided by user at runtime rather than registered for a language role
+pub struct UserQuery {
+    language_id: LanguageId,
+    source: Box<str>,
+    query: tree_sitter::Query,
+    predicates: AdditionalPredicates,
+}
+
+impl UserQuery {
+    pub fn language_id(&self) -> LanguageId {
+        self.language_id
+    }
+
+    pub fn query(&self) -> &tree_sitter::Query {
+        &self.query
+    }
+
+    pub fn capture_name(&self, capture_id: u32) -> &str {
+        self.query.capture_names()[capture_id as usize]
+    }
+}
+
+impl Isolate {
+    pub fn compile_user_query(
+        &self,
+        language_id: LanguageId,
+        query_str: &str,
+    ) -> Result<Arc<UserQuery>, QueryParseError> {
+        let key = (language_id, Box::<str>::from(query_str));
+        if let Some(query) = self.user_queries.lock().unwrap().get(&key) {
+            return Ok(query);
+        }
+        let ts_language = self.with_language(language_id, |language| language.ts_language())?;
+        let (query, predicates) = parse_query(&ts_language, query_str)?;
+        let query = Arc::new(UserQuery {
+            language_id,
+            source: query_str.into(),
+            query,
+            predicates,
+        });
+        self.user_queries
+            .lock()
+            .unwrap()
+            .insert(key, Arc::clone(&query));
+        Ok(query)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct UserQueryCapture {
+    pub capture_id: u32,
+    pub range: tree_sitter::Range,
+}
+
+#[derive(Debug, Clone)]
+pub struct UserQueryMatch {
+    pub pattern_index: usize,
+    pub captures: Vec<UserQueryCapture>,
+}
+
+/// Runs query over every snapshot layer of the query language intersecting `byte_range`
+pub fn execute_query(
+    snapshot: &SyntaxSnapshot,
+    text: SourceText<'_>,
+    query: &UserQuery,
+    byte_range: Range<usize>,
+) -> Vec<UserQueryMatch> {
+    let mut result = Vec::new();
+    for_each_query_match(snapshot, text, query, byte_range, |query_match| {
+        result.push(query_match);
+        true
+    });
+    result
+}
+
+/// Same as [`execute_query`] but hands matches to `visitor` as they are found instead of
+/// collecting them, stops once `visitor` returns false
+pub fn for_each_query_match(
+    snapshot: &SyntaxSnapshot,
+    text: SourceText<'_>,
+    query: &UserQuery,
+    byte_range: Range<usize>,
+    mut visitor: impl FnMut(UserQueryMatch) -> bool,
+) {
+    let text_provider = SourceTextProvider::new(text);
+    for (_, entry) in snapshot.intersecting_entries(byte_range.clone(), false) {
+        let SyntaxSnapshotEntryContent::Parsed { language, tree } = &entry.content else {
+            continue;
+        };
+        if *language != query.language_id {
+            continue;
+        }
+        let mut cursor = QueryCursor::new();
+        cursor.set_byte_range(
+            byte_range.start.max(entry.byte_range.start)..byte_range.end.min(entry.byte_range.end),
+        );
+        let mut matches = cursor.matches(
+            &query.query,
+            tree.root_node_with_offset(entry.byte_offset, entry.point_offset),
+            &text_provider,
+        );
+        while let Some(query_match) = matches.next() {
+            if !query
+                .predicates
+                .satisfies_predicates(&text_provider, query_match)
+            {
+                continue;
+            }
+            let query_match = UserQueryMatch {
+                pattern_index: query_match.pattern_index,
+                captures: query_match
+                    .captures
+                    .iter()
+                    .map(|capture| UserQueryCapture {
+                        capture_id: capture.index,
+                        range: capture.node.range(),
+                    })
+                    .collect(),
+            };
+            if !visitor(query_match) {
+                return;
+            }
+        }
+    }
+}
+
+/// Matches of a query pattern over the whole snapshot and time they took
+#[derive(Debug, Clone, Default)]
+pub struct PatternProfile {
+    /// Matches accepted by additional predicates
+    pub match_count: usize,
+    /// Matches rejected by additional predicates
+    pub rejected_count: usize,
+    /// Time of running the pattern alone, including its predicates
+    pub time: Duration,
+}
+
+/// Runs each pattern of the query alone over every snapshot layer of the query language, so the
+/// time of a pattern is its own cost even if it never matches. Patterns are compiled one by one
+/// from their source, match limit and time budget apply to the whole run.
+pub fn profile_query(
+    snapshot: &SyntaxSnapshot,
+    text: SourceText<'_>,
+    query: &UserQuery,
+) -> Vec<PatternProfile> {
+    let _budget = query_budget::call(&snapshot.isolate);
+    let mut result = vec![PatternProfile::default(); query.query.pattern_count()];
+    let Ok(ts_language) = snapshot
+        .isolate
+        .with_language(query.language_id, |language| language.ts_language())
+    else {
+        return result;
+    };
+    let text_provider = SourceTextProvider::new(text);
+    let layers: Vec<_> = snapshot
+        .intersecting_entries(0..text.byte_len(), false)
+        .filter_map(|(_, entry)| match &entry.content {
+            SyntaxSnapshotEntryContent::Parsed { language, tree }
+                if *language == query.language_id =>
+            {
+                let root = tree.root_node_with_offset(entry.byte_offset, entry.point_offset);
+                Some((root, entry.byte_range.clone()))
+            }
+            _ => None,
+        })
+        .collect();
+    for (pattern_index, profile) in result.iter_mut().enumerate() {
+        if query_budget::is_exhausted() {
+            break;
+        }
+        let pattern_source = &query.source[query.query.start_byte_for_pattern(pattern_index)
+            ..query.query.end_byte_for_pattern(pattern_index)];
+        let Ok((pattern_query, predicates)) = parse_query(&ts_language, pattern_source) else {
+            continue;
+        };
+        let started = Instant::now();
+        for (root, byte_range) in &layers {
+            let mut cursor = pooled_query_cursor();
+            cursor.set_byte_range(byte_range.clone());
+            query_budget::prepare_cursor(&mut cursor);
+            let mut matches = cursor.matches(&pattern_query, *root, &text_provider);
+            while let Some(query_match) = matches.next() {
+                if predicates.satisfies_predicates(&text_provider, query_match) {
+                    profile.match_count += 1;
+                } else {
+                    profile.rejected_count += 1;
+                }
+            }
+            query_budget::finish_cursor(&cursor);
+        }
+        profile.time = started.elapsed();
+    }
+    result
+}
+
+#[derive(Debug, Clone)]
+pub struct DiagnosticCapture {
+    pub capture_id: u32,
+    pub node_kind: &'static str,
+    pub range: tree_sitter::Range,
+}
+
+#[derive(Debug, Clone)]
+pub struct PredicateOutcome {
+    pub operator: Box<str>,
+    pub passed: bool,
+}
+
+/// Match reported regardless of additional predicates result, to let query authors see why
+/// a pattern is rejected. Built-in tree-sitter predicates (`#eq?`, `#match?`, ...) are still
+/// applied by the query cursor, so matches failing them are not reported.
+#[derive(Debug, Clone)]
+pub struct DiagnosticMatch {
+    pub pattern_index: usize,
+    /// Every captured node, quantified captures are reported once per node
+    pub captures: Vec<DiagnosticCapture>,
+    pub predicates: Vec<PredicateOutcome>,
+}
+
+impl DiagnosticMatch {
+    pub fn is_accepted(&self) -> bool {
+        self.predicates.iter().all(|predicate| predicate.passed)
+    }
+}
+
+/// Same as [`execute_query`] but keeps matches rejected by predicates and reports
+/// each predicate outcome
+pub fn execute_query_diagnostics(
+    snapshot: &SyntaxSnapshot,
+    text: SourceText<'_>,
+    query: &UserQuery,
+    byte_range: Range<usize>,
+) -> Vec<DiagnosticMatch> {
+    let mut result = Vec::new();
+    let text_provider = SourceTextProvider::new(text);
+    for (_, entry) in snapshot.intersecting_entries(byte_range.clone(), false) {
+        let SyntaxSnapshotEntryContent::Parsed { language, tree } = &entry.content else {
+            continue;
+        };
+        if *language != query.language_id {
+            continue;
+        }
+        let mut cursor = QueryCursor::new();
+        cursor.set_byte_range(
+            byte_range.start.max(entry.byte_range.start)..byte_range.end.min(entry.byte_range.end),
+        );
+        let mut matches = cursor.matches(
+            &query.query,
+            tree.root_node_with_offset(entry.byte_offset, entry.point_offset),
+            &text_provider,
+        );
+        while let Some(query_match) = matches.next() {
+            let predicates = query
+                .predicates
+                .evaluate_predicates(&text_provider, query_match)
+                .into_iter()
+                .map(|(operator, passed)| PredicateOutcome { operator, passed })
+                .collect();
+            result.push(DiagnosticMatch {
+                pattern_index: query_match.pattern_index,
+                captures: query_match
+                    .captures
+                    .iter()
+                    .map(|capture| DiagnosticCapture {
+                        capture_id: capture.index,
+                        node_kind: capture.node.kind(),
+                        range: capture.node.range(),
+                    })
+                    .collect(),
+                predicates,
+            });
+        }
+    }
+    result
+}
@@ -0,0 +1,604 @@
+use std::{borrow::Cow, ops::Range, sync::Arc};
+
+use jni::{
+    errors::{Error as JNIError, Result as JNIResult},
+    objects::{AutoLocal, JCharArray, JClass, JMethodID, JObject, JObjectArray, JString, JValue},
+    sys::{jint, jlong, jsize},
+    JNIEnv,
+};
+use once_cell::sync::OnceCell as JOnceLock;
+
+use crate::{
+    isolate::{Isolate, IsolateId},
+    jni_utils::{read_char_array, throw_exception_from_result, RangeDesc},
+    language_registry::QueryParseError,
+    query::SourceText,
+    syntax_snapshot::SyntaxSnapshotDesc,
+    LanguageId,
+};
+
+use super::{
+    execute_query, execute_query_diagnostics, for_each_query_match, profile_query, DiagnosticMatch,
+    PatternProfile, UserQuery, UserQueryMatch,
+};
+
+static QUERY_MATCH_CONSTRUCTOR: JOnceLock<JMethodID> = JOnceLock::new();
+static DIAGNOSTIC_MATCH_CONSTRUCTOR: JOnceLock<JMethodID> = JOnceLock::new();
+static PATTERN_PROFILE_CONSTRUCTOR: JOnceLock<JMethodID> = JOnceLock::new();
+
+fn new_string_array<'local, 'a>(
+    env: &mut JNIEnv<'local>,
+    strings: impl ExactSizeIterator<Item = &'a str>,
+) -> JNIResult<JObjectArray<'local>> {
+    let array = env.new_object_array(
+        strings.len() as jsize,
+        "java/lang/String",
+        JString::default(),
+    )?;
+    for (index, string) in strings.enumerate() {
+        let string = env.new_string(string)?;
+        let string = env.auto_local(string);
+        env.set_object_array_element(&array, index as i32, &string)?;
+    }
+    Ok(array)
+}
+
+struct QueryMatchDesc<'local> {
+    constructor: JMethodID,
+    class: AutoLocal<'local, JClass<'local>>,
+    range_desc: RangeDesc<'local>,
+}
+
+impl<'local> QueryMatchDesc<'local> {
+    fn new(env: &mut JNIEnv<'local>) -> JNIResult<QueryMatchDesc<'local>> {
+        let range_desc = RangeDesc::new(env)?;
+        let class = env.find_class("com/hulylabs/treesitter/language/QueryMatch")?;
+        let constructor = *QUERY_MATCH_CONSTRUCTOR.get_or_try_init(|| {
+            env.get_method_id(
+                &class,
+                "<init>",
+                "(I[Ljava/lang/String;[Lcom/hulylabs/treesitter/language/Range;)V",
+            )
+        })?;
+        Ok(QueryMatchDesc {
+            constructor,
+            class: env.auto_local(class),
+            range_desc,
+        })
+    }
+
+    fn to_java_object(
+        &self,
+        env: &mut JNIEnv<'local>,
+        query: &UserQuery,
+        query_match: UserQueryMatch,
+    ) -> JNIResult<JObject<'local>> {
+        let capture_names = env.new_object_array(
+            query_match.captures.len() as jsize,
+            "java/lang/String",
+            JString::default(),
+        )?;
+        let capture_names = env.auto_local(capture_names);
+        let capture_ranges = env.new_object_array(
+            query_match.captures.len() as jsize,
+            &self.range_desc.class,
+            JObject::null(),
+        )?;
+        let capture_ranges = env.auto_local(capture_ranges);
+        for (index, capture) in query_match.captures.into_iter().enumerate() {
+            let capture_name = env.new_string(query.capture_name(capture.capture_id))?;
+            let capture_name = env.auto_local(capture_name);
+            env.set_object_array_element(&capture_names, index as i32, &capture_name)?;
+            let range_obj = self.range_desc.to_java_object(env, capture.range)?;
+            let range_obj = env.auto_local(range_obj);
+            env.set_object_array_element(&capture_ranges, index as i32, &range_obj)?;
+        }
+        // SAFETY: constructor is valid and derived from class by construction of self
+        unsafe {
+            env.new_object_unchecked(
+                &self.class,
+                self.constructor,
+                &[
+                    JValue::Int(query_match.pattern_index as i32).as_jni(),
+                    JValue::Object(&capture_names).as_jni(),
+                    JValue::Object(&capture_ranges).as_jni(),
+                ],
+            )
+        }
+    }
+}
+
+struct DiagnosticMatchDesc<'local> {
+    constructor: JMethodID,
+    class: AutoLocal<'local, JClass<'local>>,
+    range_desc: RangeDesc<'local>,
+}
+
+impl<'local> DiagnosticMatchDesc<'local> {
+    fn new(env: &mut JNIEnv<'local>) -> JNIResult<DiagnosticMatchDesc<'local>> {
+        let range_desc = RangeDesc::new(env)?;
+        let class = env.find_class("com/hulylabs/treesitter/language/QueryMatchDiagnostics")?;
+        let constructor = *DIAGNOSTIC_MATCH_CONSTRUCTOR.get_or_try_init(|| {
+            env.get_method_id(
+                &class,
+                "<init>",
+                "(I[Ljava/lang/String;[Ljava/lang/String;[Lcom/hulylabs/treesitter/language/Range;[Ljava/lang/String;[Z)V",
+            )
+        })?;
+        Ok(DiagnosticMatchDesc {
+            constructor,
+            class: env.auto_local(class),
+            range_desc,
+        })
+    }
+
+    fn to_java_object(
+        &self,
+        env: &mut JNIEnv<'local>,
+        query: &UserQuery,
+        query_match: DiagnosticMatch,
+    ) -> JNIResult<JObject<'local>> {
+        let capture_names = new_string_array(
+            env,
+            query_match
+                .captures
+                .iter()
+                .map(|capture| query.capture_name(capture.capture_id)),
+        )?;
+        let capture_names = env.auto_local(capture_names);
+        let node_kinds = new_string_array(
+            env,
+            query_match.captures.iter().map(|capture| capture.node_kind),
+        )?;
+        let node_kinds = env.auto_local(node_kinds);
+        let capture_ranges = env.new_object_array(
+            query_match.captures.len() as jsize,
+            &self.range_desc.class,
+            JObject::null(),
+        )?;
+        let capture_ranges = env.auto_local(capture_ranges);
+        for (index, capture) in query_match.captures.iter().enumerate() {
+            let range_obj = self.range_desc.to_java_object(env, capture.range)?;
+            let range_obj = env.auto_local(range_obj);
+            env.set_object_array_element(&capture_ranges, index as i32, &range_obj)?;
+        }
+        let predicate_operators = new_string_array(
+            env,
+            query_match
+                .predicates
+                .iter()
+                .map(|predicate| predicate.operator.as_ref()),
+        )?;
+        let predicate_operators = env.auto_local(predicate_operators);
+        let predicate_results = env.new_boolean_array(query_match.predicates.len() as jsize)?;
+        let results: Vec<u8> = query_match
+            .predicates
+            .iter()
+            .map(|predicate| predicate.passed as u8)
+            .collect();
+        env.set_boolean_array_region(&predicate_results, 0, &results)?;
+        let predicate_results = env.auto_local(predicate_results);
+        // SAFETY: constructor is valid and derived from class by construction of self
+        unsafe {
+            env.new_object_unchecked(
+                &self.class,
+                self.constructor,
+                &[
+                    JValue::Int(query_match.pattern_index as i32).as_jni(),
+                    JValue::Object(&capture_names).as_jni(),
+                    JValue::Object(&node_kinds).as_jni(),
+                    JValue::Object(&capture_ranges).as_jni(),
+                    JValue::Object(&predicate_operators).as_jni(),
+                    JValue::Object(&predicate_results).as_jni(),
+                ],
+            )
+        }
+    }
+}
+
+struct PatternProfileDesc<'local> {
+    constructor: JMethodID,
+    class: AutoLocal<'local, JClass<'local>>,
+}
+
+impl<'local> PatternProfileDesc<'local> {
+    fn new(env: &mut JNIEnv<'local>) -> JNIResult<PatternProfileDesc<'local>> {
+        let class = env.find_class("com/hulylabs/treesitter/language/QueryPatternProfile")?;
+        let constructor = *PATTERN_PROFILE_CONSTRUCTOR
+            .get_or_try_init(|| env.get_method_id(&class, "<init>", "(IIIJ)V"))?;
+        Ok(PatternProfileDesc {
+            constructor,
+            class: env.auto_local(class),
+        })
+    }
+
+    fn to_java_object(
+        &self,
+        env: &mut JNIEnv<'local>,
+        pattern_index: usize,
+        profile: &PatternProfile,
+    ) -> JNIResult<JObject<'local>> {
+        // SAFETY: constructor is valid and derived from class by construction of self
+        unsafe {
+            env.new_object_unchecked(
+                &self.class,
+                self.constructor,
+                &[
+                    JValue::Int(pattern_index as i32).as_jni(),
+                    JValue::Int(profile.match_count as i32).as_jni(),
+                    JValue::Int(profile.rejected_count as i32).as_jni(),
+                    JValue::Long(profile.time.as_nanos() as i64).as_jni(),
+                ],
+            )
+        }
+    }
+}
+
+fn matches_to_java_array<'local>(
+    env: &mut JNIEnv<'local>,
+    query: &UserQuery,
+    matches: Vec<UserQueryMatch>,
+) -> JNIResult<JObjectArray<'local>> {
+    let match_desc = QueryMatchDesc::new(env)?;
+    let matches_array =
+        env.new_object_array(matches.len() as jsize, &match_desc.class, JObject::null())?;
+    for (index, query_match) in matches.into_iter().enumerate() {
+        let match_obj = match_desc.to_java_object(env, query, query_match)?;
+        let match_obj = env.auto_local(match_obj);
+        env.set_object_array_element(&matches_array, index as i32, match_obj)?;
+    }
+    Ok(matches_array)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeQueryExecutor_nativeExecuteQuery<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    snapshot: JObject<'local>,
+    text: JCharArray<'local>,
+    language_id: LanguageId,
+    query_source: JString<'local>,
+    start_offset: jint,
+    end_offset: jint,
+) -> JObjectArray<'local> {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        snapshot: JObject<'local>,
+        text: JCharArray<'local>,
+        language_id: LanguageId,
+        query_source: JString<'local>,
+        start_offset: jint,
+        end_offset: jint,
+    ) -> Result<JObjectArray<'local>, QueryParseError> {
+        let snapshot = SyntaxSnapshotDesc::from_java_object(env, snapshot)?;
+        let query_source = env.get_string(&query_source)?;
+        let query_source: Cow<'_, str> = (&query_source).into();
+        let query = snapshot
+            .isolate
+            .compile_user_query(language_id, &query_source)?;
+        let text_buffer = read_char_array(env, &text)?;
+
+        let matches = execute_query(
+            snapshot,
+            SourceText::Utf16(&text_buffer),
+            &query,
+            ((start_offset * 2) as usize)..((end_offset * 2) as usize),
+        );
+        Ok(matches_to_java_array(env, &query, matches)?)
+    }
+    let result = inner(
+        &mut env,
+        snapshot,
+        text,
+        language_id,
+        query_source,
+        start_offset,
+        end_offset,
+    );
+    match result {
+        Ok(matches) => matches,
+        Err(QueryParseError::JNIError(JNIError::JavaException)) => JObjectArray::default(),
+        Err(err) => {
+            env.throw_new(
+                "java/lang/RuntimeException",
+                format!("Failed to execute query: {err}"),
+            )
+            .unwrap();
+            JObjectArray::default()
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeQueryExecutor_nativeExecuteQueryDiagnostics<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    snapshot: JObject<'local>,
+    text: JCharArray<'local>,
+    language_id: LanguageId,
+    query_source: JString<'local>,
+    start_offset: jint,
+    end_offset: jint,
+) -> JObjectArray<'local> {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        snapshot: JObject<'local>,
+        text: JCharArray<'local>,
+        language_id: LanguageId,
+        query_source: JString<'local>,
+        start_offset: jint,
+        end_offset: jint,
+    ) -> Result<JObjectArray<'local>, QueryParseError> {
+        let snapshot = SyntaxSnapshotDesc::from_java_object(env, snapshot)?;
+        let query_source = env.get_string(&query_source)?;
+        let query_source: Cow<'_, str> = (&query_source).into();
+        let query = snapshot
+            .isolate
+            .compile_user_query(language_id, &query_source)?;
+        let match_desc = DiagnosticMatchDesc::new(env)?;
+        let text_buffer = read_char_array(env, &text)?;
+
+        let matches = execute_query_diagnostics(
+            snapshot,
+            SourceText::Utf16(&text_buffer),
+            &query,
+            ((start_offset * 2) as usize)..((end_offset * 2) as usize),
+        );
+        let matches_array =
+            env.new_object_array(matches.len() as jsize, &match_desc.class, JObject::null())?;
+        for (index, query_match) in matches.into_iter().enumerate() {
+            let match_obj = match_desc.to_java_object(env, &query, query_match)?;
+            let match_obj = env.auto_local(match_obj);
+            env.set_object_array_element(&matches_array, index as i32, match_obj)?;
+        }
+        Ok(matches_array)
+    }
+    let result = inner(
+        &mut env,
+        snapshot,
+        text,
+        language_id,
+        query_source,
+        start_offset,
+        end_offset,
+    );
+    match result {
+        Ok(matches) => matches,
+        Err(QueryParseError::JNIError(JNIError::JavaException)) => JObjectArray::default(),
+        Err(err) => {
+            env.throw_new(
+                "java/lang/RuntimeException",
+                format!("Failed to execute query: {err}"),
+            )
+            .unwrap();
+            JObjectArray::default()
+        }
+    }
+}
+
+/// Compiles query of the language once for repeated runs by `nativeExecQuery`, returns query
+/// handle to be released by `nativeDestroyQuery`
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeQueryExecutor_nativeCompileQuery<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    isolate_id: IsolateId,
+    language_id: LanguageId,
+    query_source: JString<'local>,
+) -> jlong {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        isolate_id: IsolateId,
+        language_id: LanguageId,
+        query_source: JString<'local>,
+    ) -> Result<jlong, QueryParseError> {
+        let isolate = Isolate::get(isolate_id)?;
+        let query_source = env.get_string(&query_source)?;
+        let query_source: Cow<'_, str> = (&query_source).into();
+        let query = isolate.compile_user_query(language_id, &query_source)?;
+        Ok(Box::into_raw(Box::new(query)) as jlong)
+    }
+    match inner(&mut env, isolate_id, language_id, query_source) {
+        Ok(handle) => handle,
+        Err(QueryParseError::JNIError(JNIError::JavaException)) => 0,
+        Err(err) => {
+            env.throw_new(
+                "java/lang/RuntimeException",
+                format!("Failed to compile query: {err}"),
+            )
+            .unwrap();
+            0
+        }
+    }
+}
+
+/// Matches of the query compiled by `nativeCompileQuery` within the range of the snapshot
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeQueryExecutor_nativeExecQuery<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+    snapshot: JObject<'local>,
+    text: JCharArray<'local>,
+    start_offset: jint,
+    end_offset: jint,
+) -> JObjectArray<'local> {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        query: &UserQuery,
+        snapshot: JObject<'local>,
+        text: JCharArray<'local>,
+        start_offset: jint,
+        end_offset: jint,
+    ) -> JNIResult<JObjectArray<'local>> {
+        let snapshot = SyntaxSnapshotDesc::from_java_object(env, snapshot)?;
+        let text_buffer = read_char_array(env, &text)?;
+        let matches = execute_query(
+            snapshot,
+            SourceText::Utf16(&text_buffer),
+            query,
+            ((start_offset * 2) as usize)..((end_offset * 2) as usize),
+        );
+        matches_to_java_array(env, query, matches)
+    }
+    // SAFETY: handle is created by nativeCompileQuery and not destroyed yet
+    let query = unsafe { &*(handle as *const Arc<UserQuery>) };
+    let result = inner(&mut env, query, snapshot, text, start_offset, end_offset);
+    throw_exception_from_result(&mut env, result)
+}
+
+/// Largest batch of `nativeExecQueryStreaming`, larger batch sizes are clamped to it
+const MAX_STREAMING_BATCH_SIZE: jint = 4096;
+
+/// Streams matches of the query compiled by `nativeCompileQuery` to `visitor` in arrays of up to
+/// `batch_size` matches, so huge results are never materialized at once. Visitor method
+/// `boolean onMatches(QueryMatch[])` returns false to stop the query. Throws
+/// `IllegalArgumentException` if `batch_size` is not positive, batches are capped at
+/// [`MAX_STREAMING_BATCH_SIZE`] matches.
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeQueryExecutor_nativeExecQueryStreaming<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+    snapshot: JObject<'local>,
+    text: JCharArray<'local>,
+    start_offset: jint,
+    end_offset: jint,
+    visitor: JObject<'local>,
+    batch_size: jint,
+) {
+    fn send_batch<'local>(
+        env: &mut JNIEnv<'local>,
+        query: &UserQuery,
+        visitor: &JObject<'local>,
+        batch: Vec<UserQueryMatch>,
+    ) -> JNIResult<bool> {
+        // Local references of the batch are released before the next one
+        env.with_local_frame(2, |env| {
+            let matches = matches_to_java_array(env, query, batch)?;
+            env.call_method(
+                visitor,
+                "onMatches",
+                "([Lcom/hulylabs/treesitter/language/QueryMatch;)Z",
+                &[JValue::Object(&matches)],
+            )?
+            .z()
+        })
+    }
+
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        query: &UserQuery,
+        snapshot: JObject<'local>,
+        text: JCharArray<'local>,
+        byte_range: Range<usize>,
+        visitor: JObject<'local>,
+        batch_size: usize,
+    ) -> JNIResult<()> {
+        let snapshot = SyntaxSnapshotDesc::from_java_object(env, snapshot)?;
+        let text_buffer = read_char_array(env, &text)?;
+        let mut batch = Vec::with_capacity(batch_size);
+        let mut result = Ok(true);
+        for_each_query_match(
+            snapshot,
+            SourceText::Utf16(&text_buffer),
+            query,
+            byte_range,
+            |query_match| {
+                batch.push(query_match);
+                if batch.len() < batch_size {
+                    return true;
+                }
+                result = send_batch(env, query, &visitor, std::mem::take(&mut batch));
+                matches!(result, Ok(true))
+            },
+        );
+        if result? && !batch.is_empty() {
+            send_batch(env, query, &visitor, batch)?;
+        }
+        Ok(())
+    }
+    if batch_size <= 0 {
+        env.throw_new(
+            "java/lang/IllegalArgumentException",
+            format!("batch size must be positive, got {batch_size}"),
+        )
+        .unwrap();
+        return;
+    }
+    // SAFETY: handle is created by nativeCompileQuery and not destroyed yet
+    let query = unsafe { &*(handle as *const Arc<UserQuery>) };
+    let result = inner(
+        &mut env,
+        query,
+        snapshot,
+        text,
+        ((start_offset * 2) as usize)..((end_offset * 2) as usize),
+        visitor,
+        batch_size.min(MAX_STREAMING_BATCH_SIZE) as usize,
+    );
+    throw_exception_from_result(&mut env, result)
+}
+
+/// Per-pattern match counts and cumulative time of the query compiled by `nativeCompileQuery`
+/// over the whole snapshot, element `i` of the result describes pattern `i`
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeQueryExecutor_nativeProfileQuery<
+    'local,
+>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+    snapshot: JObject<'local>,
+    text: JCharArray<'local>,
+) -> JObjectArray<'local> {
+    fn inner<'local>(
+        env: &mut JNIEnv<'local>,
+        query: &UserQuery,
+        snapshot: JObject<'local>,
+        text: JCharArray<'local>,
+    ) -> JNIResult<JObjectArray<'local>> {
+        let snapshot = SyntaxSnapshotDesc::from_java_object(env, snapshot)?;
+        let profile_desc = PatternProfileDesc::new(env)?;
+        let text_buffer = read_char_array(env, &text)?;
+        let profiles = profile_query(snapshot, SourceText::Utf16(&text_buffer), query);
+        let profiles_array = env.new_object_array(
+            profiles.len() as jsize,
+            &profile_desc.class,
+            JObject::null(),
+        )?;
+        for (index, profile) in profiles.iter().enumerate() {
+            let profile_obj = profile_desc.to_java_object(env, index, profile)?;
+            let profile_obj = env.auto_local(profile_obj);
+            env.set_object_array_element(&profiles_array, index as i32, profile_obj)?;
+        }
+        Ok(profiles_array)
+    }
+    // SAFETY: handle is created by nativeCompileQuery and not destroyed yet
+    let query = unsafe { &*(handle as *const Arc<UserQuery>) };
+    let result = inner(&mut env, query, snapshot, text);
+    throw_exception_from_result(&mut env, result)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_hulylabs_treesitter_rusty_TreeSitterNativeQueryExecutor_nativeDestroyQuery<
+    'local,
+>(
+    _env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+) {
+    let ptr = handle as *mut Arc<UserQuery>;
+    // SAFETY: handle is created from Box::into_raw, called by java when the query is released
+    std::mem::drop(unsafe { Box::from_raw(ptr) });
+}